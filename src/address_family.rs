@@ -0,0 +1,158 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use rand::RngCore;
+
+use crate::answer_order::AnswerOrder;
+use crate::error::DnsError;
+
+/// Address families queried by `query_addrs()`, and how the resulting
+/// addresses are ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum AddressFamilyPreference {
+    /// Query both families, and order all the addresses together according
+    /// to the answer order.
+    #[default]
+    Any,
+    /// Only query IPv4 addresses.
+    V4Only,
+    /// Only query IPv6 addresses.
+    V6Only,
+    /// Query both families, returning IPv4 addresses first.
+    PreferV4,
+    /// Query both families, returning IPv6 addresses first.
+    PreferV6,
+    /// Query both families, alternating between IPv6 and IPv4 addresses.
+    Interleaved,
+}
+
+impl AddressFamilyPreference {
+    pub(crate) fn wants_ipv4(self) -> bool {
+        self != AddressFamilyPreference::V6Only
+    }
+
+    pub(crate) fn wants_ipv6(self) -> bool {
+        self != AddressFamilyPreference::V4Only
+    }
+
+    /// Merge IPv4 and IPv6 addresses according to the preference.
+    pub(crate) fn order(
+        self,
+        ipv4_ips: Vec<Ipv4Addr>,
+        ipv6_ips: Vec<Ipv6Addr>,
+        answer_order: AnswerOrder,
+        rng: &mut dyn RngCore,
+    ) -> Vec<IpAddr> {
+        let ipv4_ips = ipv4_ips.into_iter().map(IpAddr::from);
+        let ipv6_ips = ipv6_ips.into_iter().map(IpAddr::from);
+        match self {
+            AddressFamilyPreference::Any => {
+                let mut ips: Vec<_> = ipv4_ips.chain(ipv6_ips).collect();
+                answer_order.apply(&mut ips, rng);
+                ips
+            }
+            AddressFamilyPreference::V4Only | AddressFamilyPreference::PreferV4 => {
+                ipv4_ips.chain(ipv6_ips).collect()
+            }
+            AddressFamilyPreference::V6Only | AddressFamilyPreference::PreferV6 => {
+                ipv6_ips.chain(ipv4_ips).collect()
+            }
+            AddressFamilyPreference::Interleaved => {
+                interleave(&ipv6_ips.chain(ipv4_ips).collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
+/// Alternate address families, starting with IPv6 (RFC 8305, section 4).
+pub(crate) fn interleave(ips: &[IpAddr]) -> Vec<IpAddr> {
+    let mut ipv6_ips = ips.iter().filter(|ip| ip.is_ipv6());
+    let mut ipv4_ips = ips.iter().filter(|ip| ip.is_ipv4());
+    let mut interleaved = Vec::with_capacity(ips.len());
+    loop {
+        let ipv6_ip = ipv6_ips.next();
+        let ipv4_ip = ipv4_ips.next();
+        if ipv6_ip.is_none() && ipv4_ip.is_none() {
+            break;
+        }
+        interleaved.extend(ipv6_ip);
+        interleaved.extend(ipv4_ip);
+    }
+    interleaved
+}
+
+/// Combine the responses of the `A` and `AAAA` queries. An error response
+/// for one family is ignored if the other family has addresses, as some
+/// servers fail to answer `AAAA` queries.
+pub(crate) fn merge_families(
+    ipv4_ips: Result<Vec<Ipv4Addr>, io::Error>,
+    ipv6_ips: Result<Vec<Ipv6Addr>, io::Error>,
+) -> Result<(Vec<Ipv4Addr>, Vec<Ipv6Addr>), io::Error> {
+    match (ipv4_ips, ipv6_ips) {
+        (Ok(ipv4_ips), Err(e)) if !ipv4_ips.is_empty() && DnsError::is_rcode_error(&e) => {
+            Ok((ipv4_ips, vec![]))
+        }
+        (Err(e), Ok(ipv6_ips)) if !ipv6_ips.is_empty() && DnsError::is_rcode_error(&e) => {
+            Ok((vec![], ipv6_ips))
+        }
+        (ipv4_ips, ipv6_ips) => Ok((ipv4_ips?, ipv6_ips?)),
+    }
+}
+
+#[test]
+fn test_interleave() {
+    let ips: Vec<IpAddr> = ["192.0.2.1", "192.0.2.2", "2001:db8::1"]
+        .iter()
+        .map(|ip| ip.parse().unwrap())
+        .collect();
+    assert_eq!(interleave(&ips), vec![ips[2], ips[0], ips[1]]);
+}
+
+#[test]
+fn test_merge_families() {
+    let ipv4_ips = vec![Ipv4Addr::new(192, 0, 2, 1)];
+    let servfail = || Err(DnsError::ServFail { rcode: 2 }.into());
+    assert_eq!(
+        merge_families(Ok(ipv4_ips.clone()), servfail()).unwrap(),
+        (ipv4_ips, vec![])
+    );
+    assert!(merge_families(Ok(vec![]), servfail()).is_err());
+    let timeout = Err(DnsError::Timeout.into());
+    assert!(merge_families(timeout, Ok(vec![Ipv6Addr::LOCALHOST])).is_err());
+    let e = merge_families(
+        Err(DnsError::NxDomain.into()),
+        Err(DnsError::NxDomain.into()),
+    )
+    .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_lookup_host() {
+    use std::net::SocketAddr;
+
+    use crate::testing::MockBackend;
+    use crate::upstream_server::UpstreamServer;
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    backend.add_aaaa("example.com", &[Ipv6Addr::LOCALHOST]);
+    let upstream_servers = vec![UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)))];
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    dns_client.set_address_family_preference(crate::AddressFamilyPreference::V4Only);
+    let addrs: Vec<_> = dns_client
+        .lookup_host(("example.com", 443))
+        .unwrap()
+        .collect();
+    assert_eq!(addrs, vec![SocketAddr::from(([192, 0, 2, 1], 443))]);
+    let addrs: Vec<_> = dns_client.lookup_host("192.0.2.2:53").unwrap().collect();
+    assert_eq!(addrs, vec![SocketAddr::from(([192, 0, 2, 2], 53))]);
+    assert!(dns_client.lookup_host("missing.example:80").is_err());
+    assert!(dns_client.lookup_host("example.com").is_err());
+}
@@ -0,0 +1,109 @@
+use std::cmp::Reverse;
+use std::net::IpAddr;
+
+use rand::seq::SliceRandom;
+use rand::RngCore;
+
+/// How the addresses returned by `query_a()`, `query_aaaa()` and
+/// `query_addrs()` are ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum AnswerOrder {
+    /// Shuffle the addresses, to spread the load across them.
+    #[default]
+    Shuffled,
+    /// Keep the order of the response.
+    AsReceived,
+    /// Sort the addresses according to the default policy table of RFC 6724,
+    /// keeping the order of the response for addresses of equal preference.
+    ///
+    /// Source addresses are not taken into account.
+    Rfc6724,
+}
+
+impl AnswerOrder {
+    pub(crate) fn apply<T: Copy + Into<IpAddr>>(self, ips: &mut [T], rng: &mut dyn RngCore) {
+        match self {
+            AnswerOrder::Shuffled => ips.shuffle(rng),
+            AnswerOrder::AsReceived => {}
+            AnswerOrder::Rfc6724 => ips.sort_by_key(|&ip| {
+                let ip = ip.into();
+                (Reverse(precedence(ip)), scope(ip))
+            }),
+        }
+    }
+}
+
+/// The precedence of an address in the default policy table (RFC 6724,
+/// section 2.1). IPv4 addresses are treated as IPv4-mapped addresses.
+fn precedence(ip: IpAddr) -> u8 {
+    let ip = match ip {
+        IpAddr::V4(_) => return 35,
+        IpAddr::V6(ip) => ip,
+    };
+    let segments = ip.segments();
+    if ip.is_loopback() {
+        50
+    } else if ip.to_ipv4_mapped().is_some() {
+        35
+    } else if segments[0] == 0x2002 {
+        30
+    } else if segments[0] == 0x2001 && segments[1] == 0 {
+        5
+    } else if segments[0] & 0xfe00 == 0xfc00 {
+        3
+    } else if segments[..6] == [0; 6] || segments[0] & 0xffc0 == 0xfec0 || segments[0] == 0x3ffe {
+        1
+    } else {
+        40
+    }
+}
+
+/// The scope of an address (RFC 6724, section 3.1). Smaller scopes are
+/// preferred.
+fn scope(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(ip) if ip.is_loopback() || ip.is_link_local() => 2,
+        IpAddr::V4(_) => 14,
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => scope(IpAddr::V4(ip)),
+            None if ip.is_loopback() || ip.segments()[0] & 0xffc0 == 0xfe80 => 2,
+            None if ip.segments()[0] & 0xffc0 == 0xfec0 => 5,
+            None => 14,
+        },
+    }
+}
+
+#[test]
+fn test_rfc6724_order() {
+    let mut ips: Vec<IpAddr> = [
+        "192.0.2.1",
+        "2001:0:5ef5:79fd::1",
+        "fd00::1",
+        "2001:db8::1",
+        "169.254.0.1",
+        "2002:c000:201::1",
+        "::1",
+    ]
+    .iter()
+    .map(|ip| ip.parse().unwrap())
+    .collect();
+    AnswerOrder::Rfc6724.apply(&mut ips, &mut rand::thread_rng());
+    let expected: Vec<IpAddr> = [
+        "::1",
+        "2001:db8::1",
+        "169.254.0.1",
+        "192.0.2.1",
+        "2002:c000:201::1",
+        "2001:0:5ef5:79fd::1",
+        "fd00::1",
+    ]
+    .iter()
+    .map(|ip| ip.parse().unwrap())
+    .collect();
+    assert_eq!(ips, expected);
+}
@@ -0,0 +1,99 @@
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use dnssector::ParsedPacket;
+
+use crate::query_info::QueryInfo;
+use crate::time::{SystemTime, UNIX_EPOCH};
+
+/// An audit trail of the queries made by a client, written as one JSON
+/// object per line.
+///
+/// Every query produces a line with the following properties:
+/// - `timestamp`: seconds since the Unix epoch, with millisecond precision
+/// - `qname`, `qtype`: the question
+/// - `server`: the upstream server that answered, `null` for local answers
+/// - `rcode`: the response code, `null` if the query failed
+/// - `rtt_ms`: the round-trip time to the answering server
+/// - `cache_hit`: whether the response was served from a cache
+/// - `error`: the error message if the query failed, `null` otherwise
+///
+/// Clones share the same writer. Write errors are ignored.
+#[derive(Clone)]
+pub struct AuditLog {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AuditLog")
+    }
+}
+
+impl AuditLog {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        AuditLog {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        }
+    }
+
+    pub(crate) fn record(
+        &self,
+        question: &Option<(Vec<u8>, u16, u16)>,
+        res: &Result<(ParsedPacket, QueryInfo), io::Error>,
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut line = format!("{{\"timestamp\":{:.3}", timestamp.as_secs_f64());
+        match question {
+            Some((name, rr_type, _)) => {
+                line.push_str(",\"qname\":");
+                push_json_string(&mut line, &String::from_utf8_lossy(name));
+                let _ = write!(line, ",\"qtype\":{}", rr_type);
+            }
+            None => line.push_str(",\"qname\":null,\"qtype\":null"),
+        }
+        match res {
+            Ok((parsed_response, query_info)) => {
+                line.push_str(",\"server\":");
+                match &query_info.server {
+                    Some(server) => push_json_string(&mut line, &server.to_string()),
+                    None => line.push_str("null"),
+                }
+                let _ = write!(
+                    line,
+                    ",\"rcode\":{},\"rtt_ms\":{:.3},\"cache_hit\":{},\"error\":null}}",
+                    parsed_response.rcode(),
+                    query_info.rtt.as_secs_f64() * 1000.0,
+                    query_info.cache_hit
+                );
+            }
+            Err(e) => {
+                line.push_str(
+                    ",\"server\":null,\"rcode\":null,\"rtt_ms\":null,\"cache_hit\":false,\"error\":",
+                );
+                push_json_string(&mut line, &e.to_string());
+                line.push('}');
+            }
+        }
+        line.push('\n');
+        let _ = self.writer.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+pub(crate) fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,416 @@
+use std::future::{poll_fn, Future};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::{pin, Pin};
+use std::task::Poll;
+use std::time::Duration;
+
+use crate::backend::buffer_pool::PooledBuffer;
+use crate::backend::tcp_pool::{self, tcp_pool, Pipeline};
+use crate::backend::{AsyncBackend, RuntimeAdapter};
+use crate::socket_options::SocketOptions;
+use crate::socks5::{self, Socks5Proxy};
+use crate::upstream_server::UpstreamServer;
+
+type BoxFuture<'t, T> = Pin<Box<dyn Future<Output = T> + Send + 't>>;
+
+pub(crate) async fn timeout<R: RuntimeAdapter, T>(
+    runtime: &R,
+    timeout: Duration,
+    f: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    let mut f = pin!(f);
+    let mut sleep = pin!(runtime.sleep(timeout));
+    poll_fn(|cx| {
+        if let Poll::Ready(res) = f.as_mut().poll(cx) {
+            return Poll::Ready(res);
+        }
+        if sleep.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "Timeout")));
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Send a query over a TCP connection, and read responses until the one
+/// matching the query.
+async fn tcp_exchange<R: RuntimeAdapter>(
+    runtime: &R,
+    stream: &mut R::TcpStream,
+    query: &[u8],
+) -> io::Result<Vec<u8>> {
+    runtime
+        .tcp_write_all(stream, &tcp_pool::frame(query))
+        .await?;
+    tcp_read_response(runtime, stream, query).await
+}
+
+/// Read responses from a TCP connection until the next one matching
+/// `query`.
+async fn tcp_read_response<R: RuntimeAdapter>(
+    runtime: &R,
+    stream: &mut R::TcpStream,
+    query: &[u8],
+) -> io::Result<Vec<u8>> {
+    loop {
+        let mut response_len_bytes = [0u8; 2];
+        runtime
+            .tcp_read_exact(stream, &mut response_len_bytes)
+            .await?;
+        let mut response = vec![0; tcp_pool::response_len(response_len_bytes)?];
+        runtime.tcp_read_exact(stream, &mut response).await?;
+        if tcp_pool::is_response_to(query, &response) {
+            return Ok(response);
+        }
+    }
+}
+
+/// Send pipelined queries over a TCP connection, and read responses until
+/// every query has received one.
+async fn tcp_pipelined_exchange<R: RuntimeAdapter>(
+    runtime: &R,
+    stream: &mut R::TcpStream,
+    queries: &[Vec<u8>],
+) -> io::Result<Vec<Vec<u8>>> {
+    let mut pipeline = Pipeline::new(queries);
+    runtime
+        .tcp_write_all(stream, &pipeline.framed_queries())
+        .await?;
+    while !pipeline.is_complete() {
+        let mut response_len_bytes = [0u8; 2];
+        runtime
+            .tcp_read_exact(stream, &mut response_len_bytes)
+            .await?;
+        let mut response = vec![0; tcp_pool::response_len(response_len_bytes)?];
+        runtime.tcp_read_exact(stream, &mut response).await?;
+        pipeline.on_response(response);
+    }
+    pipeline.into_responses()
+}
+
+/// Run `exchange` over a pooled connection to `upstream_server`, or over a
+/// new connection if there is none, or if the pooled one failed. Each
+/// attempt gives up after `timeout`.
+///
+/// `exchange` returns the stream along with its result, so that the stream
+/// can be returned to the pool.
+async fn with_tcp_stream<'t, R: RuntimeAdapter, T>(
+    runtime: &'t R,
+    upstream_server: &UpstreamServer,
+    timeout: Duration,
+    exchange: impl Fn(R::TcpStream) -> BoxFuture<'t, io::Result<(R::TcpStream, T)>>,
+) -> io::Result<T> {
+    let pool = tcp_pool::<R::TcpStream>();
+    let addr = upstream_server.addr;
+    // Connections through a proxy are not pooled, so that they are never
+    // mixed with direct connections to the same server.
+    let pooled = runtime.socks5_proxy().is_none() && runtime.tcp_pooling();
+    if let Some(stream) = pool.take(&addr).filter(|_| pooled) {
+        match self::timeout(runtime, timeout, exchange(stream)).await {
+            Ok((stream, res)) => {
+                pool.put(addr, stream);
+                return Ok(res);
+            }
+            Err(e) if !tcp_pool::should_retry(&e) => return Err(e),
+            Err(_) => {}
+        }
+    }
+    self::timeout(runtime, timeout, async {
+        let stream = socks5::connect(runtime, addr).await?;
+        let (stream, res) = exchange(stream).await?;
+        if pooled {
+            pool.put(addr, stream);
+        }
+        Ok(res)
+    })
+    .await
+}
+
+/// Open a TCP connection to `addr`, and send a query answered by multiple
+/// messages, such as a zone transfer. The messages are then read with
+/// `tcp_next_message()`.
+#[cfg(feature = "stream")]
+pub(crate) async fn tcp_multi_message_query<R: RuntimeAdapter>(
+    runtime: &R,
+    addr: SocketAddr,
+    query: &[u8],
+    timeout: Duration,
+) -> io::Result<R::TcpStream> {
+    self::timeout(runtime, timeout, async {
+        let mut stream = socks5::connect(runtime, addr).await?;
+        runtime
+            .tcp_write_all(&mut stream, &tcp_pool::frame(query))
+            .await?;
+        Ok(stream)
+    })
+    .await
+}
+
+/// Read the next message answering `query` from a connection opened by
+/// `tcp_multi_message_query()`.
+#[cfg(feature = "stream")]
+pub(crate) async fn tcp_next_message<R: RuntimeAdapter>(
+    runtime: &R,
+    stream: &mut R::TcpStream,
+    query: &[u8],
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    self::timeout(runtime, timeout, tcp_read_response(runtime, stream, query)).await
+}
+
+impl<R: RuntimeAdapter> AsyncBackend for R {
+    type TcpStream = R::TcpStream;
+
+    fn set_bound_device(&mut self, device: Option<&str>) -> io::Result<()> {
+        RuntimeAdapter::set_bound_device(self, device)
+    }
+
+    fn set_socket_options(&mut self, socket_options: SocketOptions) -> io::Result<()> {
+        RuntimeAdapter::set_socket_options(self, socket_options)
+    }
+
+    fn set_socks5_proxy(&mut self, proxy: Option<Socks5Proxy>) -> io::Result<()> {
+        RuntimeAdapter::set_socks5_proxy(self, proxy)
+    }
+
+    fn spawn(&self, task: BoxFuture<'static, ()>) -> bool {
+        RuntimeAdapter::spawn(self, task)
+    }
+
+    async fn dns_exchange_udp(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self::timeout(self, timeout, async {
+            let socket = self.udp_connect(*local_addr, upstream_server.addr).await?;
+            self.udp_send(&socket, query).await?;
+            let mut response = PooledBuffer::take();
+            loop {
+                let response_len = self
+                    .udp_recv(&socket, &mut response)
+                    .await
+                    .map_err(crate::backend::recv_error)?;
+                let response = &response[..response_len];
+                if crate::backend::is_udp_response_to(query, response) {
+                    return Ok(response.to_vec());
+                }
+            }
+        })
+        .await
+    }
+
+    async fn dns_exchange_multicast(
+        &self,
+        local_addr: &SocketAddr,
+        group_addr: &SocketAddr,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self::timeout(self, timeout, async {
+            let socket = self.udp_bind(*local_addr).await?;
+            self.udp_send_to(&socket, query, *group_addr).await?;
+            let mut response = PooledBuffer::take();
+            loop {
+                let (response_len, from) = self
+                    .udp_recv_from(&socket, &mut response)
+                    .await
+                    .map_err(crate::backend::recv_error)?;
+                let response = &response[..response_len];
+                if crate::backend::is_multicast_response_to(group_addr, query, &from, response) {
+                    return Ok(response.to_vec());
+                }
+            }
+        })
+        .await
+    }
+
+    async fn dns_exchange_tcp(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        with_tcp_stream(self, upstream_server, timeout, |mut stream| {
+            Box::pin(async move {
+                let response = tcp_exchange(self, &mut stream, query).await?;
+                Ok((stream, response))
+            })
+        })
+        .await
+    }
+
+    async fn dns_exchange_tcp_pipelined(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        queries: &[Vec<u8>],
+        timeout: Duration,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        with_tcp_stream(self, upstream_server, timeout, |mut stream| {
+            Box::pin(async move {
+                let responses = tcp_pipelined_exchange(self, &mut stream, queries).await?;
+                Ok((stream, responses))
+            })
+        })
+        .await
+    }
+
+    #[cfg(feature = "tls")]
+    async fn dns_exchange_tls(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        // The TLS session state is large, and would otherwise inflate every
+        // exchange future
+        let exchange: BoxFuture<'_, _> = Box::pin(crate::tls::exchange(
+            self,
+            crate::tls::client_config(),
+            upstream_server,
+            query,
+        ));
+        self::timeout(self, timeout, exchange).await
+    }
+
+    #[cfg(feature = "tls")]
+    async fn dns_exchange_tls_pipelined(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        queries: &[Vec<u8>],
+        timeout: Duration,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let exchange: BoxFuture<'_, _> = Box::pin(crate::tls::exchange_pipelined(
+            self,
+            crate::tls::client_config(),
+            upstream_server,
+            queries,
+        ));
+        self::timeout(self, timeout, exchange).await
+    }
+
+    #[cfg(feature = "doh")]
+    async fn dns_exchange_https(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let exchange: BoxFuture<'_, _> = Box::pin(crate::doh::exchange(
+            self,
+            crate::doh::client_config(),
+            upstream_server,
+            query,
+        ));
+        self::timeout(self, timeout, exchange).await
+    }
+
+    #[cfg(feature = "odoh")]
+    async fn dns_exchange_odoh(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let exchange: BoxFuture<'_, _> = Box::pin(crate::odoh::exchange(
+            self,
+            crate::doh::client_config(),
+            upstream_server,
+            query,
+        ));
+        self::timeout(self, timeout, exchange).await
+    }
+
+    #[cfg(feature = "doq")]
+    async fn dns_exchange_quic(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let exchange: BoxFuture<'_, _> = Box::pin(crate::doq::exchange(
+            self,
+            crate::doq::client_config(),
+            local_addr,
+            upstream_server,
+            query,
+        ));
+        self::timeout(self, timeout, exchange).await
+    }
+
+    fn delay(&self, duration: Duration) -> impl Future<Output = ()> + Send + '_ {
+        self.sleep(duration)
+    }
+
+    async fn join<F1, F2>(&self, f1: F1, f2: F2) -> (F1::Output, F2::Output)
+    where
+        F1: Future + Send,
+        F2: Future + Send,
+        F1::Output: Send,
+        F2::Output: Send,
+    {
+        super::join(f1, f2).await
+    }
+
+    async fn connect_staggered(
+        &self,
+        addrs: Vec<SocketAddr>,
+        attempt_delay: Duration,
+        timeout: Duration,
+    ) -> io::Result<R::TcpStream> {
+        let connect = |addr: SocketAddr| -> BoxFuture<'_, io::Result<R::TcpStream>> {
+            Box::pin(self::timeout(self, timeout, self.tcp_connect(addr)))
+        };
+        let mut pending = addrs.into_iter();
+        let mut attempts: Vec<_> = pending.next().into_iter().map(connect).collect();
+        let mut next_attempt: BoxFuture<'_, ()> = Box::pin(self.sleep(attempt_delay));
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "No addresses to connect to");
+
+        // Attempts run concurrently within this future, so that no
+        // runtime-specific task spawning is required.
+        poll_fn(|cx| loop {
+            let mut rearm = false;
+            if pending.len() > 0 && next_attempt.as_mut().poll(cx).is_ready() {
+                attempts.extend(pending.next().map(connect));
+                next_attempt = Box::pin(self.sleep(attempt_delay));
+                rearm = true;
+            }
+            let mut i = 0;
+            while i < attempts.len() {
+                match attempts[i].as_mut().poll(cx) {
+                    Poll::Ready(Ok(stream)) => return Poll::Ready(Ok(stream)),
+                    Poll::Ready(Err(e)) => {
+                        last_error = e;
+                        drop(attempts.swap_remove(i));
+                        if let Some(addr) = pending.next() {
+                            attempts.push(connect(addr));
+                            next_attempt = Box::pin(self.sleep(attempt_delay));
+                            rearm = true;
+                        }
+                    }
+                    Poll::Pending => i += 1,
+                }
+            }
+            if attempts.is_empty() {
+                return Poll::Ready(Err(std::mem::replace(
+                    &mut last_error,
+                    io::Error::new(io::ErrorKind::NotFound, "No addresses to connect to"),
+                )));
+            }
+            if !rearm {
+                return Poll::Pending;
+            }
+        })
+        .await
+    }
+}
@@ -0,0 +1,139 @@
+use std::future::Future;
+use std::io;
+use std::net::{SocketAddr, TcpStream as StdTcpStream, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_io::{Async, Timer};
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::backend::bound_device::{self, BoundDevice};
+use crate::backend::RuntimeAdapter;
+use crate::socket_options::SocketOptions;
+use crate::socks5::Socks5Proxy;
+
+pub type TcpStream = Async<StdTcpStream>;
+
+#[derive(Clone, Debug, Default)]
+pub struct AsyncIoBackend {
+    bound_device: Option<BoundDevice>,
+    socket_options: SocketOptions,
+    socks5_proxy: Option<Arc<Socks5Proxy>>,
+}
+
+impl AsyncIoBackend {
+    fn udp_socket(&self, local_addr: SocketAddr) -> io::Result<Async<UdpSocket>> {
+        Async::new(bound_device::udp_bind(
+            &local_addr,
+            self.bound_device.as_ref(),
+            &self.socket_options,
+        )?)
+    }
+}
+
+impl RuntimeAdapter for AsyncIoBackend {
+    type UdpSocket = Async<UdpSocket>;
+    type TcpStream = TcpStream;
+
+    fn set_bound_device(&mut self, device: Option<&str>) -> io::Result<()> {
+        self.bound_device = device.map(BoundDevice::new).transpose()?;
+        Ok(())
+    }
+
+    fn set_socket_options(&mut self, socket_options: SocketOptions) -> io::Result<()> {
+        self.socket_options = socket_options;
+        Ok(())
+    }
+
+    fn set_socks5_proxy(&mut self, proxy: Option<Socks5Proxy>) -> io::Result<()> {
+        self.socks5_proxy = proxy.map(Arc::new);
+        Ok(())
+    }
+
+    fn socks5_proxy(&self) -> Option<&Socks5Proxy> {
+        self.socks5_proxy.as_deref()
+    }
+
+    fn tcp_pooling(&self) -> bool {
+        self.bound_device.is_none() && self.socket_options.is_empty()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        Timer::after(duration).await;
+    }
+
+    async fn udp_connect(
+        &self,
+        local_addr: SocketAddr,
+        remote_addr: SocketAddr,
+    ) -> io::Result<Async<UdpSocket>> {
+        let socket = self.udp_socket(local_addr)?;
+        socket.get_ref().connect(remote_addr)?;
+        Ok(socket)
+    }
+
+    fn udp_send<'t>(
+        &'t self,
+        socket: &'t Async<UdpSocket>,
+        buf: &'t [u8],
+    ) -> impl Future<Output = io::Result<usize>> + Send + 't {
+        socket.send(buf)
+    }
+
+    fn udp_recv<'t>(
+        &'t self,
+        socket: &'t Async<UdpSocket>,
+        buf: &'t mut [u8],
+    ) -> impl Future<Output = io::Result<usize>> + Send + 't {
+        socket.recv(buf)
+    }
+
+    async fn udp_bind(&self, local_addr: SocketAddr) -> io::Result<Async<UdpSocket>> {
+        self.udp_socket(local_addr)
+    }
+
+    fn udp_send_to<'t>(
+        &'t self,
+        socket: &'t Async<UdpSocket>,
+        buf: &'t [u8],
+        addr: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send + 't {
+        socket.send_to(buf, addr)
+    }
+
+    fn udp_recv_from<'t>(
+        &'t self,
+        socket: &'t Async<UdpSocket>,
+        buf: &'t mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send + 't {
+        socket.recv_from(buf)
+    }
+
+    async fn tcp_connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let stream = match &self.bound_device {
+            None => TcpStream::connect(addr).await?,
+            Some(bound_device) => {
+                Async::new(bound_device::tcp_connect_async(&addr, bound_device).await?)?
+            }
+        };
+        let _ = stream.get_ref().set_nodelay(true);
+        self.socket_options.apply(stream.get_ref(), &addr)?;
+        Ok(stream)
+    }
+
+    fn tcp_write_all<'t>(
+        &'t self,
+        stream: &'t mut TcpStream,
+        buf: &'t [u8],
+    ) -> impl Future<Output = io::Result<()>> + Send + 't {
+        stream.write_all(buf)
+    }
+
+    fn tcp_read_exact<'t>(
+        &'t self,
+        stream: &'t mut TcpStream,
+        buf: &'t mut [u8],
+    ) -> impl Future<Output = io::Result<()>> + Send + 't {
+        stream.read_exact(buf)
+    }
+}
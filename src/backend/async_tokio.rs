@@ -1,82 +1,148 @@
 use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
 
-use dnssector::constants::DNS_MAX_COMPRESSED_SIZE;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpStream, UdpSocket};
 
-use crate::upstream_server::UpstreamServer;
+use crate::backend::bound_device::{self, BoundDevice};
+use crate::backend::RuntimeAdapter;
+use crate::socket_options::SocketOptions;
+use crate::socks5::Socks5Proxy;
 
-#[derive(Clone, Debug)]
-pub struct AsyncBackend {
-    pub upstream_server_timeout: Duration,
+#[derive(Clone, Debug, Default)]
+pub struct TokioBackend {
+    bound_device: Option<BoundDevice>,
+    socket_options: SocketOptions,
+    socks5_proxy: Option<Arc<Socks5Proxy>>,
 }
 
-impl AsyncBackend {
-    pub fn new(upstream_server_timeout: Duration) -> Self {
-        AsyncBackend {
-            upstream_server_timeout,
+impl TokioBackend {
+    fn udp_socket(&self, local_addr: SocketAddr) -> io::Result<UdpSocket> {
+        let socket = bound_device::udp_bind(
+            &local_addr,
+            self.bound_device.as_ref(),
+            &self.socket_options,
+        )?;
+        socket.set_nonblocking(true)?;
+        UdpSocket::from_std(socket)
+    }
+}
+
+impl RuntimeAdapter for TokioBackend {
+    type UdpSocket = UdpSocket;
+    type TcpStream = TcpStream;
+
+    fn set_bound_device(&mut self, device: Option<&str>) -> io::Result<()> {
+        self.bound_device = device.map(BoundDevice::new).transpose()?;
+        Ok(())
+    }
+
+    fn set_socket_options(&mut self, socket_options: SocketOptions) -> io::Result<()> {
+        self.socket_options = socket_options;
+        Ok(())
+    }
+
+    fn set_socks5_proxy(&mut self, proxy: Option<Socks5Proxy>) -> io::Result<()> {
+        self.socks5_proxy = proxy.map(Arc::new);
+        Ok(())
+    }
+
+    fn socks5_proxy(&self) -> Option<&Socks5Proxy> {
+        self.socks5_proxy.as_deref()
+    }
+
+    fn tcp_pooling(&self) -> bool {
+        self.bound_device.is_none() && self.socket_options.is_empty()
+    }
+
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send + '_ {
+        tokio::time::sleep(duration)
+    }
+
+    fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) -> bool {
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(task);
+                true
+            }
+            Err(_) => false,
         }
     }
 
-    pub async fn dns_exchange_udp(
-        &self,
-        local_addr: &SocketAddr,
-        upstream_server: &UpstreamServer,
-        query: &[u8],
-    ) -> io::Result<Vec<u8>> {
-        tokio::time::timeout(self.upstream_server_timeout, async {
-            let socket = UdpSocket::bind(local_addr).await?;
-            socket.connect(upstream_server.addr).await?;
-            socket.send(query).await?;
-            let mut response = vec![0; DNS_MAX_COMPRESSED_SIZE];
-            let response_len = socket
-                .recv(&mut response)
-                .await
-                .map_err(|_| io::Error::new(io::ErrorKind::WouldBlock, "Timeout"))?;
-            response.truncate(response_len);
-            Ok(response)
-        })
-        .await
-        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Timeout"))?
-    }
-
-    pub async fn dns_exchange_tcp(
+    async fn udp_connect(
         &self,
-        _local_addr: &SocketAddr,
-        upstream_server: &UpstreamServer,
-        query: &[u8],
-    ) -> io::Result<Vec<u8>> {
-        tokio::time::timeout(self.upstream_server_timeout, async {
-            let mut stream = TcpStream::connect(&upstream_server.addr).await?;
-            let _ = stream.set_nodelay(true);
-            let query_len = query.len();
-            let mut tcp_query = Vec::with_capacity(2 + query_len);
-            tcp_query.push((query_len >> 8) as u8);
-            tcp_query.push(query_len as u8);
-            tcp_query.extend_from_slice(query);
-            stream.write_all(&tcp_query).await?;
-            let mut response_len_bytes = [0u8; 2];
-            stream.read_exact(&mut response_len_bytes).await?;
-            let response_len =
-                ((response_len_bytes[0] as usize) << 8) | (response_len_bytes[1] as usize);
-            if response_len > DNS_MAX_COMPRESSED_SIZE {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Response too large",
-                ));
+        local_addr: SocketAddr,
+        remote_addr: SocketAddr,
+    ) -> io::Result<UdpSocket> {
+        let socket = self.udp_socket(local_addr)?;
+        socket.connect(remote_addr).await?;
+        Ok(socket)
+    }
+
+    fn udp_send<'t>(
+        &'t self,
+        socket: &'t UdpSocket,
+        buf: &'t [u8],
+    ) -> impl Future<Output = io::Result<usize>> + Send + 't {
+        socket.send(buf)
+    }
+
+    fn udp_recv<'t>(
+        &'t self,
+        socket: &'t UdpSocket,
+        buf: &'t mut [u8],
+    ) -> impl Future<Output = io::Result<usize>> + Send + 't {
+        socket.recv(buf)
+    }
+
+    async fn udp_bind(&self, local_addr: SocketAddr) -> io::Result<UdpSocket> {
+        self.udp_socket(local_addr)
+    }
+
+    fn udp_send_to<'t>(
+        &'t self,
+        socket: &'t UdpSocket,
+        buf: &'t [u8],
+        addr: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send + 't {
+        socket.send_to(buf, addr)
+    }
+
+    fn udp_recv_from<'t>(
+        &'t self,
+        socket: &'t UdpSocket,
+        buf: &'t mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send + 't {
+        socket.recv_from(buf)
+    }
+
+    async fn tcp_connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let stream = match &self.bound_device {
+            None => TcpStream::connect(addr).await?,
+            Some(bound_device) => {
+                let stream =
+                    TcpStream::from_std(bound_device::tcp_connect_start(&addr, bound_device)?)?;
+                stream.writable().await?;
+                if let Some(e) = stream.take_error()? {
+                    return Err(e);
+                }
+                stream
             }
-            let mut response = vec![0; response_len];
-            stream.read_exact(&mut response).await?;
-            Ok(response)
-        })
-        .await
-        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Timeout"))?
+        };
+        let _ = stream.set_nodelay(true);
+        self.socket_options.apply(&stream, &addr)?;
+        Ok(stream)
+    }
+
+    async fn tcp_write_all(&self, stream: &mut TcpStream, buf: &[u8]) -> io::Result<()> {
+        stream.write_all(buf).await
     }
 
-    pub async fn join<F1: Future, F2: Future>(&self, f1: F1, f2: F2) -> (F1::Output, F2::Output) {
-        tokio::join!(f1, f2)
+    async fn tcp_read_exact(&self, stream: &mut TcpStream, buf: &mut [u8]) -> io::Result<()> {
+        stream.read_exact(buf).await.map(|_| ())
     }
 }
@@ -0,0 +1,360 @@
+use std::io;
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use crate::socket_options::SocketOptions;
+
+/// A network interface that sockets are bound to, so that queries are sent
+/// through it regardless of the routing table.
+///
+/// This uses `SO_BINDTODEVICE` on Linux and `IP_BOUND_IF`/`IPV6_BOUND_IF` on
+/// macOS and iOS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    )),
+    allow(dead_code)
+)]
+pub(crate) struct BoundDevice {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    name: [u8; libc::IFNAMSIZ],
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    index: u32,
+}
+
+impl BoundDevice {
+    /// Look up the interface named `name`, such as `eth0`.
+    pub(crate) fn new(name: &str) -> io::Result<Self> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            if name.is_empty() || name.len() >= libc::IFNAMSIZ || name.contains('\0') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Invalid network interface name",
+                ));
+            }
+            let mut bound_device = BoundDevice {
+                name: [0; libc::IFNAMSIZ],
+            };
+            bound_device.name[..name.len()].copy_from_slice(name.as_bytes());
+            Ok(bound_device)
+        }
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        {
+            let cname = std::ffi::CString::new(name).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Invalid network interface name",
+                )
+            })?;
+            match unsafe { libc::if_nametoindex(cname.as_ptr()) } {
+                0 => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Network interface [{}] not found", name),
+                )),
+                index => Ok(BoundDevice { index }),
+            }
+        }
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "ios"
+        )))]
+        {
+            let _ = name;
+            Err(unsupported_platform())
+        }
+    }
+
+    /// Bind `socket`, whose address family is the one of `local_addr`, to
+    /// the interface.
+    #[cfg(unix)]
+    fn apply<S: std::os::unix::io::AsRawFd>(
+        &self,
+        socket: &S,
+        local_addr: &SocketAddr,
+    ) -> io::Result<()> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let ret = {
+            let _ = local_addr;
+            unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    libc::SO_BINDTODEVICE,
+                    self.name.as_ptr() as *const libc::c_void,
+                    self.name.len() as libc::socklen_t,
+                )
+            }
+        };
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        let ret = {
+            let (level, option) = match local_addr {
+                SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_BOUND_IF),
+                SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_BOUND_IF),
+            };
+            unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    level,
+                    option,
+                    &self.index as *const u32 as *const libc::c_void,
+                    std::mem::size_of::<u32>() as libc::socklen_t,
+                )
+            }
+        };
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "ios"
+        )))]
+        let ret = {
+            let _ = (socket, local_addr);
+            0
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply<S>(&self, _socket: &S, _local_addr: &SocketAddr) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Bind a UDP socket to `local_addr`, and to `bound_device` if there is one,
+/// and set `socket_options` on it.
+pub(crate) fn udp_bind(
+    local_addr: &SocketAddr,
+    bound_device: Option<&BoundDevice>,
+    socket_options: &SocketOptions,
+) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(local_addr)?;
+    if let Some(bound_device) = bound_device {
+        bound_device.apply(&socket, local_addr)?;
+    }
+    socket_options.apply(&socket, local_addr)?;
+    Ok(socket)
+}
+
+/// Start connecting a non-blocking TCP socket, bound to `bound_device`, to
+/// `addr`. The connection is established once the socket is writable.
+///
+/// The interface has to be set before connecting, so that the route through
+/// it is used.
+#[cfg(unix)]
+pub(crate) fn tcp_connect_start(
+    addr: &SocketAddr,
+    bound_device: &BoundDevice,
+) -> io::Result<TcpStream> {
+    use std::mem;
+    use std::os::unix::io::FromRawFd;
+
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The stream owns the descriptor, and closes it on error
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    stream.set_nonblocking(true)?;
+    bound_device.apply(&stream, addr)?;
+    let ret = match addr {
+        SocketAddr::V4(addr) => {
+            let mut sin: libc::sockaddr_in = unsafe { mem::zeroed() };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            #[cfg(any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd",
+                target_os = "dragonfly"
+            ))]
+            {
+                sin.sin_len = mem::size_of::<libc::sockaddr_in>() as u8;
+            }
+            unsafe {
+                libc::connect(
+                    fd,
+                    &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        SocketAddr::V6(addr) => {
+            let mut sin6: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+            #[cfg(any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd",
+                target_os = "dragonfly"
+            ))]
+            {
+                sin6.sin6_len = mem::size_of::<libc::sockaddr_in6>() as u8;
+            }
+            unsafe {
+                libc::connect(
+                    fd,
+                    &sin6 as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if ret != 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(e);
+        }
+    }
+    Ok(stream)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn tcp_connect_start(
+    _addr: &SocketAddr,
+    _bound_device: &BoundDevice,
+) -> io::Result<TcpStream> {
+    Err(unsupported_platform())
+}
+
+/// Open a TCP connection to `addr`, through `bound_device` if there is one,
+/// giving up after `timeout`.
+pub(crate) fn tcp_connect(
+    addr: &SocketAddr,
+    bound_device: Option<&BoundDevice>,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let bound_device = match bound_device {
+        None => return TcpStream::connect_timeout(addr, timeout),
+        Some(bound_device) => bound_device,
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        use std::time::Instant;
+
+        let deadline = Instant::now() + timeout;
+        let stream = tcp_connect_start(addr, bound_device)?;
+        let mut pollfd = libc::pollfd {
+            fd: stream.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                0 => return Err(io::Error::new(io::ErrorKind::TimedOut, "Timeout")),
+                ret if ret > 0 => break,
+                _ => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        if let Some(e) = stream.take_error()? {
+            return Err(e);
+        }
+        stream.set_nonblocking(false)?;
+        Ok(stream)
+    }
+    #[cfg(not(unix))]
+    tcp_connect_start(addr, bound_device)
+}
+
+/// Open a TCP connection to `addr`, bound to `bound_device`, with the
+/// `async-io` reactor, that `async-std` also uses.
+#[cfg(any(feature = "async", feature = "async-io"))]
+pub(crate) async fn tcp_connect_async(
+    addr: &SocketAddr,
+    bound_device: &BoundDevice,
+) -> io::Result<TcpStream> {
+    let stream = async_io::Async::new(tcp_connect_start(addr, bound_device)?)?;
+    stream.writable().await?;
+    if let Some(e) = stream.get_ref().take_error()? {
+        return Err(e);
+    }
+    stream.into_inner()
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn unsupported_platform() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Binding sockets to a network interface is not supported on this platform",
+    )
+}
+
+/// The default implementation of `set_bound_device()`, for backends that
+/// don't support it.
+pub(crate) fn unsupported(device: Option<&str>) -> io::Result<()> {
+    match device {
+        None => Ok(()),
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Binding sockets to a network interface is not supported by this backend",
+        )),
+    }
+}
+
+#[test]
+fn test_bound_device() {
+    assert!(BoundDevice::new("").is_err());
+    assert!(BoundDevice::new(&"a".repeat(64)).is_err());
+    assert!(udp_bind(
+        &SocketAddr::from(([127, 0, 0, 1], 0)),
+        None,
+        &SocketOptions::default()
+    )
+    .is_ok());
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn test_tcp_connect() {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let bound_device = BoundDevice::new("lo").unwrap();
+    match tcp_connect(&addr, Some(&bound_device), Duration::from_secs(5)) {
+        Ok(stream) => {
+            assert_eq!(stream.peer_addr().unwrap(), addr);
+            assert!(listener.accept().is_ok());
+        }
+        // Binding to an interface requires CAP_NET_RAW
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+    }
+}
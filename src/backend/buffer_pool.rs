@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+
+use dnssector::constants::DNS_MAX_COMPRESSED_SIZE;
+
+/// The maximum number of idle buffers kept for reuse, per thread.
+///
+/// A thread rarely has more than a few exchanges in flight, and every idle
+/// buffer holds 64 KB until the thread exits.
+const MAX_IDLE_BUFFERS: usize = 4;
+
+thread_local! {
+    /// Idle receive buffers of the current thread, shared by all backends
+    /// without any locking.
+    static IDLE_BUFFERS: RefCell<Vec<Box<[u8]>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A buffer large enough to receive any UDP response, taken from a
+/// per-thread pool so that exchanges don't have to allocate and zero 64 KB
+/// each time.
+///
+/// The buffer returns to the pool of the thread that drops it. Its content
+/// is whatever the previous user left in it, so only the received bytes
+/// must be read. Short-lived threads, such as the ones sending `AAAA`
+/// queries in `DNSClient::query_addrs()`, don't benefit from the pool: their
+/// buffers are freed when they exit.
+pub(crate) struct PooledBuffer(Option<Box<[u8]>>);
+
+impl PooledBuffer {
+    /// Take an idle buffer, or allocate a new one if there is none.
+    pub(crate) fn take() -> Self {
+        let buffer = IDLE_BUFFERS
+            .with(|idle| idle.borrow_mut().pop())
+            .unwrap_or_else(|| vec![0; DNS_MAX_COMPRESSED_SIZE].into_boxed_slice());
+        PooledBuffer(Some(buffer))
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0.as_deref().unwrap()
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.0.as_deref_mut().unwrap()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.0.take() {
+            // The pool may already be gone if the thread is exiting
+            let _ = IDLE_BUFFERS.try_with(|idle| {
+                let mut idle = idle.borrow_mut();
+                if idle.len() < MAX_IDLE_BUFFERS {
+                    idle.push(buffer);
+                }
+            });
+        }
+    }
+}
+
+#[test]
+fn test_pooled_buffer() {
+    // A new thread starts with its own, empty pool
+    std::thread::spawn(|| {
+        assert!(IDLE_BUFFERS.with(|idle| idle.borrow().is_empty()));
+        let mut buffer = PooledBuffer::take();
+        assert_eq!(buffer.len(), DNS_MAX_COMPRESSED_SIZE);
+        buffer[0] = 42;
+        drop(buffer);
+        assert_eq!(IDLE_BUFFERS.with(|idle| idle.borrow().len()), 1);
+
+        // The idle buffer is reused
+        let buffer = PooledBuffer::take();
+        assert_eq!(buffer[0], 42);
+        assert!(IDLE_BUFFERS.with(|idle| idle.borrow().is_empty()));
+
+        // Other threads don't see it
+        drop(buffer);
+        std::thread::spawn(|| assert!(IDLE_BUFFERS.with(|idle| idle.borrow().is_empty())))
+            .join()
+            .unwrap();
+    })
+    .join()
+    .unwrap();
+}
@@ -0,0 +1,161 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::Duration;
+
+use send_wrapper::SendWrapper;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{AbortSignal, Headers, Request, RequestInit, Response};
+
+use crate::backend::AsyncBackend;
+use crate::upstream_server::{url_host, Transport, UpstreamServer};
+
+const CONTENT_TYPE: &str = "application/dns-message";
+
+/// A backend for WebAssembly modules running in a browser or a worker,
+/// sending queries to DNS-over-HTTPS servers with the `fetch` API.
+///
+/// Raw sockets are not available there, so other protocols return an
+/// `io::ErrorKind::Unsupported` error. The host name of the URL of upstream
+/// servers is resolved by the browser, and their address is ignored.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FetchBackend;
+
+impl AsyncBackend for FetchBackend {
+    type TcpStream = std::convert::Infallible;
+
+    async fn dns_exchange_udp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        _query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        Err(sockets_unsupported())
+    }
+
+    async fn dns_exchange_tcp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        _query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        Err(sockets_unsupported())
+    }
+
+    fn dns_exchange_https<'t>(
+        &'t self,
+        _local_addr: &'t SocketAddr,
+        upstream_server: &'t UpstreamServer,
+        query: &'t [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send + 't {
+        // JavaScript values are not `Send`: the future panics if it is
+        // polled from another thread than the one that created it
+        SendWrapper::new(fetch(upstream_server, query, timeout))
+    }
+
+    fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) -> bool {
+        wasm_bindgen_futures::spawn_local(task);
+        true
+    }
+
+    async fn join<F1, F2>(&self, f1: F1, f2: F2) -> (F1::Output, F2::Output)
+    where
+        F1: Future + Send,
+        F2: Future + Send,
+        F1::Output: Send,
+        F2::Output: Send,
+    {
+        super::join(f1, f2).await
+    }
+
+    async fn connect_staggered(
+        &self,
+        _addrs: Vec<SocketAddr>,
+        _attempt_delay: Duration,
+        _timeout: Duration,
+    ) -> io::Result<Self::TcpStream> {
+        Err(sockets_unsupported())
+    }
+}
+
+/// POST a query to a DNS-over-HTTPS server, and return the body of the
+/// response.
+async fn fetch(
+    upstream_server: &UpstreamServer,
+    query: &[u8],
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let (server_name, path) = match &upstream_server.transport {
+        Transport::Https { server_name, path } => (server_name, path),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Not a DNS-over-HTTPS server",
+            ))
+        }
+    };
+    let url = format!(
+        "https://{}:{}{}",
+        url_host(server_name),
+        upstream_server.addr.port(),
+        path
+    );
+    let headers = Headers::new().map_err(js_error)?;
+    headers
+        .set("Content-Type", CONTENT_TYPE)
+        .map_err(js_error)?;
+    headers.set("Accept", CONTENT_TYPE).map_err(js_error)?;
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_headers(&headers);
+    init.set_body(&js_sys::Uint8Array::from(query));
+    let timeout_ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+    init.set_signal(Some(&AbortSignal::timeout_with_u32(timeout_ms)));
+    let request = Request::new_with_str_and_init(&url, &init).map_err(js_error)?;
+    let global = js_sys::global();
+    let promise = match global.dyn_ref::<web_sys::Window>() {
+        Some(window) => window.fetch_with_request(&request),
+        None => global
+            .unchecked_into::<web_sys::WorkerGlobalScope>()
+            .fetch_with_request(&request),
+    };
+    let response: Response = JsFuture::from(promise)
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    if !response.ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("HTTP error {}", response.status()),
+        ));
+    }
+    let body = JsFuture::from(response.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    Ok(js_sys::Uint8Array::new(&body).to_vec())
+}
+
+/// Convert a JavaScript exception into an I/O error. Requests aborted after
+/// the timeout become `io::ErrorKind::TimedOut` errors.
+fn js_error(e: JsValue) -> io::Error {
+    match e.dyn_ref::<js_sys::Error>() {
+        Some(e) if e.name() == "TimeoutError" || e.name() == "AbortError" => {
+            io::Error::new(io::ErrorKind::TimedOut, "Timeout")
+        }
+        Some(e) => io::Error::other(String::from(e.message())),
+        None => io::Error::other(format!("{:?}", e)),
+    }
+}
+
+fn sockets_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Only DNS-over-HTTPS is supported by the fetch backend",
+    )
+}
@@ -1,7 +1,785 @@
+use std::future::{poll_fn, Future};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::{pin, Pin};
+use std::task::Poll;
+use std::time::Duration;
+
+use dnssector::constants::{DNS_FLAGS_OFFSET, DNS_HEADER_SIZE, DNS_TID_OFFSET};
+
+use crate::socket_options::SocketOptions;
+use crate::socks5::{self, Socks5Proxy};
+use crate::upstream_server::UpstreamServer;
+
+mod adapter;
+mod bound_device;
+mod buffer_pool;
+pub(crate) mod tcp_pool;
+
+#[cfg(feature = "doq")]
+pub(crate) use self::adapter::timeout;
+#[cfg(feature = "stream")]
+pub(crate) use self::adapter::{tcp_multi_message_query, tcp_next_message};
+
+#[cfg(feature = "async-io")]
+pub(crate) mod async_io;
+
 #[cfg(feature = "async")]
 pub(crate) mod async_std;
 
 #[cfg(feature = "async-tokio")]
 pub(crate) mod async_tokio;
 
+#[cfg(feature = "wasm")]
+pub(crate) mod fetch;
+
 pub(crate) mod sync;
+pub(crate) mod transport;
+
+/// The operations required by the synchronous client.
+///
+/// Every operation must give up after `timeout`.
+pub trait SyncBackend: Clone + Default + Send + Sync + 'static {
+    /// Bind the UDP sockets and TCP connections used to send queries to the
+    /// network interface named `device`, or stop binding them if `device` is
+    /// `None`.
+    ///
+    /// Backends that don't support this return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn set_bound_device(&mut self, device: Option<&str>) -> io::Result<()> {
+        bound_device::unsupported(device)
+    }
+
+    /// Set IP options, such as the type of service, on the sockets used to
+    /// send queries.
+    ///
+    /// Backends that don't support this return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn set_socket_options(&mut self, socket_options: SocketOptions) -> io::Result<()> {
+        socket_options.unsupported()
+    }
+
+    /// Tunnel TCP connections to upstream servers, including DNS-over-TLS
+    /// and DNS-over-HTTPS sessions, through a SOCKS5 proxy, or stop using a
+    /// proxy if `proxy` is `None`.
+    ///
+    /// Backends that don't support this return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn set_socks5_proxy(&mut self, proxy: Option<Socks5Proxy>) -> io::Result<()> {
+        socks5::unsupported(proxy)
+    }
+
+    /// Send a query over UDP, and return the raw response.
+    fn dns_exchange_udp(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>>;
+
+    /// Send a query over TCP, and return the raw response.
+    fn dns_exchange_tcp(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>>;
+
+    /// Send queries over a single TCP connection, without waiting for a
+    /// response before sending the next query (RFC 7766), and return the
+    /// raw responses, in the order of the queries. Queries have distinct
+    /// transaction IDs.
+    ///
+    /// The whole exchange gives up after `timeout`. The default
+    /// implementation sends the queries one at a time, with
+    /// `dns_exchange_tcp()`.
+    fn dns_exchange_tcp_pipelined(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        queries: &[Vec<u8>],
+        timeout: Duration,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        queries
+            .iter()
+            .map(|query| self.dns_exchange_tcp(local_addr, upstream_server, query, timeout))
+            .collect()
+    }
+
+    /// Send a query to a DNS-over-TLS server, and return the raw response.
+    ///
+    /// Backends without TLS support return an `io::ErrorKind::Unsupported`
+    /// error.
+    fn dns_exchange_tls(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        _query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        Err(tls_unsupported())
+    }
+
+    /// Send queries to a DNS-over-TLS server within a single session,
+    /// without waiting for a response before sending the next query, and
+    /// return the raw responses, in the order of the queries.
+    ///
+    /// The default implementation sends the queries one at a time, with
+    /// `dns_exchange_tls()`.
+    fn dns_exchange_tls_pipelined(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        queries: &[Vec<u8>],
+        timeout: Duration,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        queries
+            .iter()
+            .map(|query| self.dns_exchange_tls(local_addr, upstream_server, query, timeout))
+            .collect()
+    }
+
+    /// Send a query to a DNS-over-HTTPS server, and return the raw response.
+    ///
+    /// Backends without HTTPS support return an `io::ErrorKind::Unsupported`
+    /// error.
+    fn dns_exchange_https(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        _query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        Err(https_unsupported())
+    }
+
+    /// Send a query to an Oblivious DNS-over-HTTPS target, through its proxy,
+    /// and return the raw response.
+    ///
+    /// Backends without Oblivious DNS-over-HTTPS support return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn dns_exchange_odoh(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        _query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        Err(odoh_unsupported())
+    }
+
+    /// Send a one-shot multicast query (mDNS or LLMNR) to `group_addr`, and
+    /// return the first response, that can come from any responder.
+    ///
+    /// Backends without multicast support return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn dns_exchange_multicast(
+        &self,
+        _local_addr: &SocketAddr,
+        _group_addr: &SocketAddr,
+        _query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        Err(multicast_unsupported())
+    }
+}
+
+/// The runtime-specific operations required by the async client.
+///
+/// Every operation must give up after `timeout`, and return an
+/// `io::ErrorKind::TimedOut` error when this happens.
+///
+/// This trait is implemented by every `RuntimeAdapter`. Implementing it
+/// directly is only required to replace how queries are exchanged.
+pub trait AsyncBackend: Clone + Default + Send + Sync + 'static {
+    /// The TCP stream type returned by `connect_staggered()`.
+    type TcpStream: Send;
+
+    /// Bind the UDP sockets and TCP connections used to send queries to the
+    /// network interface named `device`, or stop binding them if `device` is
+    /// `None`.
+    ///
+    /// Backends that don't support this return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn set_bound_device(&mut self, device: Option<&str>) -> io::Result<()> {
+        bound_device::unsupported(device)
+    }
+
+    /// Set IP options, such as the type of service, on the sockets used to
+    /// send queries.
+    ///
+    /// Backends that don't support this return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn set_socket_options(&mut self, socket_options: SocketOptions) -> io::Result<()> {
+        socket_options.unsupported()
+    }
+
+    /// Tunnel TCP connections to upstream servers, including DNS-over-TLS
+    /// and DNS-over-HTTPS sessions, through a SOCKS5 proxy, or stop using a
+    /// proxy if `proxy` is `None`.
+    ///
+    /// Backends that don't support this return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn set_socks5_proxy(&mut self, proxy: Option<Socks5Proxy>) -> io::Result<()> {
+        socks5::unsupported(proxy)
+    }
+
+    /// Send a query over UDP, and return the raw response.
+    fn dns_exchange_udp<'t>(
+        &'t self,
+        local_addr: &'t SocketAddr,
+        upstream_server: &'t UpstreamServer,
+        query: &'t [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send + 't;
+
+    /// Send a query over TCP, and return the raw response.
+    fn dns_exchange_tcp<'t>(
+        &'t self,
+        local_addr: &'t SocketAddr,
+        upstream_server: &'t UpstreamServer,
+        query: &'t [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send + 't;
+
+    /// Send queries over a single TCP connection, without waiting for a
+    /// response before sending the next query (RFC 7766), and return the
+    /// raw responses, in the order of the queries. Queries have distinct
+    /// transaction IDs.
+    ///
+    /// The whole exchange gives up after `timeout`. The default
+    /// implementation sends the queries one at a time, with
+    /// `dns_exchange_tcp()`.
+    fn dns_exchange_tcp_pipelined<'t>(
+        &'t self,
+        local_addr: &'t SocketAddr,
+        upstream_server: &'t UpstreamServer,
+        queries: &'t [Vec<u8>],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<Vec<u8>>>> + Send + 't {
+        async move {
+            let mut responses = Vec::with_capacity(queries.len());
+            for query in queries {
+                responses.push(
+                    self.dns_exchange_tcp(local_addr, upstream_server, query, timeout)
+                        .await?,
+                );
+            }
+            Ok(responses)
+        }
+    }
+
+    /// Send a query to a DNS-over-TLS server, and return the raw response.
+    ///
+    /// Backends without TLS support return an `io::ErrorKind::Unsupported`
+    /// error.
+    fn dns_exchange_tls<'t>(
+        &'t self,
+        _local_addr: &'t SocketAddr,
+        _upstream_server: &'t UpstreamServer,
+        _query: &'t [u8],
+        _timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send + 't {
+        async { Err(tls_unsupported()) }
+    }
+
+    /// Send queries to a DNS-over-TLS server within a single session,
+    /// without waiting for a response before sending the next query, and
+    /// return the raw responses, in the order of the queries.
+    ///
+    /// The default implementation sends the queries one at a time, with
+    /// `dns_exchange_tls()`.
+    fn dns_exchange_tls_pipelined<'t>(
+        &'t self,
+        local_addr: &'t SocketAddr,
+        upstream_server: &'t UpstreamServer,
+        queries: &'t [Vec<u8>],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<Vec<u8>>>> + Send + 't {
+        async move {
+            let mut responses = Vec::with_capacity(queries.len());
+            for query in queries {
+                responses.push(
+                    self.dns_exchange_tls(local_addr, upstream_server, query, timeout)
+                        .await?,
+                );
+            }
+            Ok(responses)
+        }
+    }
+
+    /// Send a query to a DNS-over-HTTPS server, and return the raw response.
+    ///
+    /// Backends without HTTPS support return an `io::ErrorKind::Unsupported`
+    /// error.
+    fn dns_exchange_https<'t>(
+        &'t self,
+        _local_addr: &'t SocketAddr,
+        _upstream_server: &'t UpstreamServer,
+        _query: &'t [u8],
+        _timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send + 't {
+        async { Err(https_unsupported()) }
+    }
+
+    /// Send a query to an Oblivious DNS-over-HTTPS target, through its proxy,
+    /// and return the raw response.
+    ///
+    /// Backends without Oblivious DNS-over-HTTPS support return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn dns_exchange_odoh<'t>(
+        &'t self,
+        _local_addr: &'t SocketAddr,
+        _upstream_server: &'t UpstreamServer,
+        _query: &'t [u8],
+        _timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send + 't {
+        async { Err(odoh_unsupported()) }
+    }
+
+    /// Send a query to a DNS-over-QUIC server, and return the raw response.
+    ///
+    /// Backends without QUIC support return an `io::ErrorKind::Unsupported`
+    /// error.
+    fn dns_exchange_quic<'t>(
+        &'t self,
+        _local_addr: &'t SocketAddr,
+        _upstream_server: &'t UpstreamServer,
+        _query: &'t [u8],
+        _timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send + 't {
+        async { Err(quic_unsupported()) }
+    }
+
+    /// Send a one-shot multicast query (mDNS or LLMNR) to `group_addr`, and
+    /// return the first response, that can come from any responder.
+    ///
+    /// Backends without multicast support return an
+    /// `io::ErrorKind::Unsupported` error.
+    fn dns_exchange_multicast<'t>(
+        &'t self,
+        _local_addr: &'t SocketAddr,
+        _group_addr: &'t SocketAddr,
+        _query: &'t [u8],
+        _timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send + 't {
+        async { Err(multicast_unsupported()) }
+    }
+
+    /// Complete after `duration`.
+    ///
+    /// This is used to stagger hedged queries. The default implementation
+    /// never completes, so that backends without timers never send hedged
+    /// queries.
+    fn delay(&self, _duration: Duration) -> impl Future<Output = ()> + Send + '_ {
+        std::future::pending()
+    }
+
+    /// Run `task` in the background, without waiting for it to complete.
+    ///
+    /// This is used to refresh stale cached responses. Backends that can't
+    /// spawn tasks return `false`, and the task is dropped: responses are
+    /// then refreshed by the queries themselves.
+    fn spawn(&self, _task: Pin<Box<dyn Future<Output = ()> + Send>>) -> bool {
+        false
+    }
+
+    /// Run two futures concurrently, and return both outputs.
+    fn join<F1, F2>(&self, f1: F1, f2: F2) -> impl Future<Output = (F1::Output, F2::Output)> + Send
+    where
+        F1: Future + Send,
+        F2: Future + Send,
+        F1::Output: Send,
+        F2::Output: Send;
+
+    /// Connect to the first address that accepts a TCP connection, starting a
+    /// new attempt every `attempt_delay` or as soon as a previous one fails.
+    fn connect_staggered(
+        &self,
+        addrs: Vec<SocketAddr>,
+        attempt_delay: Duration,
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<Self::TcpStream>> + Send + '_;
+}
+
+/// The primitives the async client needs from a runtime: timers, UDP and TCP
+/// sockets.
+///
+/// Implementing this trait is enough to run the client on a custom executor.
+/// Timeouts, concurrency and the DNS exchanges themselves are built on top of
+/// these primitives.
+pub trait RuntimeAdapter: Clone + Default + Send + Sync + 'static {
+    type UdpSocket: Send + Sync;
+    type TcpStream: Send + 'static;
+
+    /// Bind the UDP sockets returned by `udp_connect()` and `udp_bind()`, and
+    /// the TCP streams returned by `tcp_connect()`, to the network interface
+    /// named `device`, or stop binding them if `device` is `None`.
+    ///
+    /// The default implementation returns an `io::ErrorKind::Unsupported`
+    /// error.
+    fn set_bound_device(&mut self, device: Option<&str>) -> io::Result<()> {
+        bound_device::unsupported(device)
+    }
+
+    /// Set IP options, such as the type of service, on the sockets used to
+    /// send queries.
+    ///
+    /// The default implementation returns an `io::ErrorKind::Unsupported`
+    /// error.
+    fn set_socket_options(&mut self, socket_options: SocketOptions) -> io::Result<()> {
+        socket_options.unsupported()
+    }
+
+    /// Tunnel the TCP connections of the client through a SOCKS5 proxy, or
+    /// stop using a proxy if `proxy` is `None`. The proxy is then returned
+    /// by `socks5_proxy()`.
+    ///
+    /// The default implementation returns an `io::ErrorKind::Unsupported`
+    /// error.
+    fn set_socks5_proxy(&mut self, proxy: Option<Socks5Proxy>) -> io::Result<()> {
+        socks5::unsupported(proxy)
+    }
+
+    /// The SOCKS5 proxy that TCP connections are tunneled through, if any.
+    ///
+    /// Connections are opened with `tcp_connect()` to the proxy, and the
+    /// SOCKS5 handshake is performed by the client.
+    fn socks5_proxy(&self) -> Option<&Socks5Proxy> {
+        None
+    }
+
+    /// Whether the TCP connections returned by `tcp_connect()` can be kept
+    /// open, and reused by any client of this runtime.
+    ///
+    /// Idle connections are pooled by server address, so this has to be
+    /// `false` if connections depend on settings of this instance, such as
+    /// a network interface or IP options. The default implementation
+    /// returns `true`.
+    fn tcp_pooling(&self) -> bool {
+        true
+    }
+
+    /// Complete after `duration`.
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send + '_;
+
+    /// Run `task` in the background, without waiting for it to complete.
+    ///
+    /// The default implementation returns `false`, and drops the task.
+    fn spawn(&self, _task: Pin<Box<dyn Future<Output = ()> + Send>>) -> bool {
+        false
+    }
+
+    /// Bind a UDP socket to `local_addr`, and connect it to `remote_addr`.
+    fn udp_connect(
+        &self,
+        local_addr: SocketAddr,
+        remote_addr: SocketAddr,
+    ) -> impl Future<Output = io::Result<Self::UdpSocket>> + Send + '_;
+
+    /// Send a datagram to the connected peer.
+    fn udp_send<'t>(
+        &'t self,
+        socket: &'t Self::UdpSocket,
+        buf: &'t [u8],
+    ) -> impl Future<Output = io::Result<usize>> + Send + 't;
+
+    /// Receive a datagram from the connected peer.
+    fn udp_recv<'t>(
+        &'t self,
+        socket: &'t Self::UdpSocket,
+        buf: &'t mut [u8],
+    ) -> impl Future<Output = io::Result<usize>> + Send + 't;
+
+    /// Bind a UDP socket to `local_addr`, without connecting it.
+    ///
+    /// This is only required for mDNS and LLMNR. The default implementation
+    /// returns an `io::ErrorKind::Unsupported` error.
+    fn udp_bind(
+        &self,
+        _local_addr: SocketAddr,
+    ) -> impl Future<Output = io::Result<Self::UdpSocket>> + Send + '_ {
+        async { Err(multicast_unsupported()) }
+    }
+
+    /// Send a datagram to `addr`, from a socket returned by `udp_bind()`.
+    fn udp_send_to<'t>(
+        &'t self,
+        _socket: &'t Self::UdpSocket,
+        _buf: &'t [u8],
+        _addr: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send + 't {
+        async { Err(multicast_unsupported()) }
+    }
+
+    /// Receive a datagram from any peer, on a socket returned by
+    /// `udp_bind()`.
+    fn udp_recv_from<'t>(
+        &'t self,
+        _socket: &'t Self::UdpSocket,
+        _buf: &'t mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send + 't {
+        async { Err(multicast_unsupported()) }
+    }
+
+    /// Open a TCP connection, with `TCP_NODELAY` set.
+    fn tcp_connect(
+        &self,
+        addr: SocketAddr,
+    ) -> impl Future<Output = io::Result<Self::TcpStream>> + Send + '_;
+
+    fn tcp_write_all<'t>(
+        &'t self,
+        stream: &'t mut Self::TcpStream,
+        buf: &'t [u8],
+    ) -> impl Future<Output = io::Result<()>> + Send + 't;
+
+    fn tcp_read_exact<'t>(
+        &'t self,
+        stream: &'t mut Self::TcpStream,
+        buf: &'t mut [u8],
+    ) -> impl Future<Output = io::Result<()>> + Send + 't;
+}
+
+/// Run futures concurrently, and return their outputs in the same order.
+pub(crate) async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let mut futures: Vec<Pin<Box<F>>> = futures.into_iter().map(Box::pin).collect();
+    let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    poll_fn(|cx| {
+        let mut pending = false;
+        for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+            if output.is_some() {
+                continue;
+            }
+            match future.as_mut().poll(cx) {
+                Poll::Ready(res) => *output = Some(res),
+                Poll::Pending => pending = true,
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await;
+    outputs.into_iter().flatten().collect()
+}
+
+/// Run futures from an iterator concurrently, with at most `limit` of them
+/// in flight, and return their outputs in the same order.
+pub(crate) async fn join_all_bounded<I>(
+    futures: I,
+    limit: usize,
+) -> Vec<<I::Item as Future>::Output>
+where
+    I: IntoIterator,
+    I::Item: Future,
+{
+    let mut pending = futures.into_iter().enumerate();
+    let mut running: Vec<(usize, Pin<Box<I::Item>>)> = vec![];
+    let mut outputs = vec![];
+    let limit = limit.max(1);
+    poll_fn(|cx| loop {
+        while running.len() < limit {
+            match pending.next() {
+                Some((i, future)) => {
+                    outputs.push(None);
+                    running.push((i, Box::pin(future)));
+                }
+                None => break,
+            }
+        }
+        if running.is_empty() {
+            return Poll::Ready(());
+        }
+        let running_count = running.len();
+        running.retain_mut(|(i, future)| match future.as_mut().poll(cx) {
+            Poll::Ready(output) => {
+                outputs[*i] = Some(output);
+                false
+            }
+            Poll::Pending => true,
+        });
+        // Only wait if nothing completed, otherwise start the next futures
+        if running.len() == running_count {
+            return Poll::Pending;
+        }
+    })
+    .await;
+    outputs.into_iter().flatten().collect()
+}
+
+/// Run two futures concurrently, and return both outputs.
+pub(crate) async fn join<F1: Future, F2: Future>(f1: F1, f2: F2) -> (F1::Output, F2::Output) {
+    let (mut f1, mut f2) = (pin!(f1), pin!(f2));
+    let (mut o1, mut o2) = (None, None);
+    poll_fn(|cx| {
+        if o1.is_none() {
+            if let Poll::Ready(o) = f1.as_mut().poll(cx) {
+                o1 = Some(o);
+            }
+        }
+        if o2.is_none() {
+            if let Poll::Ready(o) = f2.as_mut().poll(cx) {
+                o2 = Some(o);
+            }
+        }
+        match (o1.take(), o2.take()) {
+            (Some(o1), Some(o2)) => Poll::Ready((o1, o2)),
+            (p1, p2) => {
+                o1 = p1;
+                o2 = p2;
+                Poll::Pending
+            }
+        }
+    })
+    .await
+}
+
+/// Map the error of a UDP receive operation: read timeouts become
+/// `io::ErrorKind::TimedOut` errors, while other errors, such as
+/// `io::ErrorKind::ConnectionRefused` after an ICMP port unreachable
+/// message, are returned as is so that the next server can be tried
+/// immediately.
+pub(crate) fn recv_error(e: io::Error) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            io::Error::new(io::ErrorKind::TimedOut, "Timeout")
+        }
+        _ => e,
+    }
+}
+
+/// Whether a datagram received on a UDP socket is a response to `query`,
+/// with the same transaction ID and question.
+///
+/// Other datagrams, such as stray or spoofed packets, or late responses to
+/// previous queries, are discarded, and the response is still waited for.
+pub(crate) fn is_udp_response_to(query: &[u8], response: &[u8]) -> bool {
+    let tid = DNS_TID_OFFSET..DNS_TID_OFFSET + 2;
+    if response.len() < DNS_HEADER_SIZE
+        || query.get(tid.clone()) != response.get(tid)
+        || response[DNS_FLAGS_OFFSET] & 0x80 == 0
+    {
+        return false;
+    }
+    match (question(query), question(response)) {
+        (Some(query_question), Some(response_question)) => {
+            query_question.eq_ignore_ascii_case(response_question)
+        }
+        (Some(_), None) => false,
+        (None, _) => true,
+    }
+}
+
+/// Whether a datagram received from `from` on an unconnected socket is a
+/// response to a multicast `query` sent to `group_addr`.
+///
+/// Responders send from the port of the group, so datagrams from other ports
+/// are discarded in addition to the ones `is_udp_response_to()` rejects.
+pub(crate) fn is_multicast_response_to(
+    group_addr: &SocketAddr,
+    query: &[u8],
+    from: &SocketAddr,
+    response: &[u8],
+) -> bool {
+    from.port() == group_addr.port()
+        && from.is_ipv4() == group_addr.is_ipv4()
+        && is_udp_response_to(query, response)
+}
+
+/// The offset of the number of questions, in the header.
+const QDCOUNT_OFFSET: usize = 4;
+
+/// The question section of a packet with a single question, whose name is
+/// not compressed.
+fn question(packet: &[u8]) -> Option<&[u8]> {
+    if packet.get(QDCOUNT_OFFSET..QDCOUNT_OFFSET + 2)? != &[0, 1][..] {
+        return None;
+    }
+    let mut offset = DNS_HEADER_SIZE;
+    loop {
+        let label_len = *packet.get(offset)? as usize;
+        if label_len & 0xc0 != 0 {
+            return None;
+        }
+        offset += 1 + label_len;
+        if label_len == 0 {
+            break;
+        }
+    }
+    packet.get(DNS_HEADER_SIZE..offset + 4)
+}
+
+fn multicast_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Multicast queries are not supported by this backend",
+    )
+}
+
+fn tls_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "DNS-over-TLS is not supported by this backend, or the tls feature is disabled",
+    )
+}
+
+fn https_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "DNS-over-HTTPS is not supported by this backend, or the doh feature is disabled",
+    )
+}
+
+fn quic_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "DNS-over-QUIC is not supported by this backend, or the doq feature is disabled",
+    )
+}
+
+fn odoh_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Oblivious DNS-over-HTTPS is not supported by this backend, or the odoh feature is disabled",
+    )
+}
+
+#[test]
+fn test_is_udp_response_to() {
+    let query =
+        b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01";
+    let mut response = query.to_vec();
+    response[DNS_FLAGS_OFFSET] |= 0x80;
+    assert!(is_udp_response_to(query, &response));
+    response[DNS_HEADER_SIZE + 1] = b'E';
+    assert!(is_udp_response_to(query, &response));
+    assert!(!is_udp_response_to(query, query));
+    let mut other_tid = response.clone();
+    other_tid[DNS_TID_OFFSET] ^= 0xff;
+    assert!(!is_udp_response_to(query, &other_tid));
+    let mut other_question = response.clone();
+    other_question[DNS_HEADER_SIZE + 1] = b'x';
+    assert!(!is_udp_response_to(query, &other_question));
+    assert!(!is_udp_response_to(query, &response[..DNS_HEADER_SIZE]));
+
+    let group_addr: SocketAddr = "224.0.0.251:5353".parse().unwrap();
+    let responder: SocketAddr = "192.0.2.1:5353".parse().unwrap();
+    assert!(is_multicast_response_to(
+        &group_addr,
+        query,
+        &responder,
+        &response
+    ));
+    let stray: SocketAddr = "192.0.2.1:53".parse().unwrap();
+    assert!(!is_multicast_response_to(
+        &group_addr,
+        query,
+        &stray,
+        &response
+    ));
+}
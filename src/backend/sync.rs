@@ -1,70 +1,297 @@
 use std::io::{self, Read, Write};
-use std::net::{SocketAddr, TcpStream, UdpSocket};
-use std::time::Duration;
-
-use dnssector::constants::DNS_MAX_COMPRESSED_SIZE;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::backend::bound_device::{self, BoundDevice};
+use crate::backend::buffer_pool::PooledBuffer;
+use crate::backend::tcp_pool::{self, tcp_pool, Pipeline};
+use crate::backend::SyncBackend;
+use crate::socket_options::SocketOptions;
+use crate::socks5::{self, Socks5Proxy};
 use crate::upstream_server::UpstreamServer;
 
-#[derive(Clone, Debug)]
-pub struct SyncBackend {
-    pub upstream_server_timeout: Duration,
+/// A backend using the blocking sockets from the standard library.
+#[derive(Clone, Debug, Default)]
+pub struct StdBackend {
+    bound_device: Option<BoundDevice>,
+    socket_options: SocketOptions,
+    socks5_proxy: Option<Arc<Socks5Proxy>>,
 }
 
-impl SyncBackend {
-    pub fn new(upstream_server_timeout: Duration) -> Self {
-        SyncBackend {
-            upstream_server_timeout,
-        }
+impl SyncBackend for StdBackend {
+    fn set_bound_device(&mut self, device: Option<&str>) -> io::Result<()> {
+        self.bound_device = device.map(BoundDevice::new).transpose()?;
+        Ok(())
     }
 
-    pub fn dns_exchange_udp(
+    fn set_socket_options(&mut self, socket_options: SocketOptions) -> io::Result<()> {
+        self.socket_options = socket_options;
+        Ok(())
+    }
+
+    fn set_socks5_proxy(&mut self, proxy: Option<Socks5Proxy>) -> io::Result<()> {
+        self.socks5_proxy = proxy.map(Arc::new);
+        Ok(())
+    }
+
+    fn dns_exchange_udp(
         &self,
         local_addr: &SocketAddr,
         upstream_server: &UpstreamServer,
         query: &[u8],
+        timeout: Duration,
     ) -> io::Result<Vec<u8>> {
-        let socket = UdpSocket::bind(local_addr)?;
-        let _ = socket.set_read_timeout(Some(self.upstream_server_timeout));
+        let socket =
+            bound_device::udp_bind(local_addr, self.bound_device.as_ref(), &self.socket_options)?;
+        let deadline = Instant::now() + timeout;
         socket.connect(upstream_server.addr)?;
         socket.send(query)?;
-        let mut response = vec![0; DNS_MAX_COMPRESSED_SIZE];
-        let response_len = socket
-            .recv(&mut response)
-            .map_err(|_| io::Error::new(io::ErrorKind::WouldBlock, "Timeout"))?;
-        response.truncate(response_len);
-        Ok(response)
+        let mut response = PooledBuffer::take();
+        loop {
+            let _ = socket.set_read_timeout(Some(remaining(deadline)?));
+            let response_len = socket
+                .recv(&mut response)
+                .map_err(crate::backend::recv_error)?;
+            let response = &response[..response_len];
+            if crate::backend::is_udp_response_to(query, response) {
+                return Ok(response.to_vec());
+            }
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn dns_exchange_tls(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        crate::tls::exchange_blocking(
+            crate::tls::client_config(),
+            upstream_server,
+            query,
+            self,
+            timeout,
+        )
+    }
+
+    #[cfg(feature = "doh")]
+    fn dns_exchange_https(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        crate::doh::exchange_blocking(
+            crate::doh::client_config(),
+            upstream_server,
+            query,
+            self,
+            timeout,
+        )
+    }
+
+    #[cfg(feature = "odoh")]
+    fn dns_exchange_odoh(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        crate::odoh::exchange_blocking(
+            crate::doh::client_config(),
+            upstream_server,
+            query,
+            self,
+            timeout,
+        )
+    }
+
+    fn dns_exchange_multicast(
+        &self,
+        local_addr: &SocketAddr,
+        group_addr: &SocketAddr,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let socket =
+            bound_device::udp_bind(local_addr, self.bound_device.as_ref(), &self.socket_options)?;
+        let deadline = Instant::now() + timeout;
+        socket.send_to(query, group_addr)?;
+        let mut response = PooledBuffer::take();
+        loop {
+            let _ = socket.set_read_timeout(Some(remaining(deadline)?));
+            let (response_len, from) = socket
+                .recv_from(&mut response)
+                .map_err(crate::backend::recv_error)?;
+            let response = &response[..response_len];
+            if crate::backend::is_multicast_response_to(group_addr, query, &from, response) {
+                return Ok(response.to_vec());
+            }
+        }
     }
 
-    pub fn dns_exchange_tcp(
+    fn dns_exchange_tcp(
         &self,
         _local_addr: &SocketAddr,
         upstream_server: &UpstreamServer,
         query: &[u8],
+        timeout: Duration,
     ) -> io::Result<Vec<u8>> {
+        // The whole exchange gives up after `timeout`
+        let deadline = Instant::now() + timeout;
+        self.with_tcp_stream(upstream_server, deadline, |stream| {
+            tcp_exchange(stream, query, deadline)
+        })
+    }
+
+    fn dns_exchange_tcp_pipelined(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        queries: &[Vec<u8>],
+        timeout: Duration,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let deadline = Instant::now() + timeout;
+        self.with_tcp_stream(upstream_server, deadline, |stream| {
+            tcp_pipelined_exchange(stream, queries, deadline)
+        })
+    }
+
+    #[cfg(feature = "tls")]
+    fn dns_exchange_tls_pipelined(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        queries: &[Vec<u8>],
+        timeout: Duration,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        crate::tls::exchange_pipelined_blocking(
+            crate::tls::client_config(),
+            upstream_server,
+            queries,
+            self,
+            timeout,
+        )
+    }
+}
+
+impl StdBackend {
+    /// Open a TCP connection to `addr`, through the SOCKS5 proxy if there is
+    /// one.
+    pub(crate) fn tcp_connect(
+        &self,
+        addr: &SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<TcpStream> {
+        let proxy = self.socks5_proxy.as_deref();
+        let connect_addr = proxy.map_or(addr, |proxy| &proxy.addr);
         let mut stream =
-            TcpStream::connect_timeout(&upstream_server.addr, self.upstream_server_timeout)?;
-        let _ = stream.set_read_timeout(Some(self.upstream_server_timeout));
-        let _ = stream.set_write_timeout(Some(self.upstream_server_timeout));
+            bound_device::tcp_connect(connect_addr, self.bound_device.as_ref(), timeout)?;
         let _ = stream.set_nodelay(true);
-        let query_len = query.len();
-        let mut tcp_query = Vec::with_capacity(2 + query_len);
-        tcp_query.push((query_len >> 8) as u8);
-        tcp_query.push(query_len as u8);
-        tcp_query.extend_from_slice(query);
-        stream.write_all(&tcp_query)?;
+        self.socket_options.apply(&stream, connect_addr)?;
+        if let Some(proxy) = proxy {
+            socks5::handshake_blocking(&mut stream, proxy, addr, timeout)?;
+        }
+        Ok(stream)
+    }
+
+    /// Run `exchange` over a pooled connection to `upstream_server`, or
+    /// over a new connection if there is none, or if the pooled one failed.
+    fn with_tcp_stream<T>(
+        &self,
+        upstream_server: &UpstreamServer,
+        deadline: Instant,
+        exchange: impl Fn(&mut TcpStream) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let pool = tcp_pool::<TcpStream>();
+        let addr = upstream_server.addr;
+        // Connections through a proxy, bound to an interface or with IP
+        // options are not pooled, so that they are never mixed with other
+        // connections to the same server.
+        let pooled = self.socks5_proxy.is_none()
+            && self.bound_device.is_none()
+            && self.socket_options.is_empty();
+        if let Some(mut stream) = pool.take(&addr).filter(|_| pooled) {
+            match exchange(&mut stream) {
+                Ok(res) => {
+                    pool.put(addr, stream);
+                    return Ok(res);
+                }
+                Err(e) if !tcp_pool::should_retry(&e) => return Err(e),
+                Err(_) => {}
+            }
+        }
+        let mut stream = self.tcp_connect(&addr, remaining(deadline)?)?;
+        let res = exchange(&mut stream)?;
+        if pooled {
+            pool.put(addr, stream);
+        }
+        Ok(res)
+    }
+}
+
+/// The time left until `deadline`, or an `io::ErrorKind::TimedOut` error if
+/// it has passed.
+fn remaining(deadline: Instant) -> io::Result<Duration> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|timeout| !timeout.is_zero())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "Timeout"))
+}
+
+/// Send a query over a TCP connection, and read responses until the one
+/// matching the query, or until `deadline`.
+fn tcp_exchange(stream: &mut TcpStream, query: &[u8], deadline: Instant) -> io::Result<Vec<u8>> {
+    let _ = stream.set_write_timeout(Some(remaining(deadline)?));
+    stream.write_all(&tcp_pool::frame(query))?;
+    loop {
         let mut response_len_bytes = [0u8; 2];
-        stream.read_exact(&mut response_len_bytes)?;
-        let response_len =
-            ((response_len_bytes[0] as usize) << 8) | (response_len_bytes[1] as usize);
-        if response_len > DNS_MAX_COMPRESSED_SIZE {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Response too large",
-            ));
+        read_exact(stream, &mut response_len_bytes, deadline)?;
+        let mut response = vec![0; tcp_pool::response_len(response_len_bytes)?];
+        read_exact(stream, &mut response, deadline)?;
+        if tcp_pool::is_response_to(query, &response) {
+            return Ok(response);
+        }
+    }
+}
+
+/// Send pipelined queries over a TCP connection, and read responses until
+/// every query has received one, or until `deadline`.
+fn tcp_pipelined_exchange(
+    stream: &mut TcpStream,
+    queries: &[Vec<u8>],
+    deadline: Instant,
+) -> io::Result<Vec<Vec<u8>>> {
+    let mut pipeline = Pipeline::new(queries);
+    let _ = stream.set_write_timeout(Some(remaining(deadline)?));
+    stream.write_all(&pipeline.framed_queries())?;
+    while !pipeline.is_complete() {
+        let mut response_len_bytes = [0u8; 2];
+        read_exact(stream, &mut response_len_bytes, deadline)?;
+        let mut response = vec![0; tcp_pool::response_len(response_len_bytes)?];
+        read_exact(stream, &mut response, deadline)?;
+        pipeline.on_response(response);
+    }
+    pipeline.into_responses()
+}
+
+/// Fill `buf` from `stream`, giving up at `deadline` even if a server keeps
+/// sending data slowly.
+fn read_exact(stream: &mut TcpStream, buf: &mut [u8], deadline: Instant) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let _ = stream.set_read_timeout(Some(remaining(deadline)?));
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
         }
-        let mut response = vec![0; response_len];
-        stream.read_exact(&mut response)?;
-        Ok(response)
     }
+    Ok(())
 }
@@ -0,0 +1,205 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use dnssector::constants::{DNS_MAX_COMPRESSED_SIZE, DNS_TID_OFFSET};
+
+use crate::time::Instant;
+
+/// How long an idle connection is kept open, waiting for another query.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum number of idle connections kept open to a single upstream
+/// server.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+/// Idle TCP connections to upstream servers, kept open so that subsequent
+/// queries can reuse them instead of opening new connections (RFC 7766).
+///
+/// A connection is used by a single exchange at a time. It is returned to
+/// the pool only after a complete response was read, so that it never has
+/// queries in flight while idle.
+pub(crate) struct TcpPool<S> {
+    idle: Mutex<HashMap<SocketAddr, Vec<(Instant, S)>>>,
+}
+
+impl<S> TcpPool<S> {
+    fn new() -> Self {
+        TcpPool {
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take the most recently used idle connection to `addr`, if any.
+    pub(crate) fn take(&self, addr: &SocketAddr) -> Option<S> {
+        let mut idle = self.idle.lock().unwrap();
+        let streams = idle.get_mut(addr)?;
+        streams.retain(|(idle_since, _)| idle_since.elapsed() < IDLE_TIMEOUT);
+        let stream = streams.pop().map(|(_, stream)| stream);
+        if streams.is_empty() {
+            idle.remove(addr);
+        }
+        stream
+    }
+
+    /// Return a connection to the pool, after a complete exchange.
+    pub(crate) fn put(&self, addr: SocketAddr, stream: S) {
+        let mut idle = self.idle.lock().unwrap();
+        let streams = idle.entry(addr).or_default();
+        streams.retain(|(idle_since, _)| idle_since.elapsed() < IDLE_TIMEOUT);
+        if streams.len() >= MAX_IDLE_CONNECTIONS {
+            streams.remove(0);
+        }
+        streams.push((Instant::now(), stream));
+    }
+}
+
+/// The pool of connections of a given stream type.
+///
+/// Backends are stateless, so pools are global, one per stream type.
+pub(crate) fn tcp_pool<S: Send + 'static>() -> &'static TcpPool<S> {
+    type Pools = Mutex<HashMap<TypeId, &'static (dyn Any + Send + Sync)>>;
+    static POOLS: OnceLock<Pools> = OnceLock::new();
+    let mut pools = POOLS.get_or_init(Default::default).lock().unwrap();
+    let pool = *pools.entry(TypeId::of::<S>()).or_insert_with(|| {
+        let pool: &'static TcpPool<S> = Box::leak(Box::new(TcpPool::new()));
+        pool
+    });
+    pool.downcast_ref().expect("TCP pool type mismatch")
+}
+
+/// Prefix a query with its length.
+pub(crate) fn frame(query: &[u8]) -> Vec<u8> {
+    let query_len = query.len();
+    let mut tcp_query = Vec::with_capacity(2 + query_len);
+    tcp_query.push((query_len >> 8) as u8);
+    tcp_query.push(query_len as u8);
+    tcp_query.extend_from_slice(query);
+    tcp_query
+}
+
+/// Return the length of a response from its 2-byte prefix.
+pub(crate) fn response_len(response_len_bytes: [u8; 2]) -> io::Result<usize> {
+    let response_len = ((response_len_bytes[0] as usize) << 8) | (response_len_bytes[1] as usize);
+    if response_len > DNS_MAX_COMPRESSED_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Response too large",
+        ));
+    }
+    Ok(response_len)
+}
+
+/// Whether `response` has the transaction ID of `query`.
+///
+/// Responses to earlier queries on a reused connection, such as queries
+/// that timed out, are skipped.
+pub(crate) fn is_response_to(query: &[u8], response: &[u8]) -> bool {
+    let tid = DNS_TID_OFFSET..DNS_TID_OFFSET + 2;
+    matches!((query.get(tid.clone()), response.get(tid)), (Some(a), Some(b)) if a == b)
+}
+
+/// Queries pipelined over a single connection (RFC 7766): they are all
+/// sent at once, and their responses, that can come in any order, are
+/// matched by transaction ID.
+pub(crate) struct Pipeline<'t> {
+    queries: &'t [Vec<u8>],
+    responses: Vec<Option<Vec<u8>>>,
+    pending: usize,
+}
+
+impl<'t> Pipeline<'t> {
+    /// Queries must have distinct transaction IDs.
+    pub(crate) fn new(queries: &'t [Vec<u8>]) -> Self {
+        Pipeline {
+            queries,
+            responses: vec![None; queries.len()],
+            pending: queries.len(),
+        }
+    }
+
+    /// All the queries, each prefixed with its length.
+    pub(crate) fn framed_queries(&self) -> Vec<u8> {
+        self.queries.iter().flat_map(|query| frame(query)).collect()
+    }
+
+    /// Keep a response if it matches a query still waiting for one.
+    pub(crate) fn on_response(&mut self, response: Vec<u8>) {
+        let pending = self
+            .queries
+            .iter()
+            .zip(self.responses.iter_mut())
+            .find(|(query, slot)| slot.is_none() && is_response_to(query, &response));
+        if let Some((_, slot)) = pending {
+            *slot = Some(response);
+            self.pending -= 1;
+        }
+    }
+
+    /// Whether every query has received a response.
+    pub(crate) fn is_complete(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// The responses, in the order of the queries.
+    pub(crate) fn into_responses(self) -> io::Result<Vec<Vec<u8>>> {
+        self.responses
+            .into_iter()
+            .map(|response| {
+                response.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Missing pipelined response")
+                })
+            })
+            .collect()
+    }
+}
+
+/// Whether an exchange over a reused connection should be retried over a
+/// new connection. Reused connections may have been closed by the server
+/// while idle; timeouts are not retried, so that they don't add up.
+pub(crate) fn should_retry(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+#[test]
+fn test_tcp_pool() {
+    let pool = tcp_pool::<u32>();
+    let addr: SocketAddr = "192.0.2.1:53".parse().unwrap();
+    assert!(pool.take(&addr).is_none());
+    for i in 0..=MAX_IDLE_CONNECTIONS as u32 {
+        pool.put(addr, i);
+    }
+    assert_eq!(pool.take(&addr), Some(MAX_IDLE_CONNECTIONS as u32));
+    assert!(std::ptr::eq(pool, tcp_pool::<u32>()));
+    let mut taken = 1;
+    while pool.take(&addr).is_some() {
+        taken += 1;
+    }
+    assert_eq!(taken, MAX_IDLE_CONNECTIONS);
+
+    assert!(is_response_to(b"\x12\x34\x01", b"\x12\x34\x81"));
+    assert!(!is_response_to(b"\x12\x34\x01", b"\x12\x35\x81"));
+    assert_eq!(frame(b"abc"), b"\x00\x03abc");
+
+    let queries = vec![b"\x00\x01\x01".to_vec(), b"\x00\x02\x01".to_vec()];
+    let mut pipeline = Pipeline::new(&queries);
+    assert_eq!(
+        pipeline.framed_queries(),
+        b"\x00\x03\x00\x01\x01\x00\x03\x00\x02\x01"
+    );
+    pipeline.on_response(b"\x00\x02\x81".to_vec());
+    pipeline.on_response(b"\x00\x03\x81".to_vec());
+    assert!(!pipeline.is_complete());
+    pipeline.on_response(b"\x00\x01\x81".to_vec());
+    assert!(pipeline.is_complete());
+    assert_eq!(
+        pipeline.into_responses().unwrap(),
+        vec![b"\x00\x01\x81".to_vec(), b"\x00\x02\x81".to_vec()]
+    );
+}
@@ -0,0 +1,312 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::backend::{AsyncBackend, SyncBackend};
+use crate::upstream_server::UpstreamServer;
+
+/// A way to exchange DNS messages with upstream servers, such as over a
+/// message bus, for the synchronous client.
+///
+/// This is simpler to implement than a `SyncBackend`: queries are sent the
+/// same way, whatever the transport of the upstream server is.
+pub trait DnsTransport: Send + Sync + 'static {
+    /// Send a raw query to `upstream_server`, and return the raw response.
+    ///
+    /// The exchange must give up after `timeout`.
+    fn exchange(
+        &self,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>>;
+}
+
+/// A way to exchange DNS messages with upstream servers, such as over a
+/// message bus, for the async client.
+///
+/// This is simpler to implement than an `AsyncBackend`: queries are sent
+/// the same way, whatever the transport of the upstream server is.
+pub trait AsyncDnsTransport: Send + Sync + 'static {
+    /// Send a raw query to `upstream_server`, and return the raw response.
+    ///
+    /// The exchange must give up after `timeout`, and return an
+    /// `io::ErrorKind::TimedOut` error when this happens.
+    fn exchange<'t>(
+        &'t self,
+        upstream_server: &'t UpstreamServer,
+        query: &'t [u8],
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send + 't;
+}
+
+/// A backend sending every query through a `DnsTransport` or an
+/// `AsyncDnsTransport`, created by `with_transport()`.
+///
+/// A default `TransportBackend` has no transport, and every exchange fails
+/// with an `io::ErrorKind::NotConnected` error.
+#[derive(Debug)]
+pub struct TransportBackend<T> {
+    transport: Option<Arc<T>>,
+}
+
+impl<T> TransportBackend<T> {
+    pub fn new(transport: T) -> Self {
+        TransportBackend {
+            transport: Some(Arc::new(transport)),
+        }
+    }
+
+    fn transport(&self) -> io::Result<&T> {
+        self.transport
+            .as_deref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "No transport has been set"))
+    }
+}
+
+impl<T> Clone for TransportBackend<T> {
+    fn clone(&self) -> Self {
+        TransportBackend {
+            transport: self.transport.clone(),
+        }
+    }
+}
+
+impl<T> Default for TransportBackend<T> {
+    fn default() -> Self {
+        TransportBackend { transport: None }
+    }
+}
+
+impl<T: DnsTransport> SyncBackend for TransportBackend<T> {
+    fn dns_exchange_udp(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?.exchange(upstream_server, query, timeout)
+    }
+
+    fn dns_exchange_tcp(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?.exchange(upstream_server, query, timeout)
+    }
+
+    fn dns_exchange_tls(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?.exchange(upstream_server, query, timeout)
+    }
+
+    fn dns_exchange_https(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?.exchange(upstream_server, query, timeout)
+    }
+
+    fn dns_exchange_odoh(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?.exchange(upstream_server, query, timeout)
+    }
+}
+
+impl<T: AsyncDnsTransport> AsyncBackend for TransportBackend<T> {
+    type TcpStream = std::net::TcpStream;
+
+    async fn dns_exchange_udp(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?
+            .exchange(upstream_server, query, timeout)
+            .await
+    }
+
+    async fn dns_exchange_tcp(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?
+            .exchange(upstream_server, query, timeout)
+            .await
+    }
+
+    async fn dns_exchange_tls(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?
+            .exchange(upstream_server, query, timeout)
+            .await
+    }
+
+    async fn dns_exchange_https(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?
+            .exchange(upstream_server, query, timeout)
+            .await
+    }
+
+    async fn dns_exchange_odoh(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?
+            .exchange(upstream_server, query, timeout)
+            .await
+    }
+
+    async fn dns_exchange_quic(
+        &self,
+        _local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.transport()?
+            .exchange(upstream_server, query, timeout)
+            .await
+    }
+
+    fn join<F1, F2>(&self, f1: F1, f2: F2) -> impl Future<Output = (F1::Output, F2::Output)> + Send
+    where
+        F1: Future + Send,
+        F2: Future + Send,
+        F1::Output: Send,
+        F2::Output: Send,
+    {
+        super::join(f1, f2)
+    }
+
+    async fn connect_staggered(
+        &self,
+        _addrs: Vec<SocketAddr>,
+        _attempt_delay: Duration,
+        _timeout: Duration,
+    ) -> io::Result<std::net::TcpStream> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Custom transports don't open connections",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::packet;
+
+    /// A transport answering every query with the same address, and
+    /// remembering the servers it was sent to.
+    #[derive(Default)]
+    struct Loopback {
+        upstream_servers: Arc<Mutex<Vec<UpstreamServer>>>,
+    }
+
+    impl Loopback {
+        fn respond(&self, upstream_server: &UpstreamServer, query: &[u8]) -> io::Result<Vec<u8>> {
+            self.upstream_servers
+                .lock()
+                .unwrap()
+                .push(upstream_server.clone());
+            let response = packet::synthesize_response(query, 0, &[(1, 300, &[192, 0, 2, 1][..])])?;
+            Ok(response.into_packet())
+        }
+    }
+
+    impl DnsTransport for Loopback {
+        fn exchange(
+            &self,
+            upstream_server: &UpstreamServer,
+            query: &[u8],
+            _timeout: Duration,
+        ) -> io::Result<Vec<u8>> {
+            self.respond(upstream_server, query)
+        }
+    }
+
+    impl AsyncDnsTransport for Loopback {
+        async fn exchange(
+            &self,
+            upstream_server: &UpstreamServer,
+            query: &[u8],
+            _timeout: Duration,
+        ) -> io::Result<Vec<u8>> {
+            self.respond(upstream_server, query)
+        }
+    }
+
+    #[test]
+    fn test_sync_transport() {
+        let upstream_server = UpstreamServer::new_tls(([192, 0, 2, 53], 853), "dns.test");
+        let transport = Loopback::default();
+        let upstream_servers = transport.upstream_servers.clone();
+        let dns_client =
+            crate::sync::GenericDNSClient::with_transport(vec![upstream_server.clone()], transport);
+        assert_eq!(
+            dns_client.query_a("example.com").unwrap(),
+            vec![Ipv4Addr::new(192, 0, 2, 1)]
+        );
+        assert_eq!(*upstream_servers.lock().unwrap(), vec![upstream_server]);
+
+        let dns_client: crate::sync::GenericDNSClient<TransportBackend<Loopback>> =
+            crate::sync::GenericDNSClient::new(vec![UpstreamServer::new(([192, 0, 2, 53], 53))]);
+        assert!(dns_client.query_a("example.com").is_err());
+    }
+
+    #[cfg(feature = "async-io")]
+    #[test]
+    fn test_async_transport() {
+        let upstream_server = UpstreamServer::new(([192, 0, 2, 53], 53));
+        let dns_client = crate::r#async::GenericDNSClient::with_transport(
+            vec![upstream_server],
+            Loopback::default(),
+        );
+        assert_eq!(
+            ::async_io::block_on(dns_client.query_a("example.com")).unwrap(),
+            vec![Ipv4Addr::new(192, 0, 2, 1)]
+        );
+    }
+}
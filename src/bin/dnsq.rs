@@ -0,0 +1,247 @@
+//! A small `dig`-like tool.
+//!
+//! ```text
+//! dnsq [@server] name [type] [class] [+tcp] [+short] [+json]
+//! dnsq [@server] +bulk[=file] [+concurrency=N] [+rate=N]
+//! ```
+//!
+//! In bulk mode, names are read from a file or from the standard input, one
+//! per line, and their addresses are written as JSON lines.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::future::poll_fn;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::pin::pin;
+use std::process;
+use std::time::{Duration, Instant};
+
+use dnsclient::presentation;
+use dnsclient::r#async::tokio::DNSClient as AsyncDNSClient;
+use dnsclient::reexports::dnssector::constants::{Class, Type};
+use dnsclient::reexports::dnssector::gen;
+use dnsclient::reexports::futures_core::Stream;
+use dnsclient::reexports::tokio;
+use dnsclient::sync::DNSClient;
+use dnsclient::UpstreamServer;
+
+const USAGE: &str = "Usage: dnsq [@server] name [type] [class] [+tcp] [+short] [+json]
+       dnsq [@server] +bulk[=file] [+concurrency=N] [+rate=N]";
+
+const DEFAULT_CONCURRENCY: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    Message,
+    Short,
+    Json,
+}
+
+#[derive(Debug)]
+struct Bulk {
+    /// The file to read names from, the standard input if `None`.
+    path: Option<String>,
+    concurrency: usize,
+    /// The maximum number of names resolved per second.
+    rate: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Args {
+    upstream_servers: Vec<UpstreamServer>,
+    name: String,
+    query_type: String,
+    query_class: String,
+    tcp: bool,
+    output: Output,
+    bulk: Option<Bulk>,
+}
+
+fn invalid_input(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, reason)
+}
+
+fn parse_count(arg: &str, value: Option<&str>) -> Result<usize, io::Error> {
+    match value.map(str::parse) {
+        Some(Ok(count)) if count > 0 => Ok(count),
+        _ => Err(invalid_input(format!("Invalid option: {}", arg))),
+    }
+}
+
+fn parse_args() -> Result<Args, io::Error> {
+    let mut upstream_servers = vec![];
+    let mut positional = vec![];
+    let mut tcp = false;
+    let mut output = Output::Message;
+    let mut bulk = None;
+    let mut concurrency = DEFAULT_CONCURRENCY;
+    let mut rate = None;
+    for arg in env::args().skip(1) {
+        if let Some(server) = arg.strip_prefix('@') {
+            upstream_servers.push(server.parse()?);
+        } else if let Some(option) = arg.strip_prefix('+') {
+            let (option, value) = match option.split_once('=') {
+                Some((option, value)) => (option, Some(value)),
+                None => (option, None),
+            };
+            match option {
+                "tcp" => tcp = true,
+                "short" => output = Output::Short,
+                "json" => output = Output::Json,
+                "bulk" => bulk = Some(value.map(str::to_string)),
+                "concurrency" => concurrency = parse_count(&arg, value)?,
+                "rate" => rate = Some(parse_count(&arg, value)?),
+                _ => return Err(invalid_input(format!("Unknown option: {}", arg))),
+            }
+        } else {
+            positional.push(arg);
+        }
+    }
+    let mut positional = positional.into_iter();
+    let name = match bulk {
+        Some(_) => String::new(),
+        None => positional
+            .next()
+            .ok_or_else(|| invalid_input("Missing name".to_string()))?,
+    };
+    let query_type = positional.next().unwrap_or_else(|| "A".to_string());
+    let query_class = positional.next().unwrap_or_else(|| "IN".to_string());
+    if let Some(arg) = positional.next() {
+        return Err(invalid_input(format!("Unexpected argument: {}", arg)));
+    }
+    Ok(Args {
+        upstream_servers,
+        name,
+        query_type,
+        query_class,
+        tcp,
+        output,
+        bulk: bulk.map(|path| Bulk {
+            path,
+            concurrency,
+            rate,
+        }),
+    })
+}
+
+fn run(args: Args) -> Result<(), io::Error> {
+    let upstream_servers = if args.upstream_servers.is_empty() {
+        dnsclient::system::default_resolvers()?
+    } else {
+        args.upstream_servers
+    };
+    if let Some(bulk) = args.bulk {
+        return run_bulk(upstream_servers, args.tcp, bulk);
+    }
+    let mut dns_client = DNSClient::new(upstream_servers);
+    dns_client.force_tcp(args.tcp);
+    let rr_type = Type::from_string(&args.query_type).map_err(|e| invalid_input(e.to_string()))?;
+    let rr_class =
+        Class::from_string(&args.query_class).map_err(|e| invalid_input(e.to_string()))?;
+    let name = match args.name.strip_suffix('.') {
+        Some(relative_name) if !relative_name.is_empty() => relative_name,
+        _ => &args.name,
+    };
+    let query = gen::query(name.as_bytes(), rr_type, rr_class)
+        .map_err(|e| invalid_input(e.to_string()))?
+        .into_packet();
+    let response = dns_client.query_raw(&query, true)?;
+    let out = match args.output {
+        Output::Message => presentation::format_message(&response)?,
+        Output::Short => presentation::format_short(&response)?,
+        Output::Json => presentation::format_json(&response)? + "\n",
+    };
+    io::stdout().write_all(out.as_bytes())
+}
+
+fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn json_line(name: &str, res: &Result<Vec<IpAddr>, io::Error>) -> String {
+    let mut out = String::from("{\"name\":");
+    push_json_string(&mut out, name);
+    match res {
+        Ok(ips) => {
+            out.push_str(",\"addrs\":[");
+            for (i, ip) in ips.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let _ = write!(out, "\"{}\"", ip);
+            }
+            out.push(']');
+        }
+        Err(e) => {
+            out.push_str(",\"error\":");
+            push_json_string(&mut out, &e.to_string());
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn run_bulk(upstream_servers: Vec<UpstreamServer>, tcp: bool, bulk: Bulk) -> Result<(), io::Error> {
+    let input: Box<dyn BufRead> = match &bulk.path {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+    let mut names = vec![];
+    for line in input.lines() {
+        let line = line?;
+        let name = line.trim();
+        if !name.is_empty() && !name.starts_with('#') {
+            names.push(name.to_string());
+        }
+    }
+    let mut dns_client = AsyncDNSClient::new(upstream_servers);
+    dns_client.force_tcp(tcp);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    // Without a rate limit, all the names are resolved in a single batch
+    let batch_size = bulk.rate.unwrap_or(names.len()).max(1);
+    let stdout = io::stdout();
+    runtime.block_on(async {
+        for batch in names.chunks(batch_size) {
+            let start = Instant::now();
+            let mut results = pin!(dns_client.query_addrs_bulk(batch, bulk.concurrency));
+            while let Some((name, res)) = poll_fn(|cx| results.as_mut().poll_next(cx)).await {
+                stdout.lock().write_all(json_line(&name, &res).as_bytes())?;
+            }
+            if bulk.rate.is_some() {
+                if let Some(remaining) = Duration::from_secs(1).checked_sub(start.elapsed()) {
+                    tokio::time::sleep(remaining).await;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::idn;
+use crate::packet;
+
+/// A set of blocked names. Queries for blocked names are answered locally
+/// with `NXDOMAIN`.
+///
+/// Patterns can be exact names (`ads.example.com`), or suffixes written as
+/// `*.example.com`, that match every name under `example.com` (but not
+/// `example.com` itself).
+#[derive(Clone, Debug, Default)]
+pub struct Blocklist {
+    names: HashSet<Vec<u8>>,
+    suffixes: HashSet<Vec<u8>>,
+}
+
+impl Blocklist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.suffixes.is_empty()
+    }
+
+    /// Block a single name or `*.`-prefixed suffix.
+    pub fn add(&mut self, pattern: &str) {
+        match pattern.strip_prefix("*.") {
+            Some(suffix) => self.suffixes.insert(packet::normalize_name(
+                idn::to_ascii_or_raw(suffix).as_bytes(),
+            )),
+            None => self.names.insert(packet::normalize_name(
+                idn::to_ascii_or_raw(pattern).as_bytes(),
+            )),
+        };
+    }
+
+    /// Add the patterns of a list in either hosts format (`0.0.0.0 name ...`)
+    /// or domain list format (one pattern per line).
+    /// Comments starting with `#` are ignored.
+    pub fn add_list(&mut self, data: &str) {
+        for line in data.lines() {
+            let line = match line.find('#') {
+                Some(pos) => &line[..pos],
+                None => line,
+            };
+            let mut it = line.split_whitespace().peekable();
+            match it.peek() {
+                None => continue,
+                Some(first) if first.parse::<IpAddr>().is_ok() => {
+                    it.next();
+                }
+                _ => {}
+            }
+            for pattern in it {
+                if pattern.parse::<IpAddr>().is_ok()
+                    || [
+                        "localhost",
+                        "localhost.localdomain",
+                        "broadcasthost",
+                        "local",
+                    ]
+                    .contains(&pattern)
+                {
+                    continue;
+                }
+                self.add(pattern);
+            }
+        }
+    }
+
+    /// Add the patterns of a list stored in a file.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), io::Error> {
+        let data = fs::read_to_string(path)?;
+        self.add_list(&data);
+        Ok(())
+    }
+
+    /// Check if `name` is blocked.
+    pub fn is_blocked(&self, name: &str) -> bool {
+        self.is_blocked_raw(idn::to_ascii_or_raw(name).as_bytes())
+    }
+
+    pub(crate) fn is_blocked_raw(&self, name: &[u8]) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let name = packet::normalize_name(name);
+        if self.names.contains(&name) {
+            return true;
+        }
+        let mut suffix = &name[..];
+        while let Some(pos) = suffix.iter().position(|&c| c == b'.') {
+            suffix = &suffix[pos + 1..];
+            if self.suffixes.contains(suffix) {
+                return true;
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,511 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::address_family::AddressFamilyPreference;
+use crate::answer_order::AnswerOrder;
+use crate::blocklist::Blocklist;
+use crate::dns64::Dns64;
+use crate::domain_routes::DomainRoutes;
+use crate::failover_policy::FailoverPolicy;
+use crate::health::HealthPolicy;
+use crate::name_validation::NameValidation;
+use crate::padding::PaddingPolicy;
+use crate::retry_policy::RetryPolicy;
+use crate::search_list::SearchList;
+use crate::static_records::StaticRecords;
+use crate::truncation_policy::TruncationPolicy;
+use crate::upstream_server::UpstreamServer;
+use crate::upstream_strategy::UpstreamStrategy;
+
+/// A configuration that builds synchronous and async clients alike.
+///
+/// Settings that are not set keep the client defaults.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use dnsclient::sync::DNSClient;
+/// use dnsclient::{DNSClientBuilder, UpstreamServer, UpstreamStrategy};
+///
+/// let dns_client: DNSClient = DNSClientBuilder::new(vec![
+///     UpstreamServer::new(([9, 9, 9, 9], 53)),
+///     UpstreamServer::new(([1, 1, 1, 1], 53)),
+/// ])
+/// .timeout(Duration::from_secs(2))
+/// .retries(2)
+/// .strategy(UpstreamStrategy::RoundRobin)
+/// .cache(1000)
+/// .build();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DNSClientBuilder {
+    upstream_servers: Vec<UpstreamServer>,
+    timeout: Option<Duration>,
+    total_timeout: Option<Duration>,
+    retry_policy: Option<RetryPolicy>,
+    cname_limit: Option<usize>,
+    dns64: Option<Dns64>,
+    upstream_strategy: Option<UpstreamStrategy>,
+    health_policy: Option<HealthPolicy>,
+    failover_policy: Option<FailoverPolicy>,
+    local_v4_addr: Option<SocketAddr>,
+    local_v6_addr: Option<SocketAddr>,
+    edns_bufsize: Option<u16>,
+    cache_capacity: Option<usize>,
+    serve_stale: Option<Duration>,
+    force_tcp: Option<bool>,
+    truncation_policy: Option<TruncationPolicy>,
+    search_list: Option<SearchList>,
+    domain_routes: Option<DomainRoutes>,
+    resolv_conf_reload: Option<Duration>,
+    static_records: Option<StaticRecords>,
+    blocklist: Option<Blocklist>,
+    address_family_preference: Option<AddressFamilyPreference>,
+    answer_order: Option<AnswerOrder>,
+    padding_policy: Option<PaddingPolicy>,
+    name_validation: Option<NameValidation>,
+    mdns: Option<bool>,
+    llmnr: Option<bool>,
+    error_reporting: Option<bool>,
+    hedge_delay: Option<Duration>,
+    fan_out: Option<usize>,
+}
+
+impl DNSClientBuilder {
+    pub fn new(upstream_servers: Vec<UpstreamServer>) -> Self {
+        DNSClientBuilder {
+            upstream_servers,
+            ..Default::default()
+        }
+    }
+
+    /// Build a client, such as a `sync::DNSClient` or an `async` client.
+    pub fn build<C: From<DNSClientBuilder>>(self) -> C {
+        C::from(self)
+    }
+
+    /// The timeout of every exchange with an upstream server.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The time a query can take as a whole, including retries.
+    pub fn total_timeout(mut self, total_timeout: Duration) -> Self {
+        self.total_timeout = Some(total_timeout);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Retry queries up to `retries` times when no upstream server
+    /// responded.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retry_policy = Some(RetryPolicy {
+            retries,
+            ..self.retry_policy.unwrap_or_default()
+        });
+        self
+    }
+
+    /// Follow `CNAME` chains that upstream servers don't resolve, with up
+    /// to `cname_limit` additional queries.
+    pub fn cname_limit(mut self, cname_limit: usize) -> Self {
+        self.cname_limit = Some(cname_limit);
+        self
+    }
+
+    pub fn dns64(mut self, dns64: Dns64) -> Self {
+        self.dns64 = Some(dns64);
+        self
+    }
+
+    pub fn strategy(mut self, upstream_strategy: UpstreamStrategy) -> Self {
+        self.upstream_strategy = Some(upstream_strategy);
+        self
+    }
+
+    pub fn health_policy(mut self, health_policy: HealthPolicy) -> Self {
+        self.health_policy = Some(health_policy);
+        self
+    }
+
+    pub fn failover_policy(mut self, failover_policy: FailoverPolicy) -> Self {
+        self.failover_policy = Some(failover_policy);
+        self
+    }
+
+    pub fn local_v4_addr<T: Into<SocketAddr>>(mut self, addr: T) -> Self {
+        self.local_v4_addr = Some(addr.into());
+        self
+    }
+
+    pub fn local_v6_addr<T: Into<SocketAddr>>(mut self, addr: T) -> Self {
+        self.local_v6_addr = Some(addr.into());
+        self
+    }
+
+    /// Add an EDNS section advertising this buffer size to queries.
+    pub fn edns_bufsize(mut self, edns_bufsize: u16) -> Self {
+        self.edns_bufsize = Some(edns_bufsize);
+        self
+    }
+
+    /// Cache up to `capacity` responses.
+    pub fn cache(mut self, capacity: usize) -> Self {
+        self.cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Serve expired cached responses, up to `max_stale` after they expired,
+    /// when the upstream servers fail.
+    pub fn serve_stale(mut self, max_stale: Duration) -> Self {
+        self.serve_stale = Some(max_stale);
+        self
+    }
+
+    /// Send queries over TCP.
+    pub fn force_tcp(mut self, force_tcp: bool) -> Self {
+        self.force_tcp = Some(force_tcp);
+        self
+    }
+
+    /// What to do when a UDP response is truncated.
+    pub fn truncation_policy(mut self, truncation_policy: TruncationPolicy) -> Self {
+        self.truncation_policy = Some(truncation_policy);
+        self
+    }
+
+    pub fn search_list(mut self, search_list: SearchList) -> Self {
+        self.search_list = Some(search_list);
+        self
+    }
+
+    pub fn domain_routes(mut self, domain_routes: DomainRoutes) -> Self {
+        self.domain_routes = Some(domain_routes);
+        self
+    }
+
+    pub fn resolv_conf_reload(mut self, interval: Duration) -> Self {
+        self.resolv_conf_reload = Some(interval);
+        self
+    }
+
+    pub fn static_records(mut self, static_records: StaticRecords) -> Self {
+        self.static_records = Some(static_records);
+        self
+    }
+
+    pub fn blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    pub fn address_family_preference(mut self, preference: AddressFamilyPreference) -> Self {
+        self.address_family_preference = Some(preference);
+        self
+    }
+
+    pub fn answer_order(mut self, answer_order: AnswerOrder) -> Self {
+        self.answer_order = Some(answer_order);
+        self
+    }
+
+    pub fn padding_policy(mut self, padding_policy: PaddingPolicy) -> Self {
+        self.padding_policy = Some(padding_policy);
+        self
+    }
+
+    pub fn name_validation(mut self, name_validation: NameValidation) -> Self {
+        self.name_validation = Some(name_validation);
+        self
+    }
+
+    pub fn mdns(mut self, mdns: bool) -> Self {
+        self.mdns = Some(mdns);
+        self
+    }
+
+    pub fn llmnr(mut self, llmnr: bool) -> Self {
+        self.llmnr = Some(llmnr);
+        self
+    }
+
+    /// Report errors to the agent domain of the `Report-Channel` option of
+    /// responses (RFC 9567).
+    pub fn error_reporting(mut self, error_reporting: bool) -> Self {
+        self.error_reporting = Some(error_reporting);
+        self
+    }
+
+    /// Hedge queries after `hedge_delay`. Only used by the async client.
+    pub fn hedge_delay(mut self, hedge_delay: Duration) -> Self {
+        self.hedge_delay = Some(hedge_delay);
+        self
+    }
+
+    /// Send queries to `fan_out` upstream servers at once. Only used by the
+    /// async client.
+    pub fn fan_out(mut self, fan_out: usize) -> Self {
+        self.fan_out = Some(fan_out);
+        self
+    }
+}
+
+/// A client configuration that can be loaded from configuration files with
+/// the `serde` feature.
+///
+/// It covers the settings of `DNSClientBuilder` that can be represented as
+/// plain data. Missing fields keep the client defaults.
+///
+/// ```
+/// use dnsclient::sync::DNSClient;
+/// use dnsclient::{DNSClientBuilder, DNSClientConfig, UpstreamServer};
+///
+/// let config = DNSClientConfig {
+///     upstream_servers: vec![UpstreamServer::new(([9, 9, 9, 9], 53))],
+///     cache_capacity: Some(1000),
+///     ..Default::default()
+/// };
+/// let dns_client: DNSClient = DNSClientBuilder::from(config).build();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct DNSClientConfig {
+    pub upstream_servers: Vec<UpstreamServer>,
+    /// The timeout of every exchange with an upstream server.
+    pub timeout: Option<Duration>,
+    /// The time a query can take as a whole, including retries.
+    pub total_timeout: Option<Duration>,
+    pub retry_policy: Option<RetryPolicy>,
+    pub cname_limit: Option<usize>,
+    pub dns64: Option<Dns64>,
+    pub strategy: Option<UpstreamStrategy>,
+    pub health_policy: Option<HealthPolicy>,
+    pub failover_policy: Option<FailoverPolicy>,
+    pub local_v4_addr: Option<SocketAddr>,
+    pub local_v6_addr: Option<SocketAddr>,
+    pub edns_bufsize: Option<u16>,
+    /// The maximum number of cached responses.
+    pub cache_capacity: Option<usize>,
+    /// How long expired responses can be served from the cache, when the
+    /// upstream servers fail.
+    pub serve_stale: Option<Duration>,
+    pub force_tcp: Option<bool>,
+    pub truncation_policy: Option<TruncationPolicy>,
+    pub search_list: Option<SearchList>,
+    pub resolv_conf_reload: Option<Duration>,
+    pub address_family_preference: Option<AddressFamilyPreference>,
+    pub answer_order: Option<AnswerOrder>,
+    pub padding_policy: Option<PaddingPolicy>,
+    pub name_validation: Option<NameValidation>,
+    pub mdns: Option<bool>,
+    pub llmnr: Option<bool>,
+    pub error_reporting: Option<bool>,
+    /// Only used by the async client.
+    pub hedge_delay: Option<Duration>,
+    /// Only used by the async client.
+    pub fan_out: Option<usize>,
+}
+
+impl From<DNSClientConfig> for DNSClientBuilder {
+    fn from(config: DNSClientConfig) -> Self {
+        DNSClientBuilder {
+            upstream_servers: config.upstream_servers,
+            timeout: config.timeout,
+            total_timeout: config.total_timeout,
+            retry_policy: config.retry_policy,
+            cname_limit: config.cname_limit,
+            dns64: config.dns64,
+            upstream_strategy: config.strategy,
+            health_policy: config.health_policy,
+            failover_policy: config.failover_policy,
+            local_v4_addr: config.local_v4_addr,
+            local_v6_addr: config.local_v6_addr,
+            edns_bufsize: config.edns_bufsize,
+            cache_capacity: config.cache_capacity,
+            serve_stale: config.serve_stale,
+            force_tcp: config.force_tcp,
+            truncation_policy: config.truncation_policy,
+            search_list: config.search_list,
+            resolv_conf_reload: config.resolv_conf_reload,
+            address_family_preference: config.address_family_preference,
+            answer_order: config.answer_order,
+            padding_policy: config.padding_policy,
+            name_validation: config.name_validation,
+            mdns: config.mdns,
+            llmnr: config.llmnr,
+            error_reporting: config.error_reporting,
+            hedge_delay: config.hedge_delay,
+            fan_out: config.fan_out,
+            ..Default::default()
+        }
+    }
+}
+
+/// Apply the settings shared by both clients.
+macro_rules! configure_client {
+    ($client:expr, $builder:expr) => {{
+        let client = &mut $client;
+        let builder = $builder;
+        if let Some(timeout) = builder.timeout {
+            client.set_timeout(timeout);
+        }
+        if let Some(total_timeout) = builder.total_timeout {
+            client.set_total_timeout(Some(total_timeout));
+        }
+        if let Some(retry_policy) = builder.retry_policy {
+            client.set_retry_policy(retry_policy);
+        }
+        if let Some(cname_limit) = builder.cname_limit {
+            client.set_cname_limit(Some(cname_limit));
+        }
+        if let Some(dns64) = builder.dns64 {
+            client.set_dns64(Some(dns64));
+        }
+        if let Some(upstream_strategy) = builder.upstream_strategy {
+            client.set_strategy(upstream_strategy);
+        }
+        if let Some(health_policy) = builder.health_policy {
+            client.set_health_policy(Some(health_policy));
+        }
+        if let Some(failover_policy) = builder.failover_policy {
+            client.set_failover_policy(failover_policy);
+        }
+        if let Some(addr) = builder.local_v4_addr {
+            client.set_local_v4_addr(addr);
+        }
+        if let Some(addr) = builder.local_v6_addr {
+            client.set_local_v6_addr(addr);
+        }
+        if let Some(edns_bufsize) = builder.edns_bufsize {
+            client.set_edns_bufsize(Some(edns_bufsize));
+        }
+        if let Some(capacity) = builder.cache_capacity {
+            client.enable_cache(capacity);
+        }
+        if let Some(max_stale) = builder.serve_stale {
+            client.set_serve_stale(Some(max_stale));
+        }
+        if let Some(force_tcp) = builder.force_tcp {
+            client.force_tcp(force_tcp);
+        }
+        if let Some(truncation_policy) = builder.truncation_policy {
+            client.set_truncation_policy(truncation_policy);
+        }
+        if let Some(search_list) = builder.search_list {
+            client.set_search_list(search_list);
+        }
+        if let Some(domain_routes) = builder.domain_routes {
+            client.set_domain_routes(domain_routes);
+        }
+        if let Some(interval) = builder.resolv_conf_reload {
+            client.set_resolv_conf_reload(Some(interval));
+        }
+        if let Some(static_records) = builder.static_records {
+            client.set_static_records(static_records);
+        }
+        if let Some(blocklist) = builder.blocklist {
+            client.set_blocklist(blocklist);
+        }
+        if let Some(preference) = builder.address_family_preference {
+            client.set_address_family_preference(preference);
+        }
+        if let Some(answer_order) = builder.answer_order {
+            client.set_answer_order(answer_order);
+        }
+        if let Some(padding_policy) = builder.padding_policy {
+            client.set_padding_policy(padding_policy);
+        }
+        if let Some(name_validation) = builder.name_validation {
+            client.set_name_validation(name_validation);
+        }
+        if let Some(mdns) = builder.mdns {
+            client.set_mdns(mdns);
+        }
+        if let Some(llmnr) = builder.llmnr {
+            client.set_llmnr(llmnr);
+        }
+        if let Some(error_reporting) = builder.error_reporting {
+            client.set_error_reporting(error_reporting);
+        }
+    }};
+}
+
+impl<B: crate::sync::SyncBackend> From<DNSClientBuilder> for crate::sync::GenericDNSClient<B> {
+    fn from(builder: DNSClientBuilder) -> Self {
+        let mut client = Self::new(builder.upstream_servers.clone());
+        configure_client!(client, builder);
+        client
+    }
+}
+
+impl<B: crate::r#async::AsyncBackend> From<DNSClientBuilder>
+    for crate::r#async::GenericDNSClient<B>
+{
+    fn from(builder: DNSClientBuilder) -> Self {
+        let mut client = Self::new(builder.upstream_servers.clone());
+        if let Some(hedge_delay) = builder.hedge_delay {
+            client.set_hedge_delay(Some(hedge_delay));
+        }
+        if let Some(fan_out) = builder.fan_out {
+            client.set_fan_out(fan_out);
+        }
+        configure_client!(client, builder);
+        client
+    }
+}
+
+#[test]
+fn test_builder() {
+    use std::net::Ipv4Addr;
+
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    let dns_client: crate::sync::DNSClient = DNSClientBuilder::new(vec![])
+        .timeout(Duration::from_secs(1))
+        .retries(2)
+        .edns_bufsize(1232)
+        .static_records(static_records)
+        .build();
+    assert_eq!(
+        dns_client.query_a("example.com").unwrap(),
+        vec![Ipv4Addr::new(192, 0, 2, 1)]
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_config_serde() {
+    let config: DNSClientConfig = serde_json::from_str(
+        r#"{
+            "upstream_servers": ["9.9.9.9", "tcp://192.0.2.1"],
+            "timeout": { "secs": 2, "nanos": 0 },
+            "strategy": "round_robin",
+            "cache_capacity": 1000
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(config.upstream_servers.len(), 2);
+    assert_eq!(config.timeout, Some(Duration::from_secs(2)));
+    assert_eq!(config.strategy, Some(UpstreamStrategy::RoundRobin));
+    assert_eq!(config.cache_capacity, Some(1000));
+    assert_eq!(config.force_tcp, None);
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(
+        serde_json::from_str::<DNSClientConfig>(&json).unwrap(),
+        config
+    );
+    assert!(serde_json::from_str::<DNSClientConfig>(r#"{"timout": 1}"#).is_err());
+
+    let builder = DNSClientBuilder::from(config);
+    assert_eq!(builder.upstream_servers.len(), 2);
+    assert_eq!(builder.cache_capacity, Some(1000));
+}
@@ -0,0 +1,453 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use dnssector::constants::*;
+use dnssector::*;
+
+use crate::packet;
+use crate::presentation::Message;
+use crate::query_info::{AuthoritySoa, QueryInfo};
+use crate::time::Instant;
+use crate::upstream_server::UpstreamServer;
+
+/// The TTL of the records of stale responses (RFC 8767).
+const STALE_TTL: u32 = 30;
+
+/// How long stale responses are served without trying to refresh them, after
+/// a refresh failed (RFC 8767).
+const STALE_REFRESH_TIME: Duration = Duration::from_secs(30);
+
+/// Everything that the response to a query depends on, that it is cached
+/// under: its question, flags and EDNS options, and the upstream servers it
+/// is sent to, when they are not the ones of the client.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    name: Vec<u8>,
+    rr_type: u16,
+    class: u16,
+    recursion_desired: bool,
+    dnssec_ok: bool,
+    checking_disabled: bool,
+    edns_options: Vec<(u16, Vec<u8>)>,
+    upstream_servers: Option<Vec<UpstreamServer>>,
+}
+
+impl CacheKey {
+    /// `upstream_servers` are the servers set by the query options or a
+    /// domain route, if any.
+    pub(crate) fn new(
+        query_question: &Option<(Vec<u8>, u16, u16)>,
+        parsed_query: &ParsedPacket,
+        upstream_servers: Option<&[UpstreamServer]>,
+    ) -> Option<Self> {
+        let (name, rr_type, class) = query_question.as_ref()?;
+        Some(CacheKey {
+            name: packet::normalize_name(name),
+            rr_type: *rr_type,
+            class: *class,
+            recursion_desired: parsed_query.flags() & DNS_FLAG_RD != 0,
+            dnssec_ok: parsed_query.dnssec(),
+            checking_disabled: parsed_query.flags() & DNS_FLAG_CD != 0,
+            edns_options: packet::query_edns_options(parsed_query),
+            upstream_servers: upstream_servers.map(<[_]>::to_vec),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: Vec<u8>,
+    stored: Instant,
+    ttl: Duration,
+    /// When a refresh of the expired response last failed or started.
+    refresh_failed: Option<Instant>,
+    /// The position of the entry in `Entries::lru`.
+    tick: u64,
+}
+
+/// Whether a stale response is served right away, returned by
+/// `Cache::get_stale()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum StaleState {
+    /// No refresh of the response failed yet: it is only served if the
+    /// upstream servers can't be reached.
+    Expired,
+    /// A refresh failed less than `STALE_REFRESH_TIME` ago: it is served
+    /// without trying to refresh it.
+    Serving,
+    /// A refresh failed longer ago: it is still served, but can be refreshed
+    /// again.
+    RefreshDue,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<CacheKey, Entry>,
+    /// The keys of the entries, from the least to the most recently used.
+    lru: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+impl Entries {
+    fn touch(&mut self, key: &CacheKey) {
+        self.tick += 1;
+        if let Some(entry) = self.map.get_mut(key) {
+            self.lru.remove(&entry.tick);
+            entry.tick = self.tick;
+            self.lru.insert(self.tick, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.map.remove(key) {
+            self.lru.remove(&entry.tick);
+        }
+    }
+}
+
+/// A cache of responses from upstream servers, that keeps them for the
+/// duration of their TTL, and evicts the least recently used ones when it is
+/// full.
+#[derive(Debug)]
+pub(crate) struct Cache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl Cache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Cache {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Return the cached response to `query`, with the transaction ID and
+    /// the question of the query, and TTLs reduced by the time spent in the
+    /// cache.
+    ///
+    /// Expired responses are kept for `max_stale`, for `get_stale()`.
+    pub(crate) fn get(
+        &self,
+        key: &CacheKey,
+        query: &[u8],
+        max_stale: Duration,
+    ) -> Option<ParsedPacket> {
+        let (response, elapsed) = {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries.map.get(key)?;
+            let elapsed = entry.stored.elapsed();
+            if elapsed >= entry.ttl.saturating_add(max_stale) {
+                entries.remove(key);
+                return None;
+            }
+            if elapsed >= entry.ttl {
+                return None;
+            }
+            let response = entry.response.clone();
+            entries.touch(key);
+            (response, elapsed)
+        };
+        response_to(query, response, |ttl| {
+            ttl.saturating_sub(elapsed.as_secs() as u32)
+        })
+    }
+
+    /// Return the response to `query` that expired less than `max_stale`
+    /// ago, with TTLs of `STALE_TTL` seconds (RFC 8767), and whether it can
+    /// be served without trying to refresh it first.
+    pub(crate) fn get_stale(
+        &self,
+        key: &CacheKey,
+        query: &[u8],
+        max_stale: Duration,
+    ) -> Option<(ParsedPacket, StaleState)> {
+        let (response, stale_state) = {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries.map.get(key)?;
+            let elapsed = entry.stored.elapsed();
+            if elapsed < entry.ttl || elapsed >= entry.ttl.saturating_add(max_stale) {
+                return None;
+            }
+            let stale_state = match entry.refresh_failed {
+                None => StaleState::Expired,
+                Some(refresh_failed) if refresh_failed.elapsed() < STALE_REFRESH_TIME => {
+                    StaleState::Serving
+                }
+                Some(_) => StaleState::RefreshDue,
+            };
+            let response = entry.response.clone();
+            entries.touch(key);
+            (response, stale_state)
+        };
+        let parsed_response = response_to(query, response, |_| STALE_TTL)?;
+        Some((parsed_response, stale_state))
+    }
+
+    /// Record that a refresh of the expired response to `key` failed, or
+    /// started in the background, so that the stale response is served
+    /// without trying to refresh it for a while.
+    pub(crate) fn refresh_failed(&self, key: &CacheKey) {
+        if let Some(entry) = self.entries.lock().unwrap().map.get_mut(key) {
+            entry.refresh_failed = Some(Instant::now());
+        }
+    }
+
+    /// Pretend that the entries were stored, and that their refreshes
+    /// failed, `by` earlier. Return `false` if the clock can't go back that
+    /// far.
+    #[cfg(test)]
+    pub(crate) fn backdate(&self, by: Duration) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        for entry in entries.map.values_mut() {
+            match entry.stored.checked_sub(by) {
+                Some(stored) => entry.stored = stored,
+                None => return false,
+            }
+            if let Some(refresh_failed) = entry.refresh_failed {
+                match refresh_failed.checked_sub(by) {
+                    Some(refresh_failed) => entry.refresh_failed = Some(refresh_failed),
+                    None => return false,
+                }
+            }
+        }
+        true
+    }
+
+    /// Cache a response, if it has answers that can be cached.
+    ///
+    /// `NXDOMAIN` and `NODATA` responses are cached for the duration given
+    /// by the `SOA` record of their authority section (RFC 2308), and not
+    /// cached if there is none.
+    pub(crate) fn insert(&self, key: CacheKey, parsed_response: &ParsedPacket) {
+        let response = parsed_response.packet();
+        if self.capacity == 0 || parsed_response.flags() & DNS_FLAG_TC != 0 {
+            return;
+        }
+        let rcode = parsed_response.rcode();
+        let ttl = if rcode == u8::from(Rcode::NXDOMAIN)
+            || (rcode == u8::from(Rcode::NOERROR) && DNSSector::ancount(response) == 0)
+        {
+            negative_ttl(response)
+        } else if rcode == u8::from(Rcode::NOERROR) {
+            packet::min_ttl(response)
+        } else {
+            None
+        };
+        let ttl = match ttl {
+            Some(ttl) if ttl > 0 => Duration::from_secs(u64::from(ttl)),
+            _ => return,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&key);
+        while entries.map.len() >= self.capacity {
+            match entries.lru.pop_first() {
+                Some((_, lru_key)) => {
+                    entries.map.remove(&lru_key);
+                }
+                None => break,
+            }
+        }
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.lru.insert(tick, key.clone());
+        entries.map.insert(
+            key,
+            Entry {
+                response: response.to_vec(),
+                stored: Instant::now(),
+                ttl,
+                refresh_failed: None,
+                tick,
+            },
+        );
+    }
+}
+
+/// A cached response with the transaction ID and the question of `query`,
+/// and TTLs mapped with `map_ttl`.
+fn response_to(
+    query: &[u8],
+    mut response: Vec<u8>,
+    map_ttl: impl Fn(u32) -> u32,
+) -> Option<ParsedPacket> {
+    let question_end = RRIterator::skip_name(query, DNS_HEADER_SIZE);
+    if response.get(DNS_HEADER_SIZE..question_end)?.len() == question_end - DNS_HEADER_SIZE {
+        response[DNS_HEADER_SIZE..question_end]
+            .copy_from_slice(&query[DNS_HEADER_SIZE..question_end]);
+    }
+    response[DNS_TID_OFFSET..DNS_TID_OFFSET + 2]
+        .copy_from_slice(&query[DNS_TID_OFFSET..DNS_TID_OFFSET + 2]);
+    packet::map_ttls(&mut response, map_ttl);
+    DNSSector::new(response).ok()?.parse().ok()
+}
+
+/// Whether the upstream servers failed to answer a query, so that a stale
+/// response can be served instead: they couldn't be reached, or returned
+/// `SERVFAIL`.
+pub(crate) fn upstream_failed(res: &Result<(ParsedPacket, QueryInfo), io::Error>) -> bool {
+    match res {
+        Ok((parsed_response, _)) => parsed_response.rcode() == u8::from(Rcode::SERVFAIL),
+        Err(_) => true,
+    }
+}
+
+/// How long a negative response can be cached, `None` if it has no `SOA`
+/// record.
+fn negative_ttl(response: &[u8]) -> Option<u32> {
+    let message = Message::parse(response).ok()?;
+    AuthoritySoa::from_message(&message).map(|authority_soa| authority_soa.negative_ttl())
+}
+
+#[test]
+fn test_cache() {
+    let query = |name: &str, tid: u16| {
+        let mut parsed_query = packet::query(name, Type::A, Class::IN, Default::default()).unwrap();
+        parsed_query.set_tid(tid);
+        let question = parsed_query.question();
+        let key = CacheKey::new(&question, &parsed_query, None).unwrap();
+        (key, parsed_query.into_packet())
+    };
+    let cache = Cache::new(1);
+    let (key, query1) = query("example.com", 1);
+    let response =
+        packet::synthesize_response(&query1, 0, &[(1, 3600, &[192, 0, 2, 1][..])]).unwrap();
+    assert!(cache.get(&key, &query1, Duration::ZERO).is_none());
+    cache.insert(key, &response);
+
+    let (key, query2) = query("EXAMPLE.com.", 2);
+    let cached = cache.get(&key, &query2, Duration::ZERO).unwrap();
+    assert_eq!(cached.tid(), 2);
+    assert_eq!(
+        cached.packet()[DNS_HEADER_SIZE..20],
+        query2[DNS_HEADER_SIZE..20]
+    );
+    assert_eq!(packet::min_ttl(cached.packet()), Some(3600));
+
+    let (other_key, other_query) = query("example.net", 3);
+    let response =
+        packet::synthesize_response(&other_query, 0, &[(1, 3600, &[192, 0, 2, 2][..])]).unwrap();
+    cache.insert(other_key.clone(), &response);
+    assert!(cache.get(&key, &query2, Duration::ZERO).is_none());
+    assert!(cache
+        .get(&other_key, &other_query, Duration::ZERO)
+        .is_some());
+
+    let response = packet::synthesize_response(&other_query, 0, &[(1, 0, &[192, 0, 2, 2][..])]);
+    let cache = Cache::new(1);
+    cache.insert(other_key.clone(), &response.unwrap());
+    assert!(cache
+        .get(&other_key, &other_query, Duration::ZERO)
+        .is_none());
+}
+
+#[test]
+fn test_cache_key() {
+    use crate::query_options::QueryOptions;
+
+    let key = |options: &QueryOptions, upstream_servers: Option<&[UpstreamServer]>| {
+        let parsed_query = packet::query("example.com", Type::A, Class::IN, Default::default());
+        let mut parsed_query = options.apply(parsed_query.unwrap()).unwrap();
+        CacheKey::new(&parsed_query.question(), &parsed_query, upstream_servers).unwrap()
+    };
+    let default_key = key(&QueryOptions::default(), None);
+    assert_eq!(
+        key(
+            &QueryOptions {
+                timeout: Some(Duration::from_secs(1)),
+                ..Default::default()
+            },
+            None
+        ),
+        default_key
+    );
+    for options in &[
+        QueryOptions {
+            recursion_desired: Some(false),
+            ..Default::default()
+        },
+        QueryOptions {
+            nsid: true,
+            ..Default::default()
+        },
+        QueryOptions {
+            expire: true,
+            ..Default::default()
+        },
+        QueryOptions {
+            chain: Some(".".to_string()),
+            ..Default::default()
+        },
+    ] {
+        assert_ne!(key(options, None), default_key);
+    }
+    let upstream_servers = [UpstreamServer::new(([192, 0, 2, 53], 53))];
+    assert_ne!(
+        key(&QueryOptions::default(), Some(&upstream_servers)),
+        default_key
+    );
+}
+
+#[test]
+fn test_negative_cache() {
+    let mut parsed_query =
+        packet::query("nx.example", Type::A, Class::IN, Default::default()).unwrap();
+    let key = CacheKey::new(&parsed_query.question(), &parsed_query, None).unwrap();
+    let query = parsed_query.into_packet();
+    let nxdomain = packet::synthesize_response(&query, Rcode::NXDOMAIN.into(), &[]).unwrap();
+    let cache = Cache::new(1);
+    cache.insert(key.clone(), &nxdomain);
+    assert!(cache.get(&key, &query, Duration::ZERO).is_none());
+
+    let mut response = nxdomain.into_packet();
+    // example. 3600 IN SOA ns.example. admin.example. 1 3600 600 604800 300
+    response.extend_from_slice(b"\xc0\x0f\x00\x06\x00\x01\x00\x00\x0e\x10\x00\x21");
+    response.extend_from_slice(b"\x02ns\xc0\x0f\x05admin\xc0\x0f");
+    for x in &[1u32, 3600, 600, 604800, 300] {
+        response.extend_from_slice(&x.to_be_bytes());
+    }
+    DNSSector::set_nscount(&mut response, 1);
+    let nxdomain = DNSSector::new(response).unwrap().parse().unwrap();
+    cache.insert(key.clone(), &nxdomain);
+    let cached = cache.get(&key, &query, Duration::ZERO).unwrap();
+    assert_eq!(cached.rcode(), u8::from(Rcode::NXDOMAIN));
+    assert_eq!(negative_ttl(cached.packet()), Some(300));
+}
+
+#[test]
+fn test_serve_stale() {
+    let mut parsed_query =
+        packet::query("example.com", Type::A, Class::IN, Default::default()).unwrap();
+    let key = CacheKey::new(&parsed_query.question(), &parsed_query, None).unwrap();
+    let query = parsed_query.into_packet();
+    let response =
+        packet::synthesize_response(&query, 0, &[(1, 3600, &[192, 0, 2, 1][..])]).unwrap();
+    let cache = Cache::new(1);
+    cache.insert(key.clone(), &response);
+    let max_stale = Duration::from_secs(86400);
+    assert!(cache.get_stale(&key, &query, max_stale).is_none());
+
+    // The monotonic clock may not go back that far on a host that just
+    // booted
+    if !cache.backdate(Duration::from_secs(3601)) {
+        return;
+    }
+    assert!(cache.get(&key, &query, max_stale).is_none());
+    let (stale, stale_state) = cache.get_stale(&key, &query, max_stale).unwrap();
+    assert_eq!(stale_state, StaleState::Expired);
+    assert_eq!(packet::min_ttl(stale.packet()), Some(STALE_TTL));
+    cache.refresh_failed(&key);
+    let (_, stale_state) = cache.get_stale(&key, &query, max_stale).unwrap();
+    assert_eq!(stale_state, StaleState::Serving);
+    assert!(cache.backdate(STALE_REFRESH_TIME));
+    let (_, stale_state) = cache.get_stale(&key, &query, max_stale).unwrap();
+    assert_eq!(stale_state, StaleState::RefreshDue);
+
+    assert!(cache
+        .get_stale(&key, &query, Duration::from_secs(1))
+        .is_none());
+    assert!(cache.get(&key, &query, Duration::ZERO).is_none());
+    assert!(cache.get_stale(&key, &query, max_stale).is_none());
+}
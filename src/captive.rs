@@ -0,0 +1,102 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use rand::{Rng, RngCore};
+
+/// Result of a captive portal check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CaptivePortalVerdict {
+    /// The probe name resolved to the expected addresses, and names that don't
+    /// exist were not resolved.
+    Clear,
+    /// The probe name resolved to unexpected addresses.
+    Hijacked(Vec<IpAddr>),
+    /// The probe name didn't resolve to any addresses.
+    Blocked,
+    /// A name that doesn't exist resolved to these addresses.
+    NxdomainRedirect(Vec<IpAddr>),
+    /// No response could be received from the resolvers.
+    Unreachable,
+}
+
+/// A name whose addresses are known and stable, used to detect captive
+/// portals and resolvers rewriting responses.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CaptivePortalProbe {
+    pub name: String,
+    pub expected: Vec<IpAddr>,
+    /// Suffix for the random, non-existent names used to detect NXDOMAIN
+    /// redirection.
+    pub nonexistent_suffix: String,
+}
+
+impl Default for CaptivePortalProbe {
+    fn default() -> Self {
+        CaptivePortalProbe {
+            name: "dns.google".to_string(),
+            expected: vec![
+                Ipv4Addr::new(8, 8, 8, 8).into(),
+                Ipv4Addr::new(8, 8, 4, 4).into(),
+                Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888).into(),
+                Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8844).into(),
+            ],
+            nonexistent_suffix: "invalid".to_string(),
+        }
+    }
+}
+
+impl CaptivePortalProbe {
+    /// Return a random name that is not supposed to exist.
+    pub(crate) fn nonexistent_name(&self, rng: &mut dyn RngCore) -> String {
+        let label: u64 = rng.gen();
+        format!("{:016x}.{}", label, self.nonexistent_suffix)
+    }
+
+    /// Check the addresses of the probe name; `None` means that the
+    /// non-existent name still has to be checked.
+    pub(crate) fn check_probe_name(
+        &self,
+        addrs: Result<Vec<IpAddr>, io::Error>,
+    ) -> Option<CaptivePortalVerdict> {
+        let addrs = match crate::error::no_records(addrs) {
+            Err(_) => return Some(CaptivePortalVerdict::Unreachable),
+            Ok(addrs) => addrs,
+        };
+        if addrs.is_empty() {
+            return Some(CaptivePortalVerdict::Blocked);
+        }
+        let unexpected: Vec<_> = addrs
+            .into_iter()
+            .filter(|addr| !self.expected.contains(addr))
+            .collect();
+        if !unexpected.is_empty() {
+            return Some(CaptivePortalVerdict::Hijacked(unexpected));
+        }
+        None
+    }
+
+    /// Check the addresses of a non-existent name.
+    pub(crate) fn check_nonexistent_name(
+        &self,
+        addrs: Result<Vec<IpAddr>, io::Error>,
+    ) -> CaptivePortalVerdict {
+        match addrs {
+            Ok(addrs) if !addrs.is_empty() => CaptivePortalVerdict::NxdomainRedirect(addrs),
+            _ => CaptivePortalVerdict::Clear,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_verdict_serde() {
+    let verdict = CaptivePortalVerdict::Hijacked(vec![Ipv4Addr::new(10, 0, 0, 1).into()]);
+    let json = serde_json::to_string(&verdict).unwrap();
+    assert_eq!(json, r#"{"Hijacked":["10.0.0.1"]}"#);
+    assert_eq!(
+        serde_json::from_str::<CaptivePortalVerdict>(&json).unwrap(),
+        verdict
+    );
+}
@@ -0,0 +1,244 @@
+use std::fmt;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use crate::events::Protocol;
+use crate::time::{SystemTime, UNIX_EPOCH};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_SNAPLEN: u32 = 262_144;
+const LINKTYPE_RAW: u32 = 101;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// A capture of the queries and responses exchanged with upstream servers,
+/// written in the PCAP format for analysis with tools such as Wireshark.
+///
+/// DNS messages are wrapped into synthesized IP and UDP or TCP headers,
+/// whose checksums, except the IPv4 header checksum, are left empty. The
+/// source port of queries is the port of the local address, usually `0`.
+/// Encrypted exchanges (DNS-over-TLS, DNS-over-HTTPS, DNS-over-QUIC and
+/// Oblivious DNS-over-HTTPS) are written decrypted, as TCP segments carrying the DNS messages.
+///
+/// Clones share the same writer. Write errors are ignored.
+#[derive(Clone)]
+pub struct PacketCapture {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl fmt::Debug for PacketCapture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PacketCapture")
+    }
+}
+
+impl PacketCapture {
+    /// Write the PCAP header to `writer`, and return a capture writing
+    /// packets after it.
+    pub fn new<W: Write + Send + 'static>(mut writer: W) -> Result<Self, std::io::Error> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        writer.write_all(&header)?;
+        writer.flush()?;
+        Ok(PacketCapture {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        })
+    }
+
+    /// Record a query sent from `local_addr` to `server_addr`.
+    pub(crate) fn record_query(
+        &self,
+        local_addr: &SocketAddr,
+        server_addr: &SocketAddr,
+        protocol: Protocol,
+        query: &[u8],
+    ) {
+        let packet = ip_packet(local_addr, server_addr, protocol, query, 1, 1);
+        self.write_packet(&packet)
+    }
+
+    /// Record a response to `query` received by `local_addr`.
+    pub(crate) fn record_response(
+        &self,
+        local_addr: &SocketAddr,
+        server_addr: &SocketAddr,
+        protocol: Protocol,
+        query: &[u8],
+        response: &[u8],
+    ) {
+        let ack = 1 + 2 + query.len() as u32;
+        let packet = ip_packet(server_addr, local_addr, protocol, response, 1, ack);
+        self.write_packet(&packet)
+    }
+
+    fn write_packet(&self, packet: &[u8]) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut record = Vec::with_capacity(16 + packet.len());
+        record.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&timestamp.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(packet);
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writer.write_all(&record);
+        let _ = writer.flush();
+    }
+}
+
+/// Wrap a DNS message into IP and transport headers. TCP segments carry the
+/// length-prefixed message with the `seq` and `ack` sequence numbers.
+fn ip_packet(
+    src: &SocketAddr,
+    dst: &SocketAddr,
+    protocol: Protocol,
+    message: &[u8],
+    seq: u32,
+    ack: u32,
+) -> Vec<u8> {
+    let mut transport = vec![];
+    transport.extend_from_slice(&src.port().to_be_bytes());
+    transport.extend_from_slice(&dst.port().to_be_bytes());
+    let ip_protocol = match protocol {
+        Protocol::Udp => {
+            transport.extend_from_slice(&((8 + message.len()) as u16).to_be_bytes());
+            transport.extend_from_slice(&[0, 0]);
+            transport.extend_from_slice(message);
+            IPPROTO_UDP
+        }
+        Protocol::Tcp
+        | Protocol::Tls
+        | Protocol::Https
+        | Protocol::Quic
+        | Protocol::ObliviousHttps => {
+            transport.extend_from_slice(&seq.to_be_bytes());
+            transport.extend_from_slice(&ack.to_be_bytes());
+            transport.extend_from_slice(&[0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+            transport.extend_from_slice(&(message.len() as u16).to_be_bytes());
+            transport.extend_from_slice(message);
+            IPPROTO_TCP
+        }
+    };
+    let mut packet = vec![];
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            let total_len = (20 + transport.len()).min(0xffff) as u16;
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&total_len.to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, ip_protocol, 0, 0]);
+            packet.extend_from_slice(&src_ip.octets());
+            packet.extend_from_slice(&dst_ip.octets());
+            let checksum = ipv4_checksum(&packet);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        (src_ip, dst_ip) => {
+            let to_ipv6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let payload_len = transport.len().min(0xffff) as u16;
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&payload_len.to_be_bytes());
+            packet.extend_from_slice(&[ip_protocol, 64]);
+            packet.extend_from_slice(&to_ipv6(src_ip).octets());
+            packet.extend_from_slice(&to_ipv6(dst_ip).octets());
+        }
+    }
+    packet.extend_from_slice(&transport);
+    packet
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum = header
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_packet_capture() {
+    use std::io;
+    use std::net::Ipv4Addr;
+
+    use dnssector::DNSSector;
+
+    use crate::testing::{MockAction, MockBackend};
+    use crate::upstream_server::UpstreamServer;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    backend
+        .set_action(
+            "example.com",
+            "AAAA",
+            MockAction::Truncate(Box::new(MockAction::Respond {
+                rcode: 0,
+                answers: vec![],
+            })),
+        )
+        .unwrap();
+    let upstream_servers = vec![UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)))];
+    let buffer = SharedBuffer::default();
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    dns_client.set_packet_capture(PacketCapture::new(buffer.clone()).unwrap());
+    dns_client.query_a("example.com").unwrap();
+    dns_client.query_aaaa("example.com").unwrap();
+
+    let capture = buffer.0.lock().unwrap().clone();
+    assert_eq!(&capture[..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+    let mut packets = vec![];
+    let mut records = &capture[24..];
+    while !records.is_empty() {
+        let len = u32::from_le_bytes([records[8], records[9], records[10], records[11]]) as usize;
+        packets.push(records[16..16 + len].to_vec());
+        records = &records[16 + len..];
+    }
+    assert_eq!(packets.len(), 6);
+    for packet in &packets {
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(
+            u16::from_be_bytes([packet[2], packet[3]]) as usize,
+            packet.len()
+        );
+    }
+    assert_eq!(&packets[0][16..20], &[192, 0, 2, 53]);
+    assert_eq!(&packets[1][12..16], &[192, 0, 2, 53]);
+    let udp_ip_protocol = 17;
+    let tcp_ip_protocol = 6;
+    assert_eq!(packets[0][9], udp_ip_protocol);
+    assert_eq!(packets[3][9], udp_ip_protocol);
+    assert_eq!(packets[4][9], tcp_ip_protocol);
+    assert_eq!(packets[5][9], tcp_ip_protocol);
+    let dns_response = DNSSector::new(packets[1][28..].to_vec())
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(dns_response.rcode(), 0);
+}
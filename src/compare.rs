@@ -0,0 +1,288 @@
+use std::io;
+
+use crate::presentation::{name_to_string, rdata_to_string, Message, Record};
+use crate::upstream_server::UpstreamServer;
+
+/// A record of an answer section, in presentation format.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AnswerRecord {
+    pub name: String,
+    pub rr_type: u16,
+    pub ttl: u32,
+    pub data: String,
+}
+
+impl AnswerRecord {
+    pub(crate) fn new(rr: &Record) -> Self {
+        AnswerRecord {
+            name: name_to_string(&rr.name),
+            rr_type: rr.rr_type,
+            ttl: rr.ttl,
+            data: rdata_to_string(rr.rr_type, &rr.rdata),
+        }
+    }
+
+    fn same_data(&self, other: &AnswerRecord) -> bool {
+        self.name.eq_ignore_ascii_case(&other.name)
+            && self.rr_type == other.rr_type
+            && self.data == other.data
+    }
+}
+
+/// The records of the answer section of a response.
+pub(crate) fn answer_records(message: &Message) -> Vec<AnswerRecord> {
+    message.answers.iter().map(AnswerRecord::new).collect()
+}
+
+/// The response of a single upstream server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpstreamAnswer {
+    pub server: UpstreamServer,
+    /// The response code, `None` if the query failed.
+    pub rcode: Option<u8>,
+    /// The records of the answer section.
+    pub records: Vec<AnswerRecord>,
+    /// The error message if the query failed.
+    pub error: Option<String>,
+}
+
+impl UpstreamAnswer {
+    pub(crate) fn new(server: UpstreamServer, response: Result<Vec<u8>, io::Error>) -> Self {
+        match response.and_then(|response| Message::parse(&response)) {
+            Ok(message) => UpstreamAnswer {
+                server,
+                rcode: Some(message.rcode()),
+                records: answer_records(&message),
+                error: None,
+            },
+            Err(e) => UpstreamAnswer {
+                server,
+                rcode: None,
+                records: vec![],
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// A difference between the response of an upstream server and the
+/// reference response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UpstreamDifference {
+    /// The response codes differ, or only one of the servers responded.
+    Rcode {
+        server: UpstreamServer,
+        expected: Option<u8>,
+        found: Option<u8>,
+    },
+    /// A record of the reference response is missing.
+    MissingRecord {
+        server: UpstreamServer,
+        record: AnswerRecord,
+    },
+    /// A record is not in the reference response.
+    ExtraRecord {
+        server: UpstreamServer,
+        record: AnswerRecord,
+    },
+    /// The record is present in both responses, with different TTLs.
+    Ttl {
+        server: UpstreamServer,
+        record: AnswerRecord,
+        expected: u32,
+    },
+}
+
+/// The responses of every upstream server to the same query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpstreamComparison {
+    /// The responses, in the order of the upstream servers.
+    pub answers: Vec<UpstreamAnswer>,
+    /// The differences with the reference response, which is the first
+    /// successful response.
+    pub differences: Vec<UpstreamDifference>,
+}
+
+impl UpstreamComparison {
+    /// Whether all the upstream servers returned the same response.
+    pub fn is_consistent(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    pub(crate) fn new(responses: Vec<(UpstreamServer, Result<Vec<u8>, io::Error>)>) -> Self {
+        let answers: Vec<_> = responses
+            .into_iter()
+            .map(|(server, response)| UpstreamAnswer::new(server, response))
+            .collect();
+        let mut differences = vec![];
+        let reference = answers
+            .iter()
+            .find(|answer| answer.rcode.is_some())
+            .or_else(|| answers.first());
+        if let Some(reference) = reference {
+            for answer in &answers {
+                if std::ptr::eq(answer, reference) {
+                    continue;
+                }
+                differences.extend(Self::diff(reference, answer));
+            }
+        }
+        UpstreamComparison {
+            answers,
+            differences,
+        }
+    }
+
+    fn diff(reference: &UpstreamAnswer, answer: &UpstreamAnswer) -> Vec<UpstreamDifference> {
+        let mut differences = vec![];
+        let server = &answer.server;
+        if answer.rcode != reference.rcode {
+            differences.push(UpstreamDifference::Rcode {
+                server: server.clone(),
+                expected: reference.rcode,
+                found: answer.rcode,
+            });
+        }
+        if answer.rcode.is_none() {
+            return differences;
+        }
+        for expected in &reference.records {
+            match answer.records.iter().find(|rr| rr.same_data(expected)) {
+                None => differences.push(UpstreamDifference::MissingRecord {
+                    server: server.clone(),
+                    record: expected.clone(),
+                }),
+                Some(rr) if rr.ttl != expected.ttl => differences.push(UpstreamDifference::Ttl {
+                    server: server.clone(),
+                    record: rr.clone(),
+                    expected: expected.ttl,
+                }),
+                Some(_) => {}
+            }
+        }
+        for rr in &answer.records {
+            if !reference
+                .records
+                .iter()
+                .any(|expected| expected.same_data(rr))
+            {
+                differences.push(UpstreamDifference::ExtraRecord {
+                    server: server.clone(),
+                    record: rr.clone(),
+                });
+            }
+        }
+        differences
+    }
+}
+
+#[test]
+fn test_compare_upstreams() {
+    use dnssector::{Class, Type};
+    use std::net::SocketAddr;
+
+    let query = dnssector::gen::query(
+        b"example.com",
+        Type::from_string("A").unwrap(),
+        Class::from_string("IN").unwrap(),
+    )
+    .unwrap()
+    .into_packet();
+    let response = |rcode, answers: &[(u16, u32, &[u8])]| {
+        Ok(crate::packet::synthesize_response(&query, rcode, answers)
+            .unwrap()
+            .into_packet())
+    };
+    let servers: Vec<_> = (1..=5)
+        .map(|i| UpstreamServer::new(SocketAddr::from(([192, 0, 2, i], 53))))
+        .collect();
+    let comparison = UpstreamComparison::new(vec![
+        (
+            servers[0].clone(),
+            Err(io::Error::new(io::ErrorKind::TimedOut, "timeout")),
+        ),
+        (
+            servers[1].clone(),
+            response(0, &[(1, 300, &[192, 0, 2, 1]), (1, 300, &[192, 0, 2, 2])]),
+        ),
+        (
+            servers[2].clone(),
+            response(0, &[(1, 300, &[192, 0, 2, 2]), (1, 300, &[192, 0, 2, 1])]),
+        ),
+        (
+            servers[3].clone(),
+            response(0, &[(1, 60, &[192, 0, 2, 1]), (1, 300, &[192, 0, 2, 3])]),
+        ),
+        (servers[4].clone(), response(3, &[])),
+    ]);
+    assert!(!comparison.is_consistent());
+    assert_eq!(comparison.answers.len(), 5);
+    assert_eq!(comparison.answers[0].rcode, None);
+    assert_eq!(comparison.answers[1].records[0].data, "192.0.2.1");
+    let record = |ttl, data: &str| AnswerRecord {
+        name: "example.com.".to_string(),
+        rr_type: 1,
+        ttl,
+        data: data.to_string(),
+    };
+    assert_eq!(
+        comparison.differences,
+        vec![
+            UpstreamDifference::Rcode {
+                server: servers[0].clone(),
+                expected: Some(0),
+                found: None
+            },
+            UpstreamDifference::Ttl {
+                server: servers[3].clone(),
+                record: record(60, "192.0.2.1"),
+                expected: 300
+            },
+            UpstreamDifference::MissingRecord {
+                server: servers[3].clone(),
+                record: record(300, "192.0.2.2")
+            },
+            UpstreamDifference::ExtraRecord {
+                server: servers[3].clone(),
+                record: record(300, "192.0.2.3")
+            },
+            UpstreamDifference::Rcode {
+                server: servers[4].clone(),
+                expected: Some(0),
+                found: Some(3)
+            },
+            UpstreamDifference::MissingRecord {
+                server: servers[4].clone(),
+                record: record(300, "192.0.2.1")
+            },
+            UpstreamDifference::MissingRecord {
+                server: servers[4].clone(),
+                record: record(300, "192.0.2.2")
+            },
+        ]
+    );
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_client_compare_upstreams() {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::testing::MockBackend;
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let upstream_servers = vec![
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53))),
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 54], 53))),
+    ];
+    let dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    let comparison = dns_client.compare_upstreams("example.com", "A").unwrap();
+    assert!(comparison.is_consistent());
+    assert_eq!(comparison.answers.len(), 2);
+    assert_eq!(comparison.answers[1].records[0].data, "192.0.2.1");
+    assert!(dns_client
+        .compare_upstreams("example.com", "BOGUS")
+        .is_err());
+}
@@ -0,0 +1,46 @@
+use std::io;
+use std::time::Duration;
+
+use crate::error::DnsError;
+use crate::time::Instant;
+
+/// The time by which a whole resolution must be complete, including retries
+/// and fallbacks, if a total timeout is set.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub(crate) fn after(total_timeout: Option<Duration>) -> Self {
+        Deadline(total_timeout.map(|total_timeout| Instant::now() + total_timeout))
+    }
+
+    /// Shorten `timeout` so that it expires by the deadline, or return an
+    /// `io::ErrorKind::TimedOut` error if the deadline has passed.
+    pub(crate) fn timeout(&self, timeout: Duration) -> io::Result<Duration> {
+        let deadline = match self.0 {
+            Some(deadline) => deadline,
+            None => return Ok(timeout),
+        };
+        match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Ok(timeout.min(remaining)),
+            _ => Err(timed_out()),
+        }
+    }
+}
+
+pub(crate) fn timed_out() -> io::Error {
+    DnsError::Timeout.into()
+}
+
+#[test]
+fn test_deadline() {
+    let timeout = Duration::from_secs(5);
+    assert_eq!(Deadline::default().timeout(timeout).unwrap(), timeout);
+    let deadline = Deadline::after(Some(Duration::from_secs(1)));
+    assert!(deadline.timeout(timeout).unwrap() <= Duration::from_secs(1));
+    let deadline = Deadline::after(Some(Duration::ZERO));
+    assert_eq!(
+        deadline.timeout(timeout).unwrap_err().kind(),
+        io::ErrorKind::TimedOut
+    );
+}
@@ -0,0 +1,286 @@
+use std::io;
+use std::time::Duration;
+
+use dnssector::constants::*;
+use rand::{Rng, RngCore};
+
+use crate::edns_probe::EdnsComplianceReport;
+use crate::events::Protocol;
+use crate::name_validation::NameValidation;
+use crate::packet::{self, DEFAULT_EDNS_BUFSIZE, EDNS_FLAG_DO};
+use crate::presentation::{rcode_name, Message};
+use crate::upstream_server::UpstreamServer;
+
+/// The names queried by `run_diagnostics()`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiagnosticsConfig {
+    /// A DNSSEC-signed name that exists, also used as the zone of the EDNS
+    /// probes.
+    pub name: String,
+    /// Suffix for the random, non-existent names used to check that
+    /// NXDOMAIN responses are returned.
+    pub nonexistent_suffix: String,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        DiagnosticsConfig {
+            name: "example.com".to_string(),
+            nonexistent_suffix: "example.com".to_string(),
+        }
+    }
+}
+
+/// A check performed on every upstream server by `run_diagnostics()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DiagnosticProbe {
+    /// A query over UDP, that must be answered.
+    Udp,
+    /// The same query over TCP, that must be answered, since TCP is used
+    /// after truncated responses.
+    Tcp,
+    /// A query for the signed name with the `DO` flag, whose response must
+    /// have the `AD` flag set by a validating resolver.
+    Dnssec,
+    /// A query for a name that doesn't exist, that must be answered with
+    /// `NXDOMAIN`.
+    Nxdomain,
+}
+
+impl DiagnosticProbe {
+    /// All the probes, in the order they are sent.
+    pub const ALL: [DiagnosticProbe; 4] = [
+        DiagnosticProbe::Udp,
+        DiagnosticProbe::Tcp,
+        DiagnosticProbe::Dnssec,
+        DiagnosticProbe::Nxdomain,
+    ];
+
+    pub(crate) fn protocol(self) -> Protocol {
+        match self {
+            DiagnosticProbe::Tcp => Protocol::Tcp,
+            _ => Protocol::Udp,
+        }
+    }
+
+    /// Build the query for this probe, returning its transaction ID and the
+    /// raw packet.
+    pub(crate) fn query(
+        self,
+        config: &DiagnosticsConfig,
+        rng: &mut dyn RngCore,
+        name_validation: NameValidation,
+    ) -> Result<(u16, Vec<u8>), io::Error> {
+        let name = match self {
+            DiagnosticProbe::Nxdomain => {
+                let label: u64 = rng.gen();
+                format!("{:016x}.{}", label, config.nonexistent_suffix)
+            }
+            _ => config.name.clone(),
+        };
+        let parsed_query = packet::query(
+            &name,
+            Type::from_string("A").unwrap(),
+            Class::from_string("IN").unwrap(),
+            name_validation,
+        )?;
+        let tid = parsed_query.tid();
+        let mut query = parsed_query.into_packet();
+        if self == DiagnosticProbe::Dnssec {
+            packet::add_edns(&mut query, DEFAULT_EDNS_BUFSIZE, 0, EDNS_FLAG_DO, &[]);
+        }
+        Ok((tid, query))
+    }
+
+    /// Check the response to this probe.
+    pub(crate) fn check(self, query_tid: u16, response: &[u8]) -> DiagnosticOutcome {
+        let message = match Message::parse(response) {
+            Ok(message) => message,
+            Err(e) => return DiagnosticOutcome::Failed(e.to_string()),
+        };
+        if message.id != query_tid || u32::from(message.flags) & DNS_FLAG_QR == 0 {
+            return DiagnosticOutcome::Failed("Unexpected response".to_string());
+        }
+        let rcode = message.extended_rcode();
+        let rcode_is = |expected: Rcode| rcode == u16::from(u8::from(expected));
+        let unexpected = |reason: &str| DiagnosticOutcome::Unexpected(reason.to_string());
+        let unexpected_rcode = || {
+            DiagnosticOutcome::Unexpected(format!(
+                "Unexpected response code: {}",
+                rcode_name(rcode)
+            ))
+        };
+        match self {
+            DiagnosticProbe::Nxdomain if rcode_is(Rcode::NXDOMAIN) => DiagnosticOutcome::Ok,
+            DiagnosticProbe::Nxdomain
+                if rcode_is(Rcode::NOERROR) && !message.answers.is_empty() =>
+            {
+                unexpected("A non-existent name was resolved")
+            }
+            DiagnosticProbe::Nxdomain => unexpected_rcode(),
+            _ if !rcode_is(Rcode::NOERROR) => unexpected_rcode(),
+            DiagnosticProbe::Dnssec if u32::from(message.flags) & DNS_FLAG_AD == 0 => {
+                unexpected("The AD flag was not set, DNSSEC is not validated")
+            }
+            _ => DiagnosticOutcome::Ok,
+        }
+    }
+}
+
+/// The outcome of a diagnostic probe.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiagnosticOutcome {
+    /// The server responded as expected.
+    Ok,
+    /// The server responded, but not as expected.
+    Unexpected(String),
+    /// The server didn't respond, or the response was invalid.
+    Failed(String),
+}
+
+/// How an upstream server behaved during `run_diagnostics()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpstreamDiagnostics {
+    pub server: UpstreamServer,
+    /// The outcome and the round-trip time of every probe, in the order of
+    /// `DiagnosticProbe::ALL`.
+    pub probes: Vec<(DiagnosticProbe, DiagnosticOutcome, Duration)>,
+    /// The outcome of the EDNS probes.
+    pub edns: EdnsComplianceReport,
+}
+
+impl UpstreamDiagnostics {
+    /// Whether all the probes, EDNS probes included, succeeded.
+    pub fn is_healthy(&self) -> bool {
+        self.probes
+            .iter()
+            .all(|(_, outcome, _)| *outcome == DiagnosticOutcome::Ok)
+            && self.edns.is_compliant()
+    }
+
+    /// The outcome of `probe`.
+    pub fn outcome(&self, probe: DiagnosticProbe) -> Option<&DiagnosticOutcome> {
+        self.probes
+            .iter()
+            .find(|(p, ..)| *p == probe)
+            .map(|(_, outcome, _)| outcome)
+    }
+
+    /// The round-trip time of `probe`, if it received a response.
+    pub fn rtt(&self, probe: DiagnosticProbe) -> Option<Duration> {
+        self.probes
+            .iter()
+            .find(|(p, outcome, _)| *p == probe && !matches!(outcome, DiagnosticOutcome::Failed(_)))
+            .map(|(.., rtt)| *rtt)
+    }
+}
+
+/// The report returned by `run_diagnostics()`, with an entry for every
+/// upstream server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiagnosticsReport {
+    pub upstreams: Vec<UpstreamDiagnostics>,
+}
+
+impl DiagnosticsReport {
+    /// Whether every upstream server passed every probe.
+    pub fn is_healthy(&self) -> bool {
+        self.upstreams.iter().all(UpstreamDiagnostics::is_healthy)
+    }
+}
+
+#[test]
+fn test_diagnostic_probes() {
+    let config = DiagnosticsConfig::default();
+    let mut rng = rand::thread_rng();
+    let mut respond = |probe: DiagnosticProbe, rcode: Rcode, flags: u32, answer: bool| {
+        let (tid, query) = probe
+            .query(&config, &mut rng, NameValidation::Strict)
+            .unwrap();
+        let a: &[u8] = &[192, 0, 2, 1];
+        let answers = if answer {
+            vec![(Type::A.into(), 60, a)]
+        } else {
+            vec![]
+        };
+        let mut response = packet::synthesize_response(&query, rcode.into(), &answers)
+            .unwrap()
+            .into_packet();
+        response[DNS_FLAGS_OFFSET + 1] |= (flags & 0xff) as u8;
+        probe.check(tid, &response)
+    };
+    assert_eq!(
+        respond(DiagnosticProbe::Udp, Rcode::NOERROR, 0, true),
+        DiagnosticOutcome::Ok
+    );
+    assert!(matches!(
+        respond(DiagnosticProbe::Tcp, Rcode::SERVFAIL, 0, false),
+        DiagnosticOutcome::Unexpected(_)
+    ));
+    assert_eq!(
+        respond(DiagnosticProbe::Dnssec, Rcode::NOERROR, DNS_FLAG_AD, true),
+        DiagnosticOutcome::Ok
+    );
+    assert!(matches!(
+        respond(DiagnosticProbe::Dnssec, Rcode::NOERROR, 0, true),
+        DiagnosticOutcome::Unexpected(_)
+    ));
+    assert_eq!(
+        respond(DiagnosticProbe::Nxdomain, Rcode::NXDOMAIN, 0, false),
+        DiagnosticOutcome::Ok
+    );
+    assert!(matches!(
+        respond(DiagnosticProbe::Nxdomain, Rcode::NOERROR, 0, true),
+        DiagnosticOutcome::Unexpected(_)
+    ));
+
+    let (tid, query) = DiagnosticProbe::Udp
+        .query(&config, &mut rand::thread_rng(), NameValidation::Strict)
+        .unwrap();
+    assert!(matches!(
+        DiagnosticProbe::Udp.check(tid, &query),
+        DiagnosticOutcome::Failed(_)
+    ));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_run_diagnostics() {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::testing::MockBackend;
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let upstream_servers = vec![
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53))),
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 54], 53))),
+    ];
+    let dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    let report = dns_client.run_diagnostics().unwrap();
+    assert_eq!(report.upstreams.len(), 2);
+    assert!(!report.is_healthy());
+    let diagnostics = &report.upstreams[0];
+    assert_eq!(diagnostics.probes.len(), DiagnosticProbe::ALL.len());
+    assert_eq!(
+        diagnostics.outcome(DiagnosticProbe::Udp),
+        Some(&DiagnosticOutcome::Ok)
+    );
+    assert_eq!(
+        diagnostics.outcome(DiagnosticProbe::Tcp),
+        Some(&DiagnosticOutcome::Ok)
+    );
+    assert_eq!(
+        diagnostics.outcome(DiagnosticProbe::Nxdomain),
+        Some(&DiagnosticOutcome::Ok)
+    );
+    // The mock backend doesn't validate DNSSEC
+    assert!(matches!(
+        diagnostics.outcome(DiagnosticProbe::Dnssec),
+        Some(DiagnosticOutcome::Unexpected(_))
+    ));
+    assert!(diagnostics.rtt(DiagnosticProbe::Udp).is_some());
+    assert!(!diagnostics.edns.is_compliant());
+}
@@ -0,0 +1,121 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// A NAT64 prefix, used to synthesize IPv6 addresses from IPv4 addresses
+/// for names that have no `AAAA` records (DNS64, RFC 6147), so that they
+/// can be reached from IPv6-only networks.
+///
+/// ```
+/// use std::net::{Ipv4Addr, Ipv6Addr};
+///
+/// use dnsclient::Dns64;
+///
+/// let dns64 = Dns64::default();
+/// assert_eq!(
+///     dns64.synthesize(Ipv4Addr::new(192, 0, 2, 1)),
+///     "64:ff9b::c000:201".parse::<Ipv6Addr>().unwrap()
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dns64 {
+    prefix: Ipv6Addr,
+    prefix_len: u8,
+}
+
+impl Default for Dns64 {
+    /// The well-known prefix, `64:ff9b::/96`.
+    fn default() -> Self {
+        Dns64 {
+            prefix: Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0),
+            prefix_len: 96,
+        }
+    }
+}
+
+impl Dns64 {
+    /// Use a network-specific prefix, whose length must be 32, 40, 48, 56,
+    /// 64 or 96 bits (RFC 6052). Bits after the prefix are ignored.
+    pub fn new(prefix: Ipv6Addr, prefix_len: u8) -> Result<Self, io::Error> {
+        if ![32, 40, 48, 56, 64, 96].contains(&prefix_len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid NAT64 prefix length",
+            ));
+        }
+        let mask = u128::MAX << (128 - prefix_len);
+        let prefix = Ipv6Addr::from(u128::from(prefix) & mask);
+        Ok(Dns64 { prefix, prefix_len })
+    }
+
+    pub fn prefix(&self) -> Ipv6Addr {
+        self.prefix
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Embed an IPv4 address into the prefix. Bits 64 to 71 are skipped.
+    pub fn synthesize(&self, ip: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.prefix.octets();
+        let mut pos = self.prefix_len as usize / 8;
+        for &b in &ip.octets() {
+            if pos == 8 {
+                pos += 1;
+            }
+            octets[pos] = b;
+            pos += 1;
+        }
+        Ipv6Addr::from(octets)
+    }
+}
+
+#[test]
+fn test_dns64() {
+    let ip = Ipv4Addr::new(192, 0, 2, 33);
+    let prefix: Ipv6Addr = "2001:db8:122:344::".parse().unwrap();
+    // Examples from RFC 6052, section 2.4
+    for (prefix_len, expected) in &[
+        (32, "2001:db8:c000:221::"),
+        (40, "2001:db8:1c0:2:21::"),
+        (48, "2001:db8:122:c000:2:2100::"),
+        (56, "2001:db8:122:3c0:0:221::"),
+        (64, "2001:db8:122:344:c0:2:2100:0"),
+        (96, "2001:db8:122:344::192.0.2.33"),
+    ] {
+        let dns64 = Dns64::new(prefix, *prefix_len).unwrap();
+        assert_eq!(dns64.synthesize(ip), expected.parse::<Ipv6Addr>().unwrap());
+    }
+    assert!(Dns64::new(prefix, 33).is_err());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_client_dns64() {
+    use std::net::SocketAddr;
+
+    use crate::testing::MockBackend;
+    use crate::upstream_server::UpstreamServer;
+
+    let backend = MockBackend::new();
+    backend.add_a("ipv4only.example", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    backend.add_aaaa("ipv4only.example", &[]);
+    backend.add_a("dualstack.example", &[Ipv4Addr::new(192, 0, 2, 2)]);
+    backend.add_aaaa("dualstack.example", &[Ipv6Addr::LOCALHOST]);
+    let upstream_servers = vec![UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)))];
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    assert!(dns_client
+        .query_aaaa("ipv4only.example")
+        .unwrap()
+        .is_empty());
+    dns_client.set_dns64(Some(crate::Dns64::default()));
+    assert_eq!(
+        dns_client.query_aaaa("ipv4only.example").unwrap(),
+        vec!["64:ff9b::192.0.2.1".parse::<Ipv6Addr>().unwrap()]
+    );
+    assert_eq!(
+        dns_client.query_aaaa("dualstack.example").unwrap(),
+        vec![Ipv6Addr::LOCALHOST]
+    );
+}
@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+use std::io;
+
+use dnssector::constants::*;
+use dnssector::*;
+
+use crate::name_validation::NameValidation;
+use crate::packet;
+use crate::record_data::{self, Ptr, RecordData, Srv, Txt};
+
+const MAX_LABEL_LEN: usize = 63;
+
+/// The name whose `PTR` records list the service types of a domain (RFC
+/// 6763, section 9).
+const SERVICE_TYPE_ENUMERATION: &str = "_services._dns-sd._udp";
+
+/// A resolved DNS-SD service instance (RFC 6763).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ServiceInstance {
+    /// The instance name, as returned by `browse()`, such as `My Printer`.
+    pub instance: String,
+    /// The service, such as `_ipp._tcp.local`.
+    pub service: String,
+    /// The host providing the service.
+    pub host: String,
+    pub port: u16,
+    pub priority: u16,
+    pub weight: u16,
+    /// The `key=value` pairs of the `TXT` record. Keys without a value are
+    /// boolean attributes, whose value is `None`.
+    pub attributes: Vec<(String, Option<Vec<u8>>)>,
+}
+
+impl ServiceInstance {
+    /// Build an instance from its `SRV` and `TXT` records. If there are
+    /// several `SRV` records, the one with the lowest priority and the
+    /// highest weight is used.
+    pub(crate) fn new(
+        instance: &str,
+        service: &str,
+        srvs: Vec<Srv>,
+        txts: Vec<Txt>,
+    ) -> Result<Self, io::Error> {
+        let srv = srvs
+            .into_iter()
+            .min_by_key(|srv| (srv.priority, u16::MAX - srv.weight))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No SRV record for [{}.{}]", instance, service),
+                )
+            })?;
+        Ok(ServiceInstance {
+            instance: instance.to_string(),
+            service: service.to_string(),
+            host: srv.target,
+            port: srv.port,
+            priority: srv.priority,
+            weight: srv.weight,
+            attributes: parse_attributes(&txts),
+        })
+    }
+
+    /// Return the value of the attribute `key`, compared case-insensitively.
+    ///
+    /// `Some(None)` is returned for boolean attributes.
+    pub fn attribute(&self, key: &str) -> Option<Option<&[u8]>> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_deref())
+    }
+}
+
+/// Decode the attributes of `TXT` records. Only the first occurrence of a
+/// key is kept, and empty strings are ignored.
+fn parse_attributes(txts: &[Txt]) -> Vec<(String, Option<Vec<u8>>)> {
+    let mut seen = HashSet::new();
+    let mut attributes = vec![];
+    for s in txts.iter().flat_map(|txt| txt.0.iter()) {
+        let (key, value) = match s.iter().position(|&c| c == b'=') {
+            Some(pos) => (&s[..pos], Some(s[pos + 1..].to_vec())),
+            None => (&s[..], None),
+        };
+        if key.is_empty() || !seen.insert(key.to_ascii_lowercase()) {
+            continue;
+        }
+        attributes.push((String::from_utf8_lossy(key).into_owned(), value));
+    }
+    attributes
+}
+
+/// The instance name of a `PTR` record for a service: its first label,
+/// which can contain any character, including dots.
+struct InstancePtr(String);
+
+impl RecordData for InstancePtr {
+    const TYPE: u16 = 12;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        let len = *rdata.first().unwrap_or(&0) as usize;
+        match rdata.get(1..1 + len) {
+            Some(label) if len > 0 && len <= MAX_LABEL_LEN => {
+                Ok(InstancePtr(String::from_utf8_lossy(label).into_owned()))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid service instance name",
+            )),
+        }
+    }
+}
+
+/// Return the distinct instance names from a response to a `PTR` query.
+pub(crate) fn instances(response: &[u8]) -> Result<Vec<String>, io::Error> {
+    let mut seen = HashSet::new();
+    Ok(record_data::parse_answers::<InstancePtr>(response)?
+        .into_iter()
+        .map(|ptr| ptr.0)
+        .filter(|instance| seen.insert(instance.clone()))
+        .collect())
+}
+
+/// The name listing the service types of `domain`.
+pub(crate) fn service_types_name(domain: &str) -> String {
+    format!(
+        "{}.{}",
+        SERVICE_TYPE_ENUMERATION,
+        domain.trim_end_matches('.')
+    )
+}
+
+/// Return the distinct service types from a response to a `PTR` query.
+pub(crate) fn service_types(response: &[u8]) -> Result<Vec<String>, io::Error> {
+    let mut seen = HashSet::new();
+    Ok(record_data::parse_answers::<Ptr>(response)?
+        .into_iter()
+        .map(|ptr| ptr.0)
+        .filter(|service| seen.insert(service.to_ascii_lowercase()))
+        .collect())
+}
+
+/// Build a query for the name made of the `instance` label followed by
+/// `service`. Only `service` is subject to name validation, since instance
+/// names can contain any character.
+pub(crate) fn instance_query(
+    instance: &str,
+    service: &str,
+    rr_type: u16,
+    name_validation: NameValidation,
+) -> Result<ParsedPacket, io::Error> {
+    if instance.is_empty() || instance.len() > MAX_LABEL_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid service instance name [{}]", instance),
+        ));
+    }
+    let mut query = packet::query_type(service, rr_type, name_validation)?.into_packet();
+    let mut label = vec![instance.len() as u8];
+    label.extend_from_slice(instance.as_bytes());
+    query.splice(DNS_HEADER_SIZE..DNS_HEADER_SIZE, label);
+    DNSSector::new(query)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+#[test]
+fn test_dns_sd() {
+    let mut query = instance_query(
+        "My Printer",
+        "_ipp._tcp.local",
+        Srv::TYPE,
+        NameValidation::Relaxed,
+    )
+    .unwrap();
+    let (name, rr_type, _) = query.question().unwrap();
+    assert_eq!(name, b"my printer._ipp._tcp.local".to_vec());
+    assert_eq!(rr_type, Srv::TYPE);
+    assert!(instance_query("", "_ipp._tcp.local", Srv::TYPE, NameValidation::Relaxed).is_err());
+    assert_eq!(service_types_name("local."), "_services._dns-sd._udp.local");
+
+    let txts = vec![
+        Txt(vec![b"txtvers=1".to_vec(), b"color".to_vec(), b"".to_vec()]),
+        Txt(vec![b"Color=F".to_vec(), b"note=".to_vec()]),
+    ];
+    let srvs = vec![
+        Srv {
+            priority: 1,
+            weight: 0,
+            port: 631,
+            target: "backup.local".to_string(),
+        },
+        Srv {
+            priority: 0,
+            weight: 10,
+            port: 631,
+            target: "printer.local".to_string(),
+        },
+    ];
+    let service_instance =
+        ServiceInstance::new("My Printer", "_ipp._tcp.local", srvs, txts).unwrap();
+    assert_eq!(service_instance.host, "printer.local");
+    assert_eq!(service_instance.attributes.len(), 3);
+    assert_eq!(service_instance.attribute("TXTVERS"), Some(Some(&b"1"[..])));
+    assert_eq!(service_instance.attribute("color"), Some(None));
+    assert_eq!(service_instance.attribute("note"), Some(Some(&b""[..])));
+    assert_eq!(service_instance.attribute("missing"), None);
+    assert!(ServiceInstance::new("My Printer", "_ipp._tcp.local", vec![], vec![]).is_err());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_client_dns_sd() {
+    use crate::record_data::{Ptr, RecordData, Srv, Txt};
+    use crate::testing::{MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    let answers = vec![(
+        Ptr::TYPE,
+        120,
+        b"\x0aMy Printer\x04_ipp\x04_tcp\x05local\x00".to_vec(),
+    )];
+    let action = MockAction::Respond { rcode: 0, answers };
+    backend
+        .set_action("_ipp._tcp.local", "PTR", action)
+        .unwrap();
+    let answers = vec![(
+        Srv::TYPE,
+        120,
+        b"\x00\x00\x00\x00\x02\x77\x07printer\x05local\x00".to_vec(),
+    )];
+    let action = MockAction::Respond { rcode: 0, answers };
+    backend
+        .set_action("My Printer._ipp._tcp.local", "SRV", action)
+        .unwrap();
+    let answers = vec![(Txt::TYPE, 120, b"\x09txtvers=1\x05color".to_vec())];
+    let action = MockAction::Respond { rcode: 0, answers };
+    backend
+        .set_action("My Printer._ipp._tcp.local", "TXT", action)
+        .unwrap();
+    let answers = vec![
+        (Ptr::TYPE, 120, b"\x04_ipp\x04_tcp\x05local\x00".to_vec()),
+        (Ptr::TYPE, 120, b"\x04_IPP\x04_tcp\x05local\x00".to_vec()),
+    ];
+    let action = MockAction::Respond { rcode: 0, answers };
+    backend
+        .set_action("_services._dns-sd._udp.local", "PTR", action)
+        .unwrap();
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(vec![], backend);
+    dns_client.set_mdns(true);
+
+    assert_eq!(
+        dns_client.browse_service_types("local").unwrap(),
+        vec!["_ipp._tcp.local".to_string()]
+    );
+    let instances = dns_client.browse("_ipp._tcp.local").unwrap();
+    assert_eq!(instances, vec!["My Printer".to_string()]);
+    let service_instance = dns_client
+        .resolve_instance(&instances[0], "_ipp._tcp.local")
+        .unwrap();
+    assert_eq!(service_instance.host, "printer.local");
+    assert_eq!(service_instance.port, 631);
+    assert_eq!(service_instance.attribute("txtvers"), Some(Some(&b"1"[..])));
+    assert_eq!(service_instance.attribute("color"), Some(None));
+    assert!(dns_client
+        .resolve_instance("Other Printer", "_ipp._tcp.local")
+        .is_err());
+}
@@ -0,0 +1,115 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use dnssector::constants::{Class, Type};
+use tower_service::Service;
+
+use crate::name_validation::NameValidation;
+use crate::packet;
+use crate::r#async::{AsyncBackend, GenericDNSClient};
+use crate::response::DnsResponse;
+
+/// A query sent through a `DnsService`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DnsRequest {
+    pub name: String,
+    pub query_class: String,
+    pub query_type: String,
+}
+
+impl DnsRequest {
+    /// Create a request for records of type `query_type` in the `IN` class.
+    pub fn new(name: impl Into<String>, query_type: impl Into<String>) -> Self {
+        DnsRequest {
+            name: name.into(),
+            query_class: "IN".to_string(),
+            query_type: query_type.into(),
+        }
+    }
+
+    fn to_query(&self, name_validation: NameValidation) -> Result<Vec<u8>, io::Error> {
+        let rr_class = Class::from_string(&self.query_class)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let rr_type = Type::from_string(&self.query_type)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let parsed_query = packet::query(&self.name, rr_type, rr_class, name_validation)?;
+        Ok(parsed_query.into_packet())
+    }
+}
+
+/// An async client exposed as a `tower::Service`, so that standard `tower`
+/// middleware (timeouts, retries, load shedding...) can be layered on top of
+/// DNS resolution.
+///
+/// The service is always ready, and clones share the same client.
+#[derive(Clone, Debug)]
+pub struct DnsService<B> {
+    dns_client: Arc<GenericDNSClient<B>>,
+}
+
+impl<B: AsyncBackend> DnsService<B> {
+    pub fn new(dns_client: GenericDNSClient<B>) -> Self {
+        DnsService {
+            dns_client: Arc::new(dns_client),
+        }
+    }
+
+    /// The underlying client.
+    pub fn dns_client(&self) -> &GenericDNSClient<B> {
+        &self.dns_client
+    }
+}
+
+impl<B: AsyncBackend> From<GenericDNSClient<B>> for DnsService<B> {
+    fn from(dns_client: GenericDNSClient<B>) -> Self {
+        DnsService::new(dns_client)
+    }
+}
+
+impl<B: AsyncBackend + 'static> Service<DnsRequest> for DnsService<B> {
+    type Response = DnsResponse;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<DnsResponse, io::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: DnsRequest) -> Self::Future {
+        let dns_client = self.dns_client.clone();
+        Box::pin(async move {
+            let query = request.to_query(dns_client.name_validation())?;
+            let (packet, info) = dns_client.query_raw_detailed(&query, false).await?;
+            Ok(DnsResponse { packet, info })
+        })
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+#[test]
+fn test_dns_service() {
+    use std::net::Ipv4Addr;
+
+    use crate::static_records::StaticRecords;
+
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    let mut dns_client = crate::r#async::tokio::DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    let mut service = DnsService::new(dns_client);
+    let rt = ::tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let response = rt
+        .block_on(service.call(DnsRequest::new("example.com", "A")))
+        .unwrap();
+    assert_eq!(response.rcode(), 0);
+    assert!(response.to_records().unwrap().contains("192.0.2.1"));
+    assert!(rt
+        .block_on(service.call(DnsRequest::new("example.com", "NOPE")))
+        .is_err());
+}
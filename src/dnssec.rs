@@ -0,0 +1,1517 @@
+//! DNSSEC validation (RFC 4033, 4034 and 4035).
+//!
+//! A response is validated by building a chain of trust from a trust anchor
+//! down to the zone that signed it. The zone cuts are found by querying the
+//! `DS` records of every name between the trust anchor and the name of the
+//! records: the parent zone either returns signed `DS` records, or proves
+//! with `NSEC` or `NSEC3` records that there are none.
+//!
+//! Negative responses and answers expanded from wildcards are only secure
+//! if the `NSEC` or `NSEC3` records of the authority section prove that the
+//! name, the type, or a closer match doesn't exist (RFC 4035 section 5.4,
+//! RFC 5155 section 8).
+//!
+//! `Validator` doesn't send queries itself: `next()` returns the records it
+//! needs, the client fetches them, and hands the response back with
+//! `add_response()`, so that the sync and async clients share the same
+//! code. The `DNSKEY` and `DS` records included in the response, such as
+//! the validation chain requested with the `CHAIN` option (RFC 7901), are
+//! used instead of being fetched.
+
+use std::collections::HashMap;
+
+use ring::digest;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+
+use crate::packet;
+use crate::presentation::{Message, Record};
+use crate::record_data::parse_name;
+use crate::time::{SystemTime, UNIX_EPOCH};
+
+const TYPE_NS: u16 = 2;
+const TYPE_CNAME: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_PTR: u16 = 12;
+const TYPE_MX: u16 = 15;
+const TYPE_SRV: u16 = 33;
+const TYPE_DNAME: u16 = 39;
+const TYPE_DS: u16 = 43;
+const TYPE_RRSIG: u16 = 46;
+const TYPE_NSEC: u16 = 47;
+const TYPE_DNSKEY: u16 = 48;
+const TYPE_NSEC3: u16 = 50;
+const TYPE_ANY: u16 = 255;
+
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// The `ZONE` flag of `DNSKEY` records, set on keys that sign zone data.
+const DNSKEY_FLAG_ZONE: u16 = 0x0100;
+/// The `Opt-Out` flag of `NSEC3` records.
+const NSEC3_FLAG_OPT_OUT: u8 = 0x01;
+/// The size of the fixed part of the data of an `RRSIG` record, before the
+/// signer name.
+const RRSIG_HEADER_SIZE: usize = 18;
+/// Zones using more `NSEC3` iterations are treated as insecure (RFC 9276).
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+/// The maximum number of responses fetched to validate a single response.
+const MAX_FETCHES: usize = 32;
+
+/// The signature algorithms that can be verified: RSA/SHA-1, RSA/SHA-256,
+/// RSA/SHA-512, ECDSA P-256/SHA-256, ECDSA P-384/SHA-384 and Ed25519.
+const SUPPORTED_ALGORITHMS: [u8; 7] = [5, 7, 8, 10, 13, 14, 15];
+
+/// The result of the DNSSEC validation of a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DnssecStatus {
+    /// Every record of the response is signed by a chain of keys starting
+    /// at a trust anchor.
+    Secure,
+    /// The records are in a zone that is proven to be unsigned, or signed
+    /// with algorithms that are not supported, or not covered by any trust
+    /// anchor.
+    Insecure,
+    /// The records should have been signed, but signatures are missing,
+    /// invalid or expired, or the chain of trust couldn't be fetched.
+    Bogus,
+}
+
+impl DnssecStatus {
+    /// The least secure of two statuses.
+    fn worst(self, other: DnssecStatus) -> DnssecStatus {
+        match (self, other) {
+            (DnssecStatus::Bogus, _) | (_, DnssecStatus::Bogus) => DnssecStatus::Bogus,
+            (DnssecStatus::Insecure, _) | (_, DnssecStatus::Insecure) => DnssecStatus::Insecure,
+            _ => DnssecStatus::Secure,
+        }
+    }
+}
+
+/// A trust anchor: the digest of a key of a zone, in the format of a `DS`
+/// record, that chains of trust start from.
+///
+/// ```
+/// use dnsclient::TrustAnchor;
+///
+/// let mut trust_anchors = TrustAnchor::root();
+/// trust_anchors.push(TrustAnchor {
+///     zone: "example.internal".to_string(),
+///     key_tag: 12345,
+///     algorithm: 13,
+///     digest_type: 2,
+///     digest: vec![0; 32],
+/// });
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TrustAnchor {
+    /// The zone of the key, `.` for the root zone.
+    pub zone: String,
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8,
+    pub digest: Vec<u8>,
+}
+
+impl TrustAnchor {
+    /// The trust anchors of the root zone published by IANA: the keys
+    /// 20326 (2017) and 38696 (2024).
+    pub fn root() -> Vec<TrustAnchor> {
+        [
+            (
+                20326,
+                "e06d44b80b8f1d39a95c0b0d7c65d08458e880409bbc683457104237c7f8ec8d",
+            ),
+            (
+                38696,
+                "683d2d0acb8c9b712a1948b27f741219298d0a450d612c483af444a4c0fb2b16",
+            ),
+        ]
+        .iter()
+        .map(|&(key_tag, digest)| TrustAnchor {
+            zone: ".".to_string(),
+            key_tag,
+            algorithm: 8,
+            digest_type: 2,
+            digest: (0..digest.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&digest[i..i + 2], 16).unwrap())
+                .collect(),
+        })
+        .collect()
+    }
+}
+
+/// The next step of a validation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Validation {
+    Done(DnssecStatus),
+    /// The records of this name and type must be fetched with the `DO` flag,
+    /// and the response given to `Validator::add_response()`.
+    Fetch(String, u16),
+}
+
+/// Records that must be fetched before the validation can go on.
+struct Missing(Vec<u8>, u16);
+
+/// The state of the chain of trust down to a name.
+enum Chain {
+    /// The closest enclosing zone of the name, and its validated keys.
+    Secure {
+        zone: Vec<u8>,
+        keys: Vec<Vec<u8>>,
+    },
+    Insecure,
+    Bogus,
+}
+
+/// What the `DS` records of a name say about a zone cut.
+enum Delegation {
+    /// A signed delegation, with these `DS` records.
+    Secure(Vec<Ds>),
+    /// The name is not a zone cut.
+    None,
+    /// An unsigned delegation.
+    Insecure,
+    Bogus,
+}
+
+/// The keys of a zone, validated against a set of `DS` records.
+enum Keys {
+    Secure(Vec<Vec<u8>>),
+    /// None of the `DS` records use a supported algorithm.
+    Unsupported,
+    Bogus,
+}
+
+pub(crate) struct Validator {
+    trust_anchors: Vec<(Vec<u8>, Ds)>,
+    response: Message,
+    fetched: HashMap<(Vec<u8>, u16), Option<Message>>,
+    /// The `DNSKEY` and `DS` records included in the response.
+    chain: HashMap<(Vec<u8>, u16), Message>,
+    pending: Option<(Vec<u8>, u16)>,
+    now: u32,
+}
+
+impl Validator {
+    pub(crate) fn new(trust_anchors: &[TrustAnchor], response: Message) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs() as u32);
+        Self::with_time(trust_anchors, response, now)
+    }
+
+    fn with_time(trust_anchors: &[TrustAnchor], response: Message, now: u32) -> Self {
+        let trust_anchors = trust_anchors
+            .iter()
+            .filter_map(|trust_anchor| {
+                let zone = packet::name_to_wire(&trust_anchor.zone).ok()?;
+                let ds = Ds {
+                    key_tag: trust_anchor.key_tag,
+                    algorithm: trust_anchor.algorithm,
+                    digest_type: trust_anchor.digest_type,
+                    digest: trust_anchor.digest.clone(),
+                };
+                Some((zone.to_ascii_lowercase(), ds))
+            })
+            .collect();
+        let records: Vec<_> = response
+            .authority
+            .iter()
+            .chain(&response.additional)
+            .filter(|rr| [TYPE_DNSKEY, TYPE_DS, TYPE_RRSIG].contains(&rr.rr_type))
+            .cloned()
+            .collect();
+        let chain = rrsets(&records)
+            .into_iter()
+            .filter(|rrset| rrset.rr_type == TYPE_DNSKEY || rrset.rr_type == TYPE_DS)
+            .map(|rrset| {
+                let answers = rrset
+                    .records
+                    .iter()
+                    .chain(&rrset.rrsigs)
+                    .map(|&rr| rr.clone())
+                    .collect();
+                let response = Message {
+                    id: 0,
+                    flags: 0,
+                    question: None,
+                    answers,
+                    authority: vec![],
+                    additional: vec![],
+                };
+                ((rrset.owner, rrset.rr_type), response)
+            })
+            .collect();
+        Validator {
+            trust_anchors,
+            response,
+            fetched: HashMap::new(),
+            chain,
+            pending: None,
+            now,
+        }
+    }
+
+    /// Return the status of the response, or the records to fetch next.
+    pub(crate) fn next(&mut self) -> Validation {
+        if self.fetched.len() >= MAX_FETCHES {
+            return Validation::Done(DnssecStatus::Bogus);
+        }
+        match self.status() {
+            Ok(status) => Validation::Done(status),
+            Err(Missing(name, rr_type)) => match parse_name(&name) {
+                Ok((name_str, _)) => {
+                    self.pending = Some((name, rr_type));
+                    Validation::Fetch(name_str, rr_type)
+                }
+                Err(_) => Validation::Done(DnssecStatus::Bogus),
+            },
+        }
+    }
+
+    /// Add the response to the last records returned by `next()`, `None` if
+    /// they couldn't be fetched.
+    pub(crate) fn add_response(&mut self, response: Option<Message>) {
+        if let Some(key) = self.pending.take() {
+            self.fetched.insert(key, response);
+        }
+    }
+
+    fn fetched(&self, name: &[u8], rr_type: u16) -> Result<Option<&Message>, Missing> {
+        let key = (name.to_vec(), rr_type);
+        if let Some(response) = self.chain.get(&key) {
+            return Ok(Some(response));
+        }
+        match self.fetched.get(&key) {
+            Some(response) => Ok(response.as_ref()),
+            None => Err(Missing(key.0, key.1)),
+        }
+    }
+
+    fn status(&self) -> Result<DnssecStatus, Missing> {
+        let (qname, qtype) = match &self.response.question {
+            Some((qname, qtype, _)) => (qname.to_ascii_lowercase(), *qtype),
+            None => return Ok(DnssecStatus::Bogus),
+        };
+        let answers = rrsets(&self.response.answers);
+        // CNAME records synthesized from a DNAME record are not signed
+        let has_dname = answers.iter().any(|rrset| rrset.rr_type == TYPE_DNAME);
+        let mut status = DnssecStatus::Secure;
+        let mut expanded = vec![];
+        for rrset in &answers {
+            if has_dname && rrset.rr_type == TYPE_CNAME && rrset.rrsigs.is_empty() {
+                continue;
+            }
+            let (rrset_status, labels) = self.rrset_status(rrset)?;
+            status = status.worst(rrset_status);
+            if labels < label_count(&rrset.owner) {
+                expanded.push((&rrset.owner[..], labels));
+            }
+        }
+        if status == DnssecStatus::Bogus {
+            return Ok(status);
+        }
+        let sname = cname_target(&answers, &qname, qtype);
+        let denied = qtype != TYPE_ANY
+            && !answers
+                .iter()
+                .any(|rrset| rrset.owner == sname && rrset.rr_type == qtype);
+        if expanded.is_empty() && !denied {
+            return Ok(status);
+        }
+        let (proofs_status, denial) = self.denial()?;
+        if denial.is_empty() {
+            if !expanded.is_empty() {
+                return Ok(DnssecStatus::Bogus);
+            }
+            return Ok(status.worst(unsigned_status(self.chain(&sname)?)));
+        }
+        status = status.worst(proofs_status);
+        for rrset in rrsets(&self.response.authority)
+            .iter()
+            .filter(|rrset| rrset.rr_type == TYPE_SOA)
+        {
+            status = status.worst(self.rrset_status(rrset)?.0);
+        }
+        if status != DnssecStatus::Secure {
+            return Ok(status);
+        }
+        for (name, labels) in expanded {
+            status = status.worst(denial.no_closer_match(name, labels));
+        }
+        if denied {
+            status = status.worst(if self.response.rcode() == RCODE_NXDOMAIN {
+                denial.nxdomain(&sname)
+            } else {
+                denial.nodata(&sname, qtype)
+            });
+        }
+        Ok(status)
+    }
+
+    /// The status of an RRset, and the number of labels of the name it was
+    /// signed as, that is lower than the number of labels of its owner if it
+    /// was expanded from a wildcard.
+    fn rrset_status(&self, rrset: &RRset) -> Result<(DnssecStatus, usize), Missing> {
+        let labels = label_count(&rrset.owner);
+        let signer = match rrset
+            .rrsigs
+            .iter()
+            .find_map(|rrsig| Rrsig::parse(&rrsig.rdata))
+        {
+            Some(rrsig) => rrsig.signer,
+            None => return Ok((unsigned_status(self.chain(&rrset.owner)?), labels)),
+        };
+        if !is_subdomain(&rrset.owner, &signer) {
+            return Ok((DnssecStatus::Bogus, labels));
+        }
+        Ok(match self.chain(&signer)? {
+            Chain::Secure { zone, keys } if zone == signer => {
+                match self.verified_labels(rrset, &zone, &keys) {
+                    Some(labels) => (DnssecStatus::Secure, labels),
+                    None => (DnssecStatus::Bogus, labels),
+                }
+            }
+            Chain::Secure { .. } | Chain::Bogus => (DnssecStatus::Bogus, labels),
+            Chain::Insecure => (DnssecStatus::Insecure, labels),
+        })
+    }
+
+    /// Validate the `NSEC` and `NSEC3` records of the authority section.
+    /// The status is the least secure of theirs.
+    fn denial(&self) -> Result<(DnssecStatus, Denial<'_>), Missing> {
+        let mut status = DnssecStatus::Secure;
+        let mut denial = Denial {
+            nsec: vec![],
+            nsec3: vec![],
+        };
+        for rrset in rrsets(&self.response.authority) {
+            if rrset.rr_type != TYPE_NSEC && rrset.rr_type != TYPE_NSEC3 {
+                continue;
+            }
+            status = status.worst(self.rrset_status(&rrset)?.0);
+            let rdata = &rrset.records[0].rdata[..];
+            if rrset.rr_type == TYPE_NSEC {
+                match name_len(rdata) {
+                    Some(next_len) => denial.nsec.push(Nsec {
+                        next: rdata[..next_len].to_ascii_lowercase(),
+                        bitmap: &rdata[next_len..],
+                        owner: rrset.owner,
+                    }),
+                    None => status = DnssecStatus::Bogus,
+                }
+                continue;
+            }
+            let owner_hash = rrset
+                .owner
+                .first()
+                .and_then(|&len| rrset.owner.get(1..1 + usize::from(len)))
+                .and_then(base32hex_decode);
+            match (owner_hash, parent(&rrset.owner), Nsec3::parse(rdata)) {
+                (Some(owner_hash), Some(zone), Some(nsec3)) => {
+                    denial.nsec3.push((zone.to_vec(), owner_hash, nsec3))
+                }
+                _ => status = DnssecStatus::Bogus,
+            }
+        }
+        Ok((status, denial))
+    }
+
+    /// Build the chain of trust from the closest trust anchor down to the
+    /// closest enclosing zone of `name`.
+    fn chain(&self, name: &[u8]) -> Result<Chain, Missing> {
+        let anchor_zone = match ancestors(name).find(|zone| {
+            self.trust_anchors
+                .iter()
+                .any(|(anchor_zone, _)| anchor_zone == zone)
+        }) {
+            Some(anchor_zone) => anchor_zone,
+            None => return Ok(Chain::Insecure),
+        };
+        let ds_set: Vec<_> = self
+            .trust_anchors
+            .iter()
+            .filter(|(zone, _)| zone == anchor_zone)
+            .map(|(_, ds)| ds.clone())
+            .collect();
+        let (mut zone, mut keys) = match self.zone_keys(anchor_zone, &ds_set)? {
+            Keys::Secure(keys) => (anchor_zone.to_vec(), keys),
+            Keys::Unsupported => return Ok(Chain::Insecure),
+            Keys::Bogus => return Ok(Chain::Bogus),
+        };
+        let mut descendants: Vec<_> = ancestors(name)
+            .take_while(|descendant| descendant.len() > anchor_zone.len())
+            .collect();
+        descendants.reverse();
+        for child in descendants {
+            match self.delegation(child, &zone, &keys)? {
+                Delegation::Secure(ds_set) => match self.zone_keys(child, &ds_set)? {
+                    Keys::Secure(child_keys) => {
+                        zone = child.to_vec();
+                        keys = child_keys;
+                    }
+                    Keys::Unsupported => return Ok(Chain::Insecure),
+                    Keys::Bogus => return Ok(Chain::Bogus),
+                },
+                Delegation::None => {}
+                Delegation::Insecure => return Ok(Chain::Insecure),
+                Delegation::Bogus => return Ok(Chain::Bogus),
+            }
+        }
+        Ok(Chain::Secure { zone, keys })
+    }
+
+    /// Fetch the keys of `zone`, and validate them with `ds_set`.
+    fn zone_keys(&self, zone: &[u8], ds_set: &[Ds]) -> Result<Keys, Missing> {
+        let ds_set: Vec<_> = ds_set.iter().filter(|ds| ds.is_supported()).collect();
+        if ds_set.is_empty() {
+            return Ok(Keys::Unsupported);
+        }
+        let response = match self.fetched(zone, TYPE_DNSKEY)? {
+            Some(response) => response,
+            None => return Ok(Keys::Bogus),
+        };
+        let rrset = match rrsets(&response.answers)
+            .into_iter()
+            .find(|rrset| rrset.owner == zone && rrset.rr_type == TYPE_DNSKEY)
+        {
+            Some(rrset) => rrset,
+            None => return Ok(Keys::Bogus),
+        };
+        let trusted_keys: Vec<_> = rrset
+            .records
+            .iter()
+            .filter(|key| ds_set.iter().any(|ds| ds.matches(zone, &key.rdata)))
+            .map(|key| key.rdata.clone())
+            .collect();
+        if trusted_keys.is_empty() || !self.verify_rrset(&rrset, zone, &trusted_keys) {
+            return Ok(Keys::Bogus);
+        }
+        Ok(Keys::Secure(
+            rrset
+                .records
+                .iter()
+                .filter(|key| {
+                    key.rdata.len() > 4
+                        && u16::from_be_bytes([key.rdata[0], key.rdata[1]]) & DNSKEY_FLAG_ZONE != 0
+                })
+                .map(|key| key.rdata.clone())
+                .collect(),
+        ))
+    }
+
+    /// Fetch the `DS` records of `child`, whose closest enclosing zone is
+    /// `zone`, to find out whether it is a zone cut.
+    fn delegation(
+        &self,
+        child: &[u8],
+        zone: &[u8],
+        keys: &[Vec<u8>],
+    ) -> Result<Delegation, Missing> {
+        let response = match self.fetched(child, TYPE_DS)? {
+            Some(response) => response,
+            None => return Ok(Delegation::Bogus),
+        };
+        let answers = rrsets(&response.answers);
+        if let Some(rrset) = answers
+            .iter()
+            .find(|rrset| rrset.owner == child && rrset.rr_type == TYPE_DS)
+        {
+            if !self.verify_rrset(rrset, zone, keys) {
+                return Ok(Delegation::Bogus);
+            }
+            let ds_set = rrset
+                .records
+                .iter()
+                .filter_map(|rr| Ds::parse(&rr.rdata))
+                .collect();
+            return Ok(Delegation::Secure(ds_set));
+        }
+        if !answers.is_empty() {
+            return Ok(Delegation::None);
+        }
+        let proofs: Vec<_> = rrsets(&response.authority)
+            .into_iter()
+            .filter(|rrset| rrset.rr_type == TYPE_NSEC || rrset.rr_type == TYPE_NSEC3)
+            .collect();
+        if proofs.is_empty()
+            || !proofs
+                .iter()
+                .all(|rrset| self.verify_rrset(rrset, zone, keys))
+        {
+            return Ok(Delegation::Bogus);
+        }
+        for rrset in proofs.iter().filter(|rrset| rrset.rr_type == TYPE_NSEC) {
+            if rrset.owner == child {
+                let rdata = &rrset.records[0].rdata;
+                return Ok(match name_len(rdata) {
+                    Some(next_len) => delegation_from_bitmap(&rdata[next_len..]),
+                    None => Delegation::Bogus,
+                });
+            }
+        }
+        let nsec3_records = proofs
+            .iter()
+            .filter(|rrset| rrset.rr_type == TYPE_NSEC3)
+            .filter_map(|rrset| {
+                let owner_len = usize::from(*rrset.owner.first()?);
+                let owner_hash = base32hex_decode(rrset.owner.get(1..1 + owner_len)?)?;
+                Some((owner_hash, Nsec3::parse(&rrset.records[0].rdata)?))
+            });
+        let mut opt_out = false;
+        for (owner_hash, nsec3) in nsec3_records {
+            if nsec3.iterations > MAX_NSEC3_ITERATIONS {
+                return Ok(Delegation::Insecure);
+            }
+            let hash = nsec3_hash(child, nsec3.salt, nsec3.iterations);
+            if owner_hash == hash {
+                return Ok(delegation_from_bitmap(nsec3.bitmap));
+            }
+            if nsec3.flags & NSEC3_FLAG_OPT_OUT != 0 && nsec3.covers(&owner_hash, &hash) {
+                opt_out = true;
+            }
+        }
+        if opt_out {
+            return Ok(Delegation::Insecure);
+        }
+        Ok(Delegation::None)
+    }
+
+    /// Check that an RRset has a valid signature made by `zone` with one of
+    /// `keys`.
+    fn verify_rrset(&self, rrset: &RRset, zone: &[u8], keys: &[Vec<u8>]) -> bool {
+        self.verified_labels(rrset, zone, keys).is_some()
+    }
+
+    /// The number of labels of the first valid signature of an RRset made by
+    /// `zone` with one of `keys`.
+    fn verified_labels(&self, rrset: &RRset, zone: &[u8], keys: &[Vec<u8>]) -> Option<usize> {
+        rrset
+            .rrsigs
+            .iter()
+            .filter_map(|rrsig| Rrsig::parse(&rrsig.rdata))
+            .filter(|rrsig| {
+                rrsig.type_covered == rrset.rr_type
+                    && rrsig.signer == zone
+                    && usize::from(rrsig.labels) <= label_count(&rrset.owner)
+                    && rrsig.is_current(self.now)
+            })
+            .find(|rrsig| {
+                let signed_data = signed_data(rrset, rrsig);
+                keys.iter()
+                    .filter(|key| key.len() > 4 && key[3] == rrsig.algorithm)
+                    .filter(|key| key_tag(key) == rrsig.key_tag)
+                    .any(|key| {
+                        verify_signature(rrsig.algorithm, &key[4..], &signed_data, rrsig.signature)
+                    })
+            })
+            .map(|rrsig| usize::from(rrsig.labels))
+    }
+}
+
+fn unsigned_status(chain: Chain) -> DnssecStatus {
+    match chain {
+        Chain::Insecure => DnssecStatus::Insecure,
+        Chain::Secure { .. } | Chain::Bogus => DnssecStatus::Bogus,
+    }
+}
+
+/// The name that the CNAME records of the answers lead `qname` to.
+fn cname_target(answers: &[RRset], qname: &[u8], qtype: u16) -> Vec<u8> {
+    let mut sname = qname.to_vec();
+    if qtype == TYPE_CNAME {
+        return sname;
+    }
+    // Every CNAME record is followed at most once, so that loops end
+    for _ in 0..answers.len() {
+        let target = answers
+            .iter()
+            .find(|rrset| rrset.owner == sname && rrset.rr_type == TYPE_CNAME)
+            .and_then(|rrset| {
+                let rdata = &rrset.records[0].rdata;
+                Some(rdata[..name_len(rdata)?].to_ascii_lowercase())
+            });
+        match target {
+            Some(target) => sname = target,
+            None => break,
+        }
+    }
+    sname
+}
+
+fn delegation_from_bitmap(bitmap: &[u8]) -> Delegation {
+    if has_type(bitmap, TYPE_DS) {
+        Delegation::Bogus
+    } else if has_type(bitmap, TYPE_NS) && !has_type(bitmap, TYPE_SOA) {
+        Delegation::Insecure
+    } else {
+        Delegation::None
+    }
+}
+
+/// Whether the type bitmap of the `NSEC` or `NSEC3` record of a name proves
+/// that it has no records of type `rr_type`.
+fn nodata_status(bitmap: &[u8], rr_type: u16) -> DnssecStatus {
+    // The record of a delegation in the parent zone only proves the absence
+    // of `DS` records
+    let delegation = has_type(bitmap, TYPE_NS) && !has_type(bitmap, TYPE_SOA);
+    if has_type(bitmap, rr_type)
+        || has_type(bitmap, TYPE_CNAME)
+        || (delegation && rr_type != TYPE_DS)
+    {
+        DnssecStatus::Bogus
+    } else {
+        DnssecStatus::Secure
+    }
+}
+
+/// The validated `NSEC` and `NSEC3` records of a response.
+struct Denial<'t> {
+    nsec: Vec<Nsec<'t>>,
+    /// The zone, the hash of the owner and the record data.
+    nsec3: Vec<(Vec<u8>, Vec<u8>, Nsec3<'t>)>,
+}
+
+impl Denial<'_> {
+    fn is_empty(&self) -> bool {
+        self.nsec.is_empty() && self.nsec3.is_empty()
+    }
+
+    /// Check that `name` doesn't exist: no record covers it, and no
+    /// wildcard of its closest encloser exists.
+    fn nxdomain(&self, name: &[u8]) -> DnssecStatus {
+        if !self.nsec3.is_empty() {
+            if self.has_too_many_iterations() {
+                return DnssecStatus::Insecure;
+            }
+            return match self.closest_encloser(name) {
+                Some((closest_encloser, next_closer))
+                    if self.nsec3_cover(&wildcard(closest_encloser)).is_some() =>
+                {
+                    // The name may be an unsigned delegation
+                    if next_closer.flags & NSEC3_FLAG_OPT_OUT != 0 {
+                        DnssecStatus::Insecure
+                    } else {
+                        DnssecStatus::Secure
+                    }
+                }
+                _ => DnssecStatus::Bogus,
+            };
+        }
+        match self.nsec_cover(name) {
+            Some(nsec) => {
+                let closest_encloser = nsec.closest_encloser(name);
+                if closest_encloser.len() < name.len()
+                    && self.nsec_cover(&wildcard(closest_encloser)).is_some()
+                {
+                    DnssecStatus::Secure
+                } else {
+                    DnssecStatus::Bogus
+                }
+            }
+            None => DnssecStatus::Bogus,
+        }
+    }
+
+    /// Check that `name` exists, but has no records of type `rr_type`, or
+    /// that the wildcard of its closest encloser has none.
+    fn nodata(&self, name: &[u8], rr_type: u16) -> DnssecStatus {
+        if !self.nsec3.is_empty() {
+            if self.has_too_many_iterations() {
+                return DnssecStatus::Insecure;
+            }
+            if let Some(nsec3) = self.nsec3_match(name) {
+                return nodata_status(nsec3.bitmap, rr_type);
+            }
+            return match self.closest_encloser(name) {
+                Some((_, next_closer))
+                    if rr_type == TYPE_DS && next_closer.flags & NSEC3_FLAG_OPT_OUT != 0 =>
+                {
+                    DnssecStatus::Insecure
+                }
+                Some((closest_encloser, _)) => {
+                    match self.nsec3_match(&wildcard(closest_encloser)) {
+                        Some(nsec3) => nodata_status(nsec3.bitmap, rr_type),
+                        None => DnssecStatus::Bogus,
+                    }
+                }
+                None => DnssecStatus::Bogus,
+            };
+        }
+        if let Some(nsec) = self.nsec.iter().find(|nsec| nsec.owner == name) {
+            return nodata_status(nsec.bitmap, rr_type);
+        }
+        match self.nsec_cover(name) {
+            // An empty non-terminal
+            Some(nsec) if is_subdomain(&nsec.next, name) => DnssecStatus::Secure,
+            Some(nsec) => {
+                let wildcard = wildcard(nsec.closest_encloser(name));
+                match self.nsec.iter().find(|nsec| nsec.owner == wildcard) {
+                    Some(nsec) => nodata_status(nsec.bitmap, rr_type),
+                    None => DnssecStatus::Bogus,
+                }
+            }
+            None => DnssecStatus::Bogus,
+        }
+    }
+
+    /// Check that records of `name` expanded from a wildcard with `labels`
+    /// labels could not have been answered by a closer match.
+    fn no_closer_match(&self, name: &[u8], labels: usize) -> DnssecStatus {
+        if !self.nsec3.is_empty() {
+            if self.has_too_many_iterations() {
+                return DnssecStatus::Insecure;
+            }
+            return match ancestors(name).find(|next_closer| label_count(next_closer) == labels + 1)
+            {
+                Some(next_closer) if self.nsec3_cover(next_closer).is_some() => {
+                    DnssecStatus::Secure
+                }
+                _ => DnssecStatus::Bogus,
+            };
+        }
+        if self.nsec_cover(name).is_some() {
+            DnssecStatus::Secure
+        } else {
+            DnssecStatus::Bogus
+        }
+    }
+
+    fn nsec_cover(&self, name: &[u8]) -> Option<&Nsec<'_>> {
+        self.nsec.iter().find(|nsec| nsec.covers(name))
+    }
+
+    fn has_too_many_iterations(&self) -> bool {
+        self.nsec3
+            .iter()
+            .any(|(.., nsec3)| nsec3.iterations > MAX_NSEC3_ITERATIONS)
+    }
+
+    fn nsec3_match(&self, name: &[u8]) -> Option<&Nsec3<'_>> {
+        self.nsec3
+            .iter()
+            .find(|(zone, owner_hash, nsec3)| {
+                is_subdomain(name, zone)
+                    && *owner_hash == nsec3_hash(name, nsec3.salt, nsec3.iterations)
+            })
+            .map(|(.., nsec3)| nsec3)
+    }
+
+    fn nsec3_cover(&self, name: &[u8]) -> Option<&Nsec3<'_>> {
+        self.nsec3
+            .iter()
+            .find(|(zone, owner_hash, nsec3)| {
+                is_subdomain(name, zone)
+                    && nsec3.covers(owner_hash, &nsec3_hash(name, nsec3.salt, nsec3.iterations))
+            })
+            .map(|(.., nsec3)| nsec3)
+    }
+
+    /// The closest provable encloser of `name`, and the record covering the
+    /// next closer name (RFC 5155 section 8.3).
+    fn closest_encloser<'n>(&self, name: &'n [u8]) -> Option<(&'n [u8], &Nsec3<'_>)> {
+        let mut next_closer = name;
+        for closest_encloser in ancestors(name).skip(1) {
+            if self.nsec3_match(closest_encloser).is_some() {
+                return self
+                    .nsec3_cover(next_closer)
+                    .map(|nsec3| (closest_encloser, nsec3));
+            }
+            next_closer = closest_encloser;
+        }
+        None
+    }
+}
+
+/// An `NSEC` record.
+struct Nsec<'t> {
+    /// The owner name, lowercased.
+    owner: Vec<u8>,
+    /// The next name, lowercased.
+    next: Vec<u8>,
+    bitmap: &'t [u8],
+}
+
+impl Nsec<'_> {
+    /// Whether `name` is between the owner and the next name, in the
+    /// canonical order. The next name of the last record is the apex.
+    fn covers(&self, name: &[u8]) -> bool {
+        let (owner, next, name) = (
+            canonical_labels(&self.owner),
+            canonical_labels(&self.next),
+            canonical_labels(name),
+        );
+        owner < name && (name < next || (next <= owner && name.starts_with(&next)))
+    }
+
+    /// The closest encloser of a name covered by this record: its longest
+    /// ancestor that is also an ancestor of the owner or of the next name.
+    fn closest_encloser<'n>(&self, name: &'n [u8]) -> &'n [u8] {
+        ancestors(name)
+            .find(|&ancestor| {
+                is_subdomain(&self.owner, ancestor) || is_subdomain(&self.next, ancestor)
+            })
+            .unwrap_or(&b"\x00"[..])
+    }
+}
+
+/// Records with the same owner, type and class, and their signatures.
+struct RRset<'t> {
+    owner: Vec<u8>,
+    rr_type: u16,
+    class: u16,
+    records: Vec<&'t Record>,
+    rrsigs: Vec<&'t Record>,
+}
+
+fn rrsets(records: &[Record]) -> Vec<RRset<'_>> {
+    let mut rrsets: Vec<RRset> = vec![];
+    for rr in records.iter().filter(|rr| rr.rr_type != TYPE_RRSIG) {
+        let owner = rr.name.to_ascii_lowercase();
+        match rrsets.iter_mut().find(|rrset| {
+            rrset.owner == owner && rrset.rr_type == rr.rr_type && rrset.class == rr.class
+        }) {
+            Some(rrset) => rrset.records.push(rr),
+            None => rrsets.push(RRset {
+                owner,
+                rr_type: rr.rr_type,
+                class: rr.class,
+                records: vec![rr],
+                rrsigs: vec![],
+            }),
+        }
+    }
+    for rr in records.iter().filter(|rr| rr.rr_type == TYPE_RRSIG) {
+        let owner = rr.name.to_ascii_lowercase();
+        let type_covered = match rr.rdata.get(..2) {
+            Some(x) => u16::from_be_bytes([x[0], x[1]]),
+            None => continue,
+        };
+        if let Some(rrset) = rrsets
+            .iter_mut()
+            .find(|rrset| rrset.owner == owner && rrset.rr_type == type_covered)
+        {
+            rrset.rrsigs.push(rr);
+        }
+    }
+    rrsets
+}
+
+/// An `RRSIG` record.
+struct Rrsig<'t> {
+    type_covered: u16,
+    algorithm: u8,
+    labels: u8,
+    original_ttl: u32,
+    expiration: u32,
+    inception: u32,
+    key_tag: u16,
+    /// The signer name, lowercased.
+    signer: Vec<u8>,
+    /// The fixed part of the record data, that is signed along with the
+    /// records.
+    header: &'t [u8],
+    signature: &'t [u8],
+}
+
+impl<'t> Rrsig<'t> {
+    fn parse(rdata: &'t [u8]) -> Option<Self> {
+        let header = rdata.get(..RRSIG_HEADER_SIZE)?;
+        let u32_at = |offset: usize| {
+            u32::from_be_bytes([
+                header[offset],
+                header[offset + 1],
+                header[offset + 2],
+                header[offset + 3],
+            ])
+        };
+        let signer_len = name_len(&rdata[RRSIG_HEADER_SIZE..])?;
+        let signer = &rdata[RRSIG_HEADER_SIZE..RRSIG_HEADER_SIZE + signer_len];
+        Some(Rrsig {
+            type_covered: u16::from_be_bytes([header[0], header[1]]),
+            algorithm: header[2],
+            labels: header[3],
+            original_ttl: u32_at(4),
+            expiration: u32_at(8),
+            inception: u32_at(12),
+            key_tag: u16::from_be_bytes([header[16], header[17]]),
+            signer: signer.to_ascii_lowercase(),
+            header,
+            signature: &rdata[RRSIG_HEADER_SIZE + signer_len..],
+        })
+    }
+
+    /// Whether `now` is within the validity period of the signature, using
+    /// serial number arithmetic (RFC 1982).
+    fn is_current(&self, now: u32) -> bool {
+        (now.wrapping_sub(self.inception) as i32) >= 0
+            && (self.expiration.wrapping_sub(now) as i32) >= 0
+    }
+}
+
+/// A `DS` record.
+#[derive(Clone, Debug)]
+struct Ds {
+    key_tag: u16,
+    algorithm: u8,
+    digest_type: u8,
+    digest: Vec<u8>,
+}
+
+impl Ds {
+    fn parse(rdata: &[u8]) -> Option<Self> {
+        Some(Ds {
+            key_tag: u16::from_be_bytes([*rdata.first()?, *rdata.get(1)?]),
+            algorithm: *rdata.get(2)?,
+            digest_type: *rdata.get(3)?,
+            digest: rdata[4..].to_vec(),
+        })
+    }
+
+    fn digest_algorithm(&self) -> Option<&'static digest::Algorithm> {
+        match self.digest_type {
+            1 => Some(&digest::SHA1_FOR_LEGACY_USE_ONLY),
+            2 => Some(&digest::SHA256),
+            4 => Some(&digest::SHA384),
+            _ => None,
+        }
+    }
+
+    fn is_supported(&self) -> bool {
+        self.digest_algorithm().is_some() && SUPPORTED_ALGORITHMS.contains(&self.algorithm)
+    }
+
+    /// Whether this is the digest of the key `dnskey` of `owner`.
+    fn matches(&self, owner: &[u8], dnskey: &[u8]) -> bool {
+        let digest_algorithm = match self.digest_algorithm() {
+            Some(digest_algorithm) => digest_algorithm,
+            None => return false,
+        };
+        if dnskey.len() <= 4 || dnskey[3] != self.algorithm || key_tag(dnskey) != self.key_tag {
+            return false;
+        }
+        let mut ctx = digest::Context::new(digest_algorithm);
+        ctx.update(owner);
+        ctx.update(dnskey);
+        ctx.finish().as_ref() == &self.digest[..]
+    }
+}
+
+/// An `NSEC3` record using SHA-1.
+struct Nsec3<'t> {
+    flags: u8,
+    iterations: u16,
+    salt: &'t [u8],
+    next_hash: &'t [u8],
+    bitmap: &'t [u8],
+}
+
+impl<'t> Nsec3<'t> {
+    fn parse(rdata: &'t [u8]) -> Option<Self> {
+        if *rdata.first()? != 1 {
+            return None;
+        }
+        let salt_len = usize::from(*rdata.get(4)?);
+        let salt = rdata.get(5..5 + salt_len)?;
+        let hash_len = usize::from(*rdata.get(5 + salt_len)?);
+        let next_hash = rdata.get(6 + salt_len..6 + salt_len + hash_len)?;
+        Some(Nsec3 {
+            flags: rdata[1],
+            iterations: u16::from_be_bytes([rdata[2], rdata[3]]),
+            salt,
+            next_hash,
+            bitmap: &rdata[6 + salt_len + hash_len..],
+        })
+    }
+
+    /// Whether `hash` is between the hash of the owner and the next hash.
+    fn covers(&self, owner_hash: &[u8], hash: &[u8]) -> bool {
+        if owner_hash < self.next_hash {
+            owner_hash < hash && hash < self.next_hash
+        } else {
+            hash > owner_hash || hash < self.next_hash
+        }
+    }
+}
+
+/// The data covered by a signature: the fixed part of the `RRSIG` record,
+/// followed by the records in canonical form and order (RFC 4034 section
+/// 6).
+fn signed_data(rrset: &RRset, rrsig: &Rrsig) -> Vec<u8> {
+    let mut data = rrsig.header.to_vec();
+    data.extend_from_slice(&rrsig.signer);
+    let mut owner = &rrset.owner[..];
+    while label_count(owner) > usize::from(rrsig.labels) {
+        owner = parent(owner).unwrap_or(owner);
+    }
+    let owner = if owner.len() < rrset.owner.len() {
+        [&b"\x01*"[..], owner].concat()
+    } else {
+        owner.to_vec()
+    };
+    let mut rdatas: Vec<_> = rrset
+        .records
+        .iter()
+        .map(|rr| canonical_rdata(rr.rr_type, &rr.rdata))
+        .collect();
+    rdatas.sort();
+    rdatas.dedup();
+    for rdata in rdatas {
+        data.extend_from_slice(&owner);
+        data.extend_from_slice(&rrset.rr_type.to_be_bytes());
+        data.extend_from_slice(&rrset.class.to_be_bytes());
+        data.extend_from_slice(&rrsig.original_ttl.to_be_bytes());
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend_from_slice(&rdata);
+    }
+    data
+}
+
+/// Record data with the names it contains lowercased.
+fn canonical_rdata(rr_type: u16, rdata: &[u8]) -> Vec<u8> {
+    let (mut offset, names) = match rr_type {
+        TYPE_NS | TYPE_CNAME | TYPE_PTR | TYPE_DNAME => (0, 1),
+        TYPE_SOA => (0, 2),
+        TYPE_MX => (2, 1),
+        TYPE_SRV => (6, 1),
+        _ => return rdata.to_vec(),
+    };
+    let mut rdata = rdata.to_vec();
+    for _ in 0..names {
+        let len = match rdata.get(offset..).and_then(name_len) {
+            Some(len) => len,
+            None => break,
+        };
+        rdata[offset..offset + len].make_ascii_lowercase();
+        offset += len;
+    }
+    rdata
+}
+
+fn verify_signature(algorithm: u8, public_key: &[u8], data: &[u8], sig: &[u8]) -> bool {
+    match algorithm {
+        5 | 7 | 8 | 10 => {
+            let params = match algorithm {
+                5 | 7 => &signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY,
+                8 => &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                _ => &signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY,
+            };
+            // The exponent length is one byte, or zero followed by two bytes
+            // (RFC 3110)
+            let (e_len, offset) = match public_key {
+                [0, hi, lo, ..] => (usize::from(*hi) << 8 | usize::from(*lo), 3),
+                [len, ..] => (usize::from(*len), 1),
+                [] => return false,
+            };
+            let (e, n) = match public_key.get(offset..offset + e_len) {
+                Some(e) => (e, &public_key[offset + e_len..]),
+                None => return false,
+            };
+            RsaPublicKeyComponents { n, e }
+                .verify(params, data, sig)
+                .is_ok()
+        }
+        13 | 14 => {
+            let params = match algorithm {
+                13 => &signature::ECDSA_P256_SHA256_FIXED,
+                _ => &signature::ECDSA_P384_SHA384_FIXED,
+            };
+            let public_key = [&[4u8][..], public_key].concat();
+            UnparsedPublicKey::new(params, public_key)
+                .verify(data, sig)
+                .is_ok()
+        }
+        15 => UnparsedPublicKey::new(&signature::ED25519, public_key)
+            .verify(data, sig)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+/// The key tag of a `DNSKEY` record (RFC 4034 appendix B).
+fn key_tag(dnskey: &[u8]) -> u16 {
+    let mut ac: u32 = 0;
+    for (i, &x) in dnskey.iter().enumerate() {
+        ac += if i & 1 == 0 {
+            u32::from(x) << 8
+        } else {
+            u32::from(x)
+        };
+    }
+    ac += (ac >> 16) & 0xffff;
+    (ac & 0xffff) as u16
+}
+
+/// The hash of a name in `NSEC3` records (RFC 5155 section 5).
+fn nsec3_hash(name: &[u8], salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut hash = name.to_vec();
+    for _ in 0..=iterations {
+        let mut ctx = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        ctx.update(&hash);
+        ctx.update(salt);
+        hash = ctx.finish().as_ref().to_vec();
+    }
+    hash
+}
+
+fn base32hex_decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+    let (mut acc, mut bits) = (0u32, 0);
+    for &c in encoded {
+        let value = match c.to_ascii_lowercase() {
+            c @ b'0'..=b'9' => c - b'0',
+            c @ b'a'..=b'v' => c - b'a' + 10,
+            _ => return None,
+        };
+        acc = acc << 5 | u32::from(value);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(decoded)
+}
+
+/// Whether the type bitmap of an `NSEC` or `NSEC3` record includes
+/// `rr_type`.
+fn has_type(mut bitmap: &[u8], rr_type: u16) -> bool {
+    let (window, bit) = ((rr_type >> 8) as u8, usize::from(rr_type as u8));
+    while let [block, len, rest @ ..] = bitmap {
+        let len = usize::from(*len);
+        let bits = match rest.get(..len) {
+            Some(bits) => bits,
+            None => return false,
+        };
+        if *block == window {
+            return bits
+                .get(bit / 8)
+                .is_some_and(|x| x & (0x80 >> (bit % 8)) != 0);
+        }
+        bitmap = &rest[len..];
+    }
+    false
+}
+
+/// The length of an uncompressed name at the start of `data`.
+fn name_len(data: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    loop {
+        let len = usize::from(*data.get(offset)?);
+        if len > 63 {
+            return None;
+        }
+        offset += 1 + len;
+        if len == 0 {
+            return (offset <= data.len()).then_some(offset);
+        }
+    }
+}
+
+fn parent(name: &[u8]) -> Option<&[u8]> {
+    match name.first() {
+        Some(&len) if len > 0 => name.get(1 + usize::from(len)..),
+        _ => None,
+    }
+}
+
+/// `name` followed by its ancestors, up to the root.
+fn ancestors(name: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::successors(Some(name), |&name| parent(name))
+}
+
+fn label_count(name: &[u8]) -> usize {
+    ancestors(name).count() - 1
+}
+
+fn is_subdomain(name: &[u8], zone: &[u8]) -> bool {
+    ancestors(name).any(|ancestor| ancestor == zone)
+}
+
+/// The lowercased labels of a name, starting from the root, that sort names
+/// in the canonical order (RFC 4034 section 6.1).
+fn canonical_labels(name: &[u8]) -> Vec<Vec<u8>> {
+    let mut labels: Vec<_> = ancestors(name)
+        .filter_map(|name| {
+            let len = usize::from(*name.first()?);
+            name.get(1..1 + len).filter(|_| len > 0)
+        })
+        .map(<[u8]>::to_ascii_lowercase)
+        .collect();
+    labels.reverse();
+    labels
+}
+
+/// The wildcard name `*.name`.
+fn wildcard(name: &[u8]) -> Vec<u8> {
+    [&b"\x01*"[..], name].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::*;
+
+    const NOW: u32 = 1_700_000_000;
+    const A: u16 = 1;
+
+    fn record(name: &[u8], rr_type: u16, rdata: &[u8]) -> Record {
+        Record {
+            name: name.to_vec(),
+            rr_type,
+            class: 1,
+            ttl: 3600,
+            rdata: rdata.to_vec(),
+        }
+    }
+
+    fn message(name: &[u8], rr_type: u16, answers: Vec<Record>) -> Message {
+        Message {
+            id: 0,
+            flags: 0x8180,
+            question: Some((name.to_vec(), rr_type, 1)),
+            answers,
+            authority: vec![],
+            additional: vec![],
+        }
+    }
+
+    /// Sign the records of `name` with `key_pair`, as `zone`.
+    fn sign(key_pair: &Ed25519KeyPair, zone: &[u8], records: &[Record]) -> Record {
+        let dnskey = dnskey(key_pair);
+        let name = &records[0].name;
+        // The wildcard label isn't counted
+        let labels = label_count(name) - usize::from(name.starts_with(b"\x01*"));
+        let mut rdata = records[0].rr_type.to_be_bytes().to_vec();
+        rdata.extend_from_slice(&[15, labels as u8]);
+        rdata.extend_from_slice(&3600u32.to_be_bytes());
+        rdata.extend_from_slice(&(NOW + 86400).to_be_bytes());
+        rdata.extend_from_slice(&(NOW - 86400).to_be_bytes());
+        rdata.extend_from_slice(&key_tag(&dnskey).to_be_bytes());
+        rdata.extend_from_slice(zone);
+        let rrset = &rrsets(records)[0];
+        let signature = key_pair.sign(&signed_data(rrset, &Rrsig::parse(&rdata).unwrap()));
+        rdata.extend_from_slice(signature.as_ref());
+        record(&records[0].name, TYPE_RRSIG, &rdata)
+    }
+
+    fn dnskey(key_pair: &Ed25519KeyPair) -> Vec<u8> {
+        [&[1, 1, 3, 15][..], key_pair.public_key().as_ref()].concat()
+    }
+
+    /// The `example` zone, signed with a single key that is also its trust
+    /// anchor.
+    struct Zone {
+        key_pair: Ed25519KeyPair,
+        trust_anchors: Vec<TrustAnchor>,
+        dnskey_response: Message,
+    }
+
+    impl Zone {
+        const APEX: &'static [u8] = b"\x07example\x00";
+
+        fn new() -> Self {
+            let key_pair = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+            let dnskey = dnskey(&key_pair);
+            let mut ctx = digest::Context::new(&digest::SHA256);
+            ctx.update(Self::APEX);
+            ctx.update(&dnskey);
+            let trust_anchors = vec![TrustAnchor {
+                zone: "example".to_string(),
+                key_tag: key_tag(&dnskey),
+                algorithm: 15,
+                digest_type: 2,
+                digest: ctx.finish().as_ref().to_vec(),
+            }];
+            let dnskeys = vec![record(Self::APEX, TYPE_DNSKEY, &dnskey)];
+            let dnskey_rrsig = sign(&key_pair, Self::APEX, &dnskeys);
+            let dnskey_response = message(
+                Self::APEX,
+                TYPE_DNSKEY,
+                [dnskeys, vec![dnskey_rrsig]].concat(),
+            );
+            Zone {
+                key_pair,
+                trust_anchors,
+                dnskey_response,
+            }
+        }
+
+        /// The records, followed by their signature.
+        fn signed(&self, records: Vec<Record>) -> Vec<Record> {
+            let rrsig = sign(&self.key_pair, Self::APEX, &records);
+            [records, vec![rrsig]].concat()
+        }
+
+        /// A signed `NSEC` record of `owner`.
+        fn nsec(&self, owner: &[u8], next: &[u8], types: &[u16]) -> Vec<Record> {
+            let mut bitmap = [0u8; 32];
+            for &rr_type in types {
+                bitmap[usize::from(rr_type) / 8] |= 0x80 >> (rr_type % 8);
+            }
+            let len = bitmap.iter().rposition(|&x| x != 0).unwrap() + 1;
+            let rdata = [next, &[0, len as u8][..], &bitmap[..len]].concat();
+            self.signed(vec![record(owner, TYPE_NSEC, &rdata)])
+        }
+
+        /// Validate `response`, when only the keys of the zone can be
+        /// fetched.
+        fn validate(&self, response: Message, now: u32) -> DnssecStatus {
+            let mut validator = Validator::with_time(&self.trust_anchors, response, now);
+            assert_eq!(
+                validator.next(),
+                Validation::Fetch("example".to_string(), TYPE_DNSKEY)
+            );
+            validator.add_response(Some(self.dnskey_response.clone()));
+            loop {
+                match validator.next() {
+                    Validation::Done(status) => return status,
+                    Validation::Fetch(..) => validator.add_response(None),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let zone = Zone::new();
+        let validate =
+            |answers: Vec<Record>, now: u32| zone.validate(message(Zone::APEX, A, answers), now);
+        let answers = vec![record(Zone::APEX, A, &[192, 0, 2, 1])];
+        let rrsig = sign(&zone.key_pair, Zone::APEX, &answers);
+        assert_eq!(
+            validate([answers.clone(), vec![rrsig.clone()]].concat(), NOW),
+            DnssecStatus::Secure
+        );
+        assert_eq!(
+            validate(
+                [answers.clone(), vec![rrsig.clone()]].concat(),
+                NOW + 2 * 86400
+            ),
+            DnssecStatus::Bogus
+        );
+        let forged = vec![record(Zone::APEX, A, &[192, 0, 2, 2])];
+        assert_eq!(
+            validate([forged, vec![rrsig]].concat(), NOW),
+            DnssecStatus::Bogus
+        );
+        assert_eq!(validate(answers, NOW), DnssecStatus::Bogus);
+
+        let other = b"\x05other\x00";
+        let mut validator = Validator::with_time(
+            &zone.trust_anchors,
+            message(other, A, vec![record(other, A, &[192, 0, 2, 1])]),
+            NOW,
+        );
+        assert_eq!(validator.next(), Validation::Done(DnssecStatus::Insecure));
+    }
+
+    #[test]
+    fn test_validate_chain() {
+        let zone = Zone::new();
+        let mut response = message(
+            Zone::APEX,
+            A,
+            zone.signed(vec![record(Zone::APEX, A, &[192, 0, 2, 1])]),
+        );
+        response.additional = zone.dnskey_response.answers.clone();
+        let mut validator = Validator::with_time(&zone.trust_anchors, response, NOW);
+        assert_eq!(validator.next(), Validation::Done(DnssecStatus::Secure));
+    }
+
+    #[test]
+    fn test_validate_denial() {
+        const AAAA: u16 = 28;
+        let zone = Zone::new();
+        let (a, b, c) = (
+            b"\x01a\x07example\x00",
+            b"\x01b\x07example\x00",
+            b"\x01c\x07example\x00",
+        );
+        let apex_nsec = zone.nsec(
+            Zone::APEX,
+            a,
+            &[TYPE_NS, TYPE_SOA, TYPE_RRSIG, TYPE_NSEC, TYPE_DNSKEY],
+        );
+        let a_nsec = zone.nsec(a, c, &[A, TYPE_RRSIG, TYPE_NSEC]);
+        let negative = |name: &[u8], rr_type: u16, rcode: u16, authority: Vec<Record>| {
+            let mut response = message(name, rr_type, vec![]);
+            response.flags |= rcode;
+            response.authority = authority;
+            zone.validate(response, NOW)
+        };
+
+        // NXDOMAIN: `b` is between `a` and `c`, and `*.example` between the
+        // apex and `a`
+        let proofs = [apex_nsec.clone(), a_nsec.clone()].concat();
+        assert_eq!(negative(b, A, 3, proofs.clone()), DnssecStatus::Secure);
+        assert_eq!(
+            negative(b, A, 3, a_nsec.clone()),
+            DnssecStatus::Bogus,
+            "A wildcard may exist"
+        );
+        assert_eq!(
+            negative(b"\x01d\x07example\x00", A, 3, proofs),
+            DnssecStatus::Bogus,
+            "The NSEC record doesn't cover the name"
+        );
+        assert_eq!(negative(b, A, 3, vec![]), DnssecStatus::Bogus);
+
+        // NODATA: `a` only has A records
+        assert_eq!(negative(a, AAAA, 0, a_nsec.clone()), DnssecStatus::Secure);
+        assert_eq!(negative(a, A, 0, a_nsec.clone()), DnssecStatus::Bogus);
+        assert_eq!(negative(b, AAAA, 0, a_nsec), DnssecStatus::Bogus);
+    }
+
+    #[test]
+    fn test_validate_wildcard() {
+        let zone = Zone::new();
+        let www = b"\x03www\x07example\x00";
+        let wildcard_answers =
+            zone.signed(vec![record(b"\x01*\x07example\x00", A, &[192, 0, 2, 1])]);
+        let answers: Vec<_> = wildcard_answers
+            .into_iter()
+            .map(|rr| Record {
+                name: www.to_vec(),
+                ..rr
+            })
+            .collect();
+        // `www` is after `c`, the last name of the zone
+        let c_nsec = zone.nsec(
+            b"\x01c\x07example\x00",
+            Zone::APEX,
+            &[A, TYPE_RRSIG, TYPE_NSEC],
+        );
+        let mut response = message(www, A, answers);
+        assert_eq!(
+            zone.validate(response.clone(), NOW),
+            DnssecStatus::Bogus,
+            "A closer match may exist"
+        );
+        response.authority = c_nsec;
+        assert_eq!(zone.validate(response, NOW), DnssecStatus::Secure);
+    }
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(key_tag(&[1, 1, 3, 15, 0xab, 0xcd]), 0xafdd);
+        assert_eq!(label_count(b"\x03www\x07example\x00"), 2);
+        assert_eq!(label_count(b"\x00"), 0);
+        assert!(is_subdomain(b"\x03www\x07example\x00", b"\x07example\x00"));
+        assert!(!is_subdomain(b"\x03www\x07example\x00", b"\x05ample\x00"));
+        assert_eq!(base32hex_decode(b"cpnmuoj1e8"), Some(b"foobar".to_vec()));
+        // Window 0: A, NS, SOA
+        let bitmap = [0, 1, 0x62];
+        assert!(has_type(&bitmap, TYPE_NS));
+        assert!(has_type(&bitmap, TYPE_SOA));
+        assert!(!has_type(&bitmap, TYPE_DS));
+        assert_eq!(TrustAnchor::root()[0].digest.len(), 32);
+    }
+}
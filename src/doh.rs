@@ -0,0 +1,476 @@
+//! DNS-over-HTTPS (RFC 8484).
+//!
+//! Every query is POSTed as `application/dns-message` over HTTP/1.1, within
+//! a new TLS session that is closed after the response.
+
+use std::io;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use dnssector::constants::DNS_MAX_COMPRESSED_SIZE;
+use rustls::ClientConfig;
+
+use crate::backend::sync::StdBackend;
+use crate::backend::RuntimeAdapter;
+use crate::tls;
+use crate::upstream_server::{url_host, Transport, UpstreamServer};
+
+const CONTENT_TYPE: &str = "application/dns-message";
+
+/// The maximum size of the status line and the headers of a response.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// The configuration shared by all the DNS-over-HTTPS sessions.
+pub(crate) fn client_config() -> Arc<ClientConfig> {
+    static CLIENT_CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CLIENT_CONFIG
+        .get_or_init(|| with_alpn(&tls::client_config()))
+        .clone()
+}
+
+fn with_alpn(config: &ClientConfig) -> Arc<ClientConfig> {
+    let mut config = config.clone();
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Arc::new(config)
+}
+
+fn invalid_response(reason: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.into())
+}
+
+/// An HTTP/1.1 request to `upstream_server`, closing the connection after
+/// the response. Requests with a body are POST requests.
+pub(crate) fn http_request(
+    upstream_server: &UpstreamServer,
+    path: &str,
+    content_type: &str,
+    body: Option<&[u8]>,
+) -> Result<Vec<u8>, io::Error> {
+    let server_name = upstream_server.tls_server_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("[{}] is not an HTTPS server", upstream_server),
+        )
+    })?;
+    let host = match upstream_server.addr.port() {
+        443 => url_host(server_name),
+        port => format!("{}:{}", url_host(server_name), port),
+    };
+    let mut request = match body {
+        Some(body) => format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nAccept: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            path,
+            host,
+            content_type,
+            content_type,
+            body.len()
+        ),
+        None => format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nAccept: {}\r\nConnection: close\r\n\r\n",
+            path, host, content_type
+        ),
+    }
+    .into_bytes();
+    request.extend_from_slice(body.unwrap_or_default());
+    Ok(request)
+}
+
+/// The HTTP request carrying `query`.
+fn request(upstream_server: &UpstreamServer, query: &[u8]) -> Result<Vec<u8>, io::Error> {
+    match &upstream_server.transport {
+        Transport::Https { path, .. } => {
+            http_request(upstream_server, path, CONTENT_TYPE, Some(query))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("[{}] is not a DNS-over-HTTPS server", upstream_server),
+        )),
+    }
+}
+
+/// Decode a chunked body, returning `None` until the last chunk was
+/// received.
+fn dechunk(mut body: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut decoded = vec![];
+    loop {
+        let line_end = match body.windows(2).position(|w| w == b"\r\n") {
+            Some(line_end) => line_end,
+            None => return Ok(None),
+        };
+        let size_line = std::str::from_utf8(&body[..line_end])
+            .map_err(|_| invalid_response("Invalid chunk size"))?;
+        let size = size_line.split(';').next().unwrap_or_default().trim();
+        let size =
+            usize::from_str_radix(size, 16).map_err(|_| invalid_response("Invalid chunk size"))?;
+        if size == 0 {
+            return Ok(Some(decoded));
+        }
+        // Checked before any arithmetic, so that huge sizes can't overflow
+        match decoded.len().checked_add(size) {
+            Some(len) if size <= DNS_MAX_COMPRESSED_SIZE && len <= DNS_MAX_COMPRESSED_SIZE => {}
+            _ => return Err(invalid_response("Response too large")),
+        }
+        body = &body[line_end + 2..];
+        match body.get(..size + 2) {
+            Some(chunk) => decoded.extend_from_slice(&chunk[..size]),
+            None => return Ok(None),
+        }
+        body = &body[size + 2..];
+    }
+}
+
+/// Return the body once `plaintext` contains a complete HTTP response, whose
+/// content type must be `content_type`, unless it is `None`.
+pub(crate) fn read_response(
+    plaintext: &[u8],
+    eof: bool,
+    content_type: Option<&str>,
+) -> Result<Option<Vec<u8>>, io::Error> {
+    let expected_content_type = content_type;
+    let head_len = match plaintext.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(head_len) => head_len,
+        None if plaintext.len() > MAX_HEAD_SIZE => {
+            return Err(invalid_response("HTTP response headers too large"))
+        }
+        None => return Ok(None),
+    };
+    let head = std::str::from_utf8(&plaintext[..head_len])
+        .map_err(|_| invalid_response("Invalid HTTP response headers"))?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| invalid_response("Invalid HTTP status line"))?;
+    if status != 200 {
+        return Err(invalid_response(format!("HTTP status {}", status)));
+    }
+    let (mut content_type, mut content_length, mut chunked) = (None, None, false);
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => continue,
+        };
+        if name.eq_ignore_ascii_case("content-type") {
+            content_type = value.split(';').next().map(str::trim);
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| invalid_response("Invalid Content-Length"))?,
+            );
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.to_ascii_lowercase().contains("chunked");
+        }
+    }
+    if let Some(expected_content_type) = expected_content_type {
+        if !content_type
+            .is_some_and(|content_type| content_type.eq_ignore_ascii_case(expected_content_type))
+        {
+            return Err(invalid_response(format!(
+                "Unexpected content type: {}",
+                content_type.unwrap_or("none")
+            )));
+        }
+    }
+    let body = &plaintext[head_len + 4..];
+    if chunked {
+        return dechunk(body);
+    }
+    match content_length {
+        Some(content_length) if content_length > DNS_MAX_COMPRESSED_SIZE => {
+            Err(invalid_response("Response too large"))
+        }
+        Some(content_length) => Ok(body.get(..content_length).map(<[u8]>::to_vec)),
+        None if body.len() > DNS_MAX_COMPRESSED_SIZE => Err(invalid_response("Response too large")),
+        None if eof => Ok(Some(body.to_vec())),
+        None => Ok(None),
+    }
+}
+
+/// Exchange a query with a DNS-over-HTTPS server, using blocking sockets.
+pub(crate) fn exchange_blocking(
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    query: &[u8],
+    backend: &StdBackend,
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let request = request(upstream_server, query)?;
+    fetch_blocking(
+        config,
+        upstream_server,
+        &request,
+        Some(CONTENT_TYPE),
+        backend,
+        timeout,
+    )
+}
+
+/// Send an HTTP request, using blocking sockets, and return the body of the
+/// response.
+pub(crate) fn fetch_blocking(
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    request: &[u8],
+    content_type: Option<&str>,
+    backend: &StdBackend,
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    tls::session_blocking(
+        config,
+        upstream_server,
+        request,
+        |plaintext, eof| read_response(plaintext, eof, content_type),
+        backend,
+        timeout,
+    )
+}
+
+/// Exchange a query with a DNS-over-HTTPS server, over a TCP stream of
+/// `runtime`. The caller is responsible for the timeout.
+pub(crate) async fn exchange<R: RuntimeAdapter>(
+    runtime: &R,
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    query: &[u8],
+) -> io::Result<Vec<u8>> {
+    let request = request(upstream_server, query)?;
+    fetch(
+        runtime,
+        config,
+        upstream_server,
+        &request,
+        Some(CONTENT_TYPE),
+    )
+    .await
+}
+
+/// Send an HTTP request, over a TCP stream of `runtime`, and return the body
+/// of the response. The caller is responsible for the timeout.
+pub(crate) async fn fetch<R: RuntimeAdapter>(
+    runtime: &R,
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    request: &[u8],
+    content_type: Option<&str>,
+) -> io::Result<Vec<u8>> {
+    tls::session(
+        runtime,
+        config,
+        upstream_server,
+        request,
+        |plaintext, eof| read_response(plaintext, eof, content_type),
+    )
+    .await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{Read, Write};
+    use std::net::SocketAddr;
+    use std::thread;
+
+    use super::*;
+
+    pub(crate) fn client_config() -> Arc<ClientConfig> {
+        with_alpn(&tls::tests::client_config())
+    }
+
+    /// Start a DNS-over-HTTPS server answering a single request with
+    /// `response`, a raw HTTP response, and return its address. The server
+    /// returns the request.
+    pub(crate) fn serve_once(
+        response: Vec<u8>,
+    ) -> (SocketAddr, thread::JoinHandle<io::Result<Vec<u8>>>) {
+        tls::tests::serve(move |stream| {
+            let mut request = vec![];
+            let mut buf = [0u8; 1024];
+            let body_len = loop {
+                let len = stream.read(&mut buf)?;
+                if len == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                request.extend_from_slice(&buf[..len]);
+                if let Some(head_len) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&request[..head_len]).to_string();
+                    let content_length = head
+                        .split("\r\n")
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .and_then(|len| len.parse::<usize>().ok())
+                        .unwrap_or(0);
+                    break head_len + 4 + content_length;
+                }
+            };
+            while request.len() < body_len {
+                let len = stream.read(&mut buf)?;
+                request.extend_from_slice(&buf[..len]);
+            }
+            stream.write_all(&response)?;
+            stream.flush()?;
+            stream.conn.send_close_notify();
+            stream.flush()?;
+            Ok(request)
+        })
+    }
+
+    /// A successful HTTP response with `body`.
+    pub(crate) fn http_response(body: &[u8]) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    #[test]
+    fn test_read_response() {
+        assert_eq!(
+            read_response(&http_response(b"response"), false, Some(CONTENT_TYPE)).unwrap(),
+            Some(b"response".to_vec())
+        );
+        let response = http_response(b"response");
+        assert_eq!(
+            read_response(&response[..response.len() - 1], false, Some(CONTENT_TYPE)).unwrap(),
+            None
+        );
+        let chunked = b"HTTP/1.1 200 OK\r\ncontent-type: Application/DNS-Message\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nresp\r\n4;ext\r\nonse\r\n0\r\n\r\n";
+        assert_eq!(
+            read_response(chunked, false, Some(CONTENT_TYPE)).unwrap(),
+            Some(b"response".to_vec())
+        );
+        assert_eq!(
+            read_response(&chunked[..chunked.len() - 6], false, Some(CONTENT_TYPE)).unwrap(),
+            None
+        );
+        let unframed = b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\n\r\nresponse";
+        assert_eq!(
+            read_response(unframed, false, Some(CONTENT_TYPE)).unwrap(),
+            None
+        );
+        assert_eq!(
+            read_response(unframed, true, Some(CONTENT_TYPE)).unwrap(),
+            Some(b"response".to_vec())
+        );
+        assert!(read_response(
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+            false,
+            Some(CONTENT_TYPE)
+        )
+        .is_err());
+        assert!(read_response(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 0\r\n\r\n",
+            false,
+            Some(CONTENT_TYPE)
+        )
+        .is_err());
+        let html = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 4\r\n\r\nbody";
+        assert_eq!(
+            read_response(html, false, None).unwrap(),
+            Some(b"body".to_vec())
+        );
+        assert!(read_response(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: 100000\r\n\r\n",
+            false,
+            Some(CONTENT_TYPE)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_dechunk() {
+        assert_eq!(
+            dechunk(b"4\r\nresp\r\n0\r\n\r\n").unwrap(),
+            Some(b"resp".to_vec())
+        );
+        for huge in &[&b"ffffffffffffffff\r\nresp\r\n"[..], b"10000\r\nresp\r\n"] {
+            assert_eq!(
+                dechunk(huge).unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+        let mut body = b"800\r\n".to_vec();
+        body.extend_from_slice(&[0; 0x800]);
+        body.extend_from_slice(b"\r\n801\r\n");
+        assert_eq!(
+            dechunk(&body).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_exchange_blocking() {
+        let (addr, server) = serve_once(http_response(b"response"));
+        let upstream_server =
+            UpstreamServer::new_doh_with_addr(addr, "https://dns.test/dns-query").unwrap();
+        let response = exchange_blocking(
+            client_config(),
+            &upstream_server,
+            b"query",
+            &StdBackend::default(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(response, b"response");
+        let request = server.join().unwrap().unwrap();
+        let expected = format!(
+            "POST /dns-query HTTP/1.1\r\nHost: dns.test:{}\r\nContent-Type: application/dns-message\r\nAccept: application/dns-message\r\nContent-Length: 5\r\nConnection: close\r\n\r\nquery",
+            addr.port()
+        );
+        assert_eq!(String::from_utf8(request).unwrap(), expected);
+
+        // The response body is read until the end of the session
+        let (addr, _server) = serve_once(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\n\r\nresponse".to_vec(),
+        );
+        let upstream_server =
+            UpstreamServer::new_doh_with_addr(addr, "https://dns.test/dns-query").unwrap();
+        let response = exchange_blocking(
+            client_config(),
+            &upstream_server,
+            b"query",
+            &StdBackend::default(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(response, b"response");
+
+        // HTTP errors are returned
+        let (addr, _server) =
+            serve_once(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n".to_vec());
+        let upstream_server =
+            UpstreamServer::new_doh_with_addr(addr, "https://dns.test/dns-query").unwrap();
+        let err = exchange_blocking(
+            client_config(),
+            &upstream_server,
+            b"query",
+            &StdBackend::default(),
+            Duration::from_secs(5),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("500"));
+    }
+
+    #[cfg(feature = "async-io")]
+    #[test]
+    fn test_exchange() {
+        use crate::backend::async_io::AsyncIoBackend;
+
+        let (addr, server) = serve_once(http_response(&[0x42; 1000]));
+        let upstream_server =
+            UpstreamServer::new_doh_with_addr(addr, "https://dns.test/resolve?ct").unwrap();
+        let response = ::async_io::block_on(exchange(
+            &AsyncIoBackend::default(),
+            client_config(),
+            &upstream_server,
+            b"query",
+        ))
+        .unwrap();
+        assert_eq!(response, vec![0x42; 1000]);
+        let request = server.join().unwrap().unwrap();
+        assert!(request.starts_with(b"POST /resolve?ct HTTP/1.1\r\n"));
+        assert!(request.ends_with(b"\r\n\r\nquery"));
+    }
+}
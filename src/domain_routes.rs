@@ -0,0 +1,110 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::idn;
+use crate::packet;
+use crate::query_options::QueryOptions;
+use crate::upstream_server::UpstreamServer;
+
+/// Upstream servers dedicated to specific domains, such as internal domains
+/// that can only be resolved through a VPN (split DNS).
+///
+/// Queries for a domain and the names under it are sent to the servers of
+/// that domain instead of the upstream servers of the client. The most
+/// specific domain takes precedence.
+///
+/// ```
+/// use dnsclient::{DomainRoutes, UpstreamServer};
+///
+/// let mut domain_routes = DomainRoutes::new();
+/// domain_routes.add("corp.example", vec![UpstreamServer::new(([10, 0, 0, 53], 53))]);
+/// assert!(domain_routes.upstream_servers_for("intranet.corp.example").is_some());
+/// assert!(domain_routes.upstream_servers_for("example").is_none());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DomainRoutes {
+    routes: HashMap<Vec<u8>, Vec<UpstreamServer>>,
+}
+
+impl DomainRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Send queries for `domain` and the names under it to
+    /// `upstream_servers`.
+    pub fn add(&mut self, domain: &str, upstream_servers: Vec<UpstreamServer>) {
+        let domain = packet::normalize_name(idn::to_ascii_or_raw(domain).as_bytes());
+        self.routes.insert(domain, upstream_servers);
+    }
+
+    /// The upstream servers for `name`, if it belongs to a routed domain.
+    pub fn upstream_servers_for(&self, name: &str) -> Option<&[UpstreamServer]> {
+        self.upstream_servers_for_raw(idn::to_ascii_or_raw(name).as_bytes())
+    }
+
+    pub(crate) fn upstream_servers_for_raw(&self, name: &[u8]) -> Option<&[UpstreamServer]> {
+        if self.is_empty() {
+            return None;
+        }
+        let name = packet::normalize_name(name);
+        let mut suffix = &name[..];
+        loop {
+            if let Some(upstream_servers) = self.routes.get(suffix) {
+                return Some(upstream_servers);
+            }
+            let pos = suffix.iter().position(|&c| c == b'.')?;
+            suffix = &suffix[pos + 1..];
+        }
+    }
+
+    /// Send a query to the servers of its domain, unless `options` already
+    /// set upstream servers.
+    pub(crate) fn apply<'t>(
+        &self,
+        query_question: &Option<(Vec<u8>, u16, u16)>,
+        options: &'t QueryOptions,
+    ) -> Cow<'t, QueryOptions> {
+        let upstream_servers = match query_question {
+            Some((name, ..)) if options.upstream_servers.is_none() => {
+                self.upstream_servers_for_raw(name)
+            }
+            _ => None,
+        };
+        match upstream_servers {
+            Some(upstream_servers) => Cow::Owned(QueryOptions {
+                upstream_servers: Some(upstream_servers.to_vec()),
+                ..options.clone()
+            }),
+            None => Cow::Borrowed(options),
+        }
+    }
+}
+
+#[test]
+fn test_domain_routes() {
+    let corp = vec![UpstreamServer::new(([10, 0, 0, 53], 53))];
+    let lab = vec![UpstreamServer::new(([10, 1, 0, 53], 53))];
+    let mut domain_routes = DomainRoutes::new();
+    domain_routes.add("Corp.Example.", corp.clone());
+    domain_routes.add("lab.corp.example", lab.clone());
+    assert_eq!(
+        domain_routes.upstream_servers_for("corp.example"),
+        Some(&corp[..])
+    );
+    assert_eq!(
+        domain_routes.upstream_servers_for("www.corp.example"),
+        Some(&corp[..])
+    );
+    assert_eq!(
+        domain_routes.upstream_servers_for("host.lab.corp.example"),
+        Some(&lab[..])
+    );
+    assert!(domain_routes
+        .upstream_servers_for("notcorp.example")
+        .is_none());
+}
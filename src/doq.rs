@@ -0,0 +1,453 @@
+//! DNS-over-QUIC (RFC 9250).
+//!
+//! Every query is sent on its own bidirectional stream, length-prefixed and
+//! with a zero ID, within a new QUIC connection that is closed after the
+//! response. Session tickets are kept, so that queries can be sent as 0-RTT
+//! data when reconnecting to a server.
+//!
+//! `quinn-proto` doesn't perform any I/O, so that connections only require
+//! the UDP primitives of a `RuntimeAdapter`.
+
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use dnssector::constants::DNS_HEADER_SIZE;
+use quinn_proto::crypto::rustls::QuicClientConfig;
+use quinn_proto::{
+    ClientConfig, Connection, ConnectionHandle, DatagramEvent, Dir, Endpoint, EndpointConfig,
+    Event, ReadError, StreamId, VarInt, WriteError,
+};
+
+use crate::backend::{self, RuntimeAdapter};
+use crate::time::Instant;
+use crate::tls;
+use crate::upstream_server::UpstreamServer;
+
+const DOQ_ALPN: &[u8] = b"doq";
+
+/// The application error code closing connections once the response was
+/// received.
+const DOQ_NO_ERROR: u32 = 0;
+
+/// How long to wait for a datagram when the connection has no pending timer.
+const MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// The configuration shared by all the DNS-over-QUIC connections.
+pub(crate) fn client_config() -> ClientConfig {
+    static CLIENT_CONFIG: OnceLock<ClientConfig> = OnceLock::new();
+    CLIENT_CONFIG
+        .get_or_init(|| quic_client_config(&tls::client_config()))
+        .clone()
+}
+
+fn quic_client_config(config: &rustls::ClientConfig) -> ClientConfig {
+    let mut config = config.clone();
+    config.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+    config.enable_early_data = true;
+    // Sessions resumed over QUIC are kept apart from DNS-over-TLS sessions
+    config.resumption = rustls::client::Resumption::default();
+    let config = QuicClientConfig::try_from(config).expect("No QUIC initial cipher suite");
+    ClientConfig::new(Arc::new(config))
+}
+
+fn quic_error(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// A query being exchanged over a QUIC connection.
+struct Exchange {
+    endpoint: Endpoint,
+    handle: ConnectionHandle,
+    connection: Connection,
+    /// The length-prefixed query.
+    query: Vec<u8>,
+    stream: Option<StreamId>,
+    written: usize,
+    sent_as_0rtt: bool,
+    response: Vec<u8>,
+}
+
+impl Exchange {
+    fn new(
+        config: ClientConfig,
+        upstream_server: &UpstreamServer,
+        query: Vec<u8>,
+    ) -> Result<Self, io::Error> {
+        let server_name = upstream_server.tls_server_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("[{}] is not an encrypted server", upstream_server),
+            )
+        })?;
+        let mut endpoint = Endpoint::new(Arc::new(EndpointConfig::default()), None, false, None);
+        let (handle, connection) = endpoint
+            .connect(Instant::now(), config, upstream_server.addr, server_name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(Exchange {
+            endpoint,
+            handle,
+            connection,
+            query,
+            stream: None,
+            written: 0,
+            sent_as_0rtt: false,
+            response: vec![],
+        })
+    }
+
+    /// Process the connection events, send the query as soon as a stream can
+    /// be opened, and return the response once it is complete.
+    fn poll(&mut self) -> Result<Option<Vec<u8>>, io::Error> {
+        while let Some(event) = self.connection.poll() {
+            match event {
+                Event::Connected if self.sent_as_0rtt && !self.connection.accepted_0rtt() => {
+                    // Streams opened in rejected 0-RTT data were reset
+                    self.stream = None;
+                    self.written = 0;
+                }
+                Event::ConnectionLost { reason } => {
+                    return Err(io::Error::new(io::ErrorKind::ConnectionAborted, reason))
+                }
+                _ => {}
+            }
+        }
+        if self.stream.is_none() {
+            self.stream = self.connection.streams().open(Dir::Bi);
+            self.sent_as_0rtt = self.connection.is_handshaking();
+        }
+        let stream = match self.stream {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+        if self.written < self.query.len() {
+            let mut send_stream = self.connection.send_stream(stream);
+            match send_stream.write(&self.query[self.written..]) {
+                Ok(len) => self.written += len,
+                Err(WriteError::Blocked) => {}
+                Err(e) => return Err(quic_error(e)),
+            }
+            if self.written == self.query.len() {
+                send_stream.finish().map_err(quic_error)?;
+            }
+        }
+        let mut recv_stream = self.connection.recv_stream(stream);
+        let mut chunks = recv_stream.read(true).map_err(quic_error)?;
+        let res = loop {
+            match chunks.next(usize::MAX) {
+                Ok(Some(chunk)) => self.response.extend_from_slice(&chunk.bytes),
+                Ok(None) | Err(ReadError::Blocked) => break Ok(()),
+                Err(e) => break Err(quic_error(e)),
+            }
+        };
+        let _ = chunks.finalize();
+        res?;
+        tls::complete_response(&self.response, false)
+    }
+
+    /// Return the datagrams to send.
+    fn transmits(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        while let Some(event) = self.connection.poll_endpoint_events() {
+            if let Some(event) = self.endpoint.handle_event(self.handle, event) {
+                self.connection.handle_event(event);
+            }
+        }
+        let mut datagrams = vec![];
+        let mut datagram = vec![];
+        while let Some(transmit) = self.connection.poll_transmit(now, 1, &mut datagram) {
+            datagram.truncate(transmit.size);
+            datagrams.push(std::mem::take(&mut datagram));
+        }
+        datagrams
+    }
+
+    fn handle_datagram(&mut self, now: Instant, remote: SocketAddr, datagram: &[u8]) {
+        let mut response = vec![];
+        if let Some(DatagramEvent::ConnectionEvent(_, event)) = self.endpoint.handle(
+            now,
+            remote,
+            None,
+            None,
+            BytesMut::from(datagram),
+            &mut response,
+        ) {
+            self.connection.handle_event(event);
+        }
+    }
+}
+
+/// Exchange a query with a DNS-over-QUIC server, over a UDP socket of
+/// `runtime`. The caller is responsible for the timeout.
+pub(crate) async fn exchange<R: RuntimeAdapter>(
+    runtime: &R,
+    config: ClientConfig,
+    local_addr: &SocketAddr,
+    upstream_server: &UpstreamServer,
+    query: &[u8],
+) -> io::Result<Vec<u8>> {
+    if query.len() < DNS_HEADER_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Short query"));
+    }
+    // The message ID must be 0, the stream identifies the query
+    let mut doq_query = tls::length_prefixed(query);
+    doq_query[2..4].copy_from_slice(&[0, 0]);
+    let socket = runtime
+        .udp_connect(*local_addr, upstream_server.addr)
+        .await?;
+    let mut exchange = Exchange::new(config, upstream_server, doq_query)?;
+    let mut datagram = vec![0; 65535];
+    loop {
+        if let Some(mut response) = exchange.poll()? {
+            let now = Instant::now();
+            exchange
+                .connection
+                .close(now, VarInt::from_u32(DOQ_NO_ERROR), Bytes::new());
+            for datagram in exchange.transmits(now) {
+                let _ = runtime.udp_send(&socket, &datagram).await;
+            }
+            if response.len() >= 2 {
+                response[..2].copy_from_slice(&query[..2]);
+            }
+            return Ok(response);
+        }
+        for datagram in exchange.transmits(Instant::now()) {
+            runtime.udp_send(&socket, &datagram).await?;
+        }
+        let wait = exchange
+            .connection
+            .poll_timeout()
+            .map_or(MAX_WAIT, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+        match backend::timeout(runtime, wait, runtime.udp_recv(&socket, &mut datagram)).await {
+            Ok(len) => {
+                exchange.handle_datagram(Instant::now(), upstream_server.addr, &datagram[..len])
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                exchange.connection.handle_timeout(Instant::now())
+            }
+            Err(e) => return Err(backend::recv_error(e)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "async-io"))]
+mod tests {
+    use std::net::UdpSocket;
+    use std::thread;
+
+    use quinn_proto::crypto::rustls::QuicServerConfig;
+    use quinn_proto::ServerConfig;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    use super::*;
+
+    /// A server connection, with the query received so far.
+    struct ServerConnection {
+        handle: ConnectionHandle,
+        connection: Connection,
+        remote: SocketAddr,
+        stream: Option<StreamId>,
+        query: Vec<u8>,
+        responded: bool,
+    }
+
+    /// Every query received by a test server, and whether it was received
+    /// as 0-RTT data.
+    type ServerHandle = thread::JoinHandle<io::Result<Vec<(Vec<u8>, bool)>>>;
+
+    /// Start a DNS-over-QUIC server answering `connections` successive
+    /// connections with `response`, and return its address.
+    fn serve(response: Vec<u8>, connections: usize) -> (SocketAddr, ServerHandle) {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from_pem_slice(tls::tests::CERTIFICATE.as_bytes()).unwrap()],
+                PrivateKeyDer::from_pem_slice(tls::tests::PRIVATE_KEY.as_bytes()).unwrap(),
+            )
+            .unwrap();
+        config.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+        config.max_early_data_size = u32::MAX;
+        let config = QuicServerConfig::try_from(config).unwrap();
+        let server_config = Arc::new(ServerConfig::with_crypto(Arc::new(config)));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut endpoint = Endpoint::new(
+                Arc::new(EndpointConfig::default()),
+                Some(server_config),
+                false,
+                None,
+            );
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let mut queries = vec![];
+            let mut current: Option<ServerConnection> = None;
+            let mut datagram = vec![0; 65535];
+            while queries.len() < connections {
+                let now = Instant::now();
+                if now > deadline {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                let wait = current
+                    .as_mut()
+                    .and_then(|current| current.connection.poll_timeout())
+                    .map_or(Duration::from_millis(50), |timeout| {
+                        timeout.saturating_duration_since(now)
+                    });
+                socket.set_read_timeout(Some(
+                    wait.clamp(Duration::from_millis(1), Duration::from_millis(50)),
+                ))?;
+                match socket.recv_from(&mut datagram) {
+                    Ok((len, remote)) => {
+                        let mut buf = vec![];
+                        match endpoint.handle(
+                            now,
+                            remote,
+                            None,
+                            None,
+                            BytesMut::from(&datagram[..len]),
+                            &mut buf,
+                        ) {
+                            Some(DatagramEvent::NewConnection(incoming)) => {
+                                let (handle, connection) = endpoint
+                                    .accept(incoming, now, &mut buf, None)
+                                    .map_err(|e| quic_error(e.cause))?;
+                                current = Some(ServerConnection {
+                                    handle,
+                                    connection,
+                                    remote,
+                                    stream: None,
+                                    query: vec![],
+                                    responded: false,
+                                });
+                            }
+                            Some(DatagramEvent::ConnectionEvent(handle, event)) => {
+                                if let Some(current) =
+                                    current.as_mut().filter(|current| current.handle == handle)
+                                {
+                                    current.connection.handle_event(event);
+                                }
+                            }
+                            Some(DatagramEvent::Response(transmit)) => {
+                                socket.send_to(&buf[..transmit.size], remote)?;
+                            }
+                            None => {}
+                        }
+                    }
+                    Err(e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::TimedOut =>
+                    {
+                        if let Some(current) = current.as_mut() {
+                            current.connection.handle_timeout(Instant::now());
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+                let server_connection = match current.as_mut() {
+                    Some(server_connection) => server_connection,
+                    None => continue,
+                };
+                let connection = &mut server_connection.connection;
+                let mut lost = false;
+                while let Some(event) = connection.poll() {
+                    if let Event::ConnectionLost { .. } = event {
+                        lost = true;
+                    }
+                }
+                if server_connection.stream.is_none() {
+                    server_connection.stream = connection.streams().accept(Dir::Bi);
+                }
+                if let (Some(stream), false) =
+                    (server_connection.stream, server_connection.responded)
+                {
+                    let mut recv_stream = connection.recv_stream(stream);
+                    let mut chunks = recv_stream.read(true).map_err(quic_error)?;
+                    while let Ok(Some(chunk)) = chunks.next(usize::MAX) {
+                        server_connection.query.extend_from_slice(&chunk.bytes);
+                    }
+                    let _ = chunks.finalize();
+                    if tls::complete_response(&server_connection.query, false)?.is_some() {
+                        let mut send_stream = connection.send_stream(stream);
+                        send_stream
+                            .write(&tls::length_prefixed(&response))
+                            .map_err(quic_error)?;
+                        send_stream.finish().map_err(quic_error)?;
+                        server_connection.responded = true;
+                    }
+                }
+                while let Some(event) = connection.poll_endpoint_events() {
+                    if let Some(event) = endpoint.handle_event(server_connection.handle, event) {
+                        connection.handle_event(event);
+                    }
+                }
+                let mut buf = vec![];
+                while let Some(transmit) = connection.poll_transmit(Instant::now(), 1, &mut buf) {
+                    socket.send_to(&buf[..transmit.size], server_connection.remote)?;
+                    buf.clear();
+                }
+                if lost || connection.is_drained() {
+                    let query = tls::complete_response(&server_connection.query, false)?
+                        .unwrap_or_default();
+                    queries.push((query, connection.has_0rtt()));
+                    current = None;
+                }
+            }
+            Ok(queries)
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn test_exchange() {
+        use crate::backend::async_io::AsyncIoBackend;
+
+        let (addr, server) = serve(vec![0x42; 1000], 2);
+        let upstream_server = UpstreamServer::new_quic(addr, "dns.test");
+        let config = quic_client_config(&tls::tests::client_config());
+        let local_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        let mut query = vec![0x12, 0x34];
+        query.extend_from_slice(&[0; DNS_HEADER_SIZE - 2]);
+        for _ in 0..2 {
+            let response = ::async_io::block_on(exchange(
+                &AsyncIoBackend::default(),
+                config.clone(),
+                &local_addr,
+                &upstream_server,
+                &query,
+            ))
+            .unwrap();
+            // The ID of the query is restored
+            assert_eq!(response[..2], [0x12, 0x34]);
+            assert_eq!(response[2..], [0x42; 998]);
+        }
+        let queries = server.join().unwrap().unwrap();
+        assert_eq!(queries.len(), 2);
+        for (received, _) in &queries {
+            assert_eq!(received[..2], [0, 0]);
+            assert_eq!(received[2..], query[2..]);
+        }
+        // The second query was sent as 0-RTT data, after resuming the session
+        assert!(!queries[0].1);
+        assert!(queries[1].1);
+
+        // The certificate is not valid for another name
+        let (addr, _server) = serve(vec![0x42; 100], 1);
+        let upstream_server = UpstreamServer::new_quic(addr, "other.test");
+        assert!(::async_io::block_on(exchange(
+            &AsyncIoBackend::default(),
+            config,
+            &local_addr,
+            &upstream_server,
+            &query,
+        ))
+        .is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use std::io;
+
+use dnssector::constants::*;
+
+use crate::events::Protocol;
+use crate::name_validation::NameValidation;
+use crate::packet::{self, DEFAULT_EDNS_BUFSIZE, EDNS_FLAG_DO, RCODE_BADVERS};
+use crate::presentation::{rcode_name, Message};
+use crate::upstream_server::UpstreamServer;
+
+const EDNS_FLAG_UNKNOWN: u16 = 0x4000;
+const EDNS_OPTION_UNKNOWN: u16 = 100;
+
+/// A probe checking how an upstream server handles a specific kind of query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EdnsProbe {
+    /// A query without EDNS, that must be answered without EDNS.
+    Plain,
+    /// A query with EDNS version 0, that must be answered with EDNS.
+    Edns,
+    /// A query with EDNS version 1, that must be answered with `BADVERS`.
+    EdnsVersion1,
+    /// A query with an unknown EDNS option, that must be ignored.
+    UnknownOption,
+    /// A query with an unknown EDNS flag, that must not be echoed.
+    UnknownFlag,
+    /// A query advertising a 4096 bytes buffer.
+    LargeBufsize,
+    /// A query with the `DO` flag, that must be echoed.
+    DnssecOk,
+    /// A query without EDNS over TCP.
+    Tcp,
+}
+
+impl EdnsProbe {
+    /// All the probes, in the order they are sent.
+    pub const ALL: [EdnsProbe; 8] = [
+        EdnsProbe::Plain,
+        EdnsProbe::Edns,
+        EdnsProbe::EdnsVersion1,
+        EdnsProbe::UnknownOption,
+        EdnsProbe::UnknownFlag,
+        EdnsProbe::LargeBufsize,
+        EdnsProbe::DnssecOk,
+        EdnsProbe::Tcp,
+    ];
+
+    pub(crate) fn protocol(self) -> Protocol {
+        match self {
+            EdnsProbe::Tcp => Protocol::Tcp,
+            _ => Protocol::Udp,
+        }
+    }
+
+    /// Build the query for this probe, returning its transaction ID and the
+    /// raw packet.
+    pub(crate) fn query(
+        self,
+        zone: &str,
+        name_validation: NameValidation,
+    ) -> Result<(u16, Vec<u8>), io::Error> {
+        let parsed_query = packet::query(
+            zone,
+            Type::from_string("SOA").unwrap(),
+            Class::from_string("IN").unwrap(),
+            name_validation,
+        )?;
+        let tid = parsed_query.tid();
+        let mut query = parsed_query.into_packet();
+        let (bufsize, version, flags, options): (u16, u8, u16, &[u8]) = match self {
+            EdnsProbe::Plain | EdnsProbe::Tcp => return Ok((tid, query)),
+            EdnsProbe::Edns => (DEFAULT_EDNS_BUFSIZE, 0, 0, &[]),
+            EdnsProbe::EdnsVersion1 => (DEFAULT_EDNS_BUFSIZE, 1, 0, &[]),
+            EdnsProbe::UnknownOption => (
+                DEFAULT_EDNS_BUFSIZE,
+                0,
+                0,
+                &[0, EDNS_OPTION_UNKNOWN as u8, 0, 0],
+            ),
+            EdnsProbe::UnknownFlag => (DEFAULT_EDNS_BUFSIZE, 0, EDNS_FLAG_UNKNOWN, &[]),
+            EdnsProbe::LargeBufsize => (4096, 0, 0, &[]),
+            EdnsProbe::DnssecOk => (DEFAULT_EDNS_BUFSIZE, 0, EDNS_FLAG_DO, &[]),
+        };
+        packet::add_edns(&mut query, bufsize, version, flags, options);
+        Ok((tid, query))
+    }
+
+    /// Check the response to this probe.
+    pub(crate) fn check(self, query_tid: u16, response: &[u8]) -> EdnsProbeOutcome {
+        let message = match Message::parse(response) {
+            Ok(message) => message,
+            Err(e) => return EdnsProbeOutcome::Failed(e.to_string()),
+        };
+        if message.id != query_tid || u32::from(message.flags) & DNS_FLAG_QR == 0 {
+            return EdnsProbeOutcome::Failed("Unexpected response".to_string());
+        }
+        let opt = message.opt();
+        let (version, flags) = match opt {
+            Some(opt) => (((opt.ttl >> 16) & 0xff) as u8, (opt.ttl & 0xffff) as u16),
+            None => (0, 0),
+        };
+        let rcode = message.extended_rcode();
+        let unexpected = |reason: &str| EdnsProbeOutcome::Unexpected(reason.to_string());
+        if self == EdnsProbe::EdnsVersion1 {
+            return match opt {
+                None => unexpected("No EDNS in the response"),
+                Some(_) if rcode != RCODE_BADVERS => unexpected("BADVERS was not returned"),
+                Some(_) if version != 0 => unexpected("Unexpected EDNS version"),
+                Some(_) => EdnsProbeOutcome::Ok,
+            };
+        }
+        if rcode != u16::from(u8::from(Rcode::NOERROR))
+            && rcode != u16::from(u8::from(Rcode::NXDOMAIN))
+        {
+            return EdnsProbeOutcome::Unexpected(format!(
+                "Unexpected response code: {}",
+                rcode_name(rcode)
+            ));
+        }
+        match (self, opt) {
+            (EdnsProbe::Plain, Some(_)) | (EdnsProbe::Tcp, Some(_)) => {
+                unexpected("EDNS in the response to a query without EDNS")
+            }
+            (EdnsProbe::Plain, None) | (EdnsProbe::Tcp, None) => EdnsProbeOutcome::Ok,
+            (_, None) => unexpected("No EDNS in the response"),
+            (_, Some(_)) if version != 0 => unexpected("Unexpected EDNS version"),
+            (EdnsProbe::UnknownOption, Some(opt))
+                if has_option(&opt.rdata, EDNS_OPTION_UNKNOWN) =>
+            {
+                unexpected("The unknown option was echoed")
+            }
+            (EdnsProbe::UnknownFlag, Some(_)) if flags & EDNS_FLAG_UNKNOWN != 0 => {
+                unexpected("The unknown flag was echoed")
+            }
+            (EdnsProbe::DnssecOk, Some(_)) if flags & EDNS_FLAG_DO == 0 => {
+                unexpected("The DO flag was not echoed")
+            }
+            _ => EdnsProbeOutcome::Ok,
+        }
+    }
+}
+
+fn has_option(mut options: &[u8], code: u16) -> bool {
+    while options.len() >= 4 {
+        if u16::from_be_bytes([options[0], options[1]]) == code {
+            return true;
+        }
+        let len = u16::from_be_bytes([options[2], options[3]]) as usize;
+        options = options.get(4 + len..).unwrap_or_default();
+    }
+    false
+}
+
+/// The outcome of an EDNS probe.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EdnsProbeOutcome {
+    /// The server responded as expected.
+    Ok,
+    /// The server responded, but not as expected.
+    Unexpected(String),
+    /// The server didn't respond, or the response was invalid.
+    Failed(String),
+}
+
+/// The EDNS compliance profile of an upstream server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EdnsComplianceReport {
+    pub server: UpstreamServer,
+    /// The outcome of every probe, in the order of `EdnsProbe::ALL`.
+    pub probes: Vec<(EdnsProbe, EdnsProbeOutcome)>,
+}
+
+impl EdnsComplianceReport {
+    /// Whether all the probes succeeded.
+    pub fn is_compliant(&self) -> bool {
+        self.probes
+            .iter()
+            .all(|(_, outcome)| *outcome == EdnsProbeOutcome::Ok)
+    }
+
+    /// Whether `probe` succeeded.
+    pub fn supports(&self, probe: EdnsProbe) -> bool {
+        self.probes
+            .iter()
+            .any(|(p, outcome)| *p == probe && *outcome == EdnsProbeOutcome::Ok)
+    }
+}
+
+#[test]
+fn test_edns_probes() {
+    // A server echoing queries, EDNS options and flags included
+    let echo = |probe: EdnsProbe| {
+        let (tid, mut response) = probe.query("example.com", NameValidation::Strict).unwrap();
+        response[DNS_FLAGS_OFFSET] |= 0x80;
+        probe.check(tid, &response)
+    };
+    assert_eq!(echo(EdnsProbe::Plain), EdnsProbeOutcome::Ok);
+    assert_eq!(echo(EdnsProbe::Edns), EdnsProbeOutcome::Ok);
+    assert_eq!(echo(EdnsProbe::DnssecOk), EdnsProbeOutcome::Ok);
+    assert_eq!(echo(EdnsProbe::LargeBufsize), EdnsProbeOutcome::Ok);
+    for probe in &[
+        EdnsProbe::EdnsVersion1,
+        EdnsProbe::UnknownOption,
+        EdnsProbe::UnknownFlag,
+    ] {
+        assert!(matches!(echo(*probe), EdnsProbeOutcome::Unexpected(_)));
+    }
+    let (tid, query) = EdnsProbe::Edns
+        .query("example.com", NameValidation::Strict)
+        .unwrap();
+    assert!(matches!(
+        EdnsProbe::Edns.check(tid, &query),
+        EdnsProbeOutcome::Failed(_)
+    ));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_probe_edns_compliance() {
+    use std::net::SocketAddr;
+
+    use crate::testing::MockBackend;
+
+    let backend = MockBackend::new();
+    let upstream_server = UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)));
+    let dns_client = crate::sync::GenericDNSClient::with_backend(vec![], backend);
+    let report = dns_client
+        .probe_edns_compliance(&upstream_server, "example.com")
+        .unwrap();
+    assert_eq!(report.probes.len(), EdnsProbe::ALL.len());
+    assert!(report.supports(EdnsProbe::Plain));
+    assert!(report.supports(EdnsProbe::Tcp));
+    assert!(!report.supports(EdnsProbe::Edns));
+    assert!(!report.is_compliant());
+}
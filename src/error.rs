@@ -0,0 +1,212 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use dnssector::constants::Rcode;
+
+/// A structured error, telling apart what can go wrong with a query.
+///
+/// Clients return `io::Error`s for compatibility. They can be converted into
+/// a `DnsError` with `DnsError::from()`, and a `DnsError` converts back into
+/// an `io::Error` carrying it, with a matching `io::ErrorKind`.
+///
+/// Queries returning records fail with `NxDomain` or `ServFail` if the
+/// response code is not `NOERROR`, while `query_parsed()` and `query_raw()`
+/// return such responses as-is.
+///
+/// ```
+/// use std::io;
+///
+/// use dnsclient::DnsError;
+///
+/// let e: io::Error = DnsError::NxDomain.into();
+/// assert_eq!(e.kind(), io::ErrorKind::NotFound);
+/// assert!(matches!(DnsError::from(e), DnsError::NxDomain));
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DnsError {
+    /// The name doesn't exist (`NXDOMAIN`).
+    NxDomain,
+    /// The server returned an error, such as `SERVFAIL` or `REFUSED`.
+    ServFail { rcode: u8 },
+    /// No response was received in time.
+    Timeout,
+    /// The response was truncated, and couldn't be retried over TCP.
+    Truncated,
+    /// The response couldn't be parsed, or didn't match the query.
+    ProtocolError(String),
+    /// The query, or a name, is not valid.
+    InvalidInput(String),
+    /// Any other I/O error.
+    Io(io::Error),
+}
+
+impl DnsError {
+    /// The error matching a response code, `None` for `NOERROR`.
+    pub fn from_rcode(rcode: u8) -> Option<Self> {
+        match rcode {
+            x if x == u8::from(Rcode::NOERROR) => None,
+            x if x == u8::from(Rcode::NXDOMAIN) => Some(DnsError::NxDomain),
+            rcode => Some(DnsError::ServFail { rcode }),
+        }
+    }
+
+    /// Turn an error response code into an error.
+    pub(crate) fn check_rcode(rcode: u8) -> Result<(), io::Error> {
+        match DnsError::from_rcode(rcode) {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether `e` is an error response from a server, rather than a
+    /// failure to get a response.
+    pub(crate) fn is_rcode_error(e: &io::Error) -> bool {
+        e.get_ref()
+            .and_then(|inner| inner.downcast_ref::<DnsError>())
+            .is_some_and(|e| matches!(e, DnsError::NxDomain | DnsError::ServFail { .. }))
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            DnsError::NxDomain => io::ErrorKind::NotFound,
+            DnsError::ServFail { .. } => io::ErrorKind::Other,
+            DnsError::Timeout => io::ErrorKind::TimedOut,
+            DnsError::Truncated | DnsError::ProtocolError(_) => io::ErrorKind::InvalidData,
+            DnsError::InvalidInput(_) => io::ErrorKind::InvalidInput,
+            DnsError::Io(e) => e.kind(),
+        }
+    }
+}
+
+/// Treat an error response as an empty answer.
+pub(crate) fn no_records<T>(res: Result<Vec<T>, io::Error>) -> Result<Vec<T>, io::Error> {
+    match res {
+        Err(e) if DnsError::is_rcode_error(&e) => Ok(vec![]),
+        res => res,
+    }
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::NxDomain => f.write_str("Name not found"),
+            DnsError::ServFail { rcode } => write!(f, "Server error (rcode {})", rcode),
+            DnsError::Timeout => f.write_str("Timeout"),
+            DnsError::Truncated => f.write_str("Truncated response"),
+            DnsError::ProtocolError(reason) => write!(f, "Invalid response: {}", reason),
+            DnsError::InvalidInput(reason) => write!(f, "Invalid query: {}", reason),
+            DnsError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for DnsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DnsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DnsError> for io::Error {
+    fn from(e: DnsError) -> Self {
+        match e {
+            DnsError::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
+
+impl From<io::Error> for DnsError {
+    fn from(e: io::Error) -> Self {
+        if e.get_ref().is_some_and(|inner| inner.is::<DnsError>()) {
+            return *e.into_inner().unwrap().downcast::<DnsError>().unwrap();
+        }
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => DnsError::Timeout,
+            io::ErrorKind::InvalidData => DnsError::ProtocolError(e.to_string()),
+            _ => DnsError::Io(e),
+        }
+    }
+}
+
+#[test]
+fn test_dns_error() {
+    assert!(DnsError::from_rcode(0).is_none());
+    assert!(matches!(DnsError::from_rcode(3), Some(DnsError::NxDomain)));
+    assert!(matches!(
+        DnsError::from_rcode(2),
+        Some(DnsError::ServFail { rcode: 2 })
+    ));
+    let e: io::Error = DnsError::ServFail { rcode: 5 }.into();
+    assert!(matches!(DnsError::from(e), DnsError::ServFail { rcode: 5 }));
+    assert!(DnsError::check_rcode(0).is_ok());
+    let e = DnsError::check_rcode(3).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    assert!(DnsError::is_rcode_error(&e));
+    assert!(!DnsError::is_rcode_error(&DnsError::Timeout.into()));
+    let e = io::Error::new(io::ErrorKind::TimedOut, "Timeout");
+    assert!(matches!(DnsError::from(e), DnsError::Timeout));
+    let e = io::Error::new(io::ErrorKind::ConnectionRefused, "Refused");
+    match DnsError::from(e) {
+        DnsError::Io(e) => assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused),
+        e => panic!("Unexpected error: {}", e),
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_query_errors() {
+    use dnssector::constants::Type;
+
+    use crate::testing::{mock_client, MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    let dns_client = mock_client(&backend);
+    let e = dns_client.query_a("example.com").unwrap_err();
+    assert!(matches!(DnsError::from(e), DnsError::NxDomain));
+    let response = dns_client.query_parsed("example.com", "IN", "A").unwrap();
+    assert_eq!(response.rcode(), u8::from(Rcode::NXDOMAIN));
+
+    let servfail = MockAction::Respond {
+        rcode: Rcode::SERVFAIL.into(),
+        answers: vec![],
+    };
+    backend.set_action("example.com", "TXT", servfail).unwrap();
+    let e = dns_client.query_txt("example.com").unwrap_err();
+    assert!(matches!(DnsError::from(e), DnsError::ServFail { rcode: 2 }));
+
+    let timeout = MockAction::Fail(io::ErrorKind::TimedOut);
+    backend.set_action("example.com", "MX", timeout).unwrap();
+    let e = dns_client.query_mx("example.com").unwrap_err();
+    assert!(matches!(DnsError::from(e), DnsError::Timeout));
+
+    let no_data = MockAction::Respond {
+        rcode: 0,
+        answers: vec![],
+    };
+    backend.set_action("example.com", "AAAA", no_data).unwrap();
+    assert!(dns_client.query_aaaa("example.com").unwrap().is_empty());
+
+    // An error for one address family doesn't hide the other one
+    let a = (Type::A.into(), 60, vec![192, 0, 2, 1]);
+    let servfail = MockAction::Respond {
+        rcode: Rcode::SERVFAIL.into(),
+        answers: vec![],
+    };
+    backend
+        .set_action(
+            "example.net",
+            "A",
+            MockAction::Respond {
+                rcode: 0,
+                answers: vec![a],
+            },
+        )
+        .unwrap();
+    backend.set_action("example.net", "AAAA", servfail).unwrap();
+    assert_eq!(dns_client.query_addrs("example.net").unwrap().len(), 1);
+}
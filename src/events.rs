@@ -0,0 +1,457 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::stats::Stats;
+use crate::time::Instant;
+use crate::upstream_server::UpstreamServer;
+
+/// The protocol used to exchange a query with an upstream server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum Protocol {
+    Udp,
+    Tcp,
+    /// DNS-over-TLS (RFC 7858).
+    Tls,
+    /// DNS-over-HTTPS (RFC 8484).
+    Https,
+    /// DNS-over-QUIC (RFC 9250).
+    Quic,
+    /// Oblivious DNS-over-HTTPS (RFC 9230).
+    ObliviousHttps,
+}
+
+/// What happens to a query while it is being resolved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ClientEvent {
+    /// A query was sent to an upstream server.
+    QuerySent {
+        server: UpstreamServer,
+        protocol: Protocol,
+    },
+    /// An upstream server didn't respond in time.
+    Timeout {
+        server: UpstreamServer,
+        protocol: Protocol,
+    },
+    /// An exchange with an upstream server was abandoned before it completed,
+    /// because the future performing it was dropped.
+    QueryCancelled {
+        server: UpstreamServer,
+        protocol: Protocol,
+    },
+    /// An exchange with an upstream server failed for another reason.
+    QueryFailed {
+        server: UpstreamServer,
+        protocol: Protocol,
+        kind: io::ErrorKind,
+    },
+    /// A truncated response was received, and the query is sent again over
+    /// TCP.
+    TruncatedRetry { server: UpstreamServer },
+    /// An upstream server failed, and the next one is about to be tried.
+    Failover {
+        from: UpstreamServer,
+        to: UpstreamServer,
+    },
+    /// All the upstream servers failed, and the query is about to be sent to
+    /// them again after `backoff`, according to the retry policy. `attempt`
+    /// is the number of the retry, starting from `1`.
+    Retry { attempt: u32, backoff: Duration },
+    /// A response from an upstream server was rejected.
+    InvalidResponse {
+        server: UpstreamServer,
+        reason: String,
+    },
+    /// A response was received from an upstream server.
+    ResponseReceived {
+        server: UpstreamServer,
+        protocol: Protocol,
+        rtt: Duration,
+    },
+}
+
+/// A completed exchange of a query with an upstream server, with the raw
+/// packets, as seen by `EventSink::on_exchange()`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ExchangeRecord<'t> {
+    pub server: &'t UpstreamServer,
+    pub protocol: Protocol,
+    /// The query, as sent.
+    pub query: &'t [u8],
+    /// The raw response, or the reason why none was received.
+    pub response: Result<&'t [u8], &'t io::Error>,
+    pub elapsed: Duration,
+}
+
+/// A receiver for the events of a client.
+///
+/// Events are delivered synchronously, so implementations should return
+/// quickly.
+pub trait EventSink: Send + Sync {
+    fn on_event(&self, event: &ClientEvent);
+
+    /// Called after every exchange with an upstream server, including
+    /// retries and failovers, in addition to `on_event()`.
+    fn on_exchange(&self, _exchange: &ExchangeRecord<'_>) {}
+}
+
+impl<F: Fn(&ClientEvent) + Send + Sync> EventSink for F {
+    fn on_event(&self, event: &ClientEvent) {
+        self(event)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Events {
+    sink: Option<Sink>,
+    stats: Arc<Stats>,
+}
+
+#[derive(Clone)]
+struct Sink(Arc<dyn EventSink>);
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventSink")
+    }
+}
+
+impl Events {
+    pub(crate) fn set_sink(&mut self, sink: Arc<dyn EventSink>) {
+        self.sink = Some(Sink(sink))
+    }
+
+    pub(crate) fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Emit the event returned by `event`.
+    pub(crate) fn emit(&self, event: impl FnOnce() -> ClientEvent) {
+        let event = event();
+        self.stats.record_event(&event);
+        #[cfg(feature = "log")]
+        log_event(&event);
+        #[cfg(feature = "metrics")]
+        crate::telemetry::record_event(&event);
+        #[cfg(feature = "tracing")]
+        crate::instrument::trace_event(&event);
+        if let Some(sink) = &self.sink {
+            sink.0.on_event(&event)
+        }
+    }
+
+    /// Emit `QuerySent`, and return a guard that emits the outcome of the
+    /// exchange, or `QueryCancelled` if it is dropped before completion.
+    pub(crate) fn start_exchange<'t>(
+        &'t self,
+        server: &'t UpstreamServer,
+        protocol: Protocol,
+    ) -> PendingExchange<'t> {
+        self.emit(|| ClientEvent::QuerySent {
+            server: server.clone(),
+            protocol,
+        });
+        PendingExchange {
+            events: self,
+            server,
+            protocol,
+            start: Instant::now(),
+            done: false,
+        }
+    }
+
+    /// Emit the outcome of an exchange with an upstream server.
+    pub(crate) fn emit_outcome<T>(
+        &self,
+        server: &UpstreamServer,
+        protocol: Protocol,
+        rtt: Duration,
+        res: &io::Result<T>,
+    ) {
+        self.emit(|| {
+            let server = server.clone();
+            match res {
+                Ok(_) => ClientEvent::ResponseReceived {
+                    server,
+                    protocol,
+                    rtt,
+                },
+                Err(e)
+                    if e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::WouldBlock =>
+                {
+                    ClientEvent::Timeout { server, protocol }
+                }
+                Err(e) => ClientEvent::QueryFailed {
+                    server,
+                    protocol,
+                    kind: e.kind(),
+                },
+            }
+        })
+    }
+}
+
+/// An exchange with an upstream server, started with
+/// `Events::start_exchange()`.
+pub(crate) struct PendingExchange<'t> {
+    events: &'t Events,
+    server: &'t UpstreamServer,
+    protocol: Protocol,
+    start: Instant,
+    done: bool,
+}
+
+impl PendingExchange<'_> {
+    /// Emit the outcome of the exchange of `query`, and record the size of
+    /// the response.
+    pub(crate) fn finish(mut self, query: &[u8], res: &io::Result<Vec<u8>>) {
+        self.done = true;
+        let elapsed = self.start.elapsed();
+        if let Ok(response) = res {
+            self.events
+                .stats
+                .record_response_size(self.server, response.len());
+        }
+        self.events
+            .emit_outcome(self.server, self.protocol, elapsed, res);
+        if let Some(sink) = &self.events.sink {
+            sink.0.on_exchange(&ExchangeRecord {
+                server: self.server,
+                protocol: self.protocol,
+                query,
+                response: res.as_ref().map(Vec::as_slice),
+                elapsed,
+            });
+        }
+    }
+}
+
+impl Drop for PendingExchange<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.events.emit(|| ClientEvent::QueryCancelled {
+                server: self.server.clone(),
+                protocol: self.protocol,
+            });
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+fn log_event(event: &ClientEvent) {
+    match event {
+        ClientEvent::QuerySent { server, protocol } => {
+            log::debug!("Sending a query to {} over {:?}", server, protocol)
+        }
+        ClientEvent::Timeout { server, protocol } => {
+            log::warn!("Timeout waiting for {} over {:?}", server, protocol)
+        }
+        ClientEvent::QueryCancelled { server, protocol } => {
+            log::debug!("Query to {} over {:?} cancelled", server, protocol)
+        }
+        ClientEvent::QueryFailed {
+            server,
+            protocol,
+            kind,
+        } => log::warn!("Query to {} over {:?} failed: {:?}", server, protocol, kind),
+        ClientEvent::TruncatedRetry { server } => {
+            log::debug!("Truncated response from {}, retrying over TCP", server)
+        }
+        ClientEvent::Failover { from, to } => {
+            log::warn!("No usable response from {}, trying {}", from, to)
+        }
+        ClientEvent::Retry { attempt, backoff } => {
+            log::warn!("No usable response, retry #{} in {:?}", attempt, backoff)
+        }
+        ClientEvent::InvalidResponse { server, reason } => {
+            log::warn!("Invalid response from {}: {}", server, reason)
+        }
+        ClientEvent::ResponseReceived {
+            server,
+            protocol,
+            rtt,
+        } => log::debug!(
+            "Response received from {} over {:?} in {:?}",
+            server,
+            protocol,
+            rtt
+        ),
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_client_events() {
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+
+    use crate::testing::{MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    let truncated = MockAction::Respond {
+        rcode: 0,
+        answers: vec![],
+    };
+    backend
+        .set_action(
+            "example.com",
+            "A",
+            MockAction::Truncate(Box::new(truncated)),
+        )
+        .unwrap();
+    backend
+        .set_action(
+            "example.com",
+            "AAAA",
+            MockAction::Fail(io::ErrorKind::ConnectionRefused),
+        )
+        .unwrap();
+    let upstream_servers = vec![
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53))),
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 54], 53))),
+    ];
+    let events = Arc::new(Mutex::new(vec![]));
+    let mut dns_client =
+        crate::sync::GenericDNSClient::with_backend(upstream_servers.clone(), backend);
+    {
+        let events = events.clone();
+        dns_client.set_event_sink(move |event: &ClientEvent| {
+            let event = match event {
+                ClientEvent::ResponseReceived {
+                    server, protocol, ..
+                } => ClientEvent::ResponseReceived {
+                    server: server.clone(),
+                    protocol: *protocol,
+                    rtt: Duration::default(),
+                },
+                event => event.clone(),
+            };
+            events.lock().unwrap().push(event)
+        });
+    }
+
+    dns_client.query_a("example.com").unwrap();
+    let server = upstream_servers[0].clone();
+    assert_eq!(
+        std::mem::take(&mut *events.lock().unwrap()),
+        vec![
+            ClientEvent::QuerySent {
+                server: server.clone(),
+                protocol: Protocol::Udp
+            },
+            ClientEvent::ResponseReceived {
+                server: server.clone(),
+                protocol: Protocol::Udp,
+                rtt: Duration::default()
+            },
+            ClientEvent::TruncatedRetry {
+                server: server.clone()
+            },
+            ClientEvent::QuerySent {
+                server: server.clone(),
+                protocol: Protocol::Tcp
+            },
+            ClientEvent::ResponseReceived {
+                server: server.clone(),
+                protocol: Protocol::Tcp,
+                rtt: Duration::default()
+            },
+        ]
+    );
+
+    assert!(dns_client.query_aaaa("example.com").is_err());
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 5);
+    assert_eq!(
+        events[2],
+        ClientEvent::Failover {
+            from: upstream_servers[0].clone(),
+            to: upstream_servers[1].clone()
+        }
+    );
+    assert_eq!(
+        events[4],
+        ClientEvent::QueryFailed {
+            server: upstream_servers[1].clone(),
+            protocol: Protocol::Udp,
+            kind: io::ErrorKind::ConnectionRefused
+        }
+    );
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_exchange_records() {
+    use std::sync::Mutex;
+
+    use dnssector::constants::DNS_HEADER_SIZE;
+
+    use crate::testing::{mock_client, MockAction, MockBackend};
+
+    /// The protocol, the query size and the response size of an exchange.
+    type Exchange = (Protocol, usize, Result<usize, io::ErrorKind>);
+
+    #[derive(Clone, Default)]
+    struct ExchangeLog(Arc<Mutex<Vec<Exchange>>>);
+
+    impl EventSink for ExchangeLog {
+        fn on_event(&self, _event: &ClientEvent) {}
+
+        fn on_exchange(&self, exchange: &ExchangeRecord<'_>) {
+            self.0.lock().unwrap().push((
+                exchange.protocol,
+                exchange.query.len(),
+                exchange.response.map(<[u8]>::len).map_err(io::Error::kind),
+            ))
+        }
+    }
+
+    let backend = MockBackend::new();
+    backend
+        .set_action(
+            "example.com",
+            "A",
+            MockAction::Truncate(Box::new(MockAction::Respond {
+                rcode: 0,
+                answers: vec![],
+            })),
+        )
+        .unwrap();
+    backend
+        .set_action(
+            "example.com",
+            "AAAA",
+            MockAction::Fail(io::ErrorKind::ConnectionRefused),
+        )
+        .unwrap();
+    let log = ExchangeLog::default();
+    let mut dns_client = mock_client(&backend);
+    dns_client.set_event_sink(log.clone());
+
+    dns_client.query_a("example.com").unwrap();
+    let exchanges = std::mem::take(&mut *log.0.lock().unwrap());
+    assert_eq!(exchanges.len(), 2);
+    assert_eq!(exchanges[0].0, Protocol::Udp);
+    assert_eq!(exchanges[1].0, Protocol::Tcp);
+    assert!(exchanges.iter().all(|(_, query_len, response)| {
+        *query_len > DNS_HEADER_SIZE && matches!(response, Ok(len) if *len > DNS_HEADER_SIZE)
+    }));
+
+    assert!(dns_client.query_aaaa("example.com").is_err());
+    let exchanges = std::mem::take(&mut *log.0.lock().unwrap());
+    assert!(!exchanges.is_empty());
+    assert!(exchanges
+        .iter()
+        .all(|(_, _, response)| *response == Err(io::ErrorKind::ConnectionRefused)));
+}
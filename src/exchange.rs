@@ -0,0 +1,583 @@
+//! The protocol logic of an exchange with an upstream server, independent
+//! of how queries and responses are actually sent and received.
+//!
+//! An `Exchange` tells the client what to send, and decides what to do with
+//! every response: return it, or send a query again, possibly over another
+//! transport. A `Failover` decides which upstream server to send a query to
+//! next, and `Retries` when to send it to the upstream servers again. The
+//! sync and async clients only perform the I/O and wait.
+
+use std::borrow::Cow;
+use std::io;
+use std::time::Duration;
+
+use dnssector::constants::*;
+use dnssector::*;
+
+use crate::deadline::Deadline;
+use crate::error::DnsError;
+use crate::events::Protocol;
+use crate::failover_policy::FailoverPolicy;
+use crate::packet;
+use crate::query_info::QueryInfo;
+use crate::query_options::QueryOptions;
+use crate::retry_policy::RetryPolicy;
+use crate::truncation_policy::TruncationPolicy;
+use crate::upstream_server::{no_response_error, Transport, UpstreamServer};
+
+/// Why a query has to be sent again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Retry {
+    /// The UDP response was truncated, the query is sent over TCP.
+    Truncated,
+    /// The server returned `BADVERS` or `BADCOOKIE`, the query is sent again
+    /// with a lower EDNS version or with the server cookie.
+    Edns,
+}
+
+/// What to do after a response has been received.
+#[derive(Debug)]
+pub(crate) enum Step {
+    /// Send `Exchange::query()` over `Exchange::protocol()` again.
+    Retry(Retry),
+    /// The exchange is complete.
+    Done(ParsedPacket),
+}
+
+/// What the exchanges of a query with upstream servers share: the
+/// transaction ID and question of the query, and how it is resolved.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct QueryContext<'t> {
+    pub(crate) tid: u16,
+    pub(crate) question: &'t Option<(Vec<u8>, u16, u16)>,
+    pub(crate) options: &'t QueryOptions,
+    pub(crate) deadline: Deadline,
+}
+
+/// The transport to send a query to `upstream_server` over first: the only
+/// protocol of encrypted and TCP-only servers, otherwise UDP, unless
+/// `force_tcp` is set.
+pub(crate) fn initial_protocol(upstream_server: &UpstreamServer, force_tcp: bool) -> Protocol {
+    match upstream_server.transport {
+        Transport::Tls { .. } => Protocol::Tls,
+        Transport::Https { .. } => Protocol::Https,
+        Transport::Quic { .. } => Protocol::Quic,
+        Transport::ObliviousHttps { .. } => Protocol::ObliviousHttps,
+        Transport::Tcp => Protocol::Tcp,
+        _ if force_tcp => Protocol::Tcp,
+        _ => Protocol::Udp,
+    }
+}
+
+/// An exchange of a query with a single upstream server.
+#[derive(Debug)]
+pub(crate) struct Exchange<'t> {
+    query: Cow<'t, [u8]>,
+    query_tid: u16,
+    attempt_tid: u16,
+    query_question: &'t Option<(Vec<u8>, u16, u16)>,
+    initial_protocol: Protocol,
+    protocol: Protocol,
+    truncation_policy: TruncationPolicy,
+    edns_retried: bool,
+}
+
+impl<'t> Exchange<'t> {
+    pub(crate) fn new(
+        query: &'t [u8],
+        query_tid: u16,
+        query_question: &'t Option<(Vec<u8>, u16, u16)>,
+        protocol: Protocol,
+        truncation_policy: TruncationPolicy,
+    ) -> Self {
+        Exchange {
+            query: Cow::Borrowed(query),
+            query_tid,
+            attempt_tid: query_tid,
+            query_question,
+            initial_protocol: protocol,
+            protocol,
+            truncation_policy,
+            edns_retried: false,
+        }
+    }
+
+    /// Start a new attempt, sending the query with the transaction ID
+    /// `tid`.
+    ///
+    /// Every attempt uses its own transaction ID, so that responses to
+    /// previous attempts, that may have been spoofed in the meantime, are
+    /// rejected. The final response gets the transaction ID of the original
+    /// query back.
+    pub(crate) fn start_attempt(&mut self, tid: u16) {
+        self.attempt_tid = tid;
+        self.query.to_mut()[DNS_TID_OFFSET..DNS_TID_OFFSET + 2].copy_from_slice(&tid.to_be_bytes());
+    }
+
+    /// The query to send.
+    pub(crate) fn query(&self) -> &[u8] {
+        &self.query
+    }
+
+    /// The transport to send the query over.
+    pub(crate) fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Check a response to the query, and decide what to do next.
+    ///
+    /// Responses that can't be parsed or don't match the query are
+    /// rejected.
+    pub(crate) fn on_response(&mut self, response: Vec<u8>) -> Result<Step, io::Error> {
+        let mut parsed_response = DNSSector::new(response)
+            .and_then(|sector| sector.parse())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        if parsed_response.tid() != self.attempt_tid
+            || &parsed_response.question() != self.query_question
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Unexpected response",
+            ));
+        }
+        if self.protocol == Protocol::Udp && parsed_response.flags() & DNS_FLAG_TC == DNS_FLAG_TC {
+            match self.truncation_policy {
+                TruncationPolicy::RetryTcp => {
+                    self.protocol = Protocol::Tcp;
+                    return Ok(Step::Retry(Retry::Truncated));
+                }
+                TruncationPolicy::ReturnTruncated => {}
+                TruncationPolicy::Fail => return Err(DnsError::Truncated.into()),
+            }
+        }
+        if !self.edns_retried {
+            if let Some(retry_query) = packet::edns_retry_query(&self.query, &parsed_response) {
+                self.query = Cow::Owned(retry_query);
+                self.protocol = self.initial_protocol;
+                self.edns_retried = true;
+                return Ok(Step::Retry(Retry::Edns));
+            }
+        }
+        parsed_response.set_tid(self.query_tid);
+        Ok(Step::Done(parsed_response))
+    }
+}
+
+/// What to do after an exchange with an upstream server has completed.
+// Steps are matched as soon as they are returned, like the results they hold
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub(crate) enum FailoverStep {
+    /// Send the query to `to`, since `from` failed or returned a response
+    /// that triggers a failover.
+    Next {
+        from: UpstreamServer,
+        to: UpstreamServer,
+    },
+    /// All the upstream servers that had to be tried have been.
+    Done(Result<(ParsedPacket, QueryInfo), io::Error>),
+}
+
+/// The exchanges of a query with a list of upstream servers, one after the
+/// other, until one of them returns a response that doesn't trigger a
+/// failover.
+#[derive(Debug)]
+pub(crate) struct Failover<'t> {
+    upstream_servers: Cow<'t, [UpstreamServer]>,
+    failover_policy: &'t FailoverPolicy,
+    current: usize,
+    query_info: QueryInfo,
+    failover_response: Option<ParsedPacket>,
+    errors: Vec<(UpstreamServer, io::Error)>,
+}
+
+impl<'t> Failover<'t> {
+    pub(crate) fn new(
+        upstream_servers: Cow<'t, [UpstreamServer]>,
+        failover_policy: &'t FailoverPolicy,
+    ) -> Self {
+        Failover {
+            upstream_servers,
+            failover_policy,
+            current: 0,
+            query_info: QueryInfo::default(),
+            failover_response: None,
+            errors: vec![],
+        }
+    }
+
+    /// The upstream server to send the query to, or `None` if there are no
+    /// upstream servers.
+    pub(crate) fn upstream_server(&self) -> Option<&UpstreamServer> {
+        self.upstream_servers.get(self.current)
+    }
+
+    /// How the query has been sent so far.
+    pub(crate) fn query_info(&mut self) -> &mut QueryInfo {
+        &mut self.query_info
+    }
+
+    /// The result when there are no upstream servers to send the query to.
+    pub(crate) fn no_upstream_servers(self) -> Result<(ParsedPacket, QueryInfo), io::Error> {
+        Err(no_response_error(self.errors))
+    }
+
+    /// Handle the result of the exchange with `upstream_server()`, that
+    /// completed after `rtt`, and decide what to do next.
+    pub(crate) fn on_result(
+        &mut self,
+        res: Result<ParsedPacket, io::Error>,
+        rtt: Duration,
+    ) -> FailoverStep {
+        let upstream_server = &self.upstream_servers[self.current];
+        match res {
+            Ok(parsed_response) => {
+                self.query_info.server = Some(upstream_server.clone());
+                self.query_info.rtt = rtt;
+                if !self
+                    .failover_policy
+                    .should_failover(parsed_response.rcode())
+                {
+                    return FailoverStep::Done(Ok((
+                        parsed_response,
+                        std::mem::take(&mut self.query_info),
+                    )));
+                }
+                self.failover_response = Some(parsed_response);
+            }
+            Err(e) => self.errors.push((upstream_server.clone(), e)),
+        }
+        self.current += 1;
+        if let Some(next_upstream_server) = self.upstream_servers.get(self.current) {
+            return FailoverStep::Next {
+                from: self.upstream_servers[self.current - 1].clone(),
+                to: next_upstream_server.clone(),
+            };
+        }
+        FailoverStep::Done(match self.failover_response.take() {
+            Some(parsed_response) => Ok((parsed_response, std::mem::take(&mut self.query_info))),
+            None => Err(no_response_error(std::mem::take(&mut self.errors))),
+        })
+    }
+}
+
+/// What to do after a query has been sent to the upstream servers.
+// Steps are matched as soon as they are returned, like the results they hold
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub(crate) enum RetryStep {
+    /// Wait for `backoff`, then send the query to the upstream servers
+    /// again. `attempt` is the number of the retry, starting from `1`.
+    Backoff { attempt: u32, backoff: Duration },
+    /// The resolution is complete.
+    Done(Result<(ParsedPacket, QueryInfo), io::Error>),
+}
+
+/// The retries of a query, sent to the upstream servers again according to
+/// the retry policy when they all failed.
+#[derive(Debug)]
+pub(crate) struct Retries<'t> {
+    retry_policy: &'t RetryPolicy,
+    deadline: Deadline,
+    retry: u32,
+    previous_attempts: u32,
+}
+
+impl<'t> Retries<'t> {
+    pub(crate) fn new(retry_policy: &'t RetryPolicy, deadline: Deadline) -> Self {
+        Retries {
+            retry_policy,
+            deadline,
+            retry: 0,
+            previous_attempts: 0,
+        }
+    }
+
+    /// Handle the result of sending the query to the upstream servers, and
+    /// decide whether to send it again. Successful results count the
+    /// attempts of the previous rounds.
+    pub(crate) fn on_result(
+        &mut self,
+        mut res: Result<(ParsedPacket, QueryInfo), io::Error>,
+    ) -> RetryStep {
+        if let Ok((_, query_info)) = &mut res {
+            query_info.attempts += self.previous_attempts;
+            self.previous_attempts = query_info.attempts;
+        }
+        if self.retry >= self.retry_policy.retries || !self.retry_policy.should_retry(&res) {
+            return RetryStep::Done(res);
+        }
+        match self.deadline.timeout(self.retry_policy.backoff(self.retry)) {
+            Ok(backoff) => {
+                self.retry += 1;
+                RetryStep::Backoff {
+                    attempt: self.retry,
+                    backoff,
+                }
+            }
+            Err(_) => RetryStep::Done(res),
+        }
+    }
+}
+
+/// The protocol to send pipelined queries to `upstream_server` over, if it
+/// supports pipelining.
+pub(crate) fn pipelined_protocol(upstream_server: &UpstreamServer) -> Option<Protocol> {
+    match upstream_server.transport {
+        Transport::Plain | Transport::Tcp => Some(Protocol::Tcp),
+        Transport::Tls { .. } => Some(Protocol::Tls),
+        _ => None,
+    }
+}
+
+/// Raw queries sent together over a single connection.
+#[derive(Debug)]
+pub(crate) struct PipelinedQueries {
+    queries: Vec<Vec<u8>>,
+    tids: Vec<u16>,
+    questions: Vec<Option<(Vec<u8>, u16, u16)>>,
+}
+
+impl PipelinedQueries {
+    /// Apply `options` to the queries, and number them from `first_tid`, so
+    /// that every query has its own transaction ID.
+    pub(crate) fn new(
+        queries: &[Vec<u8>],
+        options: &QueryOptions,
+        first_tid: u16,
+    ) -> Result<Self, io::Error> {
+        if queries.len() > usize::from(u16::MAX) + 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Too many queries",
+            ));
+        }
+        let mut pipelined_queries = PipelinedQueries {
+            queries: Vec::with_capacity(queries.len()),
+            tids: Vec::with_capacity(queries.len()),
+            questions: Vec::with_capacity(queries.len()),
+        };
+        for (i, query) in queries.iter().enumerate() {
+            let parsed_query = DNSSector::new(query.clone())
+                .and_then(|sector| sector.parse())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+            let mut parsed_query = options.apply(parsed_query)?;
+            pipelined_queries.tids.push(parsed_query.tid());
+            pipelined_queries.questions.push(parsed_query.question());
+            let mut query = parsed_query.into_packet();
+            let tid = first_tid.wrapping_add(i as u16);
+            query[DNS_TID_OFFSET..DNS_TID_OFFSET + 2].copy_from_slice(&tid.to_be_bytes());
+            pipelined_queries.queries.push(query);
+        }
+        Ok(pipelined_queries)
+    }
+
+    /// The queries to send.
+    pub(crate) fn queries(&self) -> &[Vec<u8>] {
+        &self.queries
+    }
+
+    /// Check the responses, in the order of the queries, and give them the
+    /// transaction IDs of the original queries back.
+    pub(crate) fn responses(&self, responses: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, io::Error> {
+        responses
+            .into_iter()
+            .zip(self.tids.iter().zip(&self.questions))
+            .map(|(response, (&tid, question))| {
+                let mut parsed_response = DNSSector::new(response)
+                    .and_then(|sector| sector.parse())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+                if &parsed_response.question() != question {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "Unexpected response",
+                    ));
+                }
+                parsed_response.set_tid(tid);
+                Ok(parsed_response.into_packet())
+            })
+            .collect()
+    }
+}
+
+/// Whether the upstream servers resolved a name, so that it is not resolved
+/// with LLMNR.
+pub(crate) fn resolved(res: &Result<(ParsedPacket, QueryInfo), io::Error>) -> bool {
+    match res {
+        Ok((parsed_response, _)) => parsed_response.rcode() != u8::from(Rcode::NXDOMAIN),
+        Err(_) => false,
+    }
+}
+
+#[test]
+fn test_exchange() {
+    let parsed_query =
+        packet::query("example.com", Type::A, Class::IN, Default::default()).unwrap();
+    let query_tid = parsed_query.tid();
+    let query = parsed_query.into_packet();
+    let query_question = DNSSector::new(query.clone())
+        .unwrap()
+        .parse()
+        .unwrap()
+        .question();
+    let response = |rcode: u8| {
+        packet::synthesize_response(&query, rcode, &[])
+            .unwrap()
+            .into_packet()
+    };
+
+    let mut exchange = Exchange::new(
+        &query,
+        query_tid,
+        &query_question,
+        Protocol::Udp,
+        TruncationPolicy::RetryTcp,
+    );
+    assert_eq!(exchange.protocol(), Protocol::Udp);
+    assert_eq!(exchange.query(), &query[..]);
+    let mut truncated = response(0);
+    truncated[DNS_FLAGS_OFFSET] |= (DNS_FLAG_TC >> 8) as u8;
+    assert!(matches!(
+        exchange.on_response(truncated.clone()),
+        Ok(Step::Retry(Retry::Truncated))
+    ));
+    assert_eq!(exchange.protocol(), Protocol::Tcp);
+    // Truncated TCP responses are returned as is
+    assert!(matches!(exchange.on_response(truncated), Ok(Step::Done(_))));
+
+    let mut exchange = Exchange::new(
+        &query,
+        query_tid,
+        &query_question,
+        Protocol::Udp,
+        TruncationPolicy::RetryTcp,
+    );
+    let mut spoofed = response(0);
+    spoofed[DNS_TID_OFFSET] ^= 0xff;
+    assert_eq!(
+        exchange.on_response(spoofed).unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    assert_eq!(
+        exchange.on_response(vec![0; 4]).unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+    match exchange.on_response(response(3)) {
+        Ok(Step::Done(parsed_response)) => assert_eq!(parsed_response.rcode(), 3),
+        _ => panic!("Unexpected step"),
+    }
+
+    let mut exchange = Exchange::new(
+        &query,
+        query_tid,
+        &query_question,
+        Protocol::Udp,
+        TruncationPolicy::RetryTcp,
+    );
+    exchange.start_attempt(query_tid ^ 0x5555);
+    assert_eq!(
+        exchange.query()[DNS_HEADER_SIZE..],
+        query[DNS_HEADER_SIZE..]
+    );
+    assert_eq!(
+        exchange.on_response(response(0)).unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    exchange.start_attempt(query_tid ^ 0xaaaa);
+    let mut late = response(0);
+    late[DNS_TID_OFFSET..DNS_TID_OFFSET + 2].copy_from_slice(&(query_tid ^ 0x5555).to_be_bytes());
+    assert_eq!(
+        exchange.on_response(late).unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    let mut current = response(0);
+    current[DNS_TID_OFFSET..DNS_TID_OFFSET + 2]
+        .copy_from_slice(&(query_tid ^ 0xaaaa).to_be_bytes());
+    match exchange.on_response(current) {
+        Ok(Step::Done(parsed_response)) => assert_eq!(parsed_response.tid(), query_tid),
+        _ => panic!("Unexpected step"),
+    }
+}
+
+#[test]
+fn test_failover() {
+    let upstream_servers = vec![
+        UpstreamServer::new(([192, 0, 2, 1], 53)),
+        UpstreamServer::new(([192, 0, 2, 2], 53)),
+        UpstreamServer::new(([192, 0, 2, 3], 53)),
+    ];
+    let query = packet::query("example.com", Type::A, Class::IN, Default::default())
+        .unwrap()
+        .into_packet();
+    let response = |rcode: u8| packet::synthesize_response(&query, rcode, &[]).unwrap();
+    let failover_policy = FailoverPolicy::default();
+
+    let mut failover = Failover::new(Cow::Borrowed(&upstream_servers), &failover_policy);
+    assert_eq!(failover.upstream_server(), Some(&upstream_servers[0]));
+    let timeout = io::Error::new(io::ErrorKind::TimedOut, "Timeout");
+    match failover.on_result(Err(timeout), Duration::ZERO) {
+        FailoverStep::Next { from, to } => {
+            assert_eq!(from, upstream_servers[0]);
+            assert_eq!(to, upstream_servers[1]);
+        }
+        step => panic!("Unexpected step: {:?}", step),
+    }
+    assert_eq!(failover.upstream_server(), Some(&upstream_servers[1]));
+    let servfail = u8::from(Rcode::SERVFAIL);
+    assert!(matches!(
+        failover.on_result(Ok(response(servfail)), Duration::ZERO),
+        FailoverStep::Next { .. }
+    ));
+    let rtt = Duration::from_millis(10);
+    match failover.on_result(Ok(response(0)), rtt) {
+        FailoverStep::Done(Ok((parsed_response, query_info))) => {
+            assert_eq!(parsed_response.rcode(), 0);
+            assert_eq!(query_info.server, Some(upstream_servers[2].clone()));
+            assert_eq!(query_info.rtt, rtt);
+        }
+        step => panic!("Unexpected step: {:?}", step),
+    }
+
+    // The last response triggering a failover is returned if no server
+    // returned a better one
+    let mut failover = Failover::new(Cow::Borrowed(&upstream_servers[..1]), &failover_policy);
+    match failover.on_result(Ok(response(servfail)), Duration::ZERO) {
+        FailoverStep::Done(Ok((parsed_response, _))) => {
+            assert_eq!(parsed_response.rcode(), servfail)
+        }
+        step => panic!("Unexpected step: {:?}", step),
+    }
+
+    let failover = Failover::new(Cow::Borrowed(&[]), &failover_policy);
+    assert!(failover.upstream_server().is_none());
+    assert!(failover.no_upstream_servers().is_err());
+}
+
+#[test]
+fn test_retries() {
+    let timeout = || Err(io::Error::new(io::ErrorKind::TimedOut, "Timeout"));
+    let retry_policy = RetryPolicy {
+        backoff: Duration::from_millis(100),
+        ..RetryPolicy::new(2)
+    };
+    let mut retries = Retries::new(&retry_policy, Deadline::default());
+    assert!(matches!(
+        retries.on_result(timeout()),
+        RetryStep::Backoff { attempt: 1, backoff } if backoff == Duration::from_millis(100)
+    ));
+    assert!(matches!(
+        retries.on_result(timeout()),
+        RetryStep::Backoff { attempt: 2, backoff } if backoff == Duration::from_millis(200)
+    ));
+    assert!(matches!(
+        retries.on_result(timeout()),
+        RetryStep::Done(Err(_))
+    ));
+
+    // Nothing is retried once the deadline has passed
+    let mut retries = Retries::new(&retry_policy, Deadline::after(Some(Duration::ZERO)));
+    assert!(matches!(
+        retries.on_result(timeout()),
+        RetryStep::Done(Err(_))
+    ));
+}
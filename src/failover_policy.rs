@@ -0,0 +1,112 @@
+use dnssector::constants::Rcode;
+
+/// Which response codes make the client try the next upstream server, as it
+/// does when a server doesn't respond.
+///
+/// `NOERROR` responses, including empty ones, and `NXDOMAIN` responses are
+/// authoritative and always returned. By default, the next server is tried
+/// after `SERVFAIL` and `REFUSED` responses, that are usually specific to a
+/// server.
+///
+/// If all the servers respond with a response code that triggers a failover,
+/// the last response is returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FailoverPolicy {
+    /// Try the next server after a `SERVFAIL` response.
+    pub servfail: bool,
+    /// Try the next server after a `NOTIMP` response.
+    pub notimp: bool,
+    /// Try the next server after a `REFUSED` response.
+    pub refused: bool,
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        FailoverPolicy {
+            servfail: true,
+            notimp: false,
+            refused: true,
+        }
+    }
+}
+
+impl FailoverPolicy {
+    /// Return every response, only trying the next server when a server
+    /// doesn't respond.
+    pub fn none() -> Self {
+        FailoverPolicy {
+            servfail: false,
+            notimp: false,
+            refused: false,
+        }
+    }
+
+    /// Try the next server after `SERVFAIL`, `NOTIMP` and `REFUSED`
+    /// responses.
+    pub fn all() -> Self {
+        FailoverPolicy {
+            servfail: true,
+            notimp: true,
+            refused: true,
+        }
+    }
+
+    pub(crate) fn should_failover(&self, rcode: u8) -> bool {
+        match rcode {
+            x if x == u8::from(Rcode::SERVFAIL) => self.servfail,
+            x if x == u8::from(Rcode::NOTIMPL) => self.notimp,
+            x if x == u8::from(Rcode::REFUSED) => self.refused,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_failover_policy() {
+    use std::net::Ipv4Addr;
+
+    use crate::error::DnsError;
+    use crate::testing::{MockAction, MockBackend, MockDnsServer};
+
+    let refusing_backend = MockBackend::new();
+    let refused = MockAction::Respond {
+        rcode: Rcode::REFUSED.into(),
+        answers: vec![],
+    };
+    refusing_backend
+        .set_action("example.com", "A", refused)
+        .unwrap();
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let refusing_server = MockDnsServer::start(refusing_backend).unwrap();
+    let server = MockDnsServer::start(backend).unwrap();
+
+    let mut dns_client = crate::sync::DNSClient::new(vec![
+        refusing_server.upstream_server(),
+        server.upstream_server(),
+    ]);
+    dns_client.set_failover_policy(FailoverPolicy::none());
+    let e = dns_client.query_a_detailed("example.com").unwrap_err();
+    assert!(matches!(
+        DnsError::from(e),
+        DnsError::ServFail { rcode } if rcode == u8::from(Rcode::REFUSED)
+    ));
+
+    dns_client.set_failover_policy(FailoverPolicy::default());
+    let (ips, info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    assert_eq!(info.server, Some(server.upstream_server()));
+    assert_eq!(info.attempts, 2);
+
+    // NXDOMAIN is never retried away
+    let response = dns_client
+        .query_parsed("other.example.com", "IN", "A")
+        .unwrap();
+    assert_eq!(response.rcode(), u8::from(Rcode::NXDOMAIN));
+    assert_eq!(
+        response.info.server,
+        Some(refusing_server.upstream_server())
+    );
+}
@@ -0,0 +1,219 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use dnssector::constants::*;
+use dnssector::*;
+
+use crate::backend::SyncBackend;
+use crate::packet;
+use crate::sync::GenericDNSClient;
+
+/// A local DNS forwarder, listening over UDP and TCP on the same port, and
+/// resolving the queries it receives with a synchronous client.
+///
+/// Queries go through the client like any other query: static records, the
+/// blocklist, interceptors and the upstream servers of the client all apply.
+/// Queries that can't be resolved get a `SERVFAIL` response, and UDP
+/// responses larger than what the client supports are truncated.
+///
+/// The forwarder stops when it is dropped.
+#[derive(Debug)]
+pub struct Forwarder {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Forwarder {
+    /// Start forwarding queries received on `listen_addr` to `dns_client`.
+    ///
+    /// If the port is `0`, a port available over both UDP and TCP is chosen.
+    pub fn start<B: SyncBackend>(
+        listen_addr: SocketAddr,
+        dns_client: GenericDNSClient<B>,
+    ) -> Result<Self, io::Error> {
+        let (udp_socket, tcp_listener) = Self::bind(listen_addr)?;
+        let addr = udp_socket.local_addr()?;
+        let dns_client = Arc::new(dns_client);
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = vec![];
+        {
+            let (dns_client, stop) = (dns_client.clone(), stop.clone());
+            threads.push(thread::spawn(move || {
+                Self::serve_udp(udp_socket, dns_client, stop)
+            }));
+        }
+        {
+            let stop = stop.clone();
+            threads.push(thread::spawn(move || {
+                Self::serve_tcp(tcp_listener, dns_client, stop)
+            }));
+        }
+        Ok(Forwarder {
+            addr,
+            stop,
+            threads,
+        })
+    }
+
+    /// The address the forwarder listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn bind(listen_addr: SocketAddr) -> io::Result<(UdpSocket, TcpListener)> {
+        let mut last_error = None;
+        for _ in 0..16 {
+            let tcp_listener = TcpListener::bind(listen_addr)?;
+            match UdpSocket::bind(tcp_listener.local_addr()?) {
+                Ok(udp_socket) => return Ok((udp_socket, tcp_listener)),
+                Err(e) if listen_addr.port() == 0 => last_error = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.unwrap())
+    }
+
+    /// Resolve a query, returning a `SERVFAIL` response if this fails.
+    fn respond<B: SyncBackend>(dns_client: &GenericDNSClient<B>, query: &[u8]) -> Option<Vec<u8>> {
+        match dns_client.query_raw(query, true) {
+            Ok(response) => Some(response),
+            Err(_) => packet::synthesize_response(query, Rcode::SERVFAIL.into(), &[])
+                .ok()
+                .map(ParsedPacket::into_packet),
+        }
+    }
+
+    fn serve_udp<B: SyncBackend>(
+        socket: UdpSocket,
+        dns_client: Arc<GenericDNSClient<B>>,
+        stop: Arc<AtomicBool>,
+    ) {
+        let socket = Arc::new(socket);
+        let mut query = vec![0; DNS_MAX_COMPRESSED_SIZE];
+        while !stop.load(Ordering::Relaxed) {
+            let (query_len, client_addr) = match socket.recv_from(&mut query) {
+                Ok(res) => res,
+                Err(_) => continue,
+            };
+            let query = query[..query_len].to_vec();
+            let (socket, dns_client) = (socket.clone(), dns_client.clone());
+            thread::spawn(move || {
+                let max_payload = match DNSSector::new(query.clone()).and_then(|s| s.parse()) {
+                    Ok(parsed_query) => parsed_query.max_payload(),
+                    Err(_) => return,
+                };
+                let response = match Self::respond(&dns_client, &query) {
+                    Some(response) if response.len() > max_payload => {
+                        match Self::truncated_response(&response) {
+                            Some(response) => response,
+                            None => return,
+                        }
+                    }
+                    Some(response) => response,
+                    None => return,
+                };
+                let _ = socket.send_to(&response, client_addr);
+            });
+        }
+    }
+
+    /// Strip the records of a response too large for UDP, and set the `TC`
+    /// flag.
+    fn truncated_response(response: &[u8]) -> Option<Vec<u8>> {
+        let rcode = response.get(DNS_FLAGS_OFFSET + 1)? & 0x0f;
+        let mut truncated = packet::synthesize_response(response, rcode, &[])
+            .ok()?
+            .into_packet();
+        truncated[DNS_FLAGS_OFFSET] = response[DNS_FLAGS_OFFSET] | (DNS_FLAG_TC >> 8) as u8;
+        truncated[DNS_FLAGS_OFFSET + 1] = response[DNS_FLAGS_OFFSET + 1];
+        Some(truncated)
+    }
+
+    fn serve_tcp<B: SyncBackend>(
+        listener: TcpListener,
+        dns_client: Arc<GenericDNSClient<B>>,
+        stop: Arc<AtomicBool>,
+    ) {
+        for stream in listener.incoming() {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let dns_client = dns_client.clone();
+            thread::spawn(move || Self::serve_tcp_connection(stream, dns_client));
+        }
+    }
+
+    fn serve_tcp_connection<B: SyncBackend>(
+        mut stream: TcpStream,
+        dns_client: Arc<GenericDNSClient<B>>,
+    ) -> io::Result<()> {
+        loop {
+            let mut query_len_bytes = [0u8; 2];
+            stream.read_exact(&mut query_len_bytes)?;
+            let mut query = vec![0; u16::from_be_bytes(query_len_bytes) as usize];
+            stream.read_exact(&mut query)?;
+            let response = Self::respond(&dns_client, &query)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid query"))?;
+            let mut tcp_response = Vec::with_capacity(2 + response.len());
+            tcp_response.extend_from_slice(&(response.len() as u16).to_be_bytes());
+            tcp_response.extend_from_slice(&response);
+            stream.write_all(&tcp_response)?;
+        }
+    }
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wake up the threads blocked on the sockets
+        let unspecified: SocketAddr = match self.addr {
+            SocketAddr::V4(_) => ([0u8; 4], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        if let Ok(socket) = UdpSocket::bind(unspecified) {
+            let _ = socket.send_to(&[], self.addr);
+        }
+        let _ = TcpStream::connect(self.addr);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn test_forwarder() {
+    use std::net::Ipv4Addr;
+
+    use crate::static_records::StaticRecords;
+    use crate::sync::DNSClient;
+    use crate::upstream_server::UpstreamServer;
+
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    let mut forwarding_client = DNSClient::new(vec![]);
+    forwarding_client.set_static_records(static_records);
+    let forwarder = Forwarder::start(([127, 0, 0, 1], 0).into(), forwarding_client).unwrap();
+
+    let mut dns_client = DNSClient::new(vec![UpstreamServer::new(forwarder.addr())]);
+    assert_eq!(
+        dns_client.query_a("example.com").unwrap(),
+        vec![Ipv4Addr::new(192, 0, 2, 1)]
+    );
+    let response = dns_client
+        .query_parsed("other.example.com", "IN", "A")
+        .unwrap();
+    assert_eq!(response.rcode(), u8::from(Rcode::SERVFAIL));
+    dns_client.force_tcp(true);
+    assert_eq!(
+        dns_client.query_a("example.com").unwrap(),
+        vec![Ipv4Addr::new(192, 0, 2, 1)]
+    );
+}
@@ -0,0 +1,16 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::address_family;
+
+/// Delay between two connection attempts (RFC 8305, section 5)
+pub(crate) const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Order addresses for connection attempts, alternating address families
+/// and starting with IPv6 (RFC 8305, section 4).
+pub(crate) fn interleave(ips: &[IpAddr], port: u16) -> Vec<SocketAddr> {
+    address_family::interleave(ips)
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect()
+}
@@ -0,0 +1,109 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use crate::stats::Stats;
+use crate::upstream_server::UpstreamServer;
+
+/// When upstream servers are marked down, and skipped.
+///
+/// A server is marked down after `max_failures` consecutive failed
+/// exchanges. Queries are then sent to the other servers first, until
+/// `cooldown` has elapsed since the last failure. The server then receives
+/// queries again, and is marked down again as soon as one of them fails.
+///
+/// Servers marked down are still used as a last resort, when every other
+/// server failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HealthPolicy {
+    /// The number of consecutive failures after which a server is marked
+    /// down.
+    pub max_failures: u64,
+    /// How long a server marked down is skipped.
+    pub cooldown: Duration,
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        HealthPolicy {
+            max_failures: 3,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+impl HealthPolicy {
+    /// Whether `server` failed enough to be marked down, regardless of the
+    /// cooldown.
+    pub(crate) fn has_failed(&self, server: &UpstreamServer, stats: &Stats) -> bool {
+        stats.consecutive_failures(server) >= self.max_failures.max(1)
+    }
+
+    /// Whether `server` is marked down, and must be skipped.
+    pub(crate) fn is_down(&self, server: &UpstreamServer, stats: &Stats) -> bool {
+        self.has_failed(server, stats)
+            && stats
+                .last_failure(server)
+                .is_some_and(|last_failure| last_failure.elapsed() < self.cooldown)
+    }
+
+    /// Move the servers marked down after the other ones, keeping their
+    /// relative order.
+    pub(crate) fn order<'t>(
+        &self,
+        upstream_servers: Cow<'t, [UpstreamServer]>,
+        stats: &Stats,
+    ) -> Cow<'t, [UpstreamServer]> {
+        let down: Vec<_> = upstream_servers
+            .iter()
+            .map(|server| self.is_down(server, stats))
+            .collect();
+        if !down.contains(&true) {
+            return upstream_servers;
+        }
+        let (up, down): (Vec<_>, Vec<_>) = upstream_servers
+            .iter()
+            .cloned()
+            .zip(down)
+            .partition(|(_, down)| !down);
+        Cow::Owned(
+            up.into_iter()
+                .chain(down)
+                .map(|(server, _)| server)
+                .collect(),
+        )
+    }
+}
+
+#[test]
+fn test_health_policy() {
+    use crate::events::{ClientEvent, Protocol};
+
+    let servers: Vec<_> = (1..=3)
+        .map(|i| UpstreamServer::new(([192, 0, 2, i], 53)))
+        .collect();
+    let stats = Stats::default();
+    let health_policy = HealthPolicy {
+        max_failures: 2,
+        ..Default::default()
+    };
+    let timeout = || ClientEvent::Timeout {
+        server: servers[0].clone(),
+        protocol: Protocol::Udp,
+    };
+    stats.record_event(&timeout());
+    assert!(!health_policy.is_down(&servers[0], &stats));
+    stats.record_event(&timeout());
+    assert!(health_policy.is_down(&servers[0], &stats));
+    let ordered = health_policy.order(Cow::Borrowed(&servers[..]), &stats);
+    assert_eq!(
+        ordered.as_ref(),
+        &[servers[1].clone(), servers[2].clone(), servers[0].clone()][..]
+    );
+    let health_policy = HealthPolicy {
+        cooldown: Duration::ZERO,
+        ..health_policy
+    };
+    assert!(!health_policy.is_down(&servers[0], &stats));
+    assert!(health_policy.has_failed(&servers[0], &stats));
+}
@@ -0,0 +1,159 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Number of sub-buckets per power of two, as a power of two.
+///
+/// With 8 sub-buckets, a recorded value is at most 12.5% off.
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+
+/// Values are recorded in microseconds, up to `2^MAX_EXPONENT` (~2 minutes).
+const MAX_EXPONENT: u32 = 27;
+
+const BUCKETS: usize = (MAX_EXPONENT - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS;
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let exponent = (63 - value.leading_zeros()).min(MAX_EXPONENT);
+    if exponent == MAX_EXPONENT {
+        return BUCKETS - 1;
+    }
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub_bucket = ((value >> shift) as usize) & (SUB_BUCKETS - 1);
+    (shift as usize + 1) * SUB_BUCKETS + sub_bucket
+}
+
+/// Highest value recorded in the bucket `index`.
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let sub_bucket = (index % SUB_BUCKETS) as u64;
+    ((SUB_BUCKETS as u64 + sub_bucket + 1) << shift) - 1
+}
+
+/// A log-linear latency histogram, updated with atomics.
+#[derive(Debug)]
+pub(crate) struct AtomicHistogram {
+    buckets: Box<[AtomicU64]>,
+}
+
+impl Default for AtomicHistogram {
+    fn default() -> Self {
+        AtomicHistogram {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+impl AtomicHistogram {
+    pub(crate) fn record(&self, value: Duration) {
+        let value_us = value.as_micros().min(u64::MAX as u128) as u64;
+        self.buckets[bucket_index(value_us)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> LatencyHistogram {
+        let buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter_map(|(index, count)| match count.load(Ordering::Relaxed) {
+                0 => None,
+                count => Some((Duration::from_micros(bucket_upper_bound(index)), count)),
+            })
+            .collect();
+        LatencyHistogram { buckets }
+    }
+}
+
+/// A snapshot of a latency histogram.
+///
+/// Latencies are grouped in buckets whose width grows with the latency, so
+/// that every value is known with a precision of 12.5%, from microseconds
+/// to minutes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LatencyHistogram {
+    buckets: Vec<(Duration, u64)>,
+}
+
+impl LatencyHistogram {
+    /// The number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|(_, count)| count).sum()
+    }
+
+    /// Whether no values were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// The non-empty buckets, as `(upper bound, count)` pairs, in ascending
+    /// order.
+    pub fn buckets(&self) -> &[(Duration, u64)] {
+        &self.buckets
+    }
+
+    /// The upper bound of the bucket containing the `q` quantile, with `q`
+    /// between `0.0` and `1.0`.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((count as f64) * q.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+        let mut cumulative = 0;
+        for (upper_bound, bucket_count) in &self.buckets {
+            cumulative += bucket_count;
+            if cumulative >= rank {
+                return Some(*upper_bound);
+            }
+        }
+        self.buckets.last().map(|(upper_bound, _)| *upper_bound)
+    }
+
+    /// The number of values above `threshold`, up to the bucket precision.
+    pub fn count_above(&self, threshold: Duration) -> u64 {
+        self.buckets
+            .iter()
+            .filter(|(upper_bound, _)| *upper_bound > threshold)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+#[test]
+fn test_histogram_buckets() {
+    let mut previous = None;
+    for value in 0..100_000u64 {
+        let index = bucket_index(value);
+        assert!(bucket_upper_bound(index) >= value);
+        if index > 0 {
+            assert!(bucket_upper_bound(index - 1) < value);
+        }
+        if let Some(previous) = previous {
+            assert!(index == previous || index == previous + 1);
+        }
+        previous = Some(index);
+    }
+    assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+
+    let histogram = AtomicHistogram::default();
+    assert_eq!(histogram.snapshot().quantile(0.5), None);
+    for ms in 1..=100 {
+        histogram.record(Duration::from_millis(ms));
+    }
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.count(), 100);
+    let p50 = snapshot.quantile(0.5).unwrap();
+    assert!(p50 >= Duration::from_millis(50) && p50 <= Duration::from_millis(57));
+    let p99 = snapshot.quantile(0.99).unwrap();
+    assert!(p99 >= Duration::from_millis(99) && p99 <= Duration::from_millis(112));
+    assert_eq!(snapshot.count_above(Duration::from_millis(200)), 0);
+}
@@ -0,0 +1,104 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+/// A `host:port` string, split into its components.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostPort<'t> {
+    /// The host is a numeric IP address.
+    Addr(SocketAddr),
+    /// The host is a name that has to be resolved.
+    Name(&'t str, u16),
+}
+
+impl<'t> HostPort<'t> {
+    /// Parse `host:port`, where `host` is a name, an IPv4 address, or an
+    /// IPv6 address between brackets.
+    pub(crate) fn parse(s: &'t str) -> Result<Self, io::Error> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(HostPort::Addr(addr));
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Invalid socket address");
+        let (host, port) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let port = port.parse::<u16>().map_err(|_| invalid())?;
+        if host.is_empty() || host.contains(':') || host.starts_with('[') {
+            return Err(invalid());
+        }
+        Ok(HostPort::Name(host, port))
+    }
+
+    /// Split host and port, where `host` is a name or a numeric IP address.
+    pub(crate) fn from_host(host: &'t str, port: u16) -> Self {
+        match host.parse::<IpAddr>() {
+            Ok(ip) => HostPort::Addr(SocketAddr::new(ip, port)),
+            Err(_) => HostPort::Name(host, port),
+        }
+    }
+}
+
+/// Types accepted by `lookup_host()`, like `tokio::net::ToSocketAddrs`.
+///
+/// This trait is sealed and cannot be implemented outside this crate.
+pub trait ToSocketAddrs: sealed::Sealed {}
+
+mod sealed {
+    use std::io;
+
+    use super::HostPort;
+
+    pub trait Sealed {
+        fn host_port(&self) -> Result<HostPort<'_>, io::Error>;
+    }
+}
+
+macro_rules! impl_to_socket_addrs {
+    ($t:ty, |$self:ident| $body:expr) => {
+        impl ToSocketAddrs for $t {}
+
+        impl sealed::Sealed for $t {
+            fn host_port(&$self) -> Result<HostPort<'_>, io::Error> {
+                $body
+            }
+        }
+    };
+}
+
+impl_to_socket_addrs!(SocketAddr, |self| Ok(HostPort::Addr(*self)));
+impl_to_socket_addrs!(SocketAddrV4, |self| Ok(HostPort::Addr((*self).into())));
+impl_to_socket_addrs!(SocketAddrV6, |self| Ok(HostPort::Addr((*self).into())));
+impl_to_socket_addrs!((IpAddr, u16), |self| Ok(HostPort::Addr((*self).into())));
+impl_to_socket_addrs!((Ipv4Addr, u16), |self| Ok(HostPort::Addr((*self).into())));
+impl_to_socket_addrs!((Ipv6Addr, u16), |self| Ok(HostPort::Addr((*self).into())));
+impl_to_socket_addrs!(str, |self| HostPort::parse(self));
+impl_to_socket_addrs!(String, |self| HostPort::parse(self));
+impl_to_socket_addrs!((&str, u16), |self| Ok(HostPort::from_host(self.0, self.1)));
+impl_to_socket_addrs!((String, u16), |self| Ok(HostPort::from_host(
+    &self.0, self.1
+)));
+
+impl<T: ToSocketAddrs + ?Sized> ToSocketAddrs for &T {}
+
+impl<T: ToSocketAddrs + ?Sized> sealed::Sealed for &T {
+    fn host_port(&self) -> Result<HostPort<'_>, io::Error> {
+        (**self).host_port()
+    }
+}
+
+#[test]
+fn test_host_port() {
+    assert_eq!(
+        HostPort::parse("192.0.2.1:443").unwrap(),
+        HostPort::Addr(SocketAddr::from(([192, 0, 2, 1], 443)))
+    );
+    assert_eq!(
+        HostPort::parse("[2001:db8::1]:53").unwrap(),
+        HostPort::Addr("[2001:db8::1]:53".parse().unwrap())
+    );
+    assert_eq!(
+        HostPort::parse("example.com:443").unwrap(),
+        HostPort::Name("example.com", 443)
+    );
+    assert!(HostPort::parse("example.com").is_err());
+    assert!(HostPort::parse("example.com:https").is_err());
+    assert!(HostPort::parse(":443").is_err());
+    assert!(HostPort::parse("2001:db8::1:53").is_err());
+}
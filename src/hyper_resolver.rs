@@ -0,0 +1,95 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use hyper_util::client::legacy::connect::dns::Name;
+use tower_service::Service;
+
+use crate::backend::async_tokio::TokioBackend;
+use crate::r#async::{AsyncBackend, GenericDNSClient};
+
+/// A resolver for `hyper` clients, sending the lookups of an `HttpConnector`
+/// through an async client.
+///
+/// ```no_run
+/// use dnsclient::r#async::tokio::DNSClient;
+/// use dnsclient::HyperResolver;
+/// use hyper_util::client::legacy::connect::HttpConnector;
+///
+/// let dns_client = DNSClient::new(dnsclient::system::default_resolvers().unwrap());
+/// let connector = HttpConnector::new_with_resolver(HyperResolver::new(dns_client));
+/// ```
+#[derive(Clone, Debug)]
+pub struct HyperResolver<B = TokioBackend> {
+    dns_client: Arc<GenericDNSClient<B>>,
+}
+
+impl<B: AsyncBackend> HyperResolver<B> {
+    pub fn new(dns_client: GenericDNSClient<B>) -> Self {
+        HyperResolver {
+            dns_client: Arc::new(dns_client),
+        }
+    }
+}
+
+impl<B: AsyncBackend> From<GenericDNSClient<B>> for HyperResolver<B> {
+    fn from(dns_client: GenericDNSClient<B>) -> Self {
+        HyperResolver::new(dns_client)
+    }
+}
+
+impl<B: AsyncBackend + 'static> Service<Name> for HyperResolver<B> {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, io::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let dns_client = self.dns_client.clone();
+        Box::pin(async move {
+            let ips = dns_client.query_addrs(name.as_str()).await?;
+            if ips.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No addresses found",
+                ));
+            }
+            let addrs: Vec<_> = ips.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+#[test]
+fn test_hyper_resolver() {
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    use crate::address_family::AddressFamilyPreference;
+    use crate::static_records::StaticRecords;
+
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    let mut dns_client = crate::r#async::tokio::DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    dns_client.set_address_family_preference(AddressFamilyPreference::V4Only);
+    let mut resolver = HyperResolver::new(dns_client);
+    let rt = ::tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let addrs: Vec<_> = rt
+        .block_on(resolver.call(Name::from_str("example.com").unwrap()))
+        .unwrap()
+        .collect();
+    assert_eq!(addrs, vec![SocketAddr::from(([192, 0, 2, 1], 0))]);
+    assert!(rt
+        .block_on(resolver.call(Name::from_str("example.org").unwrap()))
+        .is_err());
+}
@@ -0,0 +1,91 @@
+use std::borrow::Cow;
+use std::io;
+
+/// Convert a name to A-labels (UTS-46), so that it can be sent on the wire.
+///
+/// ASCII names are returned as-is. Without the `idna` feature, other names
+/// are rejected.
+pub(crate) fn to_ascii(name: &str) -> Result<Cow<'_, str>, io::Error> {
+    if name.is_ascii() {
+        return Ok(Cow::Borrowed(name));
+    }
+    #[cfg(feature = "idna")]
+    {
+        idna::domain_to_ascii(name)
+            .map(Cow::Owned)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+    }
+    #[cfg(not(feature = "idna"))]
+    {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Non-ASCII name (the `idna` feature is required to query internationalized names)",
+        ))
+    }
+}
+
+/// Convert a name to A-labels like `to_ascii()`, returning it unchanged if
+/// the conversion fails.
+pub(crate) fn to_ascii_or_raw(name: &str) -> Cow<'_, str> {
+    to_ascii(name).unwrap_or(Cow::Borrowed(name))
+}
+
+/// Convert the A-labels of a name to U-labels, leaving labels that cannot be
+/// decoded untouched.
+#[cfg(feature = "idna")]
+pub(crate) fn to_unicode(name: &str) -> String {
+    let (unicode_name, res) = idna::domain_to_unicode(name);
+    match res {
+        Ok(()) => unicode_name,
+        Err(_) => name.to_string(),
+    }
+}
+
+#[test]
+fn test_idn() {
+    assert_eq!(to_ascii("example.com").unwrap(), "example.com");
+    #[cfg(feature = "idna")]
+    {
+        assert_eq!(to_ascii("bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(to_unicode("xn--bcher-kva.example."), "bücher.example.");
+    }
+    #[cfg(not(feature = "idna"))]
+    assert!(to_ascii("bücher.example").is_err());
+}
+
+#[cfg(all(feature = "testing", feature = "idna"))]
+#[test]
+fn test_unicode_names() {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::record_data::{Cname, RecordData};
+    use crate::testing::{MockAction, MockBackend};
+    use crate::upstream_server::UpstreamServer;
+
+    let backend = MockBackend::new();
+    backend.add_a("xn--bcher-kva.example", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let action = MockAction::Respond {
+        rcode: 0,
+        answers: vec![(
+            Cname::TYPE,
+            3600,
+            b"\x0dxn--bcher-kva\x07example\x00".to_vec(),
+        )],
+    };
+    backend.set_action("www.example", "CNAME", action).unwrap();
+    let upstream_servers = vec![UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)))];
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    assert_eq!(
+        dns_client.query_a("bücher.example").unwrap(),
+        vec![Ipv4Addr::new(192, 0, 2, 1)]
+    );
+    assert_eq!(
+        dns_client.query_cname("www.example").unwrap(),
+        Some("xn--bcher-kva.example".to_string())
+    );
+    dns_client.set_unicode_names(true);
+    assert_eq!(
+        dns_client.query_cname("www.example").unwrap(),
+        Some("bücher.example".to_string())
+    );
+}
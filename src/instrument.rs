@@ -0,0 +1,92 @@
+use std::io;
+
+use dnssector::ParsedPacket;
+use tracing::Span;
+
+use crate::events::ClientEvent;
+use crate::packet;
+use crate::presentation::{rcode_name, type_name};
+use crate::query_info::QueryInfo;
+use crate::time::Instant;
+
+/// The span of a query, covering its resolution, from the cache and static
+/// records to every exchange with upstream servers.
+pub(crate) struct QuerySpan {
+    span: Span,
+    start: Instant,
+}
+
+impl QuerySpan {
+    pub(crate) fn new(question: &Option<(Vec<u8>, u16, u16)>) -> Self {
+        let span = match question {
+            Some((name, rr_type, _)) => tracing::debug_span!(
+                "dns_query",
+                qname = %String::from_utf8_lossy(name),
+                qtype = %type_name(*rr_type)
+            ),
+            None => tracing::debug_span!("dns_query"),
+        };
+        QuerySpan {
+            span,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Record the outcome of the query, and how long it took.
+    pub(crate) fn finish(&self, res: &Result<(ParsedPacket, QueryInfo), io::Error>) {
+        let _enter = self.span.enter();
+        let elapsed = self.start.elapsed();
+        match res {
+            Ok((parsed_response, query_info)) => tracing::debug!(
+                rcode = %rcode_name(packet::extended_rcode(parsed_response)),
+                server = query_info.server.as_ref().map(tracing::field::display),
+                cache_hit = query_info.cache_hit,
+                ?elapsed,
+                "Query resolved"
+            ),
+            Err(e) => tracing::warn!(error = %e, ?elapsed, "Query failed"),
+        }
+    }
+}
+
+/// Emit a `tracing` event for an event of a client, in the span of the
+/// query it belongs to.
+pub(crate) fn trace_event(event: &ClientEvent) {
+    match event {
+        ClientEvent::QuerySent { server, protocol } => {
+            tracing::debug!(%server, ?protocol, "Query sent")
+        }
+        ClientEvent::Timeout { server, protocol } => {
+            tracing::warn!(%server, ?protocol, "Timeout")
+        }
+        ClientEvent::QueryCancelled { server, protocol } => {
+            tracing::debug!(%server, ?protocol, "Query cancelled")
+        }
+        ClientEvent::QueryFailed {
+            server,
+            protocol,
+            kind,
+        } => tracing::warn!(%server, ?protocol, ?kind, "Query failed"),
+        ClientEvent::TruncatedRetry { server } => {
+            tracing::debug!(%server, "Truncated response, retrying over TCP")
+        }
+        ClientEvent::Failover { from, to } => {
+            tracing::warn!(%from, %to, "No usable response, trying the next server")
+        }
+        ClientEvent::Retry { attempt, backoff } => {
+            tracing::warn!(attempt, ?backoff, "No usable response, retrying")
+        }
+        ClientEvent::InvalidResponse { server, reason } => {
+            tracing::warn!(%server, %reason, "Invalid response")
+        }
+        ClientEvent::ResponseReceived {
+            server,
+            protocol,
+            rtt,
+        } => tracing::debug!(%server, ?protocol, ?rtt, "Response received"),
+    }
+}
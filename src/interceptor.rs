@@ -0,0 +1,90 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use dnssector::ParsedPacket;
+
+/// A hook to inspect and modify queries and responses.
+///
+/// Interceptors run in the order they were added to a client. Returning an
+/// error aborts the query, and the error is returned to the caller.
+pub trait Interceptor: Send + Sync {
+    /// Called with every query, before it is answered locally or sent to
+    /// upstream servers.
+    fn on_query(&self, _query: &mut ParsedPacket) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    /// Called with every response, before it is returned to the caller.
+    fn on_response(&self, _response: &mut ParsedPacket) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors(Vec<Arc<dyn Interceptor>>);
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Interceptors({})", self.0.len())
+    }
+}
+
+impl Interceptors {
+    pub(crate) fn push(&mut self, interceptor: Arc<dyn Interceptor>) {
+        self.0.push(interceptor)
+    }
+
+    pub(crate) fn on_query(&self, query: &mut ParsedPacket) -> Result<(), io::Error> {
+        self.0
+            .iter()
+            .try_for_each(|interceptor| interceptor.on_query(query))
+    }
+
+    pub(crate) fn on_response(&self, response: &mut ParsedPacket) -> Result<(), io::Error> {
+        self.0
+            .iter()
+            .try_for_each(|interceptor| interceptor.on_response(response))
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_query_raw_passthrough() {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use dnssector::constants::{Class, Type, DNS_HEADER_SIZE};
+
+    use crate::packet;
+    use crate::testing::MockBackend;
+    use crate::upstream_server::UpstreamServer;
+
+    struct SetTid;
+
+    impl Interceptor for SetTid {
+        fn on_response(&self, response: &mut ParsedPacket) -> Result<(), io::Error> {
+            response.set_tid(0xffff);
+            Ok(())
+        }
+    }
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let upstream_servers = vec![UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)))];
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    dns_client.add_interceptor(SetTid);
+    let mut parsed_query =
+        packet::query("example.com", Type::A, Class::IN, Default::default()).unwrap();
+    parsed_query.set_tid(0x1234);
+    let query = parsed_query.into_packet();
+
+    let response = dns_client.query_raw(&query, false).unwrap();
+    assert_eq!(u16::from_be_bytes([response[0], response[1]]), 0xffff);
+    let (response, rcode) = dns_client.query_raw_passthrough(&query).unwrap();
+    assert_eq!(rcode, 0);
+    assert_eq!(u16::from_be_bytes([response[0], response[1]]), 0x1234);
+    assert_eq!(
+        &response[DNS_HEADER_SIZE..query.len()],
+        &query[DNS_HEADER_SIZE..]
+    );
+}
@@ -1,26 +1,154 @@
 #![doc = include_str!("../README.md")]
 
-#[cfg(any(feature = "async", feature = "async-tokio"))]
+mod address_family;
+mod answer_order;
 pub mod r#async;
+mod audit;
 mod backend;
+mod blocklist;
+mod builder;
+mod cache;
+mod captive;
+mod capture;
+mod compare;
+mod deadline;
+mod diagnostics;
+mod dns64;
+mod dns_sd;
+#[cfg(feature = "tower")]
+mod dns_service;
+#[cfg(feature = "dnssec")]
+mod dnssec;
+#[cfg(feature = "doh")]
+mod doh;
+mod domain_routes;
+#[cfg(feature = "doq")]
+mod doq;
+mod edns_probe;
+mod error;
+mod events;
+mod exchange;
+mod failover_policy;
+mod forwarder;
+mod happy_eyeballs;
+mod health;
+mod histogram;
+mod host_port;
+#[cfg(feature = "hyper")]
+mod hyper_resolver;
+mod idn;
+#[cfg(feature = "tracing")]
+mod instrument;
+mod interceptor;
+mod llmnr;
+mod mdns;
+mod name_validation;
+#[cfg(feature = "odoh")]
+mod odoh;
+mod opportunistic_tls;
+mod packet;
+mod padding;
+pub mod presentation;
+mod propagation;
+mod query_info;
+mod query_options;
+mod reachability;
+mod record_data;
+#[cfg(feature = "reqwest")]
+mod reqwest_resolver;
+mod resolver;
+mod response;
+mod retry_policy;
+mod reverse;
+mod rng;
+mod search_list;
+mod shutdown;
+mod socket_options;
+mod socks5;
 pub mod sync;
 
+mod stamp;
+mod static_records;
+mod stats;
+#[cfg(feature = "stream")]
+mod stream;
+mod svcb;
 pub mod system;
+#[cfg(feature = "metrics")]
+mod telemetry;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod time;
+#[cfg(feature = "tls")]
+mod tls;
+mod truncation_policy;
 mod upstream_server;
+mod upstream_strategy;
 
+pub use crate::address_family::*;
+pub use crate::answer_order::*;
+pub use crate::audit::*;
+pub use crate::blocklist::*;
+pub use crate::builder::*;
+pub use crate::captive::*;
+pub use crate::capture::*;
+pub use crate::compare::*;
+pub use crate::diagnostics::*;
+pub use crate::dns64::*;
+pub use crate::dns_sd::*;
+#[cfg(feature = "tower")]
+pub use crate::dns_service::*;
+#[cfg(feature = "dnssec")]
+pub use crate::dnssec::*;
+pub use crate::domain_routes::*;
+pub use crate::edns_probe::*;
+pub use crate::error::*;
+pub use crate::events::*;
+pub use crate::failover_policy::*;
+pub use crate::forwarder::*;
+pub use crate::health::*;
+pub use crate::histogram::*;
+#[cfg(feature = "hyper")]
+pub use crate::hyper_resolver::*;
+pub use crate::interceptor::*;
+pub use crate::name_validation::*;
+pub use crate::padding::*;
+pub use crate::propagation::*;
+pub use crate::query_info::*;
+pub use crate::query_options::*;
+pub use crate::reachability::*;
+pub use crate::record_data::*;
+#[cfg(feature = "reqwest")]
+pub use crate::reqwest_resolver::*;
+pub use crate::resolver::*;
+pub use crate::response::*;
+pub use crate::retry_policy::*;
+pub use crate::reverse::*;
+pub use crate::search_list::*;
+pub use crate::socket_options::*;
+pub use crate::socks5::*;
+pub use crate::static_records::*;
+pub use crate::stats::*;
+pub use crate::svcb::*;
+pub use crate::truncation_policy::*;
 pub use crate::upstream_server::*;
-
-#[cfg(all(feature = "async", feature = "async-tokio"))]
-compile_error!(
-    "Multiple, incompatible backends have been enabled. Use `default-features = false` in order \
-     to disable the default backend, and only pick the one you need."
-);
+pub use crate::upstream_strategy::*;
 
 pub mod reexports {
+    #[cfg(feature = "async-io")]
+    pub use async_io;
     #[cfg(feature = "async")]
     pub use async_std;
     pub use dnssector;
+    #[cfg(feature = "stream")]
+    pub use futures_core;
+    #[cfg(feature = "hyper")]
+    pub use hyper_util;
     pub use rand;
+    #[cfg(feature = "reqwest")]
+    pub use reqwest;
     #[cfg(feature = "async-tokio")]
     pub use tokio;
+    #[cfg(any(feature = "tower", feature = "hyper"))]
+    pub use tower_service;
 }
@@ -0,0 +1,73 @@
+//! Link-Local Multicast Name Resolution (RFC 4795), for single-label names.
+//!
+//! LLMNR messages share the format of DNS messages, except for some header
+//! flags. Responders answer directly to the querier, repeating the
+//! transaction ID and the question.
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+use dnssector::constants::DNS_FLAGS_OFFSET;
+
+use crate::packet;
+use crate::upstream_server::UpstreamServer;
+
+/// The IPv4 LLMNR group.
+pub(crate) const LLMNR_V4_GROUP: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 252), 5355));
+
+/// How long to wait for a response (`LLMNR_TIMEOUT`).
+const LLMNR_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Check if a name is made of a single label.
+pub(crate) fn is_single_label_name(name: &[u8]) -> bool {
+    let name = packet::normalize_name(name);
+    !name.is_empty() && !name.contains(&b'.')
+}
+
+/// Turn a DNS query into an LLMNR query, whose `RD` bit is the `T`
+/// (tentative) bit, that must be clear.
+pub(crate) fn query(query: &[u8]) -> Vec<u8> {
+    let mut query = query.to_vec();
+    query[DNS_FLAGS_OFFSET] &= !0x01;
+    query
+}
+
+/// The pseudo upstream server that LLMNR queries are reported as sent to.
+pub(crate) fn upstream_server() -> UpstreamServer {
+    UpstreamServer::new(LLMNR_V4_GROUP)
+}
+
+/// The time to wait for a response, capped by the client timeout.
+pub(crate) fn timeout(client_timeout: Duration) -> Duration {
+    client_timeout.min(LLMNR_TIMEOUT)
+}
+
+#[test]
+fn test_is_single_label_name() {
+    assert!(is_single_label_name(b"printer"));
+    assert!(is_single_label_name(b"PRINTER."));
+    assert!(!is_single_label_name(b"printer.example"));
+    assert!(!is_single_label_name(b"."));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_llmnr() {
+    use crate::testing::MockBackend;
+
+    let backend = MockBackend::new();
+    backend.add_a("printer", &[Ipv4Addr::new(192, 168, 1, 20)]);
+    backend.add_a("printer.example", &[Ipv4Addr::new(192, 168, 1, 20)]);
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(vec![], backend);
+    assert!(dns_client.query_a("printer").is_err());
+    dns_client.set_llmnr(true);
+    let (ips, query_info) = dns_client.query_a_detailed("printer").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 168, 1, 20)]);
+    assert_eq!(
+        query_info.server.unwrap().addr,
+        SocketAddr::from(([224, 0, 0, 252], 5355))
+    );
+    // Names with several labels are never resolved with LLMNR
+    assert!(dns_client.query_a("printer.example").is_err());
+}
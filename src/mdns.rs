@@ -0,0 +1,57 @@
+//! One-shot multicast DNS queries (RFC 6762, section 5.1), for names under
+//! `.local`.
+//!
+//! Queries are sent from an ephemeral port, so that responders answer
+//! directly to the querier with a regular unicast response, repeating the
+//! transaction ID and the question.
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use crate::packet;
+use crate::upstream_server::UpstreamServer;
+
+/// The IPv4 multicast DNS group.
+pub(crate) const MDNS_V4_GROUP: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353));
+
+/// Check if a name belongs to the `.local` domain, reserved for multicast
+/// DNS.
+pub(crate) fn is_local_name(name: &[u8]) -> bool {
+    let name = packet::normalize_name(name);
+    name == b"local" || name.ends_with(b".local")
+}
+
+/// The pseudo upstream server that multicast DNS queries are reported as
+/// sent to.
+pub(crate) fn upstream_server() -> UpstreamServer {
+    UpstreamServer::new(MDNS_V4_GROUP)
+}
+
+#[test]
+fn test_is_local_name() {
+    assert!(is_local_name(b"printer.local"));
+    assert!(is_local_name(b"Printer.LOCAL."));
+    assert!(is_local_name(b"local"));
+    assert!(!is_local_name(b"printer.local.example"));
+    assert!(!is_local_name(b"notlocal"));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_mdns() {
+    use crate::testing::MockBackend;
+
+    let backend = MockBackend::new();
+    backend.add_a("printer.local", &[Ipv4Addr::new(192, 168, 1, 20)]);
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(vec![], backend);
+    assert!(dns_client.query_a("printer.local").is_err());
+    dns_client.set_mdns(true);
+    let (ips, query_info) = dns_client.query_a_detailed("printer.local").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 168, 1, 20)]);
+    assert_eq!(
+        query_info.server.unwrap().addr,
+        SocketAddr::from(([224, 0, 0, 251], 5353))
+    );
+    // Other names are still sent to upstream servers
+    assert!(dns_client.query_a("printer.example").is_err());
+}
@@ -0,0 +1,188 @@
+use std::io;
+
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 253;
+const MAX_WIRE_NAME_LEN: usize = 255;
+
+/// How query names are validated before being sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum NameValidation {
+    /// Only accept host names: labels made of letters, digits and hyphens,
+    /// that don't start or end with a hyphen.
+    Strict,
+    /// Also accept labels with underscores and other printable characters,
+    /// as used by `SRV`, `TXT` and DMARC owner names.
+    #[default]
+    Relaxed,
+}
+
+impl NameValidation {
+    /// Check that `name` is a valid name, whose labels are at most 63
+    /// characters long and whose total length is at most 253 characters.
+    ///
+    /// A single trailing dot, marking a fully qualified name, is accepted.
+    /// The root zone is `.`.
+    pub fn validate(self, name: &str) -> Result<(), io::Error> {
+        let invalid = |reason: String| {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid name [{}]: {}", name, reason),
+            ))
+        };
+        if name == "." {
+            return Ok(());
+        }
+        let relative_name = name.strip_suffix('.').unwrap_or(name);
+        if relative_name.is_empty() {
+            return invalid("empty name (the root zone is `.`)".to_string());
+        }
+        if relative_name.len() > MAX_NAME_LEN {
+            return invalid(format!("longer than {} characters", MAX_NAME_LEN));
+        }
+        for label in relative_name.split('.') {
+            if label.is_empty() {
+                return invalid("empty label".to_string());
+            }
+            if label.len() > MAX_LABEL_LEN {
+                return invalid(format!(
+                    "label [{}] is longer than {} characters",
+                    label, MAX_LABEL_LEN
+                ));
+            }
+            let valid_char = |c: char| match self {
+                NameValidation::Strict => c.is_ascii_alphanumeric() || c == '-',
+                NameValidation::Relaxed => c.is_ascii_graphic(),
+            };
+            if let Some(c) = label.chars().find(|&c| !valid_char(c)) {
+                return invalid(format!("invalid character {:?} in label [{}]", c, label));
+            }
+            if self == NameValidation::Strict && (label.starts_with('-') || label.ends_with('-')) {
+                return invalid(format!("label [{}] starts or ends with a hyphen", label));
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert a name in wire format, a sequence of length-prefixed labels
+    /// ending with an empty label, to a name that can be queried, and check
+    /// that it is valid.
+    ///
+    /// ```
+    /// use dnsclient::NameValidation;
+    ///
+    /// let name = NameValidation::Strict.name_from_wire(b"\x07example\x03com\x00").unwrap();
+    /// assert_eq!(name, "example.com");
+    /// ```
+    pub fn name_from_wire(self, wire_name: &[u8]) -> Result<String, io::Error> {
+        let invalid = |reason: &str| {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid wire-format name: {}", reason),
+            ))
+        };
+        if wire_name.len() > MAX_WIRE_NAME_LEN {
+            return invalid("longer than 255 bytes");
+        }
+        let mut labels = vec![];
+        let mut it = wire_name;
+        loop {
+            let (&len, rest) = match it.split_first() {
+                Some(x) => x,
+                None => return invalid("missing final empty label"),
+            };
+            if len == 0 {
+                if !rest.is_empty() {
+                    return invalid("trailing data");
+                }
+                break;
+            }
+            if len & 0xc0 != 0 {
+                return invalid("compressed or extended label");
+            }
+            let label = match rest.get(..len as usize) {
+                Some(label) => label,
+                None => return invalid("truncated label"),
+            };
+            if label.contains(&b'.') || !label.is_ascii() {
+                return invalid("label with a dot or a non-ASCII character");
+            }
+            labels.push(String::from_utf8_lossy(label));
+            it = &rest[len as usize..];
+        }
+        if labels.is_empty() {
+            return Ok(".".to_string());
+        }
+        let name = labels.join(".");
+        self.validate(&name)?;
+        Ok(name)
+    }
+}
+
+#[test]
+fn test_name_validation() {
+    let strict = NameValidation::Strict;
+    let relaxed = NameValidation::Relaxed;
+    for name in &[
+        ".",
+        "example.com",
+        "example.com.",
+        "a-b.example",
+        "xn--bcher-kva.example",
+    ] {
+        assert!(strict.validate(name).is_ok());
+        assert!(relaxed.validate(name).is_ok());
+    }
+    for name in &[
+        "_dmarc.example.com",
+        "_sip._tcp.example.com",
+        "*.example.com",
+    ] {
+        assert!(strict.validate(name).is_err());
+        assert!(relaxed.validate(name).is_ok());
+    }
+    let long_label = "a".repeat(64);
+    let long_name = vec!["a".repeat(63); 4].join(".");
+    for name in &[
+        "",
+        "..",
+        "example..com",
+        ".example.com",
+        "example.com..",
+        "exa mple.com",
+        long_label.as_str(),
+        long_name.as_str(),
+    ] {
+        assert!(relaxed.validate(name).is_err());
+    }
+    assert!(strict.validate("-example.com").is_err());
+}
+
+#[test]
+fn test_name_from_wire() {
+    let relaxed = NameValidation::Relaxed;
+    assert_eq!(relaxed.name_from_wire(b"\x00").unwrap(), ".");
+    assert_eq!(
+        relaxed
+            .name_from_wire(b"\x04_sip\x04_tcp\x07example\x00")
+            .unwrap(),
+        "_sip._tcp.example"
+    );
+    assert!(NameValidation::Strict
+        .name_from_wire(b"\x04_sip\x07example\x00")
+        .is_err());
+    for wire_name in &[
+        &b""[..],
+        b"\x07example",
+        b"\x07example\x00\x00",
+        b"\x08example\x00",
+        b"\x03a.b\x00",
+        b"\xc0\x0c",
+    ] {
+        assert!(relaxed.name_from_wire(wire_name).is_err());
+    }
+}
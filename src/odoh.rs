@@ -0,0 +1,586 @@
+//! Oblivious DNS-over-HTTPS (RFC 9230).
+//!
+//! Queries are encrypted to a public key of the target with HPKE (RFC 9180,
+//! base mode, X25519, HKDF-SHA256 and AES-128-GCM), and POSTed to a proxy,
+//! that forwards them to the target. Responses are encrypted with a key
+//! exported from the HPKE context of the query.
+//!
+//! The configurations of the targets, with their public keys, are fetched
+//! from the targets, and cached for `CONFIG_LIFETIME`.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey};
+use ring::hkdf::{self, KeyType, Prk};
+use ring::hmac;
+use rustls::ClientConfig;
+
+use crate::backend::sync::StdBackend;
+use crate::backend::RuntimeAdapter;
+use crate::doh;
+use crate::time::Instant;
+use crate::upstream_server::{Transport, UpstreamServer};
+
+const CONTENT_TYPE: &str = "application/oblivious-dns-message";
+
+const CONFIGS_PATH: &str = "/.well-known/odohconfigs";
+
+/// How long the configuration of a target is used before being fetched
+/// again.
+const CONFIG_LIFETIME: Duration = Duration::from_secs(3600);
+
+const ODOH_VERSION: u16 = 0x0001;
+
+const MESSAGE_TYPE_QUERY: u8 = 0x01;
+const MESSAGE_TYPE_RESPONSE: u8 = 0x02;
+
+/// Queries are padded to a multiple of this size (RFC 8467).
+const QUERY_BLOCK_SIZE: usize = 128;
+
+const KEM_X25519_HKDF_SHA256: u16 = 0x0020;
+const KDF_HKDF_SHA256: u16 = 0x0001;
+const AEAD_AES_128_GCM: u16 = 0x0001;
+
+/// The sizes of the shared secret, the public keys, the hash function
+/// output, the AEAD keys and the AEAD nonces.
+const N_SECRET: usize = 32;
+const N_PK: usize = 32;
+const N_H: usize = 32;
+const N_K: usize = 16;
+const N_N: usize = 12;
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn crypto_error(_: ring::error::Unspecified) -> io::Error {
+    invalid_data("Oblivious DNS-over-HTTPS encryption error")
+}
+
+struct Len(usize);
+
+impl KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn expand(prk: &Prk, info: &[&[u8]], len: usize) -> Vec<u8> {
+    let mut out = vec![0; len];
+    prk.expand(info, Len(len))
+        .and_then(|okm| okm.fill(&mut out))
+        .expect("Invalid HKDF output length");
+    out
+}
+
+/// HKDF-Extract, returning the raw pseudorandom key, that `ring` doesn't
+/// expose when extracting with `hkdf::Salt`.
+fn extract(salt: &[u8], ikm: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, salt), ikm)
+        .as_ref()
+        .to_vec()
+}
+
+fn prk(prk: &[u8]) -> Prk {
+    Prk::new_less_safe(hkdf::HKDF_SHA256, prk)
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Vec<u8> {
+    let labeled_ikm = [b"HPKE-v1", suite_id, label, ikm].concat();
+    extract(salt, &labeled_ikm)
+}
+
+fn labeled_expand(suite_id: &[u8], prk: &[u8], label: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let len_bytes = (len as u16).to_be_bytes();
+    expand(
+        &self::prk(prk),
+        &[&len_bytes, b"HPKE-v1", suite_id, label, info],
+        len,
+    )
+}
+
+fn kem_suite_id() -> Vec<u8> {
+    [&b"KEM"[..], &KEM_X25519_HKDF_SHA256.to_be_bytes()].concat()
+}
+
+fn hpke_suite_id() -> Vec<u8> {
+    [
+        &b"HPKE"[..],
+        &KEM_X25519_HKDF_SHA256.to_be_bytes(),
+        &KDF_HKDF_SHA256.to_be_bytes(),
+        &AEAD_AES_128_GCM.to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// The shared secret of DHKEM(X25519, HKDF-SHA256), from the result of the
+/// Diffie-Hellman exchange.
+fn kem_shared_secret(dh: &[u8], enc: &[u8], public_key: &[u8]) -> Vec<u8> {
+    let suite_id = kem_suite_id();
+    let kem_context = [enc, public_key].concat();
+    let eae_prk = labeled_extract(&suite_id, b"", b"eae_prk", dh);
+    labeled_expand(
+        &suite_id,
+        &eae_prk,
+        b"shared_secret",
+        &kem_context,
+        N_SECRET,
+    )
+}
+
+/// An HPKE context, in base mode.
+struct HpkeContext {
+    key: Vec<u8>,
+    base_nonce: Vec<u8>,
+    exporter_secret: Vec<u8>,
+}
+
+impl HpkeContext {
+    fn new(shared_secret: &[u8], info: &[u8]) -> Self {
+        let suite_id = hpke_suite_id();
+        let psk_id_hash = labeled_extract(&suite_id, b"", b"psk_id_hash", b"");
+        let info_hash = labeled_extract(&suite_id, b"", b"info_hash", info);
+        let mut key_schedule_context = vec![0x00];
+        key_schedule_context.extend_from_slice(&psk_id_hash);
+        key_schedule_context.extend_from_slice(&info_hash);
+        let secret = labeled_extract(&suite_id, shared_secret, b"secret", b"");
+        HpkeContext {
+            key: labeled_expand(&suite_id, &secret, b"key", &key_schedule_context, N_K),
+            base_nonce: labeled_expand(
+                &suite_id,
+                &secret,
+                b"base_nonce",
+                &key_schedule_context,
+                N_N,
+            ),
+            exporter_secret: labeled_expand(&suite_id, &secret, b"exp", &key_schedule_context, N_H),
+        }
+    }
+
+    /// Encrypt the first, and only message of the context.
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, io::Error> {
+        seal(&self.key, &self.base_nonce, aad, plaintext)
+    }
+
+    fn export(&self, exporter_context: &[u8], len: usize) -> Vec<u8> {
+        labeled_expand(
+            &hpke_suite_id(),
+            &self.exporter_secret,
+            b"sec",
+            exporter_context,
+            len,
+        )
+    }
+}
+
+fn seal(key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let key = LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, key).map_err(crypto_error)?);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(crypto_error)?;
+    let mut in_out = plaintext.to_vec();
+    key.seal_in_place_append_tag(nonce, Aad::from(aad), &mut in_out)
+        .map_err(crypto_error)?;
+    Ok(in_out)
+}
+
+fn open(key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let key = LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, key).map_err(crypto_error)?);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(crypto_error)?;
+    let mut in_out = ciphertext.to_vec();
+    let plaintext_len = key
+        .open_in_place(nonce, Aad::from(aad), &mut in_out)
+        .map_err(crypto_error)?
+        .len();
+    in_out.truncate(plaintext_len);
+    Ok(in_out)
+}
+
+/// Append a value prefixed with its 16-bit length.
+fn put_vec(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+/// Read a value prefixed with its 16-bit length.
+fn get_vec<'t>(input: &mut &'t [u8]) -> Result<&'t [u8], io::Error> {
+    let truncated = || invalid_data("Truncated Oblivious DNS-over-HTTPS message");
+    let len = input.get(..2).ok_or_else(truncated)?;
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    let value = input.get(2..2 + len).ok_or_else(truncated)?;
+    *input = &input[2 + len..];
+    Ok(value)
+}
+
+fn get_u16(input: &mut &[u8]) -> Result<u16, io::Error> {
+    let value = input
+        .get(..2)
+        .ok_or_else(|| invalid_data("Truncated Oblivious DNS-over-HTTPS configuration"))?;
+    let value = u16::from_be_bytes([value[0], value[1]]);
+    *input = &input[2..];
+    Ok(value)
+}
+
+/// The configuration of a target, with the public key queries are
+/// encrypted to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ObliviousDohConfig {
+    key_id: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+impl ObliviousDohConfig {
+    /// Parse the configurations returned by a target, and return the first
+    /// supported one.
+    pub(crate) fn parse(configs: &[u8]) -> Result<Self, io::Error> {
+        let mut configs = configs;
+        let mut configs = get_vec(&mut configs)?;
+        while !configs.is_empty() {
+            let version = get_u16(&mut configs)?;
+            let contents = get_vec(&mut configs)?;
+            if version != ODOH_VERSION {
+                continue;
+            }
+            let mut fields = contents;
+            let suite = (
+                get_u16(&mut fields)?,
+                get_u16(&mut fields)?,
+                get_u16(&mut fields)?,
+            );
+            let public_key = get_vec(&mut fields)?;
+            if suite != (KEM_X25519_HKDF_SHA256, KDF_HKDF_SHA256, AEAD_AES_128_GCM)
+                || public_key.len() != N_PK
+            {
+                continue;
+            }
+            return Ok(ObliviousDohConfig {
+                key_id: expand(&prk(&extract(b"", contents)), &[b"odoh key id"], N_H),
+                public_key: public_key.to_vec(),
+            });
+        }
+        Err(invalid_data(
+            "No supported Oblivious DNS-over-HTTPS configuration",
+        ))
+    }
+}
+
+/// What is needed to decrypt the response to a query.
+struct QueryContext {
+    hpke_context: HpkeContext,
+    plaintext: Vec<u8>,
+}
+
+/// Encrypt `query` to the key of `config`, and return the message to send.
+fn encrypt_query(
+    config: &ObliviousDohConfig,
+    query: &[u8],
+) -> Result<(Vec<u8>, QueryContext), io::Error> {
+    let mut plaintext = vec![];
+    put_vec(&mut plaintext, query);
+    let padded_len = query.len().div_ceil(QUERY_BLOCK_SIZE) * QUERY_BLOCK_SIZE;
+    put_vec(&mut plaintext, &vec![0; padded_len - query.len()]);
+
+    let rng = ring::rand::SystemRandom::new();
+    let ephemeral_key =
+        EphemeralPrivateKey::generate(&agreement::X25519, &rng).map_err(crypto_error)?;
+    let enc = ephemeral_key.compute_public_key().map_err(crypto_error)?;
+    let enc = enc.as_ref().to_vec();
+    let shared_secret = agreement::agree_ephemeral(
+        ephemeral_key,
+        &UnparsedPublicKey::new(&agreement::X25519, &config.public_key),
+        |dh| kem_shared_secret(dh, &enc, &config.public_key),
+    )
+    .map_err(crypto_error)?;
+    let hpke_context = HpkeContext::new(&shared_secret, b"odoh query");
+
+    let mut aad = vec![MESSAGE_TYPE_QUERY];
+    put_vec(&mut aad, &config.key_id);
+    let mut encrypted = enc;
+    encrypted.extend_from_slice(&hpke_context.seal(&aad, &plaintext)?);
+    let mut message = aad;
+    put_vec(&mut message, &encrypted);
+    Ok((
+        message,
+        QueryContext {
+            hpke_context,
+            plaintext,
+        },
+    ))
+}
+
+/// The key and the nonce protecting the response to a query.
+fn response_key_and_nonce(
+    hpke_context: &HpkeContext,
+    query_plaintext: &[u8],
+    response_nonce: &[u8],
+) -> (Vec<u8>, Vec<u8>) {
+    let secret = hpke_context.export(b"odoh response", N_K);
+    let mut salt = query_plaintext.to_vec();
+    put_vec(&mut salt, response_nonce);
+    let prk = prk(&extract(&salt, &secret));
+    (
+        expand(&prk, &[b"odoh key"], N_K),
+        expand(&prk, &[b"odoh nonce"], N_N),
+    )
+}
+
+/// Decrypt a response, and return the DNS message.
+fn decrypt_response(context: &QueryContext, message: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let (&message_type, mut fields) = message
+        .split_first()
+        .ok_or_else(|| invalid_data("Empty Oblivious DNS-over-HTTPS response"))?;
+    if message_type != MESSAGE_TYPE_RESPONSE {
+        return Err(invalid_data("Unexpected Oblivious DNS-over-HTTPS message"));
+    }
+    let response_nonce = get_vec(&mut fields)?;
+    let encrypted = get_vec(&mut fields)?;
+    let (key, nonce) =
+        response_key_and_nonce(&context.hpke_context, &context.plaintext, response_nonce);
+    let mut aad = vec![MESSAGE_TYPE_RESPONSE];
+    put_vec(&mut aad, response_nonce);
+    let plaintext = open(&key, &nonce, &aad, encrypted)?;
+    let mut plaintext = &plaintext[..];
+    Ok(get_vec(&mut plaintext)?.to_vec())
+}
+
+type ConfigCache = Mutex<HashMap<UpstreamServer, (Instant, ObliviousDohConfig)>>;
+
+fn config_cache() -> &'static ConfigCache {
+    static CONFIG_CACHE: OnceLock<ConfigCache> = OnceLock::new();
+    CONFIG_CACHE.get_or_init(Default::default)
+}
+
+fn cached_config(target: &UpstreamServer) -> Option<ObliviousDohConfig> {
+    let cache = config_cache().lock().unwrap();
+    cache
+        .get(target)
+        .filter(|(fetched_at, _)| fetched_at.elapsed() < CONFIG_LIFETIME)
+        .map(|(_, config)| config.clone())
+}
+
+fn cache_config(target: &UpstreamServer, config: &ObliviousDohConfig) {
+    let mut cache = config_cache().lock().unwrap();
+    cache.insert(target.clone(), (Instant::now(), config.clone()));
+}
+
+/// Forget the configuration of a target, so that it is fetched again, in
+/// case its keys were rotated.
+fn forget_config(target: &UpstreamServer) {
+    config_cache().lock().unwrap().remove(target);
+}
+
+/// The target, and the path of the proxy requests.
+fn proxy_path(upstream_server: &UpstreamServer) -> Result<(&UpstreamServer, String), io::Error> {
+    let (path, target) = match &upstream_server.transport {
+        Transport::ObliviousHttps { path, target, .. } => (path, target),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "[{}] is not an Oblivious DNS-over-HTTPS target",
+                    upstream_server
+                ),
+            ))
+        }
+    };
+    let (target_host, target_path) = match &target.transport {
+        Transport::Https { server_name, path } => (server_name, path),
+        _ => unreachable!("Oblivious DNS-over-HTTPS targets are DNS-over-HTTPS servers"),
+    };
+    let separator = if path.contains('?') { '&' } else { '?' };
+    let proxy_path = format!(
+        "{}{}targethost={}&targetpath={}",
+        path, separator, target_host, target_path
+    );
+    Ok((target, proxy_path))
+}
+
+/// Exchange a query with an Oblivious DNS-over-HTTPS target, using
+/// blocking sockets.
+pub(crate) fn exchange_blocking(
+    tls_config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    query: &[u8],
+    backend: &StdBackend,
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let (target, proxy_path) = proxy_path(upstream_server)?;
+    let config = match cached_config(target) {
+        Some(config) => config,
+        None => {
+            let request = doh::http_request(target, CONFIGS_PATH, "*/*", None)?;
+            let configs =
+                doh::fetch_blocking(tls_config.clone(), target, &request, None, backend, timeout)?;
+            let config = ObliviousDohConfig::parse(&configs)?;
+            cache_config(target, &config);
+            config
+        }
+    };
+    let (message, context) = encrypt_query(&config, query)?;
+    let request = doh::http_request(upstream_server, &proxy_path, CONTENT_TYPE, Some(&message))?;
+    doh::fetch_blocking(
+        tls_config,
+        upstream_server,
+        &request,
+        Some(CONTENT_TYPE),
+        backend,
+        timeout,
+    )
+    .and_then(|response| decrypt_response(&context, &response))
+    .inspect_err(|_| forget_config(target))
+}
+
+/// Exchange a query with an Oblivious DNS-over-HTTPS target, over TCP
+/// streams of `runtime`. The caller is responsible for the timeout.
+pub(crate) async fn exchange<R: RuntimeAdapter>(
+    runtime: &R,
+    tls_config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    query: &[u8],
+) -> io::Result<Vec<u8>> {
+    let (target, proxy_path) = proxy_path(upstream_server)?;
+    let config = match cached_config(target) {
+        Some(config) => config,
+        None => {
+            let request = doh::http_request(target, CONFIGS_PATH, "*/*", None)?;
+            let configs = doh::fetch(runtime, tls_config.clone(), target, &request, None).await?;
+            let config = ObliviousDohConfig::parse(&configs)?;
+            cache_config(target, &config);
+            config
+        }
+    };
+    let (message, context) = encrypt_query(&config, query)?;
+    let request = doh::http_request(upstream_server, &proxy_path, CONTENT_TYPE, Some(&message))?;
+    doh::fetch(
+        runtime,
+        tls_config,
+        upstream_server,
+        &request,
+        Some(CONTENT_TYPE),
+    )
+    .await
+    .and_then(|response| decrypt_response(&context, &response))
+    .inspect_err(|_| forget_config(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_hpke_context() {
+        // RFC 9180, A.1.1
+        let context = HpkeContext::new(
+            &unhex("fe0e18c9f024ce43799ae393c7e8fe8fce9d218875e8227b0187c04e7d2ea1fc"),
+            &unhex("4f6465206f6e2061204772656369616e2055726e"),
+        );
+        assert_eq!(context.key, unhex("4531685d41d65f03dc48f6b8302c05b0"));
+        assert_eq!(context.base_nonce, unhex("56d890e5accaaf011cff4b7d"));
+        assert_eq!(
+            context.exporter_secret,
+            unhex("45ff1c2e220db587171952c0592d5f5ebe103f1561a2614e38f2ffd47e99e3f8")
+        );
+        assert_eq!(
+            context
+                .seal(
+                    &unhex("436f756e742d30"),
+                    &unhex("4265617574792069732074727574682c20747275746820626561757479")
+                )
+                .unwrap(),
+            unhex("f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218a355a96d8770ac83d07bea87e13c512a")
+        );
+        assert_eq!(
+            context.export(b"", 32),
+            unhex("3853fe2b4035195a573ffc53856e77058e15d9ea064de3e59f4961d0095250ee")
+        );
+    }
+
+    fn config_contents(kem: u16, public_key: &[u8]) -> Vec<u8> {
+        let mut contents = vec![];
+        contents.extend_from_slice(&kem.to_be_bytes());
+        contents.extend_from_slice(&KDF_HKDF_SHA256.to_be_bytes());
+        contents.extend_from_slice(&AEAD_AES_128_GCM.to_be_bytes());
+        put_vec(&mut contents, public_key);
+        contents
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let supported = config_contents(KEM_X25519_HKDF_SHA256, &[1; N_PK]);
+        let mut configs = vec![];
+        configs.extend_from_slice(&0xff00u16.to_be_bytes());
+        put_vec(&mut configs, b"future");
+        configs.extend_from_slice(&ODOH_VERSION.to_be_bytes());
+        put_vec(&mut configs, &config_contents(0x0010, &[2; 65]));
+        configs.extend_from_slice(&ODOH_VERSION.to_be_bytes());
+        put_vec(&mut configs, &supported);
+        let mut message = vec![];
+        put_vec(&mut message, &configs);
+
+        let config = ObliviousDohConfig::parse(&message).unwrap();
+        assert_eq!(config.public_key, vec![1; N_PK]);
+        assert_eq!(
+            config.key_id,
+            expand(&prk(&extract(b"", &supported)), &[b"odoh key id"], N_H)
+        );
+
+        assert!(ObliviousDohConfig::parse(&message[..message.len() - 1]).is_err());
+        assert!(ObliviousDohConfig::parse(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_query() {
+        let config = ObliviousDohConfig {
+            key_id: vec![3; N_H],
+            public_key: unhex("3948cfe0ad1ddb695d780e59077195da6c56506b207d2f6bda9b2f4a5e8c4431"),
+        };
+        let (message, context) = encrypt_query(&config, b"query").unwrap();
+        assert_eq!(message[0], MESSAGE_TYPE_QUERY);
+        let mut fields = &message[1..];
+        assert_eq!(get_vec(&mut fields).unwrap(), &config.key_id[..]);
+        let encrypted = get_vec(&mut fields).unwrap();
+        assert!(fields.is_empty());
+        assert_eq!(encrypted.len(), N_PK + QUERY_BLOCK_SIZE + 4 + 16);
+        let mut plaintext = &context.plaintext[..];
+        assert_eq!(get_vec(&mut plaintext).unwrap(), b"query");
+        assert_eq!(
+            get_vec(&mut plaintext).unwrap(),
+            &[0; QUERY_BLOCK_SIZE - 5][..]
+        );
+    }
+
+    #[test]
+    fn test_decrypt_response() {
+        let context = QueryContext {
+            hpke_context: HpkeContext::new(&[4; N_SECRET], b"odoh query"),
+            plaintext: b"query plaintext".to_vec(),
+        };
+        let response_nonce = [5; N_K];
+        let (key, nonce) =
+            response_key_and_nonce(&context.hpke_context, &context.plaintext, &response_nonce);
+        let mut aad = vec![MESSAGE_TYPE_RESPONSE];
+        put_vec(&mut aad, &response_nonce);
+        let mut plaintext = vec![];
+        put_vec(&mut plaintext, b"response");
+        put_vec(&mut plaintext, &[0; 8]);
+        let mut message = aad.clone();
+        put_vec(&mut message, &seal(&key, &nonce, &aad, &plaintext).unwrap());
+
+        assert_eq!(decrypt_response(&context, &message).unwrap(), b"response");
+
+        let last = message.len() - 1;
+        message[last] ^= 1;
+        assert!(decrypt_response(&context, &message).is_err());
+        message[0] = MESSAGE_TYPE_QUERY;
+        assert!(decrypt_response(&context, &message).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Opportunistic DNS-over-TLS (RFC 7858, section 4.1): queries to plain DNS
+//! servers are sent over TLS to port 853 when the server supports it, and
+//! over plain DNS otherwise.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::time::Instant;
+use crate::upstream_server::{Transport, UpstreamServer};
+
+/// The port of DNS-over-TLS servers.
+const DOT_PORT: u16 = 853;
+
+/// How long a server that failed to answer over TLS is only queried over
+/// plain DNS, before being probed again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(600);
+
+/// The timeout of probes, so that servers silently dropping connections to
+/// port 853 don't delay queries by a full timeout.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether a server supports DNS-over-TLS, and since when this is known.
+#[derive(Clone, Copy, Debug)]
+struct Support {
+    supported: bool,
+    since: Instant,
+}
+
+/// What is known about the DNS-over-TLS support of plain DNS servers.
+#[derive(Debug, Default)]
+pub(crate) struct OpportunisticTls {
+    servers: Mutex<HashMap<IpAddr, Support>>,
+}
+
+impl OpportunisticTls {
+    /// The DNS-over-TLS server to try first instead of `upstream_server`,
+    /// and the timeout of the exchange with it.
+    ///
+    /// Return `None` if `upstream_server` is not a plain DNS server, or if
+    /// it recently failed to answer over TLS.
+    pub(crate) fn upgrade(
+        &self,
+        upstream_server: &UpstreamServer,
+        timeout: Duration,
+    ) -> Option<(UpstreamServer, Duration)> {
+        if upstream_server.transport != Transport::Plain {
+            return None;
+        }
+        let ip = upstream_server.addr.ip();
+        let timeout = match self.servers.lock().unwrap().get(&ip) {
+            Some(support) if support.supported => timeout,
+            Some(support) if support.since.elapsed() < RETRY_INTERVAL => return None,
+            _ => timeout.min(PROBE_TIMEOUT),
+        };
+        let tls_server = UpstreamServer {
+            priority: upstream_server.priority,
+            weight: upstream_server.weight,
+            ..UpstreamServer::new_tls(SocketAddr::new(ip, DOT_PORT), &ip.to_string())
+        };
+        Some((tls_server, timeout))
+    }
+
+    /// Remember whether `upstream_server` answered over TLS.
+    pub(crate) fn set_supported(&self, upstream_server: &UpstreamServer, supported: bool) {
+        let support = Support {
+            supported,
+            since: Instant::now(),
+        };
+        self.servers
+            .lock()
+            .unwrap()
+            .insert(upstream_server.addr.ip(), support);
+    }
+}
+
+#[test]
+fn test_opportunistic_tls() {
+    let timeout = Duration::from_secs(5);
+    let opportunistic_tls = OpportunisticTls::default();
+    let upstream_server = UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)));
+    let (tls_server, probe_timeout) = opportunistic_tls
+        .upgrade(&upstream_server, timeout)
+        .unwrap();
+    assert_eq!(tls_server.addr, SocketAddr::from(([192, 0, 2, 53], 853)));
+    assert_eq!(tls_server.tls_server_name(), Some("192.0.2.53"));
+    assert_eq!(probe_timeout, PROBE_TIMEOUT);
+
+    opportunistic_tls.set_supported(&upstream_server, true);
+    let (_, tls_timeout) = opportunistic_tls
+        .upgrade(&upstream_server, timeout)
+        .unwrap();
+    assert_eq!(tls_timeout, timeout);
+
+    opportunistic_tls.set_supported(&upstream_server, false);
+    assert!(opportunistic_tls
+        .upgrade(&upstream_server, timeout)
+        .is_none());
+
+    let tls_server = UpstreamServer::new_tls(SocketAddr::from(([192, 0, 2, 53], 853)), "dns.test");
+    assert!(opportunistic_tls.upgrade(&tls_server, timeout).is_none());
+}
+
+#[cfg(all(feature = "testing", feature = "tls"))]
+#[test]
+fn test_opportunistic_tls_queries() {
+    use std::net::Ipv4Addr;
+
+    use crate::events::Protocol;
+    use crate::testing::{mock_client, MockBackend};
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let mut dns_client = mock_client(&backend);
+    let (_, query_info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert_eq!(query_info.protocol, Some(Protocol::Udp));
+    dns_client.set_opportunistic_tls(true);
+    let (ips, query_info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    assert_eq!(query_info.protocol, Some(Protocol::Tls));
+}
@@ -0,0 +1,377 @@
+use std::io;
+
+use dnssector::constants::*;
+use dnssector::*;
+
+use crate::idn;
+use crate::name_validation::NameValidation;
+
+pub(crate) const TYPE_OPT: u16 = 41;
+#[cfg(feature = "stream")]
+pub(crate) const TYPE_AXFR: u16 = 252;
+pub(crate) const EDNS_FLAG_DO: u16 = 0x8000;
+pub(crate) const DEFAULT_EDNS_BUFSIZE: u16 = 1232;
+pub(crate) const RCODE_BADVERS: u16 = 16;
+pub(crate) const RCODE_BADCOOKIE: u16 = 23;
+pub(crate) const EDNS_OPTION_NSID: u16 = 3;
+pub(crate) const EDNS_OPTION_EXPIRE: u16 = 9;
+const EDNS_OPTION_COOKIE: u16 = 10;
+pub(crate) const EDNS_OPTION_PADDING: u16 = 12;
+pub(crate) const EDNS_OPTION_CHAIN: u16 = 13;
+pub(crate) const EDNS_OPTION_EDE: u16 = 15;
+pub(crate) const EDNS_OPTION_REPORT_CHANNEL: u16 = 18;
+const CLIENT_COOKIE_LEN: usize = 8;
+
+/// Build a query for `name`, converting it to A-labels and validating it
+/// first.
+pub(crate) fn query(
+    name: &str,
+    rr_type: Type,
+    rr_class: Class,
+    name_validation: NameValidation,
+) -> Result<ParsedPacket, io::Error> {
+    let name = idn::to_ascii(name)?;
+    name_validation.validate(&name)?;
+    // Names are always fully qualified, so the trailing dot is optional,
+    // except for the root zone
+    let name = match name.strip_suffix('.') {
+        Some(relative_name) if !relative_name.is_empty() => relative_name,
+        _ => &name,
+    };
+    dnssector::gen::query(name.as_bytes(), rr_type, rr_class)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+/// Build a query for `name` in the `IN` class, with a record type that may
+/// not be known to `dnssector`.
+pub(crate) fn query_type(
+    name: &str,
+    rr_type: u16,
+    name_validation: NameValidation,
+) -> Result<ParsedPacket, io::Error> {
+    let parsed_query = query(name, Type::A, Class::IN, name_validation)?;
+    let mut query = parsed_query.into_packet();
+    let type_offset = RRIterator::skip_name(&query, DNS_HEADER_SIZE);
+    query[type_offset..type_offset + 2].copy_from_slice(&rr_type.to_be_bytes());
+    DNSSector::new(query)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+/// Append an EDNS section (OPT record) to `query`, which must not have any
+/// additional records.
+pub(crate) fn add_edns(query: &mut Vec<u8>, bufsize: u16, version: u8, flags: u16, options: &[u8]) {
+    query.push(0);
+    query.extend_from_slice(&TYPE_OPT.to_be_bytes());
+    query.extend_from_slice(&bufsize.to_be_bytes());
+    query.extend_from_slice(&[0, version]);
+    query.extend_from_slice(&flags.to_be_bytes());
+    query.extend_from_slice(&(options.len() as u16).to_be_bytes());
+    query.extend_from_slice(options);
+    DNSSector::set_arcount(query, 1);
+}
+
+/// Set the `DO` flag of a query, adding an EDNS section if it has no
+/// additional records.
+pub(crate) fn set_dnssec_ok(parsed_query: ParsedPacket) -> Result<ParsedPacket, io::Error> {
+    let offset_edns = parsed_query.offset_edns;
+    let mut query = parsed_query.into_packet();
+    match offset_edns {
+        // The flags are right before the length of the data of the OPT record
+        Some(offset) => query[offset - 4] |= (EDNS_FLAG_DO >> 8) as u8,
+        None if DNSSector::arcount(&query) == 0 => {
+            add_edns(&mut query, DEFAULT_EDNS_BUFSIZE, 0, EDNS_FLAG_DO, &[])
+        }
+        None => {}
+    }
+    DNSSector::new(query)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+/// Encode a name in uncompressed wire format. The root name is `.`.
+pub(crate) fn name_to_wire(name: &str) -> Result<Vec<u8>, io::Error> {
+    NameValidation::Relaxed.validate(name)?;
+    let mut wire = vec![];
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        wire.push(label.len() as u8);
+        wire.extend_from_slice(label.as_bytes());
+    }
+    wire.push(0);
+    Ok(wire)
+}
+
+/// The response code, including the upper bits stored in the OPT record.
+pub(crate) fn extended_rcode(parsed_response: &ParsedPacket) -> u16 {
+    u16::from(parsed_response.ext_rcode.unwrap_or(0)) << 4 | u16::from(parsed_response.rcode())
+}
+
+/// The data of the OPT record of a parsed packet, and its offset.
+fn opt_rdata(parsed_packet: &ParsedPacket) -> Option<(usize, &[u8])> {
+    let offset = parsed_packet.offset_edns?;
+    let packet = parsed_packet.packet();
+    let rdlen = u16::from_be_bytes([packet[offset - 2], packet[offset - 1]]) as usize;
+    Some((offset, packet.get(offset..offset + rdlen)?))
+}
+
+/// Split the data of an OPT record into `(code, data)` options.
+fn edns_options(mut rdata: &[u8]) -> Option<Vec<(u16, &[u8])>> {
+    let mut options = vec![];
+    while !rdata.is_empty() {
+        let code = u16::from_be_bytes([*rdata.first()?, *rdata.get(1)?]);
+        let len = u16::from_be_bytes([*rdata.get(2)?, *rdata.get(3)?]) as usize;
+        options.push((code, rdata.get(4..4 + len)?));
+        rdata = &rdata[4 + len..];
+    }
+    Some(options)
+}
+
+fn find_edns_option<'t>(options: &[(u16, &'t [u8])], code: u16) -> Option<&'t [u8]> {
+    options
+        .iter()
+        .find(|&&(option_code, _)| option_code == code)
+        .map(|&(_, data)| data)
+}
+
+/// The data of the EDNS option `code` of a parsed packet.
+pub(crate) fn edns_option(parsed_packet: &ParsedPacket, code: u16) -> Option<&[u8]> {
+    let (_, rdata) = opt_rdata(parsed_packet)?;
+    find_edns_option(&edns_options(rdata)?, code)
+}
+
+/// The EDNS options that change the response to a query, as `(code, data)`
+/// pairs: all of them, except cookies, and with the length of the padding
+/// ignored.
+pub(crate) fn query_edns_options(parsed_query: &ParsedPacket) -> Vec<(u16, Vec<u8>)> {
+    let options = opt_rdata(parsed_query).and_then(|(_, rdata)| edns_options(rdata));
+    options
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|&(code, _)| code != EDNS_OPTION_COOKIE)
+        .map(|(code, data)| match code {
+            EDNS_OPTION_PADDING => (code, vec![]),
+            _ => (code, data.to_vec()),
+        })
+        .collect()
+}
+
+/// Append the EDNS option `code` to `query`, adding an EDNS section if it
+/// has no additional records. Returns `None` if the query can't be parsed,
+/// or has additional records but no EDNS section.
+pub(crate) fn append_edns_option(query: &[u8], code: u16, data: &[u8]) -> Option<Vec<u8>> {
+    let mut option = code.to_be_bytes().to_vec();
+    option.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    option.extend_from_slice(data);
+    let parsed_query = DNSSector::new(query.to_vec()).ok()?.parse().ok()?;
+    if DNSSector::arcount(query) == 0 {
+        let mut query = query.to_vec();
+        add_edns(&mut query, DEFAULT_EDNS_BUFSIZE, 0, 0, &option);
+        return Some(query);
+    }
+    let (offset, rdata) = opt_rdata(&parsed_query)?;
+    let mut new_query = query[..offset + rdata.len()].to_vec();
+    let rdlen_offset = offset - DNS_OPT_RR_HEADER_SIZE + DNS_OPT_RR_RDLEN_OFFSET;
+    new_query[rdlen_offset..rdlen_offset + 2]
+        .copy_from_slice(&((rdata.len() + option.len()) as u16).to_be_bytes());
+    new_query.extend_from_slice(&option);
+    new_query.extend_from_slice(&query[offset + rdata.len()..]);
+    Some(new_query)
+}
+
+/// Build the query to send again after a `BADVERS` or `BADCOOKIE` response:
+/// with the EDNS version supported by the server, or with the cookie it
+/// returned.
+pub(crate) fn edns_retry_query(query: &[u8], parsed_response: &ParsedPacket) -> Option<Vec<u8>> {
+    let parsed_query = DNSSector::new(query.to_vec()).ok()?.parse().ok()?;
+    let (offset, query_rdata) = opt_rdata(&parsed_query)?;
+    match extended_rcode(parsed_response) {
+        RCODE_BADVERS => {
+            let version = parsed_response.edns_version?;
+            let version_offset = offset - DNS_OPT_RR_HEADER_SIZE + DNS_OPT_RR_EDNS_VERSION_OFFSET;
+            if query[version_offset] <= version {
+                return None;
+            }
+            let mut retry_query = query.to_vec();
+            retry_query[version_offset] = version;
+            Some(retry_query)
+        }
+        RCODE_BADCOOKIE => {
+            let (_, response_rdata) = opt_rdata(parsed_response)?;
+            let query_options = edns_options(query_rdata)?;
+            let response_options = edns_options(response_rdata)?;
+            let client_cookie =
+                find_edns_option(&query_options, EDNS_OPTION_COOKIE)?.get(..CLIENT_COOKIE_LEN)?;
+            let cookie = find_edns_option(&response_options, EDNS_OPTION_COOKIE)?;
+            if cookie.len() <= CLIENT_COOKIE_LEN || !cookie.starts_with(client_cookie) {
+                return None;
+            }
+            let mut rdata = vec![];
+            for (code, data) in query_options {
+                let data = if code == EDNS_OPTION_COOKIE {
+                    cookie
+                } else {
+                    data
+                };
+                rdata.extend_from_slice(&code.to_be_bytes());
+                rdata.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                rdata.extend_from_slice(data);
+            }
+            let mut retry_query = query[..offset].to_vec();
+            let rdlen_offset = offset - DNS_OPT_RR_HEADER_SIZE + DNS_OPT_RR_RDLEN_OFFSET;
+            retry_query[rdlen_offset..rdlen_offset + 2]
+                .copy_from_slice(&(rdata.len() as u16).to_be_bytes());
+            retry_query.extend_from_slice(&rdata);
+            retry_query.extend_from_slice(&query[offset + query_rdata.len()..]);
+            Some(retry_query)
+        }
+        _ => None,
+    }
+}
+
+/// Build a response to `query` locally, with the given rcode and answers.
+///
+/// Answers are `(rr_type, ttl, rdata)` tuples, whose owner name is the
+/// question name.
+pub(crate) fn synthesize_response(
+    query: &[u8],
+    rcode: u8,
+    answers: &[(u16, u32, &[u8])],
+) -> Result<ParsedPacket, io::Error> {
+    if query.len() < DNS_HEADER_SIZE || DNSSector::qdcount(query) != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No DNS question",
+        ));
+    }
+    let question_end = RRIterator::skip_name(query, DNS_QUESTION_OFFSET) + 4;
+    let mut response = query[..question_end].to_vec();
+    response[DNS_FLAGS_OFFSET] = 0x80 | (query[DNS_FLAGS_OFFSET] & 0x79);
+    response[DNS_FLAGS_OFFSET + 1] = 0x80 | (rcode & 0x0f);
+    DNSSector::set_ancount(&mut response, answers.len() as u16);
+    DNSSector::set_nscount(&mut response, 0);
+    DNSSector::set_arcount(&mut response, 0);
+    for &(rr_type, ttl, rdata) in answers {
+        // The root name can't be compressed
+        if query[DNS_QUESTION_OFFSET] == 0 {
+            response.push(0);
+        } else {
+            response.extend_from_slice(&[0xc0, DNS_QUESTION_OFFSET as u8]);
+        }
+        response.extend_from_slice(&rr_type.to_be_bytes());
+        response.extend_from_slice(&u16::from(Class::IN).to_be_bytes());
+        response.extend_from_slice(&ttl.to_be_bytes());
+        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        response.extend_from_slice(rdata);
+    }
+    DNSSector::new(response)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+/// The offsets of the TTLs of the records of a response, but the OPT record.
+pub(crate) fn ttl_offsets(packet: &[u8]) -> Option<Vec<usize>> {
+    if packet.len() < DNS_HEADER_SIZE {
+        return None;
+    }
+    let mut offset = DNS_HEADER_SIZE;
+    for _ in 0..DNSSector::qdcount(packet) {
+        offset = RRIterator::skip_name(packet, offset) + DNS_RR_QUESTION_HEADER_SIZE;
+    }
+    let count = usize::from(DNSSector::ancount(packet))
+        + usize::from(DNSSector::nscount(packet))
+        + usize::from(DNSSector::arcount(packet));
+    let mut ttl_offsets = vec![];
+    for _ in 0..count {
+        let name_end = RRIterator::skip_name(packet, offset);
+        let header = packet.get(name_end..name_end + DNS_RR_HEADER_SIZE)?;
+        let rr_type =
+            u16::from_be_bytes([header[DNS_RR_TYPE_OFFSET], header[DNS_RR_TYPE_OFFSET + 1]]);
+        let rdlen =
+            u16::from_be_bytes([header[DNS_RR_RDLEN_OFFSET], header[DNS_RR_RDLEN_OFFSET + 1]]);
+        if rr_type != TYPE_OPT {
+            ttl_offsets.push(name_end + DNS_RR_TTL_OFFSET);
+        }
+        offset = name_end + DNS_RR_HEADER_SIZE + usize::from(rdlen);
+        if offset > packet.len() {
+            return None;
+        }
+    }
+    Some(ttl_offsets)
+}
+
+/// The smallest TTL of the records of a response, but the OPT record.
+pub(crate) fn min_ttl(packet: &[u8]) -> Option<u32> {
+    ttl_offsets(packet)?
+        .into_iter()
+        .map(|offset| read_u32(packet, offset))
+        .min()
+}
+
+/// Replace the TTLs of the records of a response with `f(ttl)`.
+pub(crate) fn map_ttls(packet: &mut [u8], f: impl Fn(u32) -> u32) {
+    for offset in ttl_offsets(packet).unwrap_or_default() {
+        let ttl = f(read_u32(packet, offset));
+        packet[offset..offset + 4].copy_from_slice(&ttl.to_be_bytes());
+    }
+}
+
+fn read_u32(packet: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        packet[offset],
+        packet[offset + 1],
+        packet[offset + 2],
+        packet[offset + 3],
+    ])
+}
+
+/// Normalize a name for lookups in local tables: lowercase, no trailing dot.
+pub(crate) fn normalize_name(name: &[u8]) -> Vec<u8> {
+    let mut name = name.to_ascii_lowercase();
+    while name.last() == Some(&b'.') {
+        name.pop();
+    }
+    name
+}
+
+#[test]
+fn test_edns_retry_query() {
+    let parse = |packet: Vec<u8>| DNSSector::new(packet).unwrap().parse().unwrap();
+    let base_query = query("example.com", Type::A, Class::IN, Default::default())
+        .unwrap()
+        .into_packet();
+    let response_to = |query: &[u8], rcode: u16, options: &[u8]| {
+        let mut response = synthesize_response(query, (rcode & 0x0f) as u8, &[])
+            .unwrap()
+            .into_packet();
+        let opt_offset = response.len();
+        add_edns(&mut response, DEFAULT_EDNS_BUFSIZE, 0, 0, options);
+        response[opt_offset + 1 + DNS_OPT_RR_EXT_RCODE_OFFSET] = (rcode >> 4) as u8;
+        parse(response)
+    };
+
+    let mut query = base_query.clone();
+    add_edns(&mut query, DEFAULT_EDNS_BUFSIZE, 1, 0, &[]);
+    let response = response_to(&query, RCODE_BADVERS, &[]);
+    assert_eq!(extended_rcode(&response), RCODE_BADVERS);
+    let retry_query = edns_retry_query(&query, &response).unwrap();
+    assert_eq!(parse(retry_query.clone()).edns_version, Some(0));
+    assert!(edns_retry_query(&retry_query, &response).is_none());
+
+    let mut query = base_query;
+    add_edns(
+        &mut query,
+        DEFAULT_EDNS_BUFSIZE,
+        0,
+        0,
+        b"\x00\x0a\x00\x08clientck",
+    );
+    let response = response_to(&query, RCODE_BADCOOKIE, b"\x00\x0a\x00\x10clientckserverck");
+    let retry_query = edns_retry_query(&query, &response).unwrap();
+    assert!(retry_query.ends_with(b"\x00\x0a\x00\x10clientckserverck"));
+    assert!(parse(retry_query).offset_edns.is_some());
+    let response = response_to(&query, RCODE_BADCOOKIE, b"\x00\x0a\x00\x10otherclkserverck");
+    assert!(edns_retry_query(&query, &response).is_none());
+}
@@ -0,0 +1,114 @@
+use std::borrow::Cow;
+
+use dnssector::*;
+
+use crate::events::Protocol;
+use crate::packet::{self, EDNS_OPTION_PADDING};
+
+/// How queries sent to encrypted upstream servers are padded with the EDNS
+/// `Padding` option (RFC 7830), so that their size doesn't reveal the name
+/// being queried.
+///
+/// Queries sent over UDP and TCP are never padded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum PaddingPolicy {
+    /// Send queries as is.
+    #[default]
+    Disabled,
+    /// Pad queries to a multiple of this size. RFC 8467 recommends 128
+    /// bytes.
+    BlockLength(u16),
+}
+
+impl PaddingPolicy {
+    /// The block length padding recommended by RFC 8467.
+    pub const RECOMMENDED: PaddingPolicy = PaddingPolicy::BlockLength(128);
+
+    /// Pad `query`, if it is sent over `protocol`.
+    ///
+    /// Queries that already have a `Padding` option, or additional records
+    /// but no EDNS section, are sent as is.
+    pub(crate) fn apply(self, query: &[u8], protocol: Protocol) -> Cow<'_, [u8]> {
+        let block_len = match self {
+            PaddingPolicy::BlockLength(block_len) if block_len > 1 => block_len as usize,
+            _ => return Cow::Borrowed(query),
+        };
+        if matches!(protocol, Protocol::Udp | Protocol::Tcp) {
+            return Cow::Borrowed(query);
+        }
+        let already_padded = DNSSector::new(query.to_vec())
+            .and_then(|sector| sector.parse())
+            .map(|parsed_query| packet::edns_option(&parsed_query, EDNS_OPTION_PADDING).is_some());
+        if already_padded.unwrap_or(true) {
+            return Cow::Borrowed(query);
+        }
+        let unpadded_len = match packet::append_edns_option(query, EDNS_OPTION_PADDING, &[]) {
+            Some(unpadded_query) => unpadded_query.len(),
+            None => return Cow::Borrowed(query),
+        };
+        let padding_len = (block_len - unpadded_len % block_len) % block_len;
+        match packet::append_edns_option(query, EDNS_OPTION_PADDING, &vec![0; padding_len]) {
+            Some(padded_query) => Cow::Owned(padded_query),
+            None => Cow::Borrowed(query),
+        }
+    }
+}
+
+#[test]
+fn test_padding_policy() {
+    let query = packet::query("example.com", Type::A, Class::IN, Default::default())
+        .unwrap()
+        .into_packet();
+    assert_eq!(
+        PaddingPolicy::Disabled.apply(&query, Protocol::Tls),
+        &query[..]
+    );
+    assert_eq!(
+        PaddingPolicy::RECOMMENDED.apply(&query, Protocol::Udp),
+        &query[..]
+    );
+
+    let padded_query = PaddingPolicy::RECOMMENDED.apply(&query, Protocol::Https);
+    assert_eq!(padded_query.len(), 128);
+    let mut parsed_query = DNSSector::new(padded_query.to_vec())
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(
+        parsed_query.question(),
+        Some((b"example.com".to_vec(), 1, 1))
+    );
+    assert_eq!(
+        packet::edns_option(&parsed_query, EDNS_OPTION_PADDING)
+            .unwrap()
+            .len(),
+        128 - query.len() - 11 - 4
+    );
+    assert_eq!(
+        PaddingPolicy::BlockLength(64).apply(&padded_query, Protocol::Https),
+        &padded_query[..]
+    );
+
+    let mut edns_query = query.clone();
+    packet::add_edns(
+        &mut edns_query,
+        1232,
+        0,
+        packet::EDNS_FLAG_DO,
+        &[0, 9, 0, 0],
+    );
+    let padded_query = PaddingPolicy::BlockLength(64).apply(&edns_query, Protocol::Quic);
+    assert_eq!(padded_query.len(), 64);
+    let parsed_query = DNSSector::new(padded_query.to_vec())
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(parsed_query.dnssec());
+    assert!(packet::edns_option(&parsed_query, packet::EDNS_OPTION_EXPIRE).is_some());
+    assert!(packet::edns_option(&parsed_query, EDNS_OPTION_PADDING).is_some());
+}
@@ -0,0 +1,607 @@
+//! Presentation formats for DNS responses, as used in zone files, by `dig`
+//! and by DNS over HTTPS JSON APIs.
+
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use dnssector::constants::*;
+use dnssector::*;
+
+use crate::audit::push_json_string;
+use crate::packet::TYPE_OPT;
+
+/// A resource record, with uncompressed names.
+#[derive(Clone, Debug)]
+pub(crate) struct Record {
+    pub(crate) name: Vec<u8>,
+    pub(crate) rr_type: u16,
+    pub(crate) class: u16,
+    pub(crate) ttl: u32,
+    pub(crate) rdata: Vec<u8>,
+}
+
+/// A DNS message split into its sections.
+#[derive(Clone, Debug)]
+pub(crate) struct Message {
+    pub(crate) id: u16,
+    pub(crate) flags: u16,
+    pub(crate) question: Option<(Vec<u8>, u16, u16)>,
+    pub(crate) answers: Vec<Record>,
+    pub(crate) authority: Vec<Record>,
+    pub(crate) additional: Vec<Record>,
+}
+
+impl Message {
+    pub(crate) fn parse(packet: &[u8]) -> Result<Message, io::Error> {
+        let invalid = |e: Error| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+        DNSSector::new(packet.to_vec())
+            .map_err(invalid)?
+            .parse()
+            .map_err(invalid)?;
+        let packet = Compress::uncompress(packet).map_err(invalid)?;
+
+        let mut offset = DNS_HEADER_SIZE;
+        let question = match DNSSector::qdcount(&packet) {
+            0 => None,
+            _ => {
+                let name_end = RRIterator::skip_name(&packet, offset);
+                let rr_type = read_u16(&packet, name_end)?;
+                let class = read_u16(&packet, name_end + 2)?;
+                let name = packet[offset..name_end].to_vec();
+                offset = name_end + DNS_RR_QUESTION_HEADER_SIZE;
+                Some((name, rr_type, class))
+            }
+        };
+        let mut sections = [vec![], vec![], vec![]];
+        let counts = [
+            DNSSector::ancount(&packet),
+            DNSSector::nscount(&packet),
+            DNSSector::arcount(&packet),
+        ];
+        for (section, count) in sections.iter_mut().zip(counts) {
+            for _ in 0..count {
+                let name_end = RRIterator::skip_name(&packet, offset);
+                let rdlen = read_u16(&packet, name_end + DNS_RR_RDLEN_OFFSET)? as usize;
+                let rdata_start = name_end + DNS_RR_HEADER_SIZE;
+                let rdata = packet
+                    .get(rdata_start..rdata_start + rdlen)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Short record"))?;
+                section.push(Record {
+                    name: packet[offset..name_end].to_vec(),
+                    rr_type: read_u16(&packet, name_end + DNS_RR_TYPE_OFFSET)?,
+                    class: read_u16(&packet, name_end + DNS_RR_CLASS_OFFSET)?,
+                    ttl: u32::from(read_u16(&packet, name_end + DNS_RR_TTL_OFFSET)?) << 16
+                        | u32::from(read_u16(&packet, name_end + DNS_RR_TTL_OFFSET + 2)?),
+                    rdata: rdata.to_vec(),
+                });
+                offset = rdata_start + rdlen;
+            }
+        }
+        let [answers, authority, additional] = sections;
+        Ok(Message {
+            id: read_u16(&packet, DNS_TID_OFFSET)?,
+            flags: read_u16(&packet, DNS_FLAGS_OFFSET)?,
+            question,
+            answers,
+            authority,
+            additional,
+        })
+    }
+
+    pub(crate) fn opcode(&self) -> u8 {
+        ((self.flags >> 11) & 0x0f) as u8
+    }
+
+    pub(crate) fn rcode(&self) -> u8 {
+        (self.flags & 0x0f) as u8
+    }
+
+    /// The response code, including the upper bits stored in the OPT record.
+    pub(crate) fn extended_rcode(&self) -> u16 {
+        let ext_rcode = self.opt().map_or(0, |opt| (opt.ttl >> 24) as u16);
+        ext_rcode << 4 | u16::from(self.rcode())
+    }
+
+    /// The OPT pseudo-record, if the message has one.
+    pub(crate) fn opt(&self) -> Option<&Record> {
+        self.additional.iter().find(|rr| rr.rr_type == TYPE_OPT)
+    }
+
+    /// All the records but the OPT pseudo-record.
+    pub(crate) fn records(&self) -> impl Iterator<Item = &Record> {
+        self.answers
+            .iter()
+            .chain(&self.authority)
+            .chain(self.additional.iter().filter(|rr| rr.rr_type != TYPE_OPT))
+    }
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> Result<u16, io::Error> {
+    match packet.get(offset..offset + 2) {
+        Some(x) => Ok(u16::from_be_bytes([x[0], x[1]])),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Truncated packet",
+        )),
+    }
+}
+
+pub(crate) fn type_name(rr_type: u16) -> String {
+    let name = match rr_type {
+        1 => "A",
+        2 => "NS",
+        5 => "CNAME",
+        6 => "SOA",
+        12 => "PTR",
+        13 => "HINFO",
+        15 => "MX",
+        16 => "TXT",
+        28 => "AAAA",
+        29 => "LOC",
+        33 => "SRV",
+        35 => "NAPTR",
+        39 => "DNAME",
+        41 => "OPT",
+        43 => "DS",
+        44 => "SSHFP",
+        46 => "RRSIG",
+        47 => "NSEC",
+        48 => "DNSKEY",
+        50 => "NSEC3",
+        51 => "NSEC3PARAM",
+        52 => "TLSA",
+        59 => "CDS",
+        60 => "CDNSKEY",
+        64 => "SVCB",
+        65 => "HTTPS",
+        99 => "SPF",
+        255 => "ANY",
+        256 => "URI",
+        257 => "CAA",
+        _ => return format!("TYPE{}", rr_type),
+    };
+    name.to_string()
+}
+
+pub(crate) fn class_name(class: u16) -> String {
+    let name = match class {
+        1 => "IN",
+        3 => "CH",
+        4 => "HS",
+        254 => "NONE",
+        255 => "ANY",
+        _ => return format!("CLASS{}", class),
+    };
+    name.to_string()
+}
+
+pub(crate) fn rcode_name(rcode: u16) -> String {
+    let name = match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        16 => "BADVERS",
+        23 => "BADCOOKIE",
+        _ => return format!("RCODE{}", rcode),
+    };
+    name.to_string()
+}
+
+fn opcode_name(opcode: u8) -> String {
+    let name = match opcode {
+        0 => "QUERY",
+        1 => "IQUERY",
+        2 => "STATUS",
+        4 => "NOTIFY",
+        5 => "UPDATE",
+        _ => return format!("OPCODE{}", opcode),
+    };
+    name.to_string()
+}
+
+/// Render a name in wire format, returning the name and the length of its
+/// wire representation.
+fn read_name(data: &[u8]) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut offset = 0;
+    loop {
+        let len = *data.get(offset)? as usize;
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+        if len > 63 {
+            return None;
+        }
+        for &c in data.get(offset..offset + len)? {
+            match c {
+                b'.' | b'\\' | b'"' | b';' | b'(' | b')' => {
+                    name.push('\\');
+                    name.push(c as char);
+                }
+                0x21..=0x7e => name.push(c as char),
+                _ => {
+                    let _ = write!(name, "\\{:03}", c);
+                }
+            }
+        }
+        name.push('.');
+        offset += len;
+    }
+    if name.is_empty() {
+        name.push('.');
+    }
+    Some((name, offset))
+}
+
+pub(crate) fn name_to_string(name: &[u8]) -> String {
+    read_name(name)
+        .map(|(name, _)| name)
+        .unwrap_or_else(|| ".".to_string())
+}
+
+fn push_character_string(out: &mut String, data: &[u8]) {
+    out.push('"');
+    for &c in data {
+        match c {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(c as char);
+            }
+            0x20..=0x7e => out.push(c as char),
+            _ => {
+                let _ = write!(out, "\\{:03}", c);
+            }
+        }
+    }
+    out.push('"');
+}
+
+fn push_hex(out: &mut String, data: &[u8]) {
+    for c in data {
+        let _ = write!(out, "{:02X}", c);
+    }
+}
+
+fn known_rdata_to_string(rr_type: u16, rdata: &[u8]) -> Option<String> {
+    let u16_at = |offset: usize| read_u16(rdata, offset).ok();
+    let u32_at =
+        |offset: usize| Some(u32::from(u16_at(offset)?) << 16 | u32::from(u16_at(offset + 2)?));
+    let exact_name = |data: &[u8]| match read_name(data) {
+        Some((name, len)) if len == data.len() => Some(name),
+        _ => None,
+    };
+    let rdata_str = match rr_type {
+        1 => Ipv4Addr::from(<[u8; 4]>::try_from(rdata).ok()?).to_string(),
+        28 => Ipv6Addr::from(<[u8; 16]>::try_from(rdata).ok()?).to_string(),
+        2 | 5 | 12 | 39 => exact_name(rdata)?,
+        15 => format!("{} {}", u16_at(0)?, exact_name(rdata.get(2..)?)?),
+        6 => {
+            let (mname, mname_len) = read_name(rdata)?;
+            let (rname, rname_len) = read_name(rdata.get(mname_len..)?)?;
+            let offset = mname_len + rname_len;
+            if rdata.len() != offset + 20 {
+                return None;
+            }
+            format!(
+                "{} {} {} {} {} {} {}",
+                mname,
+                rname,
+                u32_at(offset)?,
+                u32_at(offset + 4)?,
+                u32_at(offset + 8)?,
+                u32_at(offset + 12)?,
+                u32_at(offset + 16)?
+            )
+        }
+        16 | 99 => {
+            let mut out = String::new();
+            let mut it = rdata;
+            while let Some((&len, rest)) = it.split_first() {
+                let s = rest.get(..len as usize)?;
+                if !out.is_empty() {
+                    out.push(' ');
+                }
+                push_character_string(&mut out, s);
+                it = &rest[len as usize..];
+            }
+            out
+        }
+        33 => format!(
+            "{} {} {} {}",
+            u16_at(0)?,
+            u16_at(2)?,
+            u16_at(4)?,
+            exact_name(rdata.get(6..)?)?
+        ),
+        43 | 59 => {
+            let mut out = format!("{} {} {} ", u16_at(0)?, rdata.get(2)?, rdata.get(3)?);
+            push_hex(&mut out, rdata.get(4..)?);
+            out
+        }
+        257 => {
+            let tag_len = *rdata.get(1)? as usize;
+            let tag = rdata.get(2..2 + tag_len)?;
+            let mut out = format!("{} {} ", rdata[0], String::from_utf8_lossy(tag));
+            push_character_string(&mut out, &rdata[2 + tag_len..]);
+            out
+        }
+        _ => return None,
+    };
+    Some(rdata_str)
+}
+
+/// Render record data, using the generic RFC 3597 syntax for unsupported
+/// types.
+pub(crate) fn rdata_to_string(rr_type: u16, rdata: &[u8]) -> String {
+    if let Some(rdata_str) = known_rdata_to_string(rr_type, rdata) {
+        return rdata_str;
+    }
+    let mut out = format!("\\# {}", rdata.len());
+    if !rdata.is_empty() {
+        out.push(' ');
+        push_hex(&mut out, rdata);
+    }
+    out
+}
+
+fn push_record(out: &mut String, rr: &Record) {
+    let _ = writeln!(
+        out,
+        "{}\t{}\t{}\t{}\t{}",
+        name_to_string(&rr.name),
+        rr.ttl,
+        class_name(rr.class),
+        type_name(rr.rr_type),
+        rdata_to_string(rr.rr_type, &rr.rdata)
+    );
+}
+
+/// Render the records of a response in zone file format, one record per
+/// line, with the owner name, TTL, class, type and data.
+///
+/// Records from all the sections are included, except the EDNS pseudo-record.
+pub fn format_records(response: &[u8]) -> Result<String, io::Error> {
+    let message = Message::parse(response)?;
+    let mut out = String::new();
+    for rr in message.records() {
+        push_record(&mut out, rr);
+    }
+    Ok(out)
+}
+
+/// Render the data of the answers of a response, one record per line, the
+/// way `dig +short` does.
+pub fn format_short(response: &[u8]) -> Result<String, io::Error> {
+    let message = Message::parse(response)?;
+    let mut out = String::new();
+    for rr in &message.answers {
+        out.push_str(&rdata_to_string(rr.rr_type, &rr.rdata));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Render a complete message the way `dig` does, with the header, flags,
+/// EDNS information and every section.
+pub fn format_message(response: &[u8]) -> Result<String, io::Error> {
+    let message = Message::parse(response)?;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+        opcode_name(message.opcode()),
+        rcode_name(message.extended_rcode()),
+        message.id
+    );
+    let flags = u32::from(message.flags);
+    let flag_names = [
+        (DNS_FLAG_QR, "qr"),
+        (DNS_FLAG_AA, "aa"),
+        (DNS_FLAG_TC, "tc"),
+        (DNS_FLAG_RD, "rd"),
+        (DNS_FLAG_RA, "ra"),
+        (DNS_FLAG_AD, "ad"),
+        (DNS_FLAG_CD, "cd"),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags & flag != 0)
+    .map(|(_, name)| *name)
+    .collect::<Vec<_>>()
+    .join(" ");
+    let _ = writeln!(
+        out,
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flag_names,
+        message.question.is_some() as u16,
+        message.answers.len(),
+        message.authority.len(),
+        message.additional.len()
+    );
+    if let Some(opt) = message.opt() {
+        let _ = writeln!(out, "\n;; OPT PSEUDOSECTION:");
+        let _ = writeln!(
+            out,
+            "; EDNS: version: {}, flags:{}; udp: {}",
+            (opt.ttl >> 16) & 0xff,
+            if opt.ttl & 0x8000 != 0 { " do" } else { "" },
+            opt.class
+        );
+    }
+    if let Some((name, rr_type, class)) = &message.question {
+        let _ = writeln!(out, "\n;; QUESTION SECTION:");
+        let _ = writeln!(
+            out,
+            ";{}\t\t{}\t{}",
+            name_to_string(name),
+            class_name(*class),
+            type_name(*rr_type)
+        );
+    }
+    let sections = [
+        ("ANSWER", message.answers.iter().collect::<Vec<_>>()),
+        ("AUTHORITY", message.authority.iter().collect()),
+        (
+            "ADDITIONAL",
+            message
+                .additional
+                .iter()
+                .filter(|rr| rr.rr_type != TYPE_OPT)
+                .collect(),
+        ),
+    ];
+    for (section_name, records) in &sections {
+        if records.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "\n;; {} SECTION:", section_name);
+        for rr in records {
+            push_record(&mut out, rr);
+        }
+    }
+    Ok(out)
+}
+
+fn push_json_records(out: &mut String, section_name: &str, records: &[&Record]) {
+    if records.is_empty() {
+        return;
+    }
+    let _ = write!(out, ",\"{}\":[", section_name);
+    for (i, rr) in records.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str("{\"name\":");
+        push_json_string(out, &name_to_string(&rr.name));
+        let _ = write!(out, ",\"type\":{},\"TTL\":{},\"data\":", rr.rr_type, rr.ttl);
+        push_json_string(out, &rdata_to_string(rr.rr_type, &rr.rdata));
+        out.push('}');
+    }
+    out.push(']');
+}
+
+/// Render a complete message as a JSON object, using the schema of the DNS
+/// over HTTPS JSON APIs.
+///
+/// The object has the `Status` (response code), `TC`, `RD`, `RA`, `AD` and
+/// `CD` properties, a `Question` array, and `Answer`, `Authority` and
+/// `Additional` arrays when these sections are not empty. Records have a
+/// `name`, a numeric `type`, a `TTL` and `data` in presentation format.
+pub fn format_json(response: &[u8]) -> Result<String, io::Error> {
+    let message = Message::parse(response)?;
+    let flags = u32::from(message.flags);
+    let mut out = format!("{{\"Status\":{}", message.extended_rcode());
+    for (flag, flag_name) in &[
+        (DNS_FLAG_TC, "TC"),
+        (DNS_FLAG_RD, "RD"),
+        (DNS_FLAG_RA, "RA"),
+        (DNS_FLAG_AD, "AD"),
+        (DNS_FLAG_CD, "CD"),
+    ] {
+        let _ = write!(out, ",\"{}\":{}", flag_name, flags & flag != 0);
+    }
+    out.push_str(",\"Question\":[");
+    if let Some((name, rr_type, _)) = &message.question {
+        out.push_str("{\"name\":");
+        push_json_string(&mut out, &name_to_string(name));
+        let _ = write!(out, ",\"type\":{}}}", rr_type);
+    }
+    out.push(']');
+    push_json_records(
+        &mut out,
+        "Answer",
+        &message.answers.iter().collect::<Vec<_>>(),
+    );
+    push_json_records(
+        &mut out,
+        "Authority",
+        &message.authority.iter().collect::<Vec<_>>(),
+    );
+    push_json_records(
+        &mut out,
+        "Additional",
+        &message
+            .additional
+            .iter()
+            .filter(|rr| rr.rr_type != TYPE_OPT)
+            .collect::<Vec<_>>(),
+    );
+    out.push('}');
+    Ok(out)
+}
+
+#[test]
+fn test_format_response() {
+    let query = dnssector::gen::query(
+        b"example.com",
+        Type::from_string("MX").unwrap(),
+        Class::from_string("IN").unwrap(),
+    )
+    .unwrap()
+    .into_packet();
+    let mx = b"\x00\x0a\x04mail\x07example\x03com\x00";
+    let txt = b"\x05hello\x0aa \"quoted\"";
+    let mut response =
+        crate::packet::synthesize_response(&query, 0, &[(15, 300, mx), (16, 60, txt)]).unwrap();
+    response.set_tid(0x1234);
+    let response = response.into_packet();
+
+    assert_eq!(
+        format_records(&response).unwrap(),
+        "example.com.\t300\tIN\tMX\t10 mail.example.com.\n\
+         example.com.\t60\tIN\tTXT\t\"hello\" \"a \\\"quoted\\\"\"\n"
+    );
+    let dump = format_message(&response).unwrap();
+    assert!(dump.starts_with(";; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4660\n"));
+    assert!(dump.contains(";; flags: qr rd ra; QUERY: 1, ANSWER: 2, AUTHORITY: 0, ADDITIONAL: 0\n"));
+    assert!(dump.contains("\n;; QUESTION SECTION:\n;example.com.\t\tIN\tMX\n"));
+    assert!(
+        dump.contains("\n;; ANSWER SECTION:\nexample.com.\t300\tIN\tMX\t10 mail.example.com.\n")
+    );
+    assert!(!dump.contains("AUTHORITY SECTION"));
+    assert_eq!(
+        format_short(&response).unwrap(),
+        "10 mail.example.com.\n\"hello\" \"a \\\"quoted\\\"\"\n"
+    );
+
+    assert_eq!(rdata_to_string(1, &[192, 0, 2, 1]), "192.0.2.1");
+    assert_eq!(rdata_to_string(1, &[192, 0, 2]), "\\# 3 C00002");
+    assert_eq!(rdata_to_string(65535, &[]), "\\# 0");
+    assert_eq!(name_to_string(b"\x03a.b\x00"), "a\\.b.");
+    assert_eq!(name_to_string(b"\x00"), ".");
+    assert!(format_records(b"invalid").is_err());
+}
+
+#[test]
+fn test_format_json() {
+    let query = dnssector::gen::query(
+        b"example.com",
+        Type::from_string("TXT").unwrap(),
+        Class::from_string("IN").unwrap(),
+    )
+    .unwrap()
+    .into_packet();
+    let txt = b"\x05hello";
+    let response = crate::packet::synthesize_response(&query, 0, &[(16, 60, txt)])
+        .unwrap()
+        .into_packet();
+    let json: serde_json::Value = serde_json::from_str(&format_json(&response).unwrap()).unwrap();
+    assert_eq!(json["Status"], 0);
+    assert_eq!(json["RD"], true);
+    assert_eq!(json["TC"], false);
+    assert_eq!(json["Question"][0]["name"], "example.com.");
+    assert_eq!(json["Question"][0]["type"], 16);
+    assert_eq!(json["Answer"][0]["TTL"], 60);
+    assert_eq!(json["Answer"][0]["data"], "\"hello\"");
+    assert!(json.get("Authority").is_none());
+}
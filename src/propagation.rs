@@ -0,0 +1,128 @@
+use std::io;
+use std::net::SocketAddr;
+
+use crate::compare::UpstreamAnswer;
+use crate::upstream_server::UpstreamServer;
+
+/// Well-known public resolvers, queried by `check_propagation()` when no
+/// resolvers are given.
+pub fn public_resolvers() -> Vec<UpstreamServer> {
+    let ips: [[u8; 4]; 10] = [
+        [1, 1, 1, 1],         // Cloudflare
+        [1, 0, 0, 1],         // Cloudflare
+        [8, 8, 8, 8],         // Google
+        [8, 8, 4, 4],         // Google
+        [9, 9, 9, 9],         // Quad9
+        [149, 112, 112, 112], // Quad9
+        [208, 67, 222, 222],  // OpenDNS
+        [208, 67, 220, 220],  // OpenDNS
+        [94, 140, 14, 14],    // AdGuard
+        [185, 228, 168, 9],   // CleanBrowsing
+    ];
+    ips.iter()
+        .map(|&ip| UpstreamServer::new(SocketAddr::from((ip, 53))))
+        .collect()
+}
+
+/// The response of a resolver, and whether it includes the expected value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropagationStatus {
+    pub answer: UpstreamAnswer,
+    pub propagated: bool,
+}
+
+/// Which resolvers see the expected value of a record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropagationReport {
+    /// The status of every resolver, in the order they were given.
+    pub statuses: Vec<PropagationStatus>,
+}
+
+fn normalize_data(data: &str) -> String {
+    data.trim()
+        .trim_matches('"')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+impl PropagationReport {
+    pub(crate) fn new(
+        rr_type: u16,
+        expected: &str,
+        responses: Vec<(UpstreamServer, Result<Vec<u8>, io::Error>)>,
+    ) -> Self {
+        let expected = normalize_data(expected);
+        let statuses = responses
+            .into_iter()
+            .map(|(server, response)| {
+                let answer = UpstreamAnswer::new(server, response);
+                let propagated = answer
+                    .records
+                    .iter()
+                    .any(|rr| rr.rr_type == rr_type && normalize_data(&rr.data) == expected);
+                PropagationStatus { answer, propagated }
+            })
+            .collect();
+        PropagationReport { statuses }
+    }
+
+    /// Whether every resolver sees the expected value.
+    pub fn is_complete(&self) -> bool {
+        self.statuses.iter().all(|status| status.propagated)
+    }
+
+    /// The resolvers that see the expected value.
+    pub fn propagated(&self) -> Vec<&UpstreamServer> {
+        self.statuses
+            .iter()
+            .filter(|status| status.propagated)
+            .map(|status| &status.answer.server)
+            .collect()
+    }
+
+    /// The resolvers that don't see the expected value yet, or that didn't
+    /// respond.
+    pub fn pending(&self) -> Vec<&UpstreamServer> {
+        self.statuses
+            .iter()
+            .filter(|status| !status.propagated)
+            .map(|status| &status.answer.server)
+            .collect()
+    }
+}
+
+#[test]
+fn test_propagation_report() {
+    use dnssector::{Class, Type};
+
+    let query = dnssector::gen::query(
+        b"example.com",
+        Type::from_string("TXT").unwrap(),
+        Class::from_string("IN").unwrap(),
+    )
+    .unwrap()
+    .into_packet();
+    let response = |txt: &[u8]| {
+        Ok(
+            crate::packet::synthesize_response(&query, 0, &[(16, 60, txt)])
+                .unwrap()
+                .into_packet(),
+        )
+    };
+    let resolvers = public_resolvers();
+    let report = PropagationReport::new(
+        16,
+        "v=spf1 -all",
+        vec![
+            (resolvers[0].clone(), response(b"\x0bv=spf1 -all")),
+            (resolvers[1].clone(), response(b"\x0bv=spf1 +all")),
+            (
+                resolvers[2].clone(),
+                Err(io::Error::new(io::ErrorKind::TimedOut, "timeout")),
+            ),
+        ],
+    );
+    assert!(!report.is_complete());
+    assert_eq!(report.propagated(), vec![&resolvers[0]]);
+    assert_eq!(report.pending(), vec![&resolvers[1], &resolvers[2]]);
+}
@@ -0,0 +1,444 @@
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use dnssector::constants::*;
+use dnssector::{DNSSector, ParsedPacket};
+
+#[cfg(feature = "dnssec")]
+use crate::dnssec::DnssecStatus;
+use crate::events::Protocol;
+use crate::packet::{
+    EDNS_OPTION_CHAIN, EDNS_OPTION_EDE, EDNS_OPTION_EXPIRE, EDNS_OPTION_NSID,
+    EDNS_OPTION_REPORT_CHANNEL,
+};
+use crate::presentation::Message;
+use crate::record_data::{parse_name, RecordData, Soa};
+use crate::upstream_server::UpstreamServer;
+
+/// The extended DNS error reported when the DNSSEC validation of a response
+/// failed (RFC 8914).
+#[cfg(feature = "dnssec")]
+const EDE_DNSSEC_BOGUS: u16 = 6;
+
+/// How a response was obtained, returned by the `*_detailed` queries.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct QueryInfo {
+    /// The upstream server that answered, `None` for local answers.
+    pub server: Option<UpstreamServer>,
+    /// The transport of the final exchange, `None` for local answers.
+    pub protocol: Option<Protocol>,
+    /// Number of exchanges with upstream servers, including failed ones.
+    pub attempts: u32,
+    /// Time spent waiting for the answering server.
+    pub rtt: Duration,
+    /// Whether a truncated UDP response was retried over TCP.
+    pub tcp_fallback: bool,
+    /// Whether the response was served from a cache.
+    pub cache_hit: bool,
+    /// Whether the response was an expired cached response, served because
+    /// the upstream servers failed (RFC 8767).
+    pub stale: bool,
+    /// The response code.
+    pub rcode: u8,
+    /// The extended response code, including the upper bits from the EDNS
+    /// section, such as 16 for `BADVERS` and 23 for `BADCOOKIE`.
+    pub extended_rcode: u16,
+    /// Whether the `AA` (authoritative answer) flag was set.
+    pub authoritative: bool,
+    /// Whether the `TC` (truncated) flag was set.
+    pub truncated: bool,
+    /// Whether the `RA` (recursion available) flag was set.
+    pub recursion_available: bool,
+    /// Whether the `AD` (authentic data) flag was set.
+    pub authentic_data: bool,
+    /// Whether the `CD` (checking disabled) flag was set.
+    pub checking_disabled: bool,
+    /// The smallest TTL of the answers, `None` if there are none.
+    pub min_ttl: Option<u32>,
+    /// The result of the DNSSEC validation of the response, if it was
+    /// enabled with `set_dnssec_validation()`. Cached responses are
+    /// validated again, other local answers are not validated.
+    #[cfg(feature = "dnssec")]
+    pub dnssec: Option<DnssecStatus>,
+    /// For responses without answers, the `SOA` record of the authority
+    /// section.
+    pub authority_soa: Option<AuthoritySoa>,
+    /// The `A` and `AAAA` records of the additional section, such as glue
+    /// for the targets of `NS`, `MX` or `SRV` records.
+    pub additional_addrs: Vec<AdditionalAddr>,
+    /// The `EXPIRE` value of the response (RFC 7314), in seconds, if it was
+    /// requested with `QueryOptions::expire` and the server returned it.
+    pub expire: Option<u32>,
+    /// The identifier of the server (NSID, RFC 5001), if it was requested
+    /// with `QueryOptions::nsid` and the server returned it. See
+    /// `nsid_string()`.
+    pub nsid: Option<Vec<u8>>,
+    /// The closest trust point of the validation chain included in the
+    /// response, if it was requested with `QueryOptions::chain` and the
+    /// server supports the `CHAIN` option (RFC 7901).
+    pub chain: Option<String>,
+    /// The agent domain of the `Report-Channel` option (RFC 9567), that
+    /// errors with this response can be reported to with
+    /// `error_report_name()`.
+    pub report_channel: Option<String>,
+}
+
+/// An address record of the additional section of a response.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdditionalAddr {
+    pub name: String,
+    pub ttl: u32,
+    pub addr: IpAddr,
+}
+
+/// The `SOA` record returned along with a negative answer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuthoritySoa {
+    /// The zone that has no records for the name.
+    pub zone: String,
+    /// The TTL of the record.
+    pub ttl: u32,
+    pub soa: Soa,
+}
+
+impl AuthoritySoa {
+    /// How long the negative answer can be cached, the smaller of the TTL
+    /// of the record and its `minimum` field (RFC 2308).
+    pub fn negative_ttl(&self) -> u32 {
+        self.ttl.min(self.soa.minimum)
+    }
+
+    pub(crate) fn from_message(message: &Message) -> Option<Self> {
+        let rr = message
+            .authority
+            .iter()
+            .find(|rr| rr.rr_type == Soa::TYPE)?;
+        Some(AuthoritySoa {
+            zone: parse_name(&rr.name).ok()?.0,
+            ttl: rr.ttl,
+            soa: Soa::parse(&rr.rdata).ok()?,
+        })
+    }
+}
+
+fn additional_addrs(message: &Message) -> Vec<AdditionalAddr> {
+    message
+        .additional
+        .iter()
+        .filter_map(|rr| {
+            let addr = match rr.rr_type {
+                Ipv4Addr::TYPE => IpAddr::V4(Ipv4Addr::parse(&rr.rdata).ok()?),
+                Ipv6Addr::TYPE => IpAddr::V6(Ipv6Addr::parse(&rr.rdata).ok()?),
+                _ => return None,
+            };
+            Some(AdditionalAddr {
+                name: parse_name(&rr.name).ok()?.0,
+                ttl: rr.ttl,
+                addr,
+            })
+        })
+        .collect()
+}
+
+impl QueryInfo {
+    /// Record the response code and flags of the final response.
+    pub(crate) fn set_response(&mut self, parsed_response: &ParsedPacket) {
+        let flags = parsed_response.flags();
+        self.rcode = parsed_response.rcode();
+        self.extended_rcode = crate::packet::extended_rcode(parsed_response);
+        self.authoritative = flags & DNS_FLAG_AA != 0;
+        self.truncated = flags & DNS_FLAG_TC != 0;
+        self.recursion_available = flags & DNS_FLAG_RA != 0;
+        self.authentic_data = flags & DNS_FLAG_AD != 0;
+        self.checking_disabled = flags & DNS_FLAG_CD != 0;
+        self.min_ttl = None;
+        self.authority_soa = None;
+        self.additional_addrs.clear();
+        self.expire = crate::packet::edns_option(parsed_response, EDNS_OPTION_EXPIRE)
+            .and_then(|data| <[u8; 4]>::try_from(data).ok())
+            .map(u32::from_be_bytes);
+        self.nsid = crate::packet::edns_option(parsed_response, EDNS_OPTION_NSID)
+            .filter(|data| !data.is_empty())
+            .map(|data| data.to_vec());
+        self.chain = crate::packet::edns_option(parsed_response, EDNS_OPTION_CHAIN)
+            .and_then(|data| parse_name(data).ok())
+            .map(|(name, _)| name);
+        self.report_channel =
+            crate::packet::edns_option(parsed_response, EDNS_OPTION_REPORT_CHANNEL)
+                .and_then(|data| parse_name(data).ok())
+                .map(|(name, _)| name)
+                .filter(|name| name != ".");
+        let packet = parsed_response.packet();
+        let negative = DNSSector::ancount(packet) == 0;
+        if negative && DNSSector::arcount(packet) == 0 && DNSSector::nscount(packet) == 0 {
+            return;
+        }
+        if let Ok(message) = Message::parse(packet) {
+            self.min_ttl = message.answers.iter().map(|rr| rr.ttl).min();
+            if negative {
+                self.authority_soa = AuthoritySoa::from_message(&message);
+            }
+            self.additional_addrs = additional_addrs(&message);
+        }
+    }
+
+    /// The identifier of the server, as text. Identifiers are opaque, but
+    /// are usually printable, such as a host name or an anycast site;
+    /// invalid UTF-8 sequences are replaced.
+    pub fn nsid_string(&self) -> Option<String> {
+        self.nsid
+            .as_ref()
+            .map(|nsid| String::from_utf8_lossy(nsid).into_owned())
+    }
+
+    /// The name to query to report the extended DNS error `info_code` for
+    /// `qname` and `qtype` to the agent of the `Report-Channel` option
+    /// (RFC 9567), `None` if the server didn't include that option.
+    ///
+    /// The report is sent by querying this name for the `TXT` type; the
+    /// response itself carries no information.
+    pub fn error_report_name(&self, qname: &str, qtype: u16, info_code: u16) -> Option<String> {
+        let agent_domain = self.report_channel.as_ref()?;
+        Some(format!(
+            "_er.{}.{}.{}._er.{}",
+            qtype,
+            qname.trim_end_matches('.'),
+            info_code,
+            agent_domain.trim_end_matches('.')
+        ))
+    }
+
+    /// The name to query to report the error of `parsed_response`: the
+    /// extended DNS error it includes, or `DNSSEC Bogus` if its validation
+    /// failed. `None` if there is no error, or no `Report-Channel` option.
+    pub(crate) fn error_report(&self, parsed_response: &ParsedPacket) -> Option<String> {
+        self.report_channel.as_ref()?;
+        let info_code = crate::packet::edns_option(parsed_response, EDNS_OPTION_EDE)
+            .and_then(|data| data.get(..2))
+            .map(|x| u16::from_be_bytes([x[0], x[1]]));
+        #[cfg(feature = "dnssec")]
+        let info_code = match self.dnssec {
+            Some(DnssecStatus::Bogus) => Some(EDE_DNSSEC_BOGUS),
+            _ => info_code,
+        };
+        let info_code = info_code?;
+        let (qname, qtype, _) = Message::parse(parsed_response.packet()).ok()?.question?;
+        let (qname, _) = parse_name(&qname).ok()?;
+        self.error_report_name(&qname, qtype, info_code)
+    }
+}
+
+#[test]
+fn test_authority_soa() {
+    let query = crate::packet::query("www.example", Type::A, Class::IN, Default::default())
+        .unwrap()
+        .into_packet();
+    let mut response = crate::packet::synthesize_response(&query, Rcode::NXDOMAIN.into(), &[])
+        .unwrap()
+        .into_packet();
+    // example. 3600 IN SOA ns.example. admin.example. 1 3600 600 604800 300
+    response.extend_from_slice(b"\xc0\x10\x00\x06\x00\x01\x00\x00\x0e\x10\x00\x21");
+    response.extend_from_slice(b"\x02ns\xc0\x10\x05admin\xc0\x10");
+    for x in &[1u32, 3600, 600, 604800, 300] {
+        response.extend_from_slice(&x.to_be_bytes());
+    }
+    DNSSector::set_nscount(&mut response, 1);
+    let parsed_response = DNSSector::new(response).unwrap().parse().unwrap();
+    let mut query_info = QueryInfo::default();
+    query_info.set_response(&parsed_response);
+    assert_eq!(query_info.rcode, u8::from(Rcode::NXDOMAIN));
+    let authority_soa = query_info.authority_soa.unwrap();
+    assert_eq!(authority_soa.zone, "example");
+    assert_eq!(authority_soa.soa.mname, "ns.example");
+    assert_eq!(authority_soa.negative_ttl(), 300);
+}
+
+#[test]
+fn test_additional_addrs() {
+    let query = crate::packet::query("example", Type::MX, Class::IN, Default::default())
+        .unwrap()
+        .into_packet();
+    let mx: &[u8] = b"\x00\x0a\x04mail\xc0\x0c";
+    let mut response =
+        crate::packet::synthesize_response(&query, 0, &[(Type::MX.into(), 3600, mx)])
+            .unwrap()
+            .into_packet();
+    // mail.example. 3600 IN A 192.0.2.25
+    response.extend_from_slice(b"\x04mail\xc0\x0c\x00\x01\x00\x01\x00\x00\x0e\x10\x00\x04");
+    response.extend_from_slice(&[192, 0, 2, 25]);
+    DNSSector::set_arcount(&mut response, 1);
+    let parsed_response = DNSSector::new(response).unwrap().parse().unwrap();
+    let mut query_info = QueryInfo::default();
+    query_info.set_response(&parsed_response);
+    assert_eq!(query_info.authority_soa, None);
+    assert_eq!(
+        query_info.additional_addrs,
+        vec![AdditionalAddr {
+            name: "mail.example".to_string(),
+            ttl: 3600,
+            addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 25)),
+        }]
+    );
+}
+
+#[test]
+fn test_edns_options() {
+    let query = crate::packet::query("example", Type::SOA, Class::IN, Default::default())
+        .unwrap()
+        .into_packet();
+    let mut response = crate::packet::synthesize_response(&query, 0, &[])
+        .unwrap()
+        .into_packet();
+    let mut option = EDNS_OPTION_NSID.to_be_bytes().to_vec();
+    option.extend_from_slice(&6u16.to_be_bytes());
+    option.extend_from_slice(b"fra-03");
+    option.extend_from_slice(&EDNS_OPTION_EXPIRE.to_be_bytes());
+    option.extend_from_slice(&4u16.to_be_bytes());
+    option.extend_from_slice(&604800u32.to_be_bytes());
+    option.extend_from_slice(&EDNS_OPTION_CHAIN.to_be_bytes());
+    option.extend_from_slice(&1u16.to_be_bytes());
+    option.push(0);
+    option.extend_from_slice(&EDNS_OPTION_REPORT_CHANNEL.to_be_bytes());
+    option.extend_from_slice(&12u16.to_be_bytes());
+    option.extend_from_slice(b"\x05agent\x04test\x00");
+    crate::packet::add_edns(&mut response, 1232, 0, 0, &option);
+    let parsed_response = DNSSector::new(response).unwrap().parse().unwrap();
+    let mut query_info = QueryInfo::default();
+    query_info.set_response(&parsed_response);
+    assert_eq!(query_info.nsid, Some(b"fra-03".to_vec()));
+    assert_eq!(query_info.nsid_string(), Some("fra-03".to_string()));
+    assert_eq!(query_info.expire, Some(604800));
+    assert_eq!(query_info.chain, Some(".".to_string()));
+    assert_eq!(query_info.report_channel, Some("agent.test".to_string()));
+    assert_eq!(
+        query_info.error_report_name("broken.example.", 1, 6),
+        Some("_er.1.broken.example.6._er.agent.test".to_string())
+    );
+    assert_eq!(
+        QueryInfo::default().error_report_name("example", 1, 6),
+        None
+    );
+    assert_eq!(QueryInfo::default().nsid_string(), None);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_query_info() {
+    use std::io;
+    use std::net::SocketAddr;
+
+    use crate::error::DnsError;
+    use crate::events::Protocol;
+    use crate::testing::{MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    backend
+        .set_action(
+            "example.com",
+            "AAAA",
+            MockAction::Truncate(Box::new(MockAction::Respond {
+                rcode: 0,
+                answers: vec![],
+            })),
+        )
+        .unwrap();
+    let upstream_servers = vec![
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53))),
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 54], 53))),
+    ];
+    let dns_client =
+        crate::sync::GenericDNSClient::with_backend(upstream_servers.clone(), backend.clone());
+
+    let (ips, info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    assert_eq!(info.server.as_ref(), Some(&upstream_servers[0]));
+    assert_eq!(info.protocol, Some(Protocol::Udp));
+    assert_eq!(info.attempts, 1);
+    assert!(!info.tcp_fallback);
+    assert_eq!(info.rcode, 0);
+
+    let (_, info) = dns_client.query_aaaa_detailed("example.com").unwrap();
+    assert_eq!(info.protocol, Some(Protocol::Tcp));
+    assert_eq!(info.attempts, 2);
+    assert!(info.tcp_fallback);
+    assert_eq!(dns_client.stats().upstreams[0].truncated, 1);
+
+    let e = dns_client.query_txt_detailed("example.org").unwrap_err();
+    assert!(matches!(DnsError::from(e), DnsError::NxDomain));
+    let info = dns_client
+        .query_parsed("example.org", "IN", "TXT")
+        .unwrap()
+        .info;
+    assert_eq!(info.rcode, u8::from(Rcode::NXDOMAIN));
+
+    backend
+        .set_action(
+            "example.com",
+            "A",
+            MockAction::Fail(io::ErrorKind::ConnectionRefused),
+        )
+        .unwrap();
+    assert!(dns_client.query_a_detailed("example.com").is_err());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_error_reporting() {
+    use std::sync::{Arc, Mutex};
+
+    use crate::events::ClientEvent;
+    use crate::packet;
+    use crate::testing::{mock_client, MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    let query = packet::query("example.com", Type::A, Class::IN, Default::default())
+        .unwrap()
+        .into_packet();
+    let mut response = packet::synthesize_response(&query, Rcode::SERVFAIL.into(), &[])
+        .unwrap()
+        .into_packet();
+    // Extended DNS error 22 (No Reachable Authority), and the agent domain
+    let mut options = packet::EDNS_OPTION_EDE.to_be_bytes().to_vec();
+    options.extend_from_slice(&2u16.to_be_bytes());
+    options.extend_from_slice(&22u16.to_be_bytes());
+    options.extend_from_slice(&packet::EDNS_OPTION_REPORT_CHANNEL.to_be_bytes());
+    options.extend_from_slice(&12u16.to_be_bytes());
+    options.extend_from_slice(b"\x05agent\x04test\x00");
+    packet::add_edns(&mut response, 1232, 0, 0, &options);
+    backend
+        .set_action("example.com", "A", MockAction::Raw(response.clone()))
+        .unwrap();
+    let mut dns_client = mock_client(&backend);
+    let sent = Arc::new(Mutex::new(0));
+    {
+        let sent = sent.clone();
+        dns_client.set_event_sink(move |event: &ClientEvent| {
+            if let ClientEvent::QuerySent { .. } = event {
+                *sent.lock().unwrap() += 1;
+            }
+        });
+    }
+
+    let info = dns_client
+        .query_parsed("example.com", "IN", "A")
+        .unwrap()
+        .info;
+    assert_eq!(info.report_channel, Some("agent.test".to_string()));
+    let parsed_response = DNSSector::new(response).unwrap().parse().unwrap();
+    assert_eq!(
+        info.error_report(&parsed_response),
+        Some("_er.1.example.com.22._er.agent.test".to_string())
+    );
+    assert_eq!(std::mem::take(&mut *sent.lock().unwrap()), 1);
+
+    // The report is a second query
+    dns_client.set_error_reporting(true);
+    assert!(dns_client.query_a_detailed("example.com").is_err());
+    assert_eq!(*sent.lock().unwrap(), 2);
+}
@@ -0,0 +1,283 @@
+use std::io;
+use std::time::Duration;
+
+use dnssector::constants::*;
+use dnssector::*;
+
+use crate::packet::{
+    self, DEFAULT_EDNS_BUFSIZE, EDNS_OPTION_CHAIN, EDNS_OPTION_EXPIRE, EDNS_OPTION_NSID,
+};
+use crate::truncation_policy::TruncationPolicy;
+use crate::upstream_server::UpstreamServer;
+
+/// Settings overriding the client defaults for a single query.
+///
+/// Fields left to `None` use the client settings.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use dnsclient::QueryOptions;
+///
+/// let options = QueryOptions {
+///     timeout: Some(Duration::from_millis(500)),
+///     total_timeout: Some(Duration::from_secs(2)),
+///     recursion_desired: Some(false),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryOptions {
+    /// The timeout of every exchange with an upstream server.
+    pub timeout: Option<Duration>,
+    /// The time the query can take as a whole, including retries, instead
+    /// of the total timeout of the client.
+    ///
+    /// Queries of the async client can also be abandoned at any time by
+    /// dropping their future, such as when a cancellation token fires in a
+    /// `select!`: exchanges in flight are then cancelled, and their sockets
+    /// closed.
+    pub total_timeout: Option<Duration>,
+    /// Whether to send the query over TCP.
+    pub force_tcp: Option<bool>,
+    /// What to do if the UDP response is truncated.
+    pub truncation_policy: Option<TruncationPolicy>,
+    /// Whether to set the `RD` (recursion desired) flag. It is set by default.
+    pub recursion_desired: Option<bool>,
+    /// Add an EDNS section advertising this buffer size, unless the query
+    /// already has additional records.
+    pub edns_bufsize: Option<u16>,
+    /// Set the `DO` flag, adding an EDNS section if needed.
+    pub dnssec_ok: bool,
+    /// Set the `CD` (checking disabled) flag, asking validating resolvers
+    /// to return responses even if they fail DNSSEC validation.
+    pub checking_disabled: bool,
+    /// Request the EDNS `EXPIRE` option (RFC 7314), adding an EDNS section
+    /// if needed. The value returned by the server is in
+    /// `QueryInfo::expire`.
+    pub expire: bool,
+    /// Request the identifier of the server (NSID, RFC 5001), adding an
+    /// EDNS section if needed. The identifier returned by the server is in
+    /// `QueryInfo::nsid`.
+    pub nsid: bool,
+    /// Request the validation chain of the response from this closest trust
+    /// point, such as `.`, with the EDNS `CHAIN` option (RFC 7901). This
+    /// sets the `DO` flag. The trust point the server used is in
+    /// `QueryInfo::chain`, and the `DNSKEY` and `DS` records of the chain
+    /// are used by the DNSSEC validation instead of being fetched.
+    pub chain: Option<String>,
+    /// Whether reverse lookups check the names they return with a forward
+    /// lookup, instead of the client setting.
+    pub verify_ptr: Option<bool>,
+    /// The upstream servers to send the query to, in order.
+    pub upstream_servers: Option<Vec<UpstreamServer>>,
+}
+
+impl QueryOptions {
+    pub(crate) fn timeout(&self, default: Duration) -> Duration {
+        self.timeout.unwrap_or(default)
+    }
+
+    pub(crate) fn total_timeout(&self, default: Option<Duration>) -> Option<Duration> {
+        self.total_timeout.or(default)
+    }
+
+    pub(crate) fn force_tcp(&self, default: bool) -> bool {
+        self.force_tcp.unwrap_or(default)
+    }
+
+    pub(crate) fn truncation_policy(&self, default: TruncationPolicy) -> TruncationPolicy {
+        self.truncation_policy.unwrap_or(default)
+    }
+
+    pub(crate) fn upstream_servers<'t>(
+        &'t self,
+        default: &'t [UpstreamServer],
+    ) -> &'t [UpstreamServer] {
+        self.upstream_servers.as_deref().unwrap_or(default)
+    }
+
+    /// Apply the flags and EDNS settings to a query.
+    ///
+    /// EDNS options are appended to the OPT record of the query if it
+    /// already has one, and the size of its buffer is then kept.
+    pub(crate) fn apply(&self, parsed_query: ParsedPacket) -> Result<ParsedPacket, io::Error> {
+        let dnssec_ok = self.dnssec_ok || self.chain.is_some();
+        let edns = self.edns_bufsize.is_some() || dnssec_ok || self.expire || self.nsid;
+        if self.recursion_desired.is_none() && !self.checking_disabled && !edns {
+            return Ok(parsed_query);
+        }
+        let has_edns = parsed_query.offset_edns.is_some();
+        let mut query = parsed_query.into_packet();
+        match self.recursion_desired {
+            Some(true) => query[DNS_FLAGS_OFFSET] |= FLAG_RD,
+            Some(false) => query[DNS_FLAGS_OFFSET] &= !FLAG_RD,
+            None => {}
+        }
+        if self.checking_disabled {
+            query[DNS_FLAGS_OFFSET + 1] |= FLAG_CD;
+        }
+        if edns {
+            if DNSSector::arcount(&query) == 0 {
+                let bufsize = self.edns_bufsize.unwrap_or(DEFAULT_EDNS_BUFSIZE);
+                packet::add_edns(&mut query, bufsize, 0, 0, &[]);
+            } else if !has_edns {
+                return Err(no_edns_section());
+            }
+            let mut options = vec![];
+            if self.expire {
+                options.push((EDNS_OPTION_EXPIRE, vec![]));
+            }
+            if self.nsid {
+                options.push((EDNS_OPTION_NSID, vec![]));
+            }
+            if let Some(trust_point) = &self.chain {
+                options.push((EDNS_OPTION_CHAIN, packet::name_to_wire(trust_point)?));
+            }
+            for (code, data) in options {
+                query =
+                    packet::append_edns_option(&query, code, &data).ok_or_else(no_edns_section)?;
+            }
+        }
+        let parsed_query = DNSSector::new(query)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        if dnssec_ok {
+            packet::set_dnssec_ok(parsed_query)
+        } else {
+            Ok(parsed_query)
+        }
+    }
+}
+
+fn no_edns_section() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "The query has additional records but no EDNS section",
+    )
+}
+
+/// The `RD` flag, in the first byte of the flags.
+const FLAG_RD: u8 = 0x01;
+/// The `CD` flag, in the second byte of the flags.
+const FLAG_CD: u8 = 0x10;
+
+#[test]
+fn test_query_options() {
+    let query = || {
+        packet::query(
+            "example.com",
+            Type::from_string("A").unwrap(),
+            Class::from_string("IN").unwrap(),
+            Default::default(),
+        )
+        .unwrap()
+    };
+    let parsed_query = QueryOptions::default().apply(query()).unwrap();
+    assert_ne!(parsed_query.flags() & DNS_FLAG_RD, 0);
+    assert!(!parsed_query.dnssec());
+    let options = QueryOptions {
+        recursion_desired: Some(false),
+        dnssec_ok: true,
+        ..Default::default()
+    };
+    let parsed_query = options.apply(query()).unwrap();
+    assert_eq!(parsed_query.flags() & DNS_FLAG_RD, 0);
+    assert_eq!(parsed_query.flags() & DNS_FLAG_CD, 0);
+    assert!(parsed_query.dnssec());
+    let options = QueryOptions {
+        checking_disabled: true,
+        ..Default::default()
+    };
+    let parsed_query = options.apply(query()).unwrap();
+    assert_ne!(parsed_query.flags() & DNS_FLAG_CD, 0);
+    assert!(!parsed_query.dnssec());
+    assert!(packet::edns_option(&parsed_query, EDNS_OPTION_EXPIRE).is_none());
+    let options = QueryOptions {
+        expire: true,
+        ..Default::default()
+    };
+    let parsed_query = options.apply(query()).unwrap();
+    assert_eq!(
+        packet::edns_option(&parsed_query, EDNS_OPTION_EXPIRE),
+        Some(&[][..])
+    );
+    let mut with_edns = query().into_packet();
+    packet::add_edns(&mut with_edns, 1232, 0, 0, b"\x00\x03\x00\x00");
+    let with_edns = DNSSector::new(with_edns).unwrap().parse().unwrap();
+    let parsed_query = options.apply(with_edns).unwrap();
+    assert_eq!(DNSSector::arcount(parsed_query.packet()), 1);
+    assert_eq!(
+        packet::edns_option(&parsed_query, EDNS_OPTION_NSID),
+        Some(&[][..])
+    );
+    assert_eq!(
+        packet::edns_option(&parsed_query, EDNS_OPTION_EXPIRE),
+        Some(&[][..])
+    );
+    let options = QueryOptions {
+        nsid: true,
+        ..Default::default()
+    };
+    let parsed_query = options.apply(query()).unwrap();
+    assert_eq!(
+        packet::edns_option(&parsed_query, EDNS_OPTION_NSID),
+        Some(&[][..])
+    );
+    let options = QueryOptions {
+        chain: Some("example.".to_string()),
+        ..Default::default()
+    };
+    let parsed_query = options.apply(query()).unwrap();
+    assert!(parsed_query.dnssec());
+    assert_eq!(
+        packet::edns_option(&parsed_query, EDNS_OPTION_CHAIN),
+        Some(&b"\x07example\x00"[..])
+    );
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_client_query_options() {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::events::Protocol;
+    use crate::record_data::Record;
+    use crate::testing::MockBackend;
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let upstream_servers = [
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53))),
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 54], 53))),
+    ];
+    let dns_client = crate::sync::GenericDNSClient::with_backend(vec![], backend);
+    assert!(dns_client.query_a("example.com").is_err());
+
+    let options = QueryOptions {
+        force_tcp: Some(true),
+        upstream_servers: Some(upstream_servers[1..].to_vec()),
+        ..Default::default()
+    };
+    let ips = dns_client.query_a_with("example.com", &options).unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    let (_, info) = dns_client
+        .query_a_detailed_with("example.com", &options)
+        .unwrap();
+    assert_eq!(info.server.as_ref(), Some(&upstream_servers[1]));
+    assert_eq!(info.protocol, Some(Protocol::Tcp));
+    assert_eq!(info.rcode, 0);
+    assert!(info.recursion_available);
+    assert!(!info.authoritative && !info.truncated && !info.authentic_data);
+
+    assert!(dns_client.query_records("example.com", "A").is_err());
+    let records = dns_client
+        .query_records_with("example.com", "A", &options)
+        .unwrap();
+    assert_eq!(records, vec![Record::A(Ipv4Addr::new(192, 0, 2, 1))]);
+    let records = dns_client
+        .query_multi_with("example.com", &["A"], &options)
+        .unwrap();
+    assert_eq!(records.len(), 1);
+}
@@ -0,0 +1,94 @@
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// Order the addresses returned by `query_addrs()` by reachability, after
+/// connecting to each of them over TCP.
+///
+/// Addresses that accepted a connection come first, fastest first, followed
+/// by the other ones in their original order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReachabilityProbe {
+    /// The TCP port to connect to.
+    pub port: u16,
+    /// How long to wait for each connection.
+    pub timeout: Duration,
+}
+
+impl ReachabilityProbe {
+    /// Probe `port`, waiting up to one second for each connection.
+    pub fn new(port: u16) -> Self {
+        ReachabilityProbe {
+            port,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    pub(crate) fn addr(&self, ip: IpAddr) -> SocketAddr {
+        SocketAddr::new(ip, self.port)
+    }
+
+    /// Connect to every address concurrently, and order them.
+    pub(crate) fn order_blocking(&self, ips: Vec<IpAddr>) -> Vec<IpAddr> {
+        if ips.len() < 2 {
+            return ips;
+        }
+        let rtts = thread::scope(|scope| {
+            let probes: Vec<_> = ips
+                .iter()
+                .map(|&ip| {
+                    scope.spawn(move || {
+                        let start = Instant::now();
+                        TcpStream::connect_timeout(&self.addr(ip), self.timeout)
+                            .ok()
+                            .map(|_| start.elapsed())
+                    })
+                })
+                .collect();
+            probes
+                .into_iter()
+                .map(|probe| probe.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        order(ips, &rtts)
+    }
+}
+
+/// Order addresses by connection time, `None` meaning unreachable. The sort
+/// is stable, so unreachable addresses keep their original order.
+pub(crate) fn order(ips: Vec<IpAddr>, rtts: &[Option<Duration>]) -> Vec<IpAddr> {
+    let mut ranked: Vec<_> = ips.into_iter().zip(rtts.iter().copied()).collect();
+    ranked.sort_by_key(|&(_, rtt)| (rtt.is_none(), rtt));
+    ranked.into_iter().map(|(ip, _)| ip).collect()
+}
+
+#[test]
+fn test_reachability() {
+    use std::net::{Ipv4Addr, TcpListener};
+
+    let ips = vec![
+        IpAddr::from(Ipv4Addr::new(192, 0, 2, 1)),
+        IpAddr::from(Ipv4Addr::new(192, 0, 2, 2)),
+        IpAddr::from(Ipv4Addr::new(192, 0, 2, 3)),
+    ];
+    let rtts = [
+        None,
+        Some(Duration::from_millis(20)),
+        Some(Duration::from_millis(10)),
+    ];
+    assert_eq!(order(ips.clone(), &rtts), vec![ips[2], ips[1], ips[0]]);
+    assert_eq!(order(ips.clone(), &[None; 3]), ips);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut probe = ReachabilityProbe::new(listener.local_addr().unwrap().port());
+    probe.timeout = Duration::from_millis(200);
+    let unreachable = IpAddr::from(Ipv4Addr::new(127, 0, 0, 2));
+    let reachable = IpAddr::from(Ipv4Addr::LOCALHOST);
+    assert_eq!(
+        probe.order_blocking(vec![unreachable, reachable]),
+        vec![reachable, unreachable]
+    );
+}
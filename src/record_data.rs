@@ -0,0 +1,736 @@
+use std::convert::TryFrom;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use rand::{Rng, RngCore};
+
+use crate::presentation::Message;
+use crate::svcb::Https;
+
+/// Record data that can be decoded from a response, for use with the
+/// generic `query::<T>()` functions of the clients.
+///
+/// Implementing this trait is all it takes to query record types this crate
+/// doesn't know about:
+///
+/// ```
+/// use std::io;
+///
+/// use dnsclient::RecordData;
+///
+/// struct Uri {
+///     priority: u16,
+///     weight: u16,
+///     target: Vec<u8>,
+/// }
+///
+/// impl RecordData for Uri {
+///     const TYPE: u16 = 256;
+///
+///     fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+///         if rdata.len() < 4 {
+///             return Err(io::Error::new(io::ErrorKind::InvalidData, "Short URI record"));
+///         }
+///         Ok(Uri {
+///             priority: u16::from_be_bytes([rdata[0], rdata[1]]),
+///             weight: u16::from_be_bytes([rdata[2], rdata[3]]),
+///             target: rdata[4..].to_vec(),
+///         })
+///     }
+/// }
+/// ```
+pub trait RecordData: Sized {
+    /// The record type.
+    const TYPE: u16;
+
+    /// Decode the record data. Names it contains are uncompressed, and can
+    /// be decoded with `parse_name()`.
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error>;
+}
+
+/// Decode a name in wire format at the start of `data`, returning the name
+/// and the length of its wire representation.
+///
+/// Labels are joined with dots, without a trailing dot. The root name is `.`.
+pub fn parse_name(data: &[u8]) -> Result<(String, usize), io::Error> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid name");
+    let mut name = vec![];
+    let mut offset = 0;
+    loop {
+        let len = *data.get(offset).ok_or_else(invalid)? as usize;
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+        if len > 63 {
+            return Err(invalid());
+        }
+        if !name.is_empty() {
+            name.push(b'.');
+        }
+        name.extend_from_slice(data.get(offset..offset + len).ok_or_else(invalid)?);
+        offset += len;
+    }
+    if name.is_empty() {
+        name.push(b'.');
+    }
+    let name = String::from_utf8(name).map_err(|_| invalid())?;
+    Ok((name, offset))
+}
+
+/// Decode a name that must span the whole of `data`.
+fn parse_exact_name(data: &[u8]) -> Result<String, io::Error> {
+    match parse_name(data)? {
+        (name, len) if len == data.len() => Ok(name),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Trailing data after name",
+        )),
+    }
+}
+
+fn read_u16(rdata: &[u8], offset: usize) -> Result<u16, io::Error> {
+    rdata
+        .get(offset..offset + 2)
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Short record"))
+}
+
+/// Decoded record data of any type.
+///
+/// Types this crate doesn't know about are kept as raw data.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Record {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(Cname),
+    Mx(Mx),
+    Ns(Ns),
+    Txt(Txt),
+    Soa(Soa),
+    Srv(Srv),
+    Ptr(Ptr),
+    Caa(Caa),
+    Https(Https),
+    Unknown { rr_type: u16, rdata: Vec<u8> },
+}
+
+impl Record {
+    /// Decode the data of a record of type `rr_type`.
+    pub fn parse(rr_type: u16, rdata: &[u8]) -> Result<Self, io::Error> {
+        Ok(match rr_type {
+            Ipv4Addr::TYPE => Record::A(Ipv4Addr::parse(rdata)?),
+            Ipv6Addr::TYPE => Record::Aaaa(Ipv6Addr::parse(rdata)?),
+            Cname::TYPE => Record::Cname(Cname::parse(rdata)?),
+            Mx::TYPE => Record::Mx(Mx::parse(rdata)?),
+            Ns::TYPE => Record::Ns(Ns::parse(rdata)?),
+            Txt::TYPE => Record::Txt(Txt::parse(rdata)?),
+            Soa::TYPE => Record::Soa(Soa::parse(rdata)?),
+            Srv::TYPE => Record::Srv(Srv::parse(rdata)?),
+            Ptr::TYPE => Record::Ptr(Ptr::parse(rdata)?),
+            Caa::TYPE => Record::Caa(Caa::parse(rdata)?),
+            Https::TYPE => Record::Https(Https::parse(rdata)?),
+            rr_type => Record::Unknown {
+                rr_type,
+                rdata: rdata.to_vec(),
+            },
+        })
+    }
+}
+
+/// Decode the answers of a response, including the `CNAME` records leading
+/// to the queried name.
+pub(crate) fn parse_records(response: &[u8]) -> Result<Vec<Record>, io::Error> {
+    let message = Message::parse(response)?;
+    message
+        .answers
+        .iter()
+        .filter(|rr| rr.class == 1)
+        .map(|rr| Record::parse(rr.rr_type, &rr.rdata))
+        .collect()
+}
+
+/// Record data, along with the remaining time to live of its record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Answer<T> {
+    pub data: T,
+    /// The TTL, in seconds. For cached responses, this is the remaining
+    /// TTL.
+    pub ttl: u32,
+}
+
+/// Decode the answers of type `T` from a response.
+pub(crate) fn parse_answers<T: RecordData>(response: &[u8]) -> Result<Vec<T>, io::Error> {
+    parse_answers_with_ttl(response)
+        .map(|answers| answers.into_iter().map(|answer| answer.data).collect())
+}
+
+/// Decode the answers of type `T` from a response, along with their TTLs.
+pub(crate) fn parse_answers_with_ttl<T: RecordData>(
+    response: &[u8],
+) -> Result<Vec<Answer<T>>, io::Error> {
+    let message = Message::parse(response)?;
+    message
+        .answers
+        .iter()
+        .filter(|rr| rr.rr_type == T::TYPE && rr.class == 1)
+        .map(|rr| {
+            Ok(Answer {
+                data: T::parse(&rr.rdata)?,
+                ttl: rr.ttl,
+            })
+        })
+        .collect()
+}
+
+impl RecordData for Ipv4Addr {
+    const TYPE: u16 = 1;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        <[u8; 4]>::try_from(rdata)
+            .map(Ipv4Addr::from)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid A record"))
+    }
+}
+
+impl RecordData for Ipv6Addr {
+    const TYPE: u16 = 28;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        <[u8; 16]>::try_from(rdata)
+            .map(Ipv6Addr::from)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid AAAA record"))
+    }
+}
+
+/// A mail exchanger (`MX` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mx {
+    pub preference: u16,
+    pub exchange: String,
+}
+
+impl RecordData for Mx {
+    const TYPE: u16 = 15;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        Ok(Mx {
+            preference: read_u16(rdata, 0)?,
+            exchange: parse_exact_name(&rdata[2..])?,
+        })
+    }
+}
+
+/// A canonical name (`CNAME` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cname(pub String);
+
+impl RecordData for Cname {
+    const TYPE: u16 = 5;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        parse_exact_name(rdata).map(Cname)
+    }
+}
+
+/// If the answers of a response are a `CNAME` chain whose target has no
+/// records of the queried type, return that target, the name to query next.
+pub(crate) fn unresolved_cname(response: &[u8]) -> Result<Option<String>, io::Error> {
+    let message = Message::parse(response)?;
+    let (mut name, rr_type, _) = match message.question {
+        Some(question) => question,
+        None => return Ok(None),
+    };
+    let answers = &message.answers;
+    let find = |rr_type: u16, name: &[u8]| {
+        answers
+            .iter()
+            .find(|rr| rr.rr_type == rr_type && rr.class == 1 && rr.name.eq_ignore_ascii_case(name))
+    };
+    // Chains looping within the response are bounded by the number of answers
+    let mut followed = 0;
+    while find(rr_type, &name).is_none() {
+        match find(Cname::TYPE, &name) {
+            Some(rr) if followed < answers.len() => name = rr.rdata.clone(),
+            _ => break,
+        }
+        followed += 1;
+    }
+    if followed == 0 || find(rr_type, &name).is_some() {
+        return Ok(None);
+    }
+    parse_name(&name).map(|(name, _)| Some(name))
+}
+
+/// A naming authority pointer (`NAPTR` record), as used by ENUM and SIP.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Naptr {
+    pub order: u16,
+    pub preference: u16,
+    pub flags: String,
+    pub service: String,
+    pub regexp: String,
+    pub replacement: String,
+}
+
+impl RecordData for Naptr {
+    const TYPE: u16 = 35;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid NAPTR record");
+        let mut offset = 4;
+        let mut character_string = || {
+            let len = *rdata.get(offset).ok_or_else(invalid)? as usize;
+            let s = rdata
+                .get(offset + 1..offset + 1 + len)
+                .ok_or_else(invalid)?;
+            offset += 1 + len;
+            String::from_utf8(s.to_vec()).map_err(|_| invalid())
+        };
+        let flags = character_string()?;
+        let service = character_string()?;
+        let regexp = character_string()?;
+        Ok(Naptr {
+            order: read_u16(rdata, 0)?,
+            preference: read_u16(rdata, 2)?,
+            flags,
+            service,
+            regexp,
+            replacement: parse_exact_name(&rdata[offset..])?,
+        })
+    }
+}
+
+/// A name server (`NS` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ns(pub String);
+
+impl RecordData for Ns {
+    const TYPE: u16 = 2;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        parse_exact_name(rdata).map(Ns)
+    }
+}
+
+/// A domain name pointer (`PTR` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ptr(pub String);
+
+impl RecordData for Ptr {
+    const TYPE: u16 = 12;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        parse_exact_name(rdata).map(Ptr)
+    }
+}
+
+/// The start of a zone of authority (`SOA` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Soa {
+    pub mname: String,
+    pub rname: String,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+impl RecordData for Soa {
+    const TYPE: u16 = 6;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        let (mname, mname_len) = parse_name(rdata)?;
+        let (rname, rname_len) = parse_name(&rdata[mname_len..])?;
+        let offset = mname_len + rname_len;
+        if rdata.len() != offset + 20 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid SOA record",
+            ));
+        }
+        let u32_at = |i: usize| {
+            let x = &rdata[offset + i * 4..offset + i * 4 + 4];
+            u32::from_be_bytes([x[0], x[1], x[2], x[3]])
+        };
+        Ok(Soa {
+            mname,
+            rname,
+            serial: u32_at(0),
+            refresh: u32_at(1),
+            retry: u32_at(2),
+            expire: u32_at(3),
+            minimum: u32_at(4),
+        })
+    }
+}
+
+/// A service location (`SRV` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+impl RecordData for Srv {
+    const TYPE: u16 = 33;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        Ok(Srv {
+            priority: read_u16(rdata, 0)?,
+            weight: read_u16(rdata, 2)?,
+            port: read_u16(rdata, 4)?,
+            target: parse_exact_name(&rdata[6..])?,
+        })
+    }
+}
+
+impl Srv {
+    /// Order records the way clients should try them, as per RFC 2782:
+    /// by increasing priority, then randomly among records of the same
+    /// priority, with a probability proportional to their weight.
+    ///
+    /// A record whose target is `.` means that the service is not available.
+    pub fn order(records: Vec<Srv>) -> Vec<Srv> {
+        Self::order_with_rng(records, &mut rand::thread_rng())
+    }
+
+    pub(crate) fn order_with_rng(mut records: Vec<Srv>, rng: &mut dyn RngCore) -> Vec<Srv> {
+        records.sort_by_key(|srv| (srv.priority, srv.weight != 0));
+        let mut ordered = Vec::with_capacity(records.len());
+        while !records.is_empty() {
+            let priority = records[0].priority;
+            let len = records
+                .iter()
+                .position(|srv| srv.priority != priority)
+                .unwrap_or(records.len());
+            let mut group: Vec<_> = records.drain(..len).collect();
+            while !group.is_empty() {
+                let total: u32 = group.iter().map(|srv| u32::from(srv.weight)).sum();
+                let pick = rng.gen_range(0..=total);
+                let mut running_sum = 0;
+                let i = group
+                    .iter()
+                    .position(|srv| {
+                        running_sum += u32::from(srv.weight);
+                        running_sum >= pick
+                    })
+                    .unwrap();
+                ordered.push(group.remove(i));
+            }
+        }
+        ordered
+    }
+}
+
+/// A certification authority authorization (`CAA` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Caa {
+    pub flags: u8,
+    /// The property, such as `issue` or `iodef`.
+    pub tag: String,
+    pub value: Vec<u8>,
+}
+
+impl Caa {
+    /// Whether the critical flag is set.
+    pub fn is_critical(&self) -> bool {
+        self.flags & 0x80 != 0
+    }
+}
+
+impl RecordData for Caa {
+    const TYPE: u16 = 257;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid CAA record");
+        let (&flags, rest) = rdata.split_first().ok_or_else(invalid)?;
+        let (&tag_len, rest) = rest.split_first().ok_or_else(invalid)?;
+        let tag = rest.get(..tag_len as usize).ok_or_else(invalid)?;
+        if tag.is_empty() || !tag.iter().all(u8::is_ascii_alphanumeric) {
+            return Err(invalid());
+        }
+        Ok(Caa {
+            flags,
+            tag: String::from_utf8(tag.to_vec()).map_err(|_| invalid())?,
+            value: rest[tag_len as usize..].to_vec(),
+        })
+    }
+}
+
+/// A certificate association for DANE (`TLSA` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tlsa {
+    /// How the certificate is matched: `0` for a CA constraint, `1` for a
+    /// service certificate constraint, `2` for a trust anchor assertion and
+    /// `3` for a domain-issued certificate.
+    pub usage: u8,
+    /// `0` for the full certificate, `1` for its public key.
+    pub selector: u8,
+    /// `0` for the data itself, `1` for its SHA-256 hash, `2` for its
+    /// SHA-512 hash.
+    pub matching_type: u8,
+    pub data: Vec<u8>,
+}
+
+impl RecordData for Tlsa {
+    const TYPE: u16 = 52;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        match rdata {
+            [usage, selector, matching_type, data @ ..] => Ok(Tlsa {
+                usage: *usage,
+                selector: *selector,
+                matching_type: *matching_type,
+                data: data.to_vec(),
+            }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Short TLSA record",
+            )),
+        }
+    }
+}
+
+/// A text record (`TXT` record), as a list of character strings.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Txt(pub Vec<Vec<u8>>);
+
+impl Txt {
+    /// The concatenation of the character strings.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.concat()
+    }
+}
+
+impl RecordData for Txt {
+    const TYPE: u16 = 16;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        let mut strings = vec![];
+        let mut it = rdata;
+        while let Some((&len, rest)) = it.split_first() {
+            let s = rest
+                .get(..len as usize)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid text record"))?;
+            strings.push(s.to_vec());
+            it = &rest[len as usize..];
+        }
+        Ok(Txt(strings))
+    }
+}
+
+#[test]
+fn test_record_data() {
+    assert_eq!(
+        Mx::parse(b"\x00\x0a\x04mail\x07example\x00").unwrap(),
+        Mx {
+            preference: 10,
+            exchange: "mail.example".to_string()
+        }
+    );
+    assert!(Mx::parse(b"\x00\x0a\x04mail\x07example\x00\x00").is_err());
+    assert_eq!(
+        Srv::parse(b"\x00\x01\x00\x02\x01\xbb\x00").unwrap(),
+        Srv {
+            priority: 1,
+            weight: 2,
+            port: 443,
+            target: ".".to_string()
+        }
+    );
+    assert_eq!(
+        Txt::parse(b"\x03abc\x00\x02de").unwrap().to_bytes(),
+        b"abcde".to_vec()
+    );
+    assert!(Txt::parse(b"\x03ab").is_err());
+    let soa = Soa::parse(
+        b"\x02ns\x07example\x00\x05admin\x07example\x00\
+          \x00\x00\x00\x01\x00\x00\x0e\x10\x00\x00\x02\x58\x00\x09\x3a\x80\x00\x00\x01\x2c",
+    )
+    .unwrap();
+    assert_eq!(soa.mname, "ns.example");
+    assert_eq!(soa.rname, "admin.example");
+    assert_eq!((soa.serial, soa.minimum), (1, 300));
+    assert!(Ipv4Addr::parse(&[192, 0, 2]).is_err());
+    let tlsa = Tlsa::parse(b"\x03\x01\x01\xab\xcd").unwrap();
+    assert_eq!((tlsa.usage, tlsa.selector, tlsa.matching_type), (3, 1, 1));
+    assert_eq!(tlsa.data, vec![0xab, 0xcd]);
+    assert!(Tlsa::parse(b"\x03\x01").is_err());
+    let caa = Caa::parse(b"\x80\x05issueca.example").unwrap();
+    assert!(caa.is_critical());
+    assert_eq!(caa.tag, "issue");
+    assert_eq!(caa.value, b"ca.example".to_vec());
+    assert_eq!(
+        Record::parse(1, &[192, 0, 2, 1]).unwrap(),
+        Record::A(Ipv4Addr::new(192, 0, 2, 1))
+    );
+    assert_eq!(
+        Record::parse(99, b"x").unwrap(),
+        Record::Unknown {
+            rr_type: 99,
+            rdata: b"x".to_vec()
+        }
+    );
+    let naptr =
+        Naptr::parse(b"\x00\x64\x00\x0a\x01u\x07E2U+sip\x1b!^.*$!sip:info@example.com!\x00")
+            .unwrap();
+    assert_eq!((naptr.order, naptr.preference), (100, 10));
+    assert_eq!(naptr.service, "E2U+sip");
+    assert_eq!(naptr.regexp, "!^.*$!sip:info@example.com!");
+    assert_eq!(naptr.replacement, ".");
+    assert!(Naptr::parse(b"\x00\x64\x00\x0a\x01u").is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_record_serde() {
+    let records = vec![
+        Record::A(Ipv4Addr::new(192, 0, 2, 1)),
+        Record::Mx(Mx {
+            preference: 10,
+            exchange: "mail.example".to_string(),
+        }),
+        Record::Txt(Txt(vec![b"abc".to_vec()])),
+        Record::Unknown {
+            rr_type: 99,
+            rdata: b"x".to_vec(),
+        },
+    ];
+    let json = serde_json::to_string(&records).unwrap();
+    assert_eq!(serde_json::from_str::<Vec<Record>>(&json).unwrap(), records);
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value[0]["A"], "192.0.2.1");
+    assert_eq!(value[1]["Mx"]["exchange"], "mail.example");
+}
+
+#[test]
+fn test_unresolved_cname() {
+    use dnssector::constants::{Class, Type};
+
+    let query = crate::packet::query("www.example", Type::A, Class::IN, Default::default())
+        .unwrap()
+        .into_packet();
+    let cname: (u16, u32, &[u8]) = (Cname::TYPE, 60, b"\x04edge\x03cdn\x00");
+    let response = crate::packet::synthesize_response(&query, 0, &[cname])
+        .unwrap()
+        .into_packet();
+    assert_eq!(
+        unresolved_cname(&response).unwrap(),
+        Some("edge.cdn".to_string())
+    );
+    let response = crate::packet::synthesize_response(&query, 0, &[])
+        .unwrap()
+        .into_packet();
+    assert_eq!(unresolved_cname(&response).unwrap(), None);
+}
+
+#[test]
+fn test_srv_order() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    let srv = |priority, weight, target: &str| Srv {
+        priority,
+        weight,
+        port: 443,
+        target: target.to_string(),
+    };
+    let records = vec![
+        srv(20, 0, "backup"),
+        srv(10, 1, "light"),
+        srv(10, 0, "zero"),
+        srv(10, 99, "heavy"),
+    ];
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut heavy_first = 0;
+    for _ in 0..100 {
+        let ordered = Srv::order_with_rng(records.clone(), &mut rng);
+        assert_eq!(ordered.len(), 4);
+        assert_eq!(ordered[3].target, "backup");
+        if ordered[0].target == "heavy" {
+            heavy_first += 1;
+        }
+    }
+    assert!(heavy_first > 80);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_typed_query() {
+    use std::net::SocketAddr;
+
+    use crate::testing::{MockAction, MockBackend};
+    use crate::upstream_server::UpstreamServer;
+
+    let backend = MockBackend::new();
+    let answers = vec![(
+        Mx::TYPE,
+        3600,
+        b"\x00\x0a\x04mail\x07example\x03com\x00".to_vec(),
+    )];
+    let action = MockAction::Respond { rcode: 0, answers };
+    backend.set_action("example.com", "MX", action).unwrap();
+    let answers = vec![
+        (Mx::TYPE, 3600, b"\x00\x14\x05mail2\x07example\x00".to_vec()),
+        (Mx::TYPE, 3600, b"\x00\x0a\x05mail1\x07example\x00".to_vec()),
+    ];
+    let action = MockAction::Respond { rcode: 0, answers };
+    backend.set_action("example.net", "MX", action).unwrap();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let upstream_servers = vec![UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)))];
+    let dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend.clone());
+    let mxs = dns_client.query::<Mx>("example.com").unwrap();
+    assert_eq!(
+        mxs,
+        vec![Mx {
+            preference: 10,
+            exchange: "mail.example.com".to_string()
+        }]
+    );
+    let ips = dns_client.query::<Ipv4Addr>("example.com").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    let answers = dns_client.query_answers::<Ipv4Addr>("example.com").unwrap();
+    assert_eq!(
+        answers,
+        vec![Answer {
+            data: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 3600
+        }]
+    );
+    let exchanges: Vec<_> = dns_client
+        .query_mx("example.net")
+        .unwrap()
+        .into_iter()
+        .map(|mx| mx.exchange)
+        .collect();
+    assert_eq!(exchanges, vec!["mail1.example", "mail2.example"]);
+
+    let answers = vec![
+        (Ns::TYPE, 3600, b"\x03ns1\x07example\x00".to_vec()),
+        (Ns::TYPE, 3600, b"\x03ns2\x07example\x00".to_vec()),
+    ];
+    let action = MockAction::Respond { rcode: 0, answers };
+    backend.set_action("example", "NS", action).unwrap();
+    assert_eq!(
+        dns_client.query_ns("example").unwrap(),
+        vec!["ns1.example", "ns2.example"]
+    );
+    assert!(dns_client.query_soa("example").is_err());
+}
@@ -0,0 +1,84 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::backend::async_tokio::TokioBackend;
+use crate::r#async::{AsyncBackend, GenericDNSClient};
+
+/// A resolver for `reqwest`, sending all the queries of a `reqwest::Client`
+/// through an async client.
+///
+/// ```no_run
+/// use std::sync::Arc;
+///
+/// use dnsclient::r#async::tokio::DNSClient;
+/// use dnsclient::ReqwestResolver;
+///
+/// let dns_client = DNSClient::new(dnsclient::system::default_resolvers().unwrap());
+/// let http_client = reqwest::Client::builder()
+///     .dns_resolver(Arc::new(ReqwestResolver::new(dns_client)))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ReqwestResolver<B = TokioBackend> {
+    dns_client: Arc<GenericDNSClient<B>>,
+}
+
+impl<B: AsyncBackend> ReqwestResolver<B> {
+    pub fn new(dns_client: GenericDNSClient<B>) -> Self {
+        ReqwestResolver {
+            dns_client: Arc::new(dns_client),
+        }
+    }
+}
+
+impl<B: AsyncBackend> From<GenericDNSClient<B>> for ReqwestResolver<B> {
+    fn from(dns_client: GenericDNSClient<B>) -> Self {
+        ReqwestResolver::new(dns_client)
+    }
+}
+
+impl<B: AsyncBackend> Resolve for ReqwestResolver<B> {
+    fn resolve(&self, name: Name) -> Resolving {
+        let dns_client = self.dns_client.clone();
+        Box::pin(async move {
+            let ips = dns_client.query_addrs(name.as_str()).await?;
+            if ips.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "No addresses found").into());
+            }
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+#[test]
+fn test_reqwest_resolver() {
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    use crate::address_family::AddressFamilyPreference;
+    use crate::static_records::StaticRecords;
+
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    let mut dns_client = crate::r#async::tokio::DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    dns_client.set_address_family_preference(AddressFamilyPreference::V4Only);
+    let resolver = ReqwestResolver::new(dns_client);
+    let rt = ::tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let addrs: Vec<_> = rt
+        .block_on(resolver.resolve(Name::from_str("example.com").unwrap()))
+        .unwrap()
+        .collect();
+    assert_eq!(addrs, vec![SocketAddr::from(([192, 0, 2, 1], 0))]);
+    assert!(rt
+        .block_on(resolver.resolve(Name::from_str("example.org").unwrap()))
+        .is_err());
+}
@@ -0,0 +1,71 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// The high-level queries of a synchronous resolver.
+///
+/// This trait is object safe: applications can accept a `Box<dyn Resolver>`
+/// and use `sync::DNSClient` or any alternative implementation, such as a mock.
+pub trait Resolver {
+    /// Send a raw query to the DNS server and return the response.
+    fn query_raw(&self, query: &[u8], tid_masking: bool) -> Result<Vec<u8>, io::Error>;
+
+    /// Return IPv4 addresses.
+    fn query_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, io::Error>;
+
+    /// Return IPv6 addresses.
+    fn query_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, io::Error>;
+
+    /// Return IPv4 and/or IPv6 addresses.
+    fn query_addrs(&self, name: &str) -> Result<Vec<IpAddr>, io::Error>;
+
+    /// Return TXT records.
+    fn query_txt(&self, name: &str) -> Result<Vec<Vec<u8>>, io::Error>;
+
+    /// Reverse IP lookup.
+    fn query_ptr(&self, ip: &IpAddr) -> Result<Vec<String>, io::Error>;
+}
+
+/// The high-level queries of an asynchronous resolver, implemented by
+/// `r#async::DNSClient`.
+///
+/// Libraries can be generic over this trait instead of depending on a
+/// concrete client type and async runtime.
+pub trait AsyncResolver {
+    /// Send a raw query to the DNS server and return the response.
+    fn query_raw<'t>(
+        &'t self,
+        query: &'t [u8],
+        tid_masking: bool,
+    ) -> impl Future<Output = Result<Vec<u8>, io::Error>> + Send + 't;
+
+    /// Return IPv4 addresses.
+    fn query_a<'t>(
+        &'t self,
+        name: &'t str,
+    ) -> impl Future<Output = Result<Vec<Ipv4Addr>, io::Error>> + Send + 't;
+
+    /// Return IPv6 addresses.
+    fn query_aaaa<'t>(
+        &'t self,
+        name: &'t str,
+    ) -> impl Future<Output = Result<Vec<Ipv6Addr>, io::Error>> + Send + 't;
+
+    /// Return IPv4 and/or IPv6 addresses.
+    fn query_addrs<'t>(
+        &'t self,
+        name: &'t str,
+    ) -> impl Future<Output = Result<Vec<IpAddr>, io::Error>> + Send + 't;
+
+    /// Return TXT records.
+    fn query_txt<'t>(
+        &'t self,
+        name: &'t str,
+    ) -> impl Future<Output = Result<Vec<Vec<u8>>, io::Error>> + Send + 't;
+
+    /// Reverse IP lookup.
+    fn query_ptr<'t>(
+        &'t self,
+        ip: &'t IpAddr,
+    ) -> impl Future<Output = Result<Vec<String>, io::Error>> + Send + 't;
+}
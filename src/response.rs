@@ -0,0 +1,167 @@
+use std::io;
+
+use crate::packet::TYPE_OPT;
+use crate::presentation::{self, rdata_to_string, Message};
+use crate::query_info::QueryInfo;
+use crate::record_data::{parse_name, Record, RecordData};
+
+/// A resource record of a response, with uncompressed names.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResourceRecord {
+    pub name: String,
+    pub rr_type: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub rdata: Vec<u8>,
+}
+
+impl ResourceRecord {
+    fn new(rr: &presentation::Record) -> Result<Self, io::Error> {
+        Ok(ResourceRecord {
+            name: parse_name(&rr.name)?.0,
+            rr_type: rr.rr_type,
+            class: rr.class,
+            ttl: rr.ttl,
+            rdata: rr.rdata.clone(),
+        })
+    }
+
+    /// Decode the record data as a `T`.
+    pub fn parse<T: RecordData>(&self) -> Result<T, io::Error> {
+        if self.rr_type != T::TYPE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unexpected record type",
+            ));
+        }
+        T::parse(&self.rdata)
+    }
+
+    /// Decode the record data, whatever its type.
+    pub fn record(&self) -> Result<Record, io::Error> {
+        Record::parse(self.rr_type, &self.rdata)
+    }
+
+    /// The record data, in presentation format.
+    pub fn data_to_string(&self) -> String {
+        rdata_to_string(self.rr_type, &self.rdata)
+    }
+}
+
+/// A complete response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DnsResponse {
+    /// The raw response.
+    pub packet: Vec<u8>,
+    /// How the response was obtained.
+    pub info: QueryInfo,
+}
+
+impl DnsResponse {
+    /// The response code.
+    pub fn rcode(&self) -> u8 {
+        self.info.rcode
+    }
+
+    /// The header flags, including the opcode and the response code.
+    pub fn flags(&self) -> u16 {
+        match self.packet.get(2..4) {
+            Some(flags) => u16::from_be_bytes([flags[0], flags[1]]),
+            None => 0,
+        }
+    }
+
+    /// The records of the answer section.
+    pub fn answers(&self) -> Result<Vec<ResourceRecord>, io::Error> {
+        let message = Message::parse(&self.packet)?;
+        message.answers.iter().map(ResourceRecord::new).collect()
+    }
+
+    /// The records of the authority section.
+    pub fn authority(&self) -> Result<Vec<ResourceRecord>, io::Error> {
+        let message = Message::parse(&self.packet)?;
+        message.authority.iter().map(ResourceRecord::new).collect()
+    }
+
+    /// The records of the additional section, without the EDNS
+    /// pseudo-record.
+    pub fn additional(&self) -> Result<Vec<ResourceRecord>, io::Error> {
+        let message = Message::parse(&self.packet)?;
+        message
+            .additional
+            .iter()
+            .filter(|rr| rr.rr_type != TYPE_OPT)
+            .map(ResourceRecord::new)
+            .collect()
+    }
+
+    /// The answer, authority and additional sections, as zone file lines.
+    pub fn to_records(&self) -> Result<String, io::Error> {
+        presentation::format_records(&self.packet)
+    }
+
+    /// The response, in the JSON format used by DNS-over-HTTPS JSON APIs.
+    pub fn to_json(&self) -> Result<String, io::Error> {
+        presentation::format_json(&self.packet)
+    }
+}
+
+#[test]
+fn test_dns_response() {
+    use std::net::Ipv4Addr;
+
+    use dnssector::constants::{Class, Type};
+
+    let query = crate::packet::query("example.com", Type::A, Class::IN, Default::default())
+        .unwrap()
+        .into_packet();
+    let answers: &[(u16, u32, &[u8])] = &[(1, 300, &[192, 0, 2, 1])];
+    let packet = crate::packet::synthesize_response(&query, 0, answers)
+        .unwrap()
+        .into_packet();
+    let response = DnsResponse {
+        packet,
+        info: QueryInfo::default(),
+    };
+    assert_eq!(response.flags() & 0x8000, 0x8000);
+    let answers = response.answers().unwrap();
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].name, "example.com");
+    assert_eq!(answers[0].ttl, 300);
+    assert_eq!(
+        answers[0].parse::<Ipv4Addr>().unwrap(),
+        Ipv4Addr::new(192, 0, 2, 1)
+    );
+    assert_eq!(answers[0].data_to_string(), "192.0.2.1");
+    assert!(response.authority().unwrap().is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_dns_response_serde() {
+    use std::time::Duration;
+
+    use crate::events::Protocol;
+    use crate::upstream_server::UpstreamServer;
+
+    let response = DnsResponse {
+        packet: vec![0x12, 0x34, 0x81, 0x80],
+        info: QueryInfo {
+            server: Some(UpstreamServer::new(([192, 0, 2, 53], 53))),
+            protocol: Some(Protocol::Udp),
+            attempts: 1,
+            rtt: Duration::from_millis(12),
+            ..Default::default()
+        },
+    };
+    let json = serde_json::to_string(&response).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["info"]["server"], "192.0.2.53:53");
+    assert_eq!(value["info"]["protocol"], "udp");
+    assert_eq!(
+        serde_json::from_str::<DnsResponse>(&json).unwrap(),
+        response
+    );
+}
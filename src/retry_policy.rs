@@ -0,0 +1,149 @@
+use std::io;
+use std::time::Duration;
+
+use dnssector::constants::Rcode;
+use dnssector::ParsedPacket;
+
+/// When and how often a query is sent again after every upstream server
+/// failed.
+///
+/// Retries start over from the first upstream server, after a delay that
+/// doubles after each retry, up to `max_backoff`. By default, queries are not
+/// retried.
+///
+/// ```
+/// use dnsclient::RetryPolicy;
+///
+/// let retry_policy = RetryPolicy {
+///     on_servfail: true,
+///     ..RetryPolicy::new(2)
+/// };
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryPolicy {
+    /// The maximum number of retries.
+    pub retries: u32,
+    /// The delay before the first retry.
+    pub backoff: Duration,
+    /// The maximum delay between two retries.
+    pub max_backoff: Duration,
+    /// Retry when no upstream server responded, after timeouts or network
+    /// errors.
+    pub on_timeout: bool,
+    /// Retry when the response is `SERVFAIL`.
+    pub on_servfail: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            on_timeout: true,
+            on_servfail: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Retry up to `retries` times when no upstream server responded.
+    pub fn new(retries: u32) -> Self {
+        RetryPolicy {
+            retries,
+            ..Default::default()
+        }
+    }
+
+    /// The delay before retry number `retry`, starting from `0`.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        self.backoff
+            .checked_mul(1 << retry.min(16))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+
+    pub(crate) fn should_retry<T>(&self, res: &Result<(ParsedPacket, T), io::Error>) -> bool {
+        match res {
+            Ok((parsed_response, _)) => {
+                self.on_servfail && parsed_response.rcode() == u8::from(Rcode::SERVFAIL)
+            }
+            // Shutting down and unsupported transports are not transient
+            Err(e) => {
+                self.on_timeout
+                    && !matches!(
+                        e.kind(),
+                        io::ErrorKind::ConnectionAborted | io::ErrorKind::Unsupported
+                    )
+            }
+        }
+    }
+}
+
+#[test]
+fn test_retry_policy() {
+    let retry_policy = RetryPolicy::new(3);
+    assert_eq!(retry_policy.backoff(0), Duration::from_millis(100));
+    assert_eq!(retry_policy.backoff(2), Duration::from_millis(400));
+    assert_eq!(retry_policy.backoff(10), Duration::from_secs(1));
+    assert_eq!(retry_policy.backoff(u32::MAX), Duration::from_secs(1));
+    let timeout: Result<(ParsedPacket, ()), _> =
+        Err(io::Error::new(io::ErrorKind::TimedOut, "Timeout"));
+    assert!(retry_policy.should_retry(&timeout));
+    let shutdown: Result<(ParsedPacket, ()), _> =
+        Err(io::Error::new(io::ErrorKind::ConnectionAborted, "Shutdown"));
+    assert!(!retry_policy.should_retry(&shutdown));
+    let query = crate::packet::query(
+        "example.com",
+        dnssector::constants::Type::A,
+        dnssector::constants::Class::IN,
+        Default::default(),
+    )
+    .unwrap()
+    .into_packet();
+    let servfail = || crate::packet::synthesize_response(&query, Rcode::SERVFAIL.into(), &[]);
+    assert!(!retry_policy.should_retry(&servfail().map(|r| (r, ()))));
+    let retry_policy = RetryPolicy {
+        on_servfail: true,
+        ..retry_policy
+    };
+    assert!(retry_policy.should_retry(&servfail().map(|r| (r, ()))));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_retry_events() {
+    use std::sync::{Arc, Mutex};
+
+    use crate::events::ClientEvent;
+    use crate::testing::{mock_client, MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    backend
+        .set_action(
+            "example.com",
+            "A",
+            MockAction::Fail(io::ErrorKind::ConnectionRefused),
+        )
+        .unwrap();
+    let mut dns_client = mock_client(&backend);
+    dns_client.set_retry_policy(RetryPolicy {
+        backoff: Duration::from_millis(1),
+        ..RetryPolicy::new(2)
+    });
+    let retries = Arc::new(Mutex::new(vec![]));
+    {
+        let retries = retries.clone();
+        dns_client.set_event_sink(move |event: &ClientEvent| {
+            if let ClientEvent::Retry { attempt, backoff } = event {
+                retries.lock().unwrap().push((*attempt, *backoff));
+            }
+        });
+    }
+    assert!(dns_client.query_a("example.com").is_err());
+    assert_eq!(
+        *retries.lock().unwrap(),
+        vec![(1, Duration::from_millis(1)), (2, Duration::from_millis(2))]
+    );
+    assert_eq!(backend.queries().len(), 3);
+}
@@ -0,0 +1,100 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+
+/// A name returned by a reverse lookup.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PtrName {
+    /// The name of the `PTR` record.
+    pub name: String,
+    /// Whether a forward lookup of the name returned the address.
+    ///
+    /// This is always `false` if forward verification is disabled.
+    pub verified: bool,
+}
+
+/// The name of the `PTR` records of an address: `in-addr.arpa` for IPv4,
+/// and one label per nibble under `ip6.arpa` for IPv6 (RFC 3596).
+pub(crate) fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            format!(
+                "{}.{}.{}.{}.in-addr.arpa",
+                octets[3], octets[2], octets[1], octets[0]
+            )
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::with_capacity(72);
+            for octet in ip.octets().iter().rev() {
+                let _ = write!(name, "{:x}.{:x}.", octet & 0xf, octet >> 4);
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+#[test]
+fn test_reverse_name() {
+    assert_eq!(
+        reverse_name(&"192.0.2.1".parse().unwrap()),
+        "1.2.0.192.in-addr.arpa"
+    );
+    assert_eq!(
+        reverse_name(&"2001:db8::567:89ab".parse().unwrap()),
+        "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+    );
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_query_ptr() {
+    use std::net::{IpAddr, Ipv6Addr};
+
+    use crate::testing::{mock_client, MockAction, MockBackend};
+
+    use crate::query_options::QueryOptions;
+    use crate::record_data::{Ptr, RecordData};
+
+    let backend = MockBackend::new();
+    let answers = vec![
+        (Ptr::TYPE, 3600, b"\x04host\x07example\x00".to_vec()),
+        (Ptr::TYPE, 3600, b"\x05alias\x07example\x00".to_vec()),
+    ];
+    let action = MockAction::Respond { rcode: 0, answers };
+    backend
+        .set_action(
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa",
+            "PTR",
+            action,
+        )
+        .unwrap();
+    let ip: Ipv6Addr = "2001:db8::567:89ab".parse().unwrap();
+    backend.add_aaaa("host.example", &[ip]);
+    backend.add_aaaa("alias.example", &[Ipv6Addr::LOCALHOST]);
+    let mut dns_client = mock_client(&backend);
+    let ip = IpAddr::V6(ip);
+    assert_eq!(
+        dns_client.query_ptr(&ip).unwrap(),
+        vec!["host.example".to_string()]
+    );
+    assert_eq!(
+        dns_client.query_ptr_names(&ip).unwrap(),
+        vec![PtrName {
+            name: "host.example".to_string(),
+            verified: true,
+        }]
+    );
+    dns_client.set_ptr_verification(false);
+    let names = dns_client.query_ptr_names(&ip).unwrap();
+    assert_eq!(names.len(), 2);
+    assert!(names.iter().all(|ptr_name| !ptr_name.verified));
+    let options = QueryOptions {
+        verify_ptr: Some(true),
+        ..Default::default()
+    };
+    assert_eq!(
+        dns_client.query_ptr_with(&ip, &options).unwrap(),
+        vec!["host.example".to_string()]
+    );
+}
@@ -0,0 +1,33 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use rand::RngCore;
+
+/// The random number generator used by a client for transaction IDs and
+/// shuffling. Clones share the same generator.
+#[derive(Clone, Default)]
+pub(crate) struct ClientRng(Option<Arc<Mutex<Box<dyn RngCore + Send>>>>);
+
+impl fmt::Debug for ClientRng {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            None => f.write_str("ClientRng(thread_rng)"),
+            Some(_) => f.write_str("ClientRng(custom)"),
+        }
+    }
+}
+
+impl ClientRng {
+    pub(crate) fn new<R: RngCore + Send + 'static>(rng: R) -> Self {
+        ClientRng(Some(Arc::new(Mutex::new(Box::new(rng)))))
+    }
+
+    /// Run `f` with the generator; `rand::thread_rng()` is used if no
+    /// generator was set.
+    pub(crate) fn with<T>(&self, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        match &self.0 {
+            None => f(&mut rand::thread_rng()),
+            Some(rng) => f(&mut **rng.lock().unwrap()),
+        }
+    }
+}
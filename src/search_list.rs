@@ -0,0 +1,103 @@
+/// Domains appended to relative names by `resolve()`, as configured with the
+/// `search`, `domain` and `ndots` options of `resolv.conf`.
+///
+/// Names with at least `ndots` dots are tried as-is first, then with each
+/// domain of the list appended. Names with fewer dots are tried with the
+/// domains first. Names ending with a dot are absolute, and only tried as-is.
+///
+/// ```
+/// use dnsclient::SearchList;
+///
+/// let search_list = SearchList::new(vec!["corp.example".to_string()]);
+/// assert_eq!(search_list.candidates("host"), vec!["host.corp.example", "host"]);
+/// assert_eq!(search_list.candidates("host."), vec!["host"]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchList {
+    pub domains: Vec<String>,
+    pub ndots: usize,
+}
+
+impl Default for SearchList {
+    fn default() -> Self {
+        SearchList {
+            domains: vec![],
+            ndots: 1,
+        }
+    }
+}
+
+impl SearchList {
+    pub fn new(domains: Vec<String>) -> Self {
+        SearchList {
+            domains,
+            ..Default::default()
+        }
+    }
+
+    /// The names to try for `name`, in order.
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        if let Some(name) = name.strip_suffix('.') {
+            return vec![name.to_string()];
+        }
+        let qualified = self
+            .domains
+            .iter()
+            .map(|domain| format!("{}.{}", name, domain.trim_matches('.')));
+        let mut candidates = vec![];
+        if name.matches('.').count() >= self.ndots {
+            candidates.push(name.to_string());
+            candidates.extend(qualified);
+        } else {
+            candidates.extend(qualified);
+            candidates.push(name.to_string());
+        }
+        candidates
+    }
+}
+
+#[test]
+fn test_search_list() {
+    let search_list = SearchList {
+        domains: vec!["a.example".to_string(), "b.example.".to_string()],
+        ndots: 2,
+    };
+    assert_eq!(
+        search_list.candidates("www.host"),
+        vec!["www.host.a.example", "www.host.b.example", "www.host"]
+    );
+    assert_eq!(
+        search_list.candidates("www.host.test"),
+        vec![
+            "www.host.test",
+            "www.host.test.a.example",
+            "www.host.test.b.example"
+        ]
+    );
+    assert_eq!(SearchList::default().candidates("host"), vec!["host"]);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_resolve_search_list() {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::testing::MockBackend;
+    use crate::upstream_server::UpstreamServer;
+
+    let backend = MockBackend::new();
+    backend.add_a("host.corp.example", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let upstream_servers = vec![UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)))];
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    assert!(dns_client.resolve("host").is_err());
+    dns_client.set_search_list(SearchList::new(vec![
+        "other.example".to_string(),
+        "corp.example".to_string(),
+    ]));
+    assert_eq!(
+        dns_client.resolve("host").unwrap(),
+        vec![std::net::IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+    );
+    assert!(dns_client.resolve("host.").is_err());
+}
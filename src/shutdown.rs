@@ -0,0 +1,109 @@
+use std::future::poll_fn;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Poll, Waker};
+
+/// The shutdown state of a client, shared by its clones.
+///
+/// Every exchange with an upstream server holds an `InFlight` guard. Once a
+/// client has been shut down, new exchanges are rejected, and shutting down
+/// completes when the guards of the exchanges in flight have been dropped,
+/// either because the exchanges completed or because they were cancelled.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: Mutex<State>,
+    idle: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    shut_down: bool,
+    in_flight: usize,
+    wakers: Vec<Waker>,
+}
+
+impl Shutdown {
+    /// Register an exchange, unless the client was shut down.
+    pub(crate) fn enter(&self) -> Result<InFlight<'_>, io::Error> {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.shut_down {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "The client was shut down",
+            ));
+        }
+        state.in_flight += 1;
+        Ok(InFlight { shutdown: self })
+    }
+
+    /// Reject new exchanges, and block until the exchanges in flight are
+    /// over.
+    pub(crate) fn shutdown_blocking(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.shut_down = true;
+        while state.in_flight > 0 {
+            state = self.inner.idle.wait(state).unwrap();
+        }
+    }
+
+    /// Reject new exchanges, and wait until the exchanges in flight are
+    /// over.
+    pub(crate) async fn shutdown(&self) {
+        self.inner.state.lock().unwrap().shut_down = true;
+        poll_fn(|cx| {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.in_flight == 0 {
+                return Poll::Ready(());
+            }
+            if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                state.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// An exchange in flight, until it is dropped.
+#[derive(Debug)]
+pub(crate) struct InFlight<'t> {
+    shutdown: &'t Shutdown,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut state = self.shutdown.inner.state.lock().unwrap();
+        state.in_flight -= 1;
+        if state.in_flight == 0 {
+            self.shutdown.inner.idle.notify_all();
+            for waker in state.wakers.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_shutdown() {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::Context;
+
+    let shutdown = Shutdown::default();
+    let in_flight = shutdown.enter().unwrap();
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut shutting_down = pin!(shutdown.shutdown());
+    assert!(shutting_down.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(
+        shutdown.enter().unwrap_err().kind(),
+        io::ErrorKind::ConnectionAborted
+    );
+    drop(in_flight);
+    assert!(shutting_down.as_mut().poll(&mut cx).is_ready());
+    shutdown.shutdown_blocking();
+}
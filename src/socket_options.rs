@@ -0,0 +1,123 @@
+use std::io;
+use std::net::SocketAddr;
+
+/// IP options set on the sockets used to send queries, over UDP and TCP.
+///
+/// ```
+/// use dnsclient::SocketOptions;
+///
+/// // Expedited Forwarding
+/// let socket_options = SocketOptions::default().with_dscp(46);
+/// assert_eq!(socket_options.tos, Some(0xb8));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SocketOptions {
+    /// The type of service (IPv4) or traffic class (IPv6) byte: the DSCP
+    /// value in the upper 6 bits, and the ECN bits.
+    pub tos: Option<u8>,
+    /// The time-to-live (IPv4) or hop limit (IPv6) of outgoing packets.
+    pub ttl: Option<u8>,
+}
+
+impl SocketOptions {
+    /// Set the type of service byte to a DSCP value, such as `46` for
+    /// Expedited Forwarding.
+    pub fn with_dscp(mut self, dscp: u8) -> Self {
+        self.tos = Some(dscp << 2);
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: u8) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.tos.is_none() && self.ttl.is_none()
+    }
+
+    /// Set the options on `socket`, whose address family is the one of
+    /// `addr`.
+    #[cfg(unix)]
+    pub(crate) fn apply<S: std::os::unix::io::AsRawFd>(
+        &self,
+        socket: &S,
+        addr: &SocketAddr,
+    ) -> io::Result<()> {
+        let ipv6 = addr.is_ipv6();
+        if let Some(tos) = self.tos {
+            let (level, option) = match ipv6 {
+                false => (libc::IPPROTO_IP, libc::IP_TOS),
+                true => (libc::IPPROTO_IPV6, libc::IPV6_TCLASS),
+            };
+            setsockopt(socket, level, option, tos as libc::c_int)?;
+        }
+        if let Some(ttl) = self.ttl {
+            let (level, option) = match ipv6 {
+                false => (libc::IPPROTO_IP, libc::IP_TTL),
+                true => (libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS),
+            };
+            setsockopt(socket, level, option, ttl as libc::c_int)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn apply<S>(&self, _socket: &S, _addr: &SocketAddr) -> io::Result<()> {
+        match self.is_empty() {
+            true => Ok(()),
+            false => Err(unsupported()),
+        }
+    }
+
+    /// The default implementation of `set_socket_options()`, for backends
+    /// that don't support it.
+    pub(crate) fn unsupported(&self) -> io::Result<()> {
+        match self.is_empty() {
+            true => Ok(()),
+            false => Err(unsupported()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn setsockopt<S: std::os::unix::io::AsRawFd>(
+    socket: &S,
+    level: libc::c_int,
+    option: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Setting socket options is not supported by this backend or on this platform",
+    )
+}
+
+#[cfg(unix)]
+#[test]
+fn test_socket_options() {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let socket = std::net::UdpSocket::bind(addr).unwrap();
+    let socket_options = SocketOptions::default().with_dscp(46).with_ttl(7);
+    socket_options.apply(&socket, &addr).unwrap();
+    assert_eq!(socket.ttl().unwrap(), 7);
+    assert!(socket_options.unsupported().is_err());
+    assert!(SocketOptions::default().unsupported().is_ok());
+}
@@ -0,0 +1,237 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::backend::RuntimeAdapter;
+
+const SOCKS_VERSION: u8 = 5;
+const METHOD_NO_AUTH: u8 = 0;
+const METHOD_USER_PASS: u8 = 2;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const USER_PASS_VERSION: u8 = 1;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// A SOCKS5 proxy (RFC 1928) that TCP connections to upstream servers are
+/// tunneled through, such as Tor or `ssh -D`.
+///
+/// ```
+/// use dnsclient::Socks5Proxy;
+///
+/// let proxy = Socks5Proxy::new(([127, 0, 0, 1], 9050)).with_credentials("user", "password");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Socks5Proxy {
+    pub addr: SocketAddr,
+    /// A user name and a password (RFC 1929), for proxies requiring
+    /// authentication.
+    pub credentials: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    pub fn new<T: Into<SocketAddr>>(addr: T) -> Self {
+        Socks5Proxy {
+            addr: addr.into(),
+            credentials: None,
+        }
+    }
+
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        self.credentials = Some((user.to_string(), password.to_string()));
+        self
+    }
+
+    /// The methods the client supports.
+    fn greeting(&self) -> Vec<u8> {
+        match self.credentials {
+            None => vec![SOCKS_VERSION, 1, METHOD_NO_AUTH],
+            Some(_) => vec![SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS],
+        }
+    }
+
+    /// Check the method selected by the proxy, and return the
+    /// authentication request to send, if any.
+    fn auth_request(&self, reply: [u8; 2]) -> io::Result<Option<Vec<u8>>> {
+        if reply[0] != SOCKS_VERSION {
+            return Err(proxy_error("unexpected version"));
+        }
+        match (reply[1], &self.credentials) {
+            (METHOD_NO_AUTH, _) => Ok(None),
+            (METHOD_USER_PASS, Some((user, password))) => {
+                if user.len() > 255 || password.len() > 255 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "SOCKS5 credentials are too long",
+                    ));
+                }
+                let mut request = vec![USER_PASS_VERSION, user.len() as u8];
+                request.extend_from_slice(user.as_bytes());
+                request.push(password.len() as u8);
+                request.extend_from_slice(password.as_bytes());
+                Ok(Some(request))
+            }
+            (METHOD_NONE_ACCEPTABLE, _) => Err(proxy_error("no acceptable authentication method")),
+            _ => Err(proxy_error("unexpected authentication method")),
+        }
+    }
+}
+
+/// The default implementation of `set_socks5_proxy()`, for backends that
+/// don't support it.
+pub(crate) fn unsupported(proxy: Option<Socks5Proxy>) -> io::Result<()> {
+    match proxy {
+        None => Ok(()),
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SOCKS5 proxies are not supported by this backend",
+        )),
+    }
+}
+
+fn proxy_error(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionRefused,
+        format!("SOCKS5 proxy error: {}", reason),
+    )
+}
+
+fn check_auth_reply(reply: [u8; 2]) -> io::Result<()> {
+    match reply {
+        [USER_PASS_VERSION, 0] => Ok(()),
+        _ => Err(proxy_error("authentication failed")),
+    }
+}
+
+fn connect_request(target: &SocketAddr) -> Vec<u8> {
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    request
+}
+
+/// Check the first 5 bytes of the reply to a `CONNECT` request, and return
+/// the length of the rest of the reply: the bound address and port.
+fn connect_reply_len(reply: [u8; 5]) -> io::Result<usize> {
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error("unexpected version"));
+    }
+    match reply[1] {
+        0 => {}
+        2 => return Err(proxy_error("connection not allowed by ruleset")),
+        3 => return Err(proxy_error("network unreachable")),
+        4 => return Err(proxy_error("host unreachable")),
+        5 => return Err(proxy_error("connection refused")),
+        6 => return Err(proxy_error("TTL expired")),
+        _ => return Err(proxy_error("general failure")),
+    }
+    match reply[3] {
+        ATYP_IPV4 => Ok(4 - 1 + 2),
+        ATYP_IPV6 => Ok(16 - 1 + 2),
+        ATYP_DOMAIN => Ok(reply[4] as usize + 2),
+        _ => Err(proxy_error("unexpected address type")),
+    }
+}
+
+/// Ask `proxy`, over `stream`, a new connection to it, to open a tunnel to
+/// `addr`, using blocking sockets.
+pub(crate) fn handshake_blocking(
+    stream: &mut TcpStream,
+    proxy: &Socks5Proxy,
+    addr: &SocketAddr,
+    timeout: Duration,
+) -> io::Result<()> {
+    let _ = stream.set_read_timeout(Some(timeout));
+    let _ = stream.set_write_timeout(Some(timeout));
+    stream.write_all(&proxy.greeting())?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if let Some(auth_request) = proxy.auth_request(reply)? {
+        stream.write_all(&auth_request)?;
+        stream.read_exact(&mut reply)?;
+        check_auth_reply(reply)?;
+    }
+    stream.write_all(&connect_request(addr))?;
+    let mut reply = [0u8; 5];
+    stream.read_exact(&mut reply)?;
+    let mut bound_addr = vec![0; connect_reply_len(reply)?];
+    stream.read_exact(&mut bound_addr)
+}
+
+/// Open a TCP connection to `addr` with `runtime`, through its proxy if it
+/// has one. The caller is responsible for the timeout.
+pub(crate) async fn connect<R: RuntimeAdapter>(
+    runtime: &R,
+    addr: SocketAddr,
+) -> io::Result<R::TcpStream> {
+    let proxy = match runtime.socks5_proxy() {
+        None => return runtime.tcp_connect(addr).await,
+        Some(proxy) => proxy,
+    };
+    let mut stream = runtime.tcp_connect(proxy.addr).await?;
+    runtime
+        .tcp_write_all(&mut stream, &proxy.greeting())
+        .await?;
+    let mut reply = [0u8; 2];
+    runtime.tcp_read_exact(&mut stream, &mut reply).await?;
+    if let Some(auth_request) = proxy.auth_request(reply)? {
+        runtime.tcp_write_all(&mut stream, &auth_request).await?;
+        runtime.tcp_read_exact(&mut stream, &mut reply).await?;
+        check_auth_reply(reply)?;
+    }
+    runtime
+        .tcp_write_all(&mut stream, &connect_request(&addr))
+        .await?;
+    let mut reply = [0u8; 5];
+    runtime.tcp_read_exact(&mut stream, &mut reply).await?;
+    let mut bound_addr = vec![0; connect_reply_len(reply)?];
+    runtime.tcp_read_exact(&mut stream, &mut bound_addr).await?;
+    Ok(stream)
+}
+
+#[test]
+fn test_socks5() {
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let proxy = Socks5Proxy::new(listener.local_addr().unwrap()).with_credentials("user", "pass");
+    let target = SocketAddr::from(([192, 0, 2, 53], 53));
+    let server = thread::spawn(move || -> io::Result<Vec<u8>> {
+        let (mut stream, _) = listener.accept()?;
+        let mut greeting = [0u8; 4];
+        stream.read_exact(&mut greeting)?;
+        assert_eq!(greeting, [5, 2, 0, 2]);
+        stream.write_all(&[5, 2])?;
+        let mut auth = [0u8; 11];
+        stream.read_exact(&mut auth)?;
+        assert_eq!(&auth, b"\x01\x04user\x04pass");
+        stream.write_all(&[1, 0])?;
+        let mut request = [0u8; 10];
+        stream.read_exact(&mut request)?;
+        stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0, b'!'])?;
+        Ok(request.to_vec())
+    });
+    let mut stream = TcpStream::connect(proxy.addr).unwrap();
+    handshake_blocking(&mut stream, &proxy, &target, Duration::from_secs(5)).unwrap();
+    let mut tunneled = [0u8; 1];
+    stream.read_exact(&mut tunneled).unwrap();
+    assert_eq!(&tunneled, b"!");
+    assert_eq!(
+        server.join().unwrap().unwrap(),
+        vec![5, 1, 0, 1, 192, 0, 2, 53, 0, 53]
+    );
+
+    assert!(connect_reply_len([5, 5, 0, 1, 0]).is_err());
+    assert_eq!(connect_reply_len([5, 0, 0, 3, 7]).unwrap(), 9);
+}
@@ -0,0 +1,333 @@
+//! DNS Stamps (`sdns://...`), as used by public resolver lists.
+//!
+//! A stamp is the URL-safe, unpadded base64 encoding of a protocol
+//! identifier, 64 bits of properties, and protocol-specific fields, most of
+//! them prefixed with their length.
+
+use std::io;
+use std::net::SocketAddr;
+
+use crate::upstream_server::{parse_addr, UpstreamServer, DOH_PORT, DOT_PORT};
+
+const PROTOCOL_PLAIN: u8 = 0x00;
+const PROTOCOL_DNSCRYPT: u8 = 0x01;
+const PROTOCOL_DOH: u8 = 0x02;
+const PROTOCOL_DOT: u8 = 0x03;
+const PROTOCOL_DOQ: u8 = 0x04;
+const PROTOCOL_ODOH_TARGET: u8 = 0x05;
+const PROTOCOL_DNSCRYPT_RELAY: u8 = 0x81;
+const PROTOCOL_ODOH_RELAY: u8 = 0x85;
+
+/// What a stamp describes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum StampKind {
+    /// A server that can be queried directly.
+    Server,
+    /// An Oblivious DNS-over-HTTPS target, only reachable through a relay.
+    ObliviousTarget,
+    /// An Oblivious DNS-over-HTTPS relay, forwarding queries to targets.
+    ObliviousRelay,
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid DNS stamp: {}", reason),
+    )
+}
+
+/// Decode URL-safe base64, with or without padding.
+fn base64_url_decode(s: &str) -> Result<Vec<u8>, io::Error> {
+    let s = s.trim_end_matches('=');
+    let mut decoded = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0u32);
+    for c in s.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return Err(invalid("invalid base64 encoding")),
+        };
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 6 {
+        return Err(invalid("invalid base64 encoding"));
+    }
+    Ok(decoded)
+}
+
+/// The fields of a decoded stamp.
+struct Reader<'t>(&'t [u8]);
+
+impl<'t> Reader<'t> {
+    fn bytes(&mut self, len: usize) -> Result<&'t [u8], io::Error> {
+        if self.0.len() < len {
+            return Err(invalid("truncated"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    /// A value prefixed with its 8-bit length.
+    fn lp(&mut self) -> Result<&'t [u8], io::Error> {
+        let len = self.bytes(1)?[0] as usize;
+        self.bytes(len)
+    }
+
+    fn lp_str(&mut self) -> Result<&'t str, io::Error> {
+        std::str::from_utf8(self.lp()?).map_err(|_| invalid("invalid string"))
+    }
+
+    /// Skip a set of values, whose lengths have their high bit set when
+    /// more values follow.
+    fn skip_vlp(&mut self) -> Result<(), io::Error> {
+        loop {
+            let len = self.bytes(1)?[0];
+            self.bytes((len & 0x7f) as usize)?;
+            if len & 0x80 == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Split a host name, optionally followed by a port. IPv6 addresses are
+/// returned without their brackets.
+fn split_host(host: &str, default_port: u16) -> Result<(&str, u16), io::Error> {
+    let (name, port) = match host.strip_prefix('[') {
+        Some(bracketed) => {
+            let (name, port) = bracketed
+                .split_once(']')
+                .ok_or_else(|| invalid(&format!("unterminated IPv6 address [{}]", host)))?;
+            (name, port.strip_prefix(':'))
+        }
+        None => match host.rsplit_once(':') {
+            Some((name, port)) if !name.contains(':') => (name, Some(port)),
+            _ => (host, None),
+        },
+    };
+    if name.is_empty() {
+        return Err(invalid("missing host name"));
+    }
+    let port = match port {
+        Some(port) => port
+            .parse()
+            .map_err(|_| invalid(&format!("invalid port in [{}]", host)))?,
+        None => default_port,
+    };
+    Ok((name, port))
+}
+
+/// The address of a server. Host names are not resolved: stamps without an
+/// address are only accepted if the host is an IP address.
+fn server_addr(addr: &str, host: &str, default_port: u16) -> Result<SocketAddr, io::Error> {
+    let (name, port) = split_host(host, default_port)?;
+    if !addr.is_empty() {
+        return parse_addr(addr, port);
+    }
+    parse_addr(name, port).map_err(|_| invalid(&format!("missing address for [{}]", name)))
+}
+
+fn doh_server(addr: &str, host: &str, path: &str) -> Result<UpstreamServer, io::Error> {
+    let addr = server_addr(addr, host, DOH_PORT)?;
+    UpstreamServer::new_doh_with_addr(addr, &format!("https://{}{}", host, path))
+}
+
+/// Parse a DNS stamp. Certificate hashes, bootstrap resolvers and
+/// properties are ignored.
+///
+/// Stamps without an address are rejected, unless the host is an IP address.
+pub(crate) fn parse(stamp: &str) -> Result<(StampKind, UpstreamServer), io::Error> {
+    let encoded = stamp
+        .strip_prefix("sdns://")
+        .ok_or_else(|| invalid("the scheme must be sdns"))?;
+    let decoded = base64_url_decode(encoded)?;
+    let mut reader = Reader(&decoded);
+    let protocol = reader.bytes(1)?[0];
+    if protocol == PROTOCOL_DNSCRYPT_RELAY {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Anonymized DNSCrypt relays are not supported",
+        ));
+    }
+    let _properties = reader.bytes(8)?;
+    match protocol {
+        PROTOCOL_PLAIN => {
+            let addr = parse_addr(reader.lp_str()?, 53)?;
+            Ok((StampKind::Server, UpstreamServer::new(addr)))
+        }
+        PROTOCOL_DNSCRYPT => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "DNSCrypt servers are not supported",
+        )),
+        PROTOCOL_DOH | PROTOCOL_ODOH_RELAY => {
+            let addr = reader.lp_str()?;
+            reader.skip_vlp()?;
+            let host = reader.lp_str()?;
+            let path = reader.lp_str()?;
+            let kind = match protocol {
+                PROTOCOL_DOH => StampKind::Server,
+                _ => StampKind::ObliviousRelay,
+            };
+            Ok((kind, doh_server(addr, host, path)?))
+        }
+        PROTOCOL_DOT | PROTOCOL_DOQ => {
+            let addr = reader.lp_str()?;
+            reader.skip_vlp()?;
+            let host = reader.lp_str()?;
+            let (name, _) = split_host(host, DOT_PORT)?;
+            let addr = server_addr(addr, host, DOT_PORT)?;
+            let upstream_server = match protocol {
+                PROTOCOL_DOT => UpstreamServer::new_tls(addr, name),
+                _ => UpstreamServer::new_quic(addr, name),
+            };
+            Ok((StampKind::Server, upstream_server))
+        }
+        PROTOCOL_ODOH_TARGET => {
+            let host = reader.lp_str()?;
+            let path = reader.lp_str()?;
+            Ok((StampKind::ObliviousTarget, doh_server("", host, path)?))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Unsupported DNS stamp protocol: 0x{:02x}", protocol),
+        )),
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn encode(fields: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut encoded = String::from("sdns://");
+    for chunk in fields.chunks(3) {
+        let acc = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[(acc >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+pub(crate) fn stamp(protocol: u8, fields: &[&[u8]]) -> String {
+    let mut decoded = vec![protocol];
+    decoded.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+    for field in fields {
+        decoded.push(field.len() as u8);
+        decoded.extend_from_slice(field);
+    }
+    encode(&decoded)
+}
+
+#[test]
+fn test_base64_url_decode() {
+    assert_eq!(base64_url_decode("").unwrap(), b"");
+    assert_eq!(base64_url_decode("Zg").unwrap(), b"f");
+    assert_eq!(base64_url_decode("Zm8=").unwrap(), b"fo");
+    assert_eq!(base64_url_decode("Zm9v").unwrap(), b"foo");
+    assert_eq!(base64_url_decode("-_8").unwrap(), [0xfb, 0xff]);
+    assert!(base64_url_decode("Z").is_err());
+    assert!(base64_url_decode("Zm9+").is_err());
+    for len in 0..8 {
+        let bytes: Vec<u8> = (0..len).map(|i| i * 37).collect();
+        let encoded = encode(&bytes);
+        assert_eq!(
+            base64_url_decode(encoded.strip_prefix("sdns://").unwrap()).unwrap(),
+            bytes
+        );
+    }
+}
+
+#[test]
+fn test_parse() {
+    let (kind, upstream_server) = parse(&stamp(PROTOCOL_PLAIN, &[b"9.9.9.9"])).unwrap();
+    assert_eq!(kind, StampKind::Server);
+    assert_eq!(upstream_server, UpstreamServer::new(([9, 9, 9, 9], 53)));
+    let (_, upstream_server) = parse(&stamp(PROTOCOL_PLAIN, &[b"[2620:fe::fe]:5353"])).unwrap();
+    assert_eq!(upstream_server.to_string(), "[2620:fe::fe]:5353");
+    let (_, upstream_server) = parse(&stamp(PROTOCOL_PLAIN, &[b"[2620:fe::fe]"])).unwrap();
+    assert_eq!(upstream_server.to_string(), "[2620:fe::fe]:53");
+
+    let (kind, upstream_server) = parse(&stamp(
+        PROTOCOL_DOH,
+        &[b"9.9.9.9", b"", b"dns.quad9.net", b"/dns-query"],
+    ))
+    .unwrap();
+    assert_eq!(kind, StampKind::Server);
+    assert_eq!(
+        upstream_server.to_string(),
+        "https://dns.quad9.net/dns-query#9.9.9.9:443"
+    );
+    let (_, upstream_server) = parse(&stamp(
+        PROTOCOL_DOH,
+        &[b"192.0.2.1", b"", b"doh.test:8443", b"/q"],
+    ))
+    .unwrap();
+    assert_eq!(
+        upstream_server.to_string(),
+        "https://doh.test/q#192.0.2.1:8443"
+    );
+
+    let (_, upstream_server) =
+        parse(&stamp(PROTOCOL_DOT, &[b"1.1.1.1", b"", b"one.one.one.one"])).unwrap();
+    assert_eq!(
+        upstream_server.to_string(),
+        "tls://one.one.one.one@1.1.1.1:853"
+    );
+    let (_, upstream_server) = parse(&stamp(
+        PROTOCOL_DOQ,
+        &[b"94.140.14.14:784", b"", b"dns.adguard-dns.com"],
+    ))
+    .unwrap();
+    assert_eq!(
+        upstream_server.to_string(),
+        "quic://dns.adguard-dns.com@94.140.14.14:784"
+    );
+
+    let (kind, upstream_server) = parse(&stamp(
+        PROTOCOL_ODOH_RELAY,
+        &[b"192.0.2.2", b"", b"relay.test", b"/proxy"],
+    ))
+    .unwrap();
+    assert_eq!(kind, StampKind::ObliviousRelay);
+    assert!(upstream_server.is_doh());
+    let (kind, upstream_server) =
+        parse(&stamp(PROTOCOL_ODOH_TARGET, &[b"192.0.2.1", b"/dns-query"])).unwrap();
+    assert_eq!(kind, StampKind::ObliviousTarget);
+    assert_eq!(
+        upstream_server.to_string(),
+        "https://192.0.2.1:443/dns-query"
+    );
+
+    assert_eq!(
+        parse(&stamp(
+            PROTOCOL_DNSCRYPT,
+            &[b"9.9.9.9", &[0; 32], b"2.dnscrypt-cert"]
+        ))
+        .unwrap_err()
+        .kind(),
+        io::ErrorKind::Unsupported
+    );
+    assert!(parse(&stamp(PROTOCOL_DOH, &[b"9.9.9.9", b""])).is_err());
+    assert!(parse(&stamp(
+        PROTOCOL_DOH,
+        &[b"", b"", b"dns.quad9.net", b"/dns-query"]
+    ))
+    .is_err());
+    let (_, upstream_server) =
+        parse(&stamp(PROTOCOL_DOH, &[b"", b"", b"9.9.9.9", b"/dns-query"])).unwrap();
+    assert_eq!(upstream_server.addr, SocketAddr::from(([9, 9, 9, 9], 443)));
+    assert!(parse("sdns://").is_err());
+    assert!(parse("https://dns.quad9.net/dns-query").is_err());
+}
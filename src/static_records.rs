@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use dnssector::constants::{Class, Type};
+use dnssector::*;
+
+use crate::idn;
+use crate::packet;
+
+/// Static records, returned by a client without contacting any upstream
+/// servers.
+///
+/// An entry only shadows upstream servers for the exact name and type it was
+/// registered for; other types for the same name are still resolved normally.
+#[derive(Clone, Debug)]
+pub struct StaticRecords {
+    ttl: u32,
+    records: HashMap<(Vec<u8>, u16), Vec<Vec<u8>>>,
+}
+
+impl Default for StaticRecords {
+    fn default() -> Self {
+        StaticRecords {
+            ttl: 3600,
+            records: HashMap::new(),
+        }
+    }
+}
+
+impl StaticRecords {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the TTL of the records returned for static entries.
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = ttl
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Add an IPv4 address for `name`.
+    pub fn add_a(&mut self, name: &str, ip: Ipv4Addr) {
+        self.add(name, Type::A.into(), ip.octets().to_vec())
+    }
+
+    /// Add an IPv6 address for `name`.
+    pub fn add_aaaa(&mut self, name: &str, ip: Ipv6Addr) {
+        self.add(name, Type::AAAA.into(), ip.octets().to_vec())
+    }
+
+    /// Add a text record for `name`, split into as many strings as required.
+    pub fn add_txt(&mut self, name: &str, txt: &[u8]) {
+        let mut rdata = Vec::with_capacity(txt.len() + txt.len() / 255 + 1);
+        for chunk in txt.chunks(255) {
+            rdata.push(chunk.len() as u8);
+            rdata.extend_from_slice(chunk);
+        }
+        if txt.is_empty() {
+            rdata.push(0);
+        }
+        self.add(name, Type::TXT.into(), rdata)
+    }
+
+    /// Add a record of any type, given its raw (uncompressed) data.
+    pub fn add_rr_data(
+        &mut self,
+        name: &str,
+        query_type: &str,
+        rdata: Vec<u8>,
+    ) -> Result<(), io::Error> {
+        if rdata.len() > 0xffff {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Record data too large",
+            ));
+        }
+        let rr_type = Type::from_string(query_type)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        self.add(name, rr_type.into(), rdata);
+        Ok(())
+    }
+
+    /// Remove all the static records for `name`.
+    pub fn remove(&mut self, name: &str) {
+        let name = packet::normalize_name(idn::to_ascii_or_raw(name).as_bytes());
+        self.records.retain(|(rr_name, _), _| rr_name != &name);
+    }
+
+    fn add(&mut self, name: &str, rr_type: u16, rdata: Vec<u8>) {
+        let name = idn::to_ascii_or_raw(name);
+        let key = (packet::normalize_name(name.as_bytes()), rr_type);
+        self.records.entry(key).or_default().push(rdata);
+    }
+
+    /// Return a response for `query` if static records exist for its question.
+    pub(crate) fn response_for(
+        &self,
+        query_question: &Option<(Vec<u8>, u16, u16)>,
+        query: &[u8],
+    ) -> Result<Option<ParsedPacket>, io::Error> {
+        let (name, rr_type, rr_class) = match query_question {
+            Some(question) => question,
+            None => return Ok(None),
+        };
+        if self.records.is_empty() || *rr_class != u16::from(Class::IN) {
+            return Ok(None);
+        }
+        let rdatas = match self.records.get(&(packet::normalize_name(name), *rr_type)) {
+            None => return Ok(None),
+            Some(rdatas) => rdatas,
+        };
+        let answers: Vec<_> = rdatas
+            .iter()
+            .map(|rdata| (*rr_type, self.ttl, rdata.as_slice()))
+            .collect();
+        packet::synthesize_response(query, 0, &answers).map(Some)
+    }
+}
@@ -0,0 +1,325 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::events::ClientEvent;
+use crate::histogram::{AtomicHistogram, LatencyHistogram};
+use crate::time::Instant;
+use crate::upstream_server::UpstreamServer;
+
+/// Number of consecutive failures after which a server is `Unhealthy`.
+const UNHEALTHY_AFTER_FAILURES: u64 = 3;
+
+/// The health of an upstream server, according to its recent exchanges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum UpstreamHealth {
+    /// The last exchange succeeded, or the server was never used.
+    Healthy,
+    /// The last exchanges failed.
+    Degraded,
+    /// At least 3 consecutive exchanges failed.
+    Unhealthy,
+}
+
+/// Statistics for an upstream server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[non_exhaustive]
+pub struct UpstreamStats {
+    pub server: UpstreamServer,
+    /// Queries sent to the server.
+    pub sent: u64,
+    /// Responses received from the server.
+    pub responses: u64,
+    /// Exchanges that timed out.
+    pub timeouts: u64,
+    /// Exchanges that failed for other reasons.
+    pub errors: u64,
+    /// Exchanges that were abandoned before they completed.
+    pub cancelled: u64,
+    /// Responses that were rejected.
+    pub invalid_responses: u64,
+    /// Truncated responses, after which the query was sent again over TCP.
+    pub truncated: u64,
+    /// The total size of the responses received from the server, in bytes.
+    pub response_bytes: u64,
+    pub rtt_avg: Option<Duration>,
+    /// The smoothed response time, weighting recent exchanges more
+    /// (RFC 6298).
+    pub rtt_smoothed: Option<Duration>,
+    pub rtt_p50: Option<Duration>,
+    pub rtt_p90: Option<Duration>,
+    pub rtt_p99: Option<Duration>,
+    /// The distribution of the response times.
+    pub rtt_histogram: LatencyHistogram,
+    pub health: UpstreamHealth,
+    /// Exchanges that failed since the last response.
+    pub consecutive_failures: u64,
+}
+
+/// A snapshot of the statistics of a client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[non_exhaustive]
+pub struct ClientStats {
+    /// Statistics for every upstream server, in the client order.
+    pub upstreams: Vec<UpstreamStats>,
+}
+
+#[derive(Debug, Default)]
+struct UpstreamCounters {
+    sent: AtomicU64,
+    responses: AtomicU64,
+    timeouts: AtomicU64,
+    errors: AtomicU64,
+    cancelled: AtomicU64,
+    invalid_responses: AtomicU64,
+    truncated: AtomicU64,
+    response_bytes: AtomicU64,
+    consecutive_failures: AtomicU64,
+    last_failure: Mutex<Option<Instant>>,
+    rtt_sum_us: AtomicU64,
+    /// The smoothed RTT, in microseconds, `0` before the first response.
+    srtt_us: AtomicU64,
+    rtt_histogram: AtomicHistogram,
+}
+
+impl UpstreamCounters {
+    fn record_rtt(&self, rtt: Duration) {
+        let rtt_us = rtt.as_micros().min(u64::MAX as u128) as u64;
+        self.rtt_sum_us.fetch_add(rtt_us, Ordering::Relaxed);
+        let _ = self
+            .srtt_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |srtt_us| {
+                Some(match srtt_us {
+                    0 => rtt_us.max(1),
+                    _ => (srtt_us - srtt_us / 8 + rtt_us / 8).max(1),
+                })
+            });
+        self.rtt_histogram.record(rtt);
+    }
+
+    fn srtt(&self) -> Option<Duration> {
+        match self.srtt_us.load(Ordering::Relaxed) {
+            0 => None,
+            srtt_us => Some(Duration::from_micros(srtt_us)),
+        }
+    }
+
+    fn record_failure(&self, counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_failure.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn snapshot(&self, server: &UpstreamServer) -> UpstreamStats {
+        let rtt_histogram = self.rtt_histogram.snapshot();
+        let rtt_count = rtt_histogram.count();
+        let rtt_avg = match rtt_count {
+            0 => None,
+            _ => Some(Duration::from_micros(
+                self.rtt_sum_us.load(Ordering::Relaxed) / rtt_count,
+            )),
+        };
+        let consecutive_failures = self.consecutive_failures.load(Ordering::Relaxed);
+        let health = match consecutive_failures {
+            0 => UpstreamHealth::Healthy,
+            n if n < UNHEALTHY_AFTER_FAILURES => UpstreamHealth::Degraded,
+            _ => UpstreamHealth::Unhealthy,
+        };
+        UpstreamStats {
+            server: server.clone(),
+            sent: self.sent.load(Ordering::Relaxed),
+            responses: self.responses.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            cancelled: self.cancelled.load(Ordering::Relaxed),
+            invalid_responses: self.invalid_responses.load(Ordering::Relaxed),
+            truncated: self.truncated.load(Ordering::Relaxed),
+            response_bytes: self.response_bytes.load(Ordering::Relaxed),
+            rtt_avg,
+            rtt_smoothed: self.srtt(),
+            rtt_p50: rtt_histogram.quantile(0.5),
+            rtt_p90: rtt_histogram.quantile(0.9),
+            rtt_p99: rtt_histogram.quantile(0.99),
+            rtt_histogram,
+            health,
+            consecutive_failures,
+        }
+    }
+}
+
+/// Counters for every upstream server a client exchanged queries with.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    upstreams: RwLock<Vec<(UpstreamServer, Arc<UpstreamCounters>)>>,
+}
+
+impl Stats {
+    fn counters(&self, server: &UpstreamServer) -> Arc<UpstreamCounters> {
+        if let Some((_, counters)) = self
+            .upstreams
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(s, _)| s == server)
+        {
+            return counters.clone();
+        }
+        let mut upstreams = self.upstreams.write().unwrap();
+        if let Some((_, counters)) = upstreams.iter().find(|(s, _)| s == server) {
+            return counters.clone();
+        }
+        let counters = Arc::new(UpstreamCounters::default());
+        upstreams.push((server.clone(), counters.clone()));
+        counters
+    }
+
+    pub(crate) fn record_event(&self, event: &ClientEvent) {
+        match event {
+            ClientEvent::QuerySent { server, .. } => {
+                self.counters(server).sent.fetch_add(1, Ordering::Relaxed);
+            }
+            ClientEvent::ResponseReceived { server, rtt, .. } => {
+                let counters = self.counters(server);
+                counters.responses.fetch_add(1, Ordering::Relaxed);
+                counters.consecutive_failures.store(0, Ordering::Relaxed);
+                counters.record_rtt(*rtt);
+            }
+            ClientEvent::Timeout { server, .. } => {
+                let counters = self.counters(server);
+                counters.record_failure(&counters.timeouts);
+            }
+            ClientEvent::QueryCancelled { server, .. } => {
+                self.counters(server)
+                    .cancelled
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            ClientEvent::QueryFailed { server, .. } => {
+                let counters = self.counters(server);
+                counters.record_failure(&counters.errors);
+            }
+            ClientEvent::InvalidResponse { server, .. } => {
+                let counters = self.counters(server);
+                counters.record_failure(&counters.invalid_responses);
+            }
+            ClientEvent::TruncatedRetry { server } => {
+                self.counters(server)
+                    .truncated
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            ClientEvent::Failover { .. } | ClientEvent::Retry { .. } => {}
+        }
+    }
+
+    pub(crate) fn record_response_size(&self, server: &UpstreamServer, size: usize) {
+        self.counters(server)
+            .response_bytes
+            .fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    /// The smoothed RTT of a server, `None` if it never responded.
+    pub(crate) fn srtt(&self, server: &UpstreamServer) -> Option<Duration> {
+        self.counters(server).srtt()
+    }
+
+    /// The number of exchanges with a server that failed since its last
+    /// response.
+    pub(crate) fn consecutive_failures(&self, server: &UpstreamServer) -> u64 {
+        self.counters(server)
+            .consecutive_failures
+            .load(Ordering::Relaxed)
+    }
+
+    /// When the last exchange with a server failed.
+    pub(crate) fn last_failure(&self, server: &UpstreamServer) -> Option<Instant> {
+        *self.counters(server).last_failure.lock().unwrap()
+    }
+
+    pub(crate) fn snapshot(&self, upstream_servers: &[UpstreamServer]) -> ClientStats {
+        let upstreams = upstream_servers
+            .iter()
+            .map(|server| self.counters(server).snapshot(server))
+            .collect();
+        ClientStats { upstreams }
+    }
+}
+
+#[test]
+fn test_srtt() {
+    let server = UpstreamServer::new(([192, 0, 2, 53], 53));
+    let stats = Stats::default();
+    assert_eq!(stats.srtt(&server), None);
+    let response = |rtt| ClientEvent::ResponseReceived {
+        server: server.clone(),
+        protocol: crate::events::Protocol::Udp,
+        rtt,
+    };
+    stats.record_event(&response(Duration::from_millis(80)));
+    assert_eq!(stats.srtt(&server), Some(Duration::from_millis(80)));
+    stats.record_event(&response(Duration::from_millis(160)));
+    assert_eq!(stats.srtt(&server), Some(Duration::from_millis(90)));
+    stats.record_event(&ClientEvent::Timeout {
+        server: server.clone(),
+        protocol: crate::events::Protocol::Udp,
+    });
+    assert_eq!(stats.consecutive_failures(&server), 1);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_client_stats() {
+    use std::io;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::testing::{MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    backend
+        .set_action(
+            "example.com",
+            "AAAA",
+            MockAction::Fail(io::ErrorKind::ConnectionRefused),
+        )
+        .unwrap();
+    let upstream_servers = vec![
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53))),
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 54], 53))),
+    ];
+    let dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    let stats = dns_client.stats();
+    assert_eq!(stats.upstreams.len(), 2);
+    assert_eq!(stats.upstreams[0].sent, 0);
+    assert_eq!(stats.upstreams[0].rtt_avg, None);
+    assert_eq!(stats.upstreams[0].health, UpstreamHealth::Healthy);
+
+    dns_client.query_a("example.com").unwrap();
+    let stats = dns_client.stats();
+    assert_eq!(stats.upstreams[0].sent, 1);
+    assert_eq!(stats.upstreams[0].responses, 1);
+    assert!(stats.upstreams[0].response_bytes > 0);
+    assert!(stats.upstreams[0].rtt_p50.is_some());
+    assert!(stats.upstreams[0].rtt_p50 <= stats.upstreams[0].rtt_p99);
+    assert_eq!(stats.upstreams[0].rtt_histogram.count(), 1);
+
+    assert!(dns_client.query_aaaa("example.com").is_err());
+    assert!(dns_client.query_aaaa("example.com").is_err());
+    let stats = dns_client.clone().stats();
+    assert_eq!(stats.upstreams[0].sent, 3);
+    assert_eq!(stats.upstreams[0].errors, 2);
+    assert_eq!(stats.upstreams[0].health, UpstreamHealth::Degraded);
+    assert_eq!(stats.upstreams[1].errors, 2);
+    assert_eq!(stats.upstreams[1].health, UpstreamHealth::Degraded);
+
+    assert!(dns_client.query_aaaa("example.com").is_err());
+    assert_eq!(
+        dns_client.stats().upstreams[1].health,
+        UpstreamHealth::Unhealthy
+    );
+}
@@ -0,0 +1,171 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+/// A stream running up to `limit` futures from an iterator concurrently, and
+/// yielding their outputs in completion order.
+pub(crate) struct Unordered<I: Iterator> {
+    pending: I,
+    running: Vec<Pin<Box<I::Item>>>,
+    limit: usize,
+}
+
+impl<I> Unordered<I>
+where
+    I: Iterator,
+    I::Item: Future,
+{
+    pub(crate) fn new(pending: I, limit: usize) -> Self {
+        Unordered {
+            pending,
+            running: vec![],
+            limit: limit.max(1),
+        }
+    }
+}
+
+impl<I> Stream for Unordered<I>
+where
+    I: Iterator + Unpin,
+    I::Item: Future,
+{
+    type Item = <I::Item as Future>::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while this.running.len() < this.limit {
+            match this.pending.next() {
+                Some(future) => this.running.push(Box::pin(future)),
+                None => break,
+            }
+        }
+        if this.running.is_empty() {
+            return Poll::Ready(None);
+        }
+        for i in 0..this.running.len() {
+            if let Poll::Ready(output) = this.running[i].as_mut().poll(cx) {
+                drop(this.running.swap_remove(i));
+                return Poll::Ready(Some(output));
+            }
+        }
+        Poll::Pending
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.pending.size_hint();
+        (
+            lower + self.running.len(),
+            upper.map(|upper| upper + self.running.len()),
+        )
+    }
+}
+
+/// A stream yielding the outputs of futures created from a state, like
+/// `futures::stream::unfold()`.
+///
+/// `f` returns a future completing with the next item and state, or `None`
+/// at the end of the stream.
+pub(crate) struct Unfold<S, F, Fut> {
+    state: Option<S>,
+    f: F,
+    pending: Option<Pin<Box<Fut>>>,
+}
+
+impl<S, F, Fut> Unfold<S, F, Fut>
+where
+    F: FnMut(S) -> Fut,
+    Fut: Future,
+{
+    pub(crate) fn new(state: S, f: F) -> Self {
+        Unfold {
+            state: Some(state),
+            f,
+            pending: None,
+        }
+    }
+}
+
+// The state and the function are never pinned
+impl<S, F, Fut> Unpin for Unfold<S, F, Fut> {}
+
+impl<S, F, Fut, T> Stream for Unfold<S, F, Fut>
+where
+    F: FnMut(S) -> Fut,
+    Fut: Future<Output = Option<(T, S)>>,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        if this.pending.is_none() {
+            match this.state.take() {
+                Some(state) => this.pending = Some(Box::pin((this.f)(state))),
+                None => return Poll::Ready(None),
+            }
+        }
+        let pending = this.pending.as_mut().expect("No pending future");
+        match pending.as_mut().poll(cx) {
+            Poll::Ready(Some((item, state))) => {
+                this.pending = None;
+                this.state = Some(state);
+                Poll::Ready(Some(item))
+            }
+            Poll::Ready(None) => {
+                this.pending = None;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[test]
+fn test_unordered() {
+    use std::future::poll_fn;
+
+    // Futures completing after being polled `n` times
+    let countdown = |n: usize| {
+        let mut remaining = n;
+        poll_fn(move |cx| {
+            if remaining == 0 {
+                return Poll::Ready(n);
+            }
+            remaining -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+    };
+    let waker = std::task::Waker::noop();
+    let mut cx = Context::from_waker(waker);
+    let mut stream = Unordered::new([3, 0, 1, 5].iter().map(|&n| countdown(n)), 2);
+    let mut outputs = vec![];
+    loop {
+        match Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(Some(n)) => outputs.push(n),
+            Poll::Ready(None) => break,
+            Poll::Pending => {}
+        }
+    }
+    assert_eq!(outputs, vec![0, 1, 3, 5]);
+}
+
+#[test]
+fn test_unfold() {
+    let waker = std::task::Waker::noop();
+    let mut cx = Context::from_waker(waker);
+    let mut stream = Unfold::new(0, |n| async move {
+        if n < 3 {
+            Some((n * 10, n + 1))
+        } else {
+            None
+        }
+    });
+    let mut outputs = vec![];
+    while let Poll::Ready(Some(n)) = Pin::new(&mut stream).poll_next(&mut cx) {
+        outputs.push(n);
+    }
+    assert_eq!(outputs, vec![0, 10, 20]);
+    assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Ready(None));
+}
@@ -0,0 +1,174 @@
+use std::convert::TryFrom;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::record_data::{parse_name, RecordData};
+
+const KEY_MANDATORY: u16 = 0;
+const KEY_ALPN: u16 = 1;
+const KEY_NO_DEFAULT_ALPN: u16 = 2;
+const KEY_PORT: u16 = 3;
+const KEY_IPV4HINT: u16 = 4;
+const KEY_ECH: u16 = 5;
+const KEY_IPV6HINT: u16 = 6;
+
+/// The parameters of a service binding (RFC 9460).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SvcParams {
+    /// The keys of the parameters clients must support.
+    pub mandatory: Vec<u16>,
+    /// The supported protocols, such as `h2` or `h3`.
+    pub alpn: Vec<String>,
+    /// The default protocol (`http/1.1` for `HTTPS` records) is not
+    /// supported.
+    pub no_default_alpn: bool,
+    /// The port to connect to, if not the default one.
+    pub port: Option<u16>,
+    /// IPv4 addresses of the target.
+    pub ipv4hint: Vec<Ipv4Addr>,
+    /// An Encrypted Client Hello configuration list.
+    pub ech: Option<Vec<u8>>,
+    /// IPv6 addresses of the target.
+    pub ipv6hint: Vec<Ipv6Addr>,
+    /// Other parameters, as `(key, value)` pairs.
+    pub other: Vec<(u16, Vec<u8>)>,
+}
+
+/// A service binding (`SVCB` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Svcb {
+    /// The priority, `0` for alias records.
+    pub priority: u16,
+    /// The target name, `.` for the owner name of the record.
+    pub target: String,
+    pub params: SvcParams,
+}
+
+impl Svcb {
+    /// Whether this record is an alias to `target`, rather than a service
+    /// endpoint.
+    pub fn is_alias(&self) -> bool {
+        self.priority == 0
+    }
+}
+
+impl RecordData for Svcb {
+    const TYPE: u16 = 64;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        let priority = rdata
+            .get(..2)
+            .map(|x| u16::from_be_bytes([x[0], x[1]]))
+            .ok_or_else(|| invalid("Short service binding"))?;
+        let (target, target_len) = parse_name(&rdata[2..])?;
+        let params = parse_params(&rdata[2 + target_len..])?;
+        Ok(Svcb {
+            priority,
+            target,
+            params,
+        })
+    }
+}
+
+/// A service binding for an HTTPS origin (`HTTPS` record).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Https(pub Svcb);
+
+impl RecordData for Https {
+    const TYPE: u16 = 65;
+
+    fn parse(rdata: &[u8]) -> Result<Self, io::Error> {
+        Svcb::parse(rdata).map(Https)
+    }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+fn parse_params(mut data: &[u8]) -> Result<SvcParams, io::Error> {
+    let mut params = SvcParams::default();
+    let mut last_key = None;
+    while !data.is_empty() {
+        let header = data.get(..4).ok_or_else(|| invalid("Short parameter"))?;
+        let key = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let value = data
+            .get(4..4 + len)
+            .ok_or_else(|| invalid("Short parameter"))?;
+        data = &data[4 + len..];
+        if last_key.is_some_and(|last_key| key <= last_key) {
+            return Err(invalid("Parameters out of order"));
+        }
+        last_key = Some(key);
+        match key {
+            KEY_MANDATORY => {
+                params.mandatory = parse_list(value, 2, |x| u16::from_be_bytes([x[0], x[1]]))?
+            }
+            KEY_ALPN => {
+                let mut it = value;
+                while let Some((&len, rest)) = it.split_first() {
+                    let alpn = rest
+                        .get(..len as usize)
+                        .ok_or_else(|| invalid("Invalid ALPN parameter"))?;
+                    params.alpn.push(String::from_utf8_lossy(alpn).into_owned());
+                    it = &rest[len as usize..];
+                }
+            }
+            KEY_NO_DEFAULT_ALPN if value.is_empty() => params.no_default_alpn = true,
+            KEY_PORT => {
+                let port = <[u8; 2]>::try_from(value).map_err(|_| invalid("Invalid port"))?;
+                params.port = Some(u16::from_be_bytes(port));
+            }
+            KEY_IPV4HINT => {
+                params.ipv4hint = parse_list(value, 4, |x| Ipv4Addr::new(x[0], x[1], x[2], x[3]))?
+            }
+            KEY_ECH => params.ech = Some(value.to_vec()),
+            KEY_IPV6HINT => {
+                params.ipv6hint = parse_list(value, 16, |x| {
+                    Ipv6Addr::from(<[u8; 16]>::try_from(x).unwrap())
+                })?
+            }
+            KEY_NO_DEFAULT_ALPN => return Err(invalid("Invalid no-default-alpn parameter")),
+            _ => params.other.push((key, value.to_vec())),
+        }
+    }
+    Ok(params)
+}
+
+/// Decode a parameter made of fixed-size items.
+fn parse_list<T>(value: &[u8], size: usize, f: impl Fn(&[u8]) -> T) -> Result<Vec<T>, io::Error> {
+    let items = value.chunks_exact(size);
+    if value.is_empty() || !items.remainder().is_empty() {
+        return Err(invalid("Invalid parameter length"));
+    }
+    Ok(items.map(f).collect())
+}
+
+#[test]
+fn test_svcb() {
+    let rdata = b"\x00\x01\x00\
+        \x00\x01\x00\x06\x02h2\x02h3\
+        \x00\x03\x00\x02\x01\xbb\
+        \x00\x04\x00\x04\xc0\x00\x02\x01\
+        \x00\x05\x00\x02\xfe\x0d";
+    let https = Https::parse(rdata).unwrap().0;
+    assert!(!https.is_alias());
+    assert_eq!(https.target, ".");
+    assert_eq!(https.params.alpn, vec!["h2", "h3"]);
+    assert_eq!(https.params.port, Some(443));
+    assert_eq!(https.params.ipv4hint, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    assert_eq!(https.params.ech, Some(vec![0xfe, 0x0d]));
+
+    let alias = Svcb::parse(b"\x00\x00\x03svc\x07example\x00").unwrap();
+    assert!(alias.is_alias());
+    assert_eq!(alias.target, "svc.example");
+    assert_eq!(alias.params, SvcParams::default());
+
+    // Keys must be in increasing order
+    assert!(Svcb::parse(b"\x00\x01\x00\x00\x03\x00\x02\x01\xbb\x00\x01\x00\x03\x02h2").is_err());
+    assert!(Svcb::parse(b"\x00\x01\x00\x00\x03\x00\x01\x01").is_err());
+}
@@ -1,41 +1,213 @@
+use std::borrow::Cow;
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
 use dnssector::constants::{Class, Type};
 use dnssector::*;
-use rand::{seq::SliceRandom, Rng};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
 
-use crate::backend::sync::SyncBackend;
-use crate::upstream_server::UpstreamServer;
+use crate::address_family::{merge_families, AddressFamilyPreference};
+use crate::answer_order::AnswerOrder;
+use crate::audit::AuditLog;
+use crate::blocklist::Blocklist;
+use crate::cache::{self, Cache, CacheKey, StaleState};
+use crate::captive::{CaptivePortalProbe, CaptivePortalVerdict};
+use crate::capture::PacketCapture;
+use crate::compare::{self, AnswerRecord, UpstreamComparison};
+use crate::deadline::Deadline;
+use crate::diagnostics::{
+    DiagnosticOutcome, DiagnosticProbe, DiagnosticsConfig, DiagnosticsReport, UpstreamDiagnostics,
+};
+use crate::dns64::Dns64;
+use crate::dns_sd::{self, ServiceInstance};
+#[cfg(feature = "dnssec")]
+use crate::dnssec::{DnssecStatus, TrustAnchor, Validation, Validator};
+use crate::domain_routes::DomainRoutes;
+use crate::edns_probe::{EdnsComplianceReport, EdnsProbe, EdnsProbeOutcome};
+use crate::error::{no_records, DnsError};
+use crate::events::{ClientEvent, EventSink, Events, Protocol};
+use crate::exchange::{
+    self, Exchange, Failover, FailoverStep, PipelinedQueries, QueryContext, Retries, Retry,
+    RetryStep, Step,
+};
+use crate::failover_policy::FailoverPolicy;
+use crate::health::HealthPolicy;
+use crate::host_port::HostPort;
+use crate::interceptor::{Interceptor, Interceptors};
+use crate::llmnr;
+use crate::mdns;
+use crate::name_validation::NameValidation;
+use crate::opportunistic_tls::OpportunisticTls;
+use crate::packet;
+use crate::padding::PaddingPolicy;
+use crate::presentation::Message;
+use crate::propagation::{self, PropagationReport};
+use crate::query_info::QueryInfo;
+use crate::query_options::QueryOptions;
+use crate::reachability::ReachabilityProbe;
+use crate::record_data::{
+    self, Answer, Cname, Mx, Naptr, Ns, Ptr, Record, RecordData, Soa, Srv, Tlsa, Txt,
+};
+use crate::resolver::Resolver;
+use crate::response::DnsResponse;
+use crate::retry_policy::RetryPolicy;
+use crate::reverse::{self, PtrName};
+use crate::rng::ClientRng;
+use crate::search_list::SearchList;
+use crate::shutdown::Shutdown;
+use crate::socket_options::SocketOptions;
+use crate::socks5::Socks5Proxy;
+use crate::static_records::StaticRecords;
+use crate::stats::{ClientStats, UpstreamStats};
+use crate::svcb::{Https, Svcb};
+use crate::system::ResolvConfWatcher;
+use crate::time::Instant;
+use crate::truncation_policy::TruncationPolicy;
+use crate::upstream_server::{no_response_error, Transport, UpstreamServer};
+use crate::upstream_strategy::UpstreamStrategy;
 
+pub use crate::backend::sync::StdBackend;
+pub use crate::backend::transport::{DnsTransport, TransportBackend};
+pub use crate::backend::SyncBackend;
+pub use crate::host_port::ToSocketAddrs;
+
+/// A client using the blocking sockets from the standard library.
+pub type DNSClient = GenericDNSClient<StdBackend>;
+
+/// A synchronous client, generic over the backend used to exchange queries.
+///
+/// Cloning a client is cheap: clones share their configuration until it is
+/// changed, as well as their statistics and shutdown state, so a clone can be
+/// handed to every thread or task.
 #[derive(Clone, Debug)]
-pub struct DNSClient {
-    backend: SyncBackend,
-    upstream_servers: Vec<UpstreamServer>,
+pub struct GenericDNSClient<B> {
+    backend: B,
+    timeout: Duration,
+    total_timeout: Option<Duration>,
+    upstream_servers: Arc<Vec<UpstreamServer>>,
     local_v4_addr: SocketAddr,
     local_v6_addr: SocketAddr,
     force_tcp: bool,
+    edns_bufsize: Option<u16>,
+    static_records: Arc<StaticRecords>,
+    search_list: Arc<SearchList>,
+    domain_routes: Arc<DomainRoutes>,
+    resolv_conf_watcher: Option<Arc<ResolvConfWatcher>>,
+    blocklist: Arc<Blocklist>,
+    cache: Option<Arc<Cache>>,
+    serve_stale: Option<Duration>,
+    address_family_preference: AddressFamilyPreference,
+    answer_order: AnswerOrder,
+    padding_policy: PaddingPolicy,
+    reachability_probe: Option<ReachabilityProbe>,
+    failover_policy: FailoverPolicy,
+    upstream_strategy: UpstreamStrategy,
+    health_policy: Option<HealthPolicy>,
+    rotation: Arc<AtomicUsize>,
+    retry_policy: RetryPolicy,
+    cname_limit: Option<usize>,
+    dns64: Option<Dns64>,
+    mdns: bool,
+    llmnr: bool,
+    error_reporting: bool,
+    ptr_verification: bool,
+    truncation_policy: TruncationPolicy,
+    opportunistic_tls: Option<Arc<OpportunisticTls>>,
+    name_validation: NameValidation,
+    #[cfg(feature = "idna")]
+    unicode_names: bool,
+    #[cfg(feature = "dnssec")]
+    trust_anchors: Option<Arc<Vec<TrustAnchor>>>,
+    rng: ClientRng,
+    interceptors: Arc<Interceptors>,
+    events: Events,
+    shutdown: Shutdown,
+    audit_log: Option<AuditLog>,
+    packet_capture: Option<PacketCapture>,
+}
+
+impl<T: DnsTransport> GenericDNSClient<TransportBackend<T>> {
+    /// Create a client sending every query through `transport`.
+    pub fn with_transport(upstream_servers: Vec<UpstreamServer>, transport: T) -> Self {
+        Self::with_backend(upstream_servers, TransportBackend::new(transport))
+    }
 }
 
-impl DNSClient {
+impl<B: SyncBackend> GenericDNSClient<B> {
     pub fn new(upstream_servers: Vec<UpstreamServer>) -> Self {
-        DNSClient {
-            backend: SyncBackend::new(Duration::new(6, 0)),
-            upstream_servers,
+        Self::with_backend(upstream_servers, B::default())
+    }
+
+    /// Create a client using a specific backend instance.
+    pub fn with_backend(upstream_servers: Vec<UpstreamServer>, backend: B) -> Self {
+        GenericDNSClient {
+            backend,
+            timeout: Duration::new(6, 0),
+            total_timeout: None,
+            upstream_servers: Arc::new(upstream_servers),
             local_v4_addr: ([0; 4], 0).into(),
             local_v6_addr: ([0; 16], 0).into(),
             force_tcp: false,
+            edns_bufsize: None,
+            static_records: Arc::new(StaticRecords::new()),
+            search_list: Arc::default(),
+            domain_routes: Arc::default(),
+            resolv_conf_watcher: None,
+            blocklist: Arc::new(Blocklist::new()),
+            cache: None,
+            serve_stale: None,
+            address_family_preference: AddressFamilyPreference::default(),
+            answer_order: AnswerOrder::default(),
+            padding_policy: PaddingPolicy::default(),
+            reachability_probe: None,
+            failover_policy: FailoverPolicy::default(),
+            upstream_strategy: UpstreamStrategy::default(),
+            health_policy: None,
+            rotation: Arc::new(AtomicUsize::new(0)),
+            retry_policy: RetryPolicy::default(),
+            cname_limit: None,
+            dns64: None,
+            mdns: false,
+            llmnr: false,
+            error_reporting: false,
+            ptr_verification: true,
+            truncation_policy: TruncationPolicy::default(),
+            opportunistic_tls: None,
+            name_validation: NameValidation::default(),
+            #[cfg(feature = "idna")]
+            unicode_names: false,
+            #[cfg(feature = "dnssec")]
+            trust_anchors: None,
+            rng: ClientRng::default(),
+            interceptors: Arc::new(Interceptors::default()),
+            events: Events::default(),
+            shutdown: Shutdown::default(),
+            audit_log: None,
+            packet_capture: None,
         }
     }
 
     #[cfg(unix)]
+    /// Create a client using the system resolvers, honoring the options of
+    /// /etc/resolv.conf.
     pub fn new_with_system_resolvers() -> Result<Self, io::Error> {
-        Ok(DNSClient::new(crate::system::default_resolvers()?))
+        Ok(crate::system::resolv_conf()?.to_builder().build())
     }
 
     pub fn set_timeout(&mut self, timeout: Duration) {
-        self.backend.upstream_server_timeout = timeout
+        self.timeout = timeout
+    }
+
+    /// Bound the time a query can take as a whole, including every upstream
+    /// server, retries and TCP fallbacks. `set_timeout()` only bounds a
+    /// single exchange. There is no total timeout by default.
+    pub fn set_total_timeout(&mut self, total_timeout: Option<Duration>) {
+        self.total_timeout = total_timeout;
     }
 
     pub fn set_local_v4_addr<T: Into<SocketAddr>>(&mut self, addr: T) {
@@ -46,56 +218,642 @@ impl DNSClient {
         self.local_v6_addr = addr.into()
     }
 
+    /// Send queries through the network interface named `device`, such as
+    /// `eth0`, regardless of the routing table, or through any interface if
+    /// `device` is `None`.
+    ///
+    /// This binds UDP sockets and TCP connections, including DNS-over-TLS
+    /// and DNS-over-HTTPS sessions, with `SO_BINDTODEVICE` on Linux, which
+    /// usually requires the `CAP_NET_RAW` capability, and `IP_BOUND_IF` on
+    /// macOS. TCP connections to the interface are not shared with other
+    /// clients. An `io::ErrorKind::Unsupported` error is returned on other
+    /// platforms, and by backends that don't support it.
+    pub fn set_bound_device(&mut self, device: Option<&str>) -> Result<(), io::Error> {
+        self.backend.set_bound_device(device)
+    }
+
+    /// Set IP options, such as the DSCP value or the TTL, on the sockets
+    /// used to send queries, over UDP and TCP.
+    ///
+    /// An `io::ErrorKind::Unsupported` error is returned by backends that
+    /// don't support it.
+    pub fn set_socket_options(&mut self, socket_options: SocketOptions) -> Result<(), io::Error> {
+        self.backend.set_socket_options(socket_options)
+    }
+
+    /// Tunnel TCP connections to upstream servers through a SOCKS5 proxy,
+    /// such as Tor, or stop using a proxy if `proxy` is `None`.
+    ///
+    /// This covers queries over TCP, including the retries of truncated
+    /// responses, as well as DNS-over-TLS and DNS-over-HTTPS. Queries over
+    /// UDP, DNS-over-QUIC and multicast queries are still sent directly:
+    /// use `force_tcp(true)` or encrypted upstream servers to prevent this.
+    ///
+    /// An `io::ErrorKind::Unsupported` error is returned by backends that
+    /// don't support it.
+    pub fn set_socks5_proxy(&mut self, proxy: Option<Socks5Proxy>) -> Result<(), io::Error> {
+        self.backend.set_socks5_proxy(proxy)
+    }
+
     pub fn force_tcp(&mut self, force_tcp: bool) {
         self.force_tcp = force_tcp;
     }
 
+    /// Add an EDNS section advertising `edns_bufsize` to queries that don't
+    /// have additional records, unless `QueryOptions::edns_bufsize` is set.
+    pub fn set_edns_bufsize(&mut self, edns_bufsize: Option<u16>) {
+        self.edns_bufsize = edns_bufsize;
+    }
+
+    /// Set the domains `resolve()` appends to relative names. The search
+    /// list is empty by default, and read from /etc/resolv.conf by
+    /// `new_with_system_resolvers()`.
+    pub fn set_search_list(&mut self, search_list: SearchList) {
+        self.search_list = Arc::new(search_list);
+    }
+
+    /// Send queries for specific domains to dedicated upstream servers, such
+    /// as the servers of a VPN.
+    pub fn set_domain_routes(&mut self, domain_routes: DomainRoutes) {
+        self.domain_routes = Arc::new(domain_routes);
+    }
+
+    /// Reload the upstream servers from /etc/resolv.conf when it changes,
+    /// checking it at most every `interval`, or stop reloading them.
+    ///
+    /// This lets long-running clients created with
+    /// `new_with_system_resolvers()` follow network changes, such as a VPN
+    /// connection. The other options of the file are not reloaded.
+    pub fn set_resolv_conf_reload(&mut self, interval: Option<Duration>) {
+        self.resolv_conf_watcher =
+            interval.map(|interval| Arc::new(ResolvConfWatcher::new(interval)));
+    }
+
+    /// Set static records, that take precedence over upstream servers.
+    pub fn set_static_records(&mut self, static_records: StaticRecords) {
+        self.static_records = Arc::new(static_records);
+    }
+
+    /// Set a list of names that will be answered locally with `NXDOMAIN`.
+    pub fn set_blocklist(&mut self, blocklist: Blocklist) {
+        self.blocklist = Arc::new(blocklist);
+    }
+
+    /// Cache up to `capacity` responses from upstream servers, for the
+    /// duration of their TTL. The least recently used responses are evicted
+    /// first, and the TTLs of cached responses are reduced by the time they
+    /// spent in the cache.
+    ///
+    /// Clones of a client created after this share the same cache.
+    pub fn enable_cache(&mut self, capacity: usize) {
+        self.cache = Some(Arc::new(Cache::new(capacity)));
+    }
+
+    /// Serve expired responses from the cache, up to `max_stale` after they
+    /// expired, when the upstream servers can't be reached or return
+    /// `SERVFAIL` (RFC 8767). Stale responses have a TTL of 30 seconds.
+    ///
+    /// After a failed refresh, a stale response is served right away for 30
+    /// seconds, then refreshed in the background while it keeps being
+    /// served, until a refresh succeeds or it is too old.
+    ///
+    /// This requires the cache to be enabled with `enable_cache()`.
+    pub fn set_serve_stale(&mut self, max_stale: Option<Duration>) {
+        self.serve_stale = max_stale;
+    }
+
+    /// Set the address families queried by `query_addrs()`, and how the
+    /// addresses are ordered.
+    pub fn set_address_family_preference(&mut self, preference: AddressFamilyPreference) {
+        self.address_family_preference = preference;
+    }
+
+    /// Set how query names are validated before being sent. Names with
+    /// underscores are accepted by default.
+    pub fn set_name_validation(&mut self, name_validation: NameValidation) {
+        self.name_validation = name_validation;
+    }
+
+    /// Set how the addresses returned by `query_a()`, `query_aaaa()` and
+    /// `query_addrs()` are ordered. They are shuffled by default.
+    pub fn set_answer_order(&mut self, answer_order: AnswerOrder) {
+        self.answer_order = answer_order;
+    }
+
+    /// Set how queries sent to encrypted upstream servers are padded. They
+    /// are sent as is by default; `PaddingPolicy::RECOMMENDED` pads them as
+    /// recommended by RFC 8467.
+    pub fn set_padding_policy(&mut self, padding_policy: PaddingPolicy) {
+        self.padding_policy = padding_policy;
+    }
+
+    /// Order the addresses returned by `query_addrs()` by reachability, by
+    /// connecting to each of them with `reachability_probe` after they have
+    /// been resolved. This is disabled by default.
+    pub fn set_reachability_probe(&mut self, reachability_probe: Option<ReachabilityProbe>) {
+        self.reachability_probe = reachability_probe;
+    }
+
+    /// Set which response codes make the client try the next upstream
+    /// server. By default, it is tried after `SERVFAIL` and `REFUSED`
+    /// responses.
+    pub fn set_failover_policy(&mut self, failover_policy: FailoverPolicy) {
+        self.failover_policy = failover_policy;
+    }
+
+    /// Follow `CNAME` chains that upstream servers don't resolve, such as
+    /// authoritative servers only returning a `CNAME` record, in
+    /// `query_a()` and `query_aaaa()`.
+    ///
+    /// Up to `cname_limit` additional queries are sent per lookup. This is
+    /// disabled by default.
+    pub fn set_cname_limit(&mut self, cname_limit: Option<usize>) {
+        self.cname_limit = cname_limit;
+    }
+
+    /// Synthesize IPv6 addresses from the IPv4 addresses of names that have
+    /// no `AAAA` records, using a NAT64 prefix (DNS64, RFC 6147), in
+    /// `query_aaaa()` and `query_addrs()`. This is disabled by default.
+    ///
+    /// Names that don't exist are not synthesized.
+    pub fn set_dns64(&mut self, dns64: Option<Dns64>) {
+        self.dns64 = dns64;
+    }
+
+    /// Set how upstream servers are ordered before a query is sent to them:
+    /// in the configured order (the default), round-robin, randomly, or by
+    /// response time.
+    pub fn set_strategy(&mut self, upstream_strategy: UpstreamStrategy) {
+        self.upstream_strategy = upstream_strategy;
+    }
+
+    /// Mark upstream servers down after consecutive failures, and skip them
+    /// for a cooldown period. Disabled by default.
+    ///
+    /// `check_health()` can be called periodically to bring servers marked
+    /// down back as soon as they respond again.
+    pub fn set_health_policy(&mut self, health_policy: Option<HealthPolicy>) {
+        self.health_policy = health_policy;
+    }
+
+    /// Set when and how often queries are sent again after every upstream
+    /// server failed. Queries are not retried by default.
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Retry queries up to `retries` times when no upstream server
+    /// responded, with the default backoff.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retry_policy.retries = retries;
+    }
+
+    /// Resolve names under `.local` with one-shot multicast DNS queries
+    /// instead of sending them to upstream servers. Disabled by default.
+    pub fn set_mdns(&mut self, mdns: bool) {
+        self.mdns = mdns;
+    }
+
+    /// Resolve single-label names with LLMNR (RFC 4795) when upstream
+    /// servers return an error or `NXDOMAIN`. Disabled by default.
+    pub fn set_llmnr(&mut self, llmnr: bool) {
+        self.llmnr = llmnr;
+    }
+
+    /// Report errors to the agent domain of the `Report-Channel` option of
+    /// responses (RFC 9567): when the DNSSEC validation of a response fails,
+    /// or when it includes an extended DNS error, a `TXT` query is sent for
+    /// the name returned by `QueryInfo::error_report_name()`. Disabled by
+    /// default.
+    pub fn set_error_reporting(&mut self, error_reporting: bool) {
+        self.error_reporting = error_reporting;
+    }
+
+    /// Set what happens when a UDP response is truncated. By default, the
+    /// query is sent again over TCP.
+    pub fn set_truncation_policy(&mut self, truncation_policy: TruncationPolicy) {
+        self.truncation_policy = truncation_policy;
+    }
+
+    /// Check the names returned by reverse lookups with a forward lookup,
+    /// keeping only those that resolve back to the address. Enabled by
+    /// default.
+    ///
+    /// When disabled, `query_ptr()` returns all the names, without sending
+    /// additional queries.
+    pub fn set_ptr_verification(&mut self, ptr_verification: bool) {
+        self.ptr_verification = ptr_verification;
+    }
+
+    /// Opportunistically send queries to plain DNS servers over TLS, to
+    /// their port 853, falling back to plain DNS if they don't answer.
+    /// Disabled by default.
+    ///
+    /// The certificate of a server must be valid for its IP address. Servers
+    /// that fail to answer over TLS are only probed again after a while.
+    #[cfg(feature = "tls")]
+    pub fn set_opportunistic_tls(&mut self, opportunistic_tls: bool) {
+        self.opportunistic_tls = opportunistic_tls.then(|| Arc::new(OpportunisticTls::default()));
+    }
+
+    /// Return the names of `PTR`, `CNAME` and `NS` records, as returned by
+    /// `query_ptr()`, `query_cname()` and `query_ns()`, as U-labels instead
+    /// of A-labels.
+    #[cfg(feature = "idna")]
+    pub fn set_unicode_names(&mut self, unicode_names: bool) {
+        self.unicode_names = unicode_names;
+    }
+
+    /// Validate responses with DNSSEC, from `trust_anchors`, such as
+    /// `TrustAnchor::root()`. Queries are then sent with the `DO` flag, and
+    /// the result of the validation is in `QueryInfo::dnssec`. Disabled by
+    /// default.
+    #[cfg(feature = "dnssec")]
+    pub fn set_dnssec_validation(&mut self, trust_anchors: Option<Vec<TrustAnchor>>) {
+        self.trust_anchors = trust_anchors.map(Arc::new);
+    }
+
+    /// Set the random number generator used for transaction IDs and to
+    /// shuffle addresses, instead of `rand::thread_rng()`.
+    pub fn set_rng<R: RngCore + Send + 'static>(&mut self, rng: R) {
+        self.rng = ClientRng::new(rng)
+    }
+
+    /// Use a random number generator seeded with `seed`, so that transaction
+    /// IDs and the order of addresses are reproducible.
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.set_rng(StdRng::seed_from_u64(seed))
+    }
+
+    /// Add an interceptor, that runs after the previously added ones.
+    pub fn add_interceptor<I: Interceptor + 'static>(&mut self, interceptor: I) {
+        Arc::make_mut(&mut self.interceptors).push(Arc::new(interceptor))
+    }
+
+    /// Set a sink receiving the events of every query sent upstream.
+    pub fn set_event_sink<S: EventSink + 'static>(&mut self, sink: S) {
+        self.events.set_sink(Arc::new(sink))
+    }
+
+    /// Shut the client down: queries that need to be sent upstream fail
+    /// from now on, and this returns once the exchanges in flight are over.
+    ///
+    /// Clones of a client share the same shutdown state.
+    pub fn shutdown(&self) {
+        self.shutdown.shutdown_blocking()
+    }
+
+    /// Return a snapshot of the statistics of the upstream servers.
+    ///
+    /// Clones of a client share the same statistics.
+    pub fn stats(&self) -> ClientStats {
+        self.events.stats().snapshot(&self.upstream_servers)
+    }
+
+    /// Return the statistics of every upstream server, including their
+    /// smoothed response time and health, in the configured order.
+    pub fn upstream_stats(&self) -> Vec<UpstreamStats> {
+        self.stats().upstreams
+    }
+
+    /// Write a line to `audit_log` for every query.
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.audit_log = Some(audit_log)
+    }
+
+    /// Write every query and response exchanged with upstream servers to
+    /// `packet_capture`.
+    pub fn set_packet_capture(&mut self, packet_capture: PacketCapture) {
+        self.packet_capture = Some(packet_capture)
+    }
+
+    fn exchange(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        protocol: Protocol,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        // Encrypted and TCP-only servers are only queried over their own
+        // protocol, EDNS probes and diagnostics included
+        let protocol = match upstream_server.transport {
+            Transport::Tls { .. } => Protocol::Tls,
+            Transport::Https { .. } => Protocol::Https,
+            Transport::Quic { .. } => Protocol::Quic,
+            Transport::ObliviousHttps { .. } => Protocol::ObliviousHttps,
+            Transport::Tcp => Protocol::Tcp,
+            _ => protocol,
+        };
+        let query = &self.padding_policy.apply(query, protocol)[..];
+        let _in_flight = self.shutdown.enter()?;
+        let pending_exchange = self.events.start_exchange(upstream_server, protocol);
+        if let Some(packet_capture) = &self.packet_capture {
+            packet_capture.record_query(local_addr, &upstream_server.addr, protocol, query);
+        }
+        let res = match protocol {
+            Protocol::Udp => {
+                self.backend
+                    .dns_exchange_udp(local_addr, upstream_server, query, timeout)
+            }
+            Protocol::Tcp => {
+                self.backend
+                    .dns_exchange_tcp(local_addr, upstream_server, query, timeout)
+            }
+            Protocol::Tls => {
+                self.backend
+                    .dns_exchange_tls(local_addr, upstream_server, query, timeout)
+            }
+            Protocol::Https => {
+                self.backend
+                    .dns_exchange_https(local_addr, upstream_server, query, timeout)
+            }
+            Protocol::ObliviousHttps => {
+                self.backend
+                    .dns_exchange_odoh(local_addr, upstream_server, query, timeout)
+            }
+            Protocol::Quic => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "DNS-over-QUIC is only supported by the async client",
+            )),
+        };
+        pending_exchange.finish(query, &res);
+        if let (Some(packet_capture), Ok(response)) = (&self.packet_capture, &res) {
+            packet_capture.record_response(
+                local_addr,
+                &upstream_server.addr,
+                protocol,
+                query,
+                response,
+            );
+        }
+        res
+    }
+
     fn send_query_to_upstream_server(
         &self,
         upstream_server: &UpstreamServer,
-        query_tid: u16,
-        query_question: &Option<(Vec<u8>, u16, u16)>,
         query: &[u8],
+        query_info: &mut QueryInfo,
+        context: QueryContext<'_>,
+    ) -> Result<ParsedPacket, io::Error> {
+        let timeout = context.options.timeout(self.timeout);
+        if let Some(opportunistic_tls) = &self.opportunistic_tls {
+            if let Some((tls_server, tls_timeout)) =
+                opportunistic_tls.upgrade(upstream_server, timeout)
+            {
+                match self.exchange_with_upstream_server(
+                    &tls_server,
+                    query,
+                    query_info,
+                    context,
+                    tls_timeout,
+                ) {
+                    Ok(parsed_response) => {
+                        opportunistic_tls.set_supported(upstream_server, true);
+                        return Ok(parsed_response);
+                    }
+                    Err(_) => opportunistic_tls.set_supported(upstream_server, false),
+                }
+            }
+        }
+        self.exchange_with_upstream_server(upstream_server, query, query_info, context, timeout)
+    }
+
+    /// Exchange a query with a single upstream server, retrying over TCP if
+    /// the response is truncated.
+    fn exchange_with_upstream_server(
+        &self,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        query_info: &mut QueryInfo,
+        context: QueryContext<'_>,
+        timeout: Duration,
     ) -> Result<ParsedPacket, io::Error> {
         let local_addr = match upstream_server.addr {
             SocketAddr::V4(_) => &self.local_v4_addr,
             SocketAddr::V6(_) => &self.local_v6_addr,
         };
-        let response = if self.force_tcp {
-            self.backend
-                .dns_exchange_tcp(local_addr, upstream_server, query)?
-        } else {
-            self.backend
-                .dns_exchange_udp(local_addr, upstream_server, query)?
-        };
-        let mut parsed_response = DNSSector::new(response)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
-            .parse()
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
-        if !self.force_tcp && parsed_response.flags() & DNS_FLAG_TC == DNS_FLAG_TC {
-            parsed_response = {
-                let response = self
-                    .backend
-                    .dns_exchange_tcp(local_addr, upstream_server, query)?;
-                DNSSector::new(response)
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
-                    .parse()
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
-            };
+        let force_tcp = context.options.force_tcp(self.force_tcp);
+        let protocol = exchange::initial_protocol(upstream_server, force_tcp);
+        let truncation_policy = context.options.truncation_policy(self.truncation_policy);
+        let mut exchange = Exchange::new(
+            query,
+            context.tid,
+            context.question,
+            protocol,
+            truncation_policy,
+        );
+        loop {
+            let protocol = exchange.protocol();
+            let timeout = context.deadline.timeout(timeout)?;
+            query_info.attempts += 1;
+            exchange.start_attempt(self.rng.with(|rng| rng.gen()));
+            query_info.protocol = Some(protocol);
+            let response = self.exchange(
+                local_addr,
+                upstream_server,
+                protocol,
+                exchange.query(),
+                timeout,
+            )?;
+            match exchange.on_response(response) {
+                Ok(Step::Done(parsed_response)) => return Ok(parsed_response),
+                Ok(Step::Retry(Retry::Truncated)) => {
+                    self.events.emit(|| ClientEvent::TruncatedRetry {
+                        server: upstream_server.clone(),
+                    });
+                    query_info.tcp_fallback = true;
+                }
+                Ok(Step::Retry(Retry::Edns)) => {}
+                Err(e) => {
+                    self.events.emit(|| ClientEvent::InvalidResponse {
+                        server: upstream_server.clone(),
+                        reason: e.to_string(),
+                    });
+                    return Err(e);
+                }
+            }
         }
-        if parsed_response.tid() != query_tid || &parsed_response.question() != query_question {
-            return Err(io::Error::new(
-                io::ErrorKind::PermissionDenied,
-                "Unexpected response",
-            ));
+    }
+
+    /// Send a query to a multicast group, and wait for the first response.
+    fn send_multicast_query(
+        &self,
+        upstream_server: UpstreamServer,
+        query_tid: u16,
+        query_question: &Option<(Vec<u8>, u16, u16)>,
+        query: &[u8],
+        timeout: Duration,
+    ) -> Result<(ParsedPacket, QueryInfo), io::Error> {
+        let mut query_info = QueryInfo::default();
+        // There is no TCP fallback for multicast queries: starting the
+        // exchange as TCP makes it return truncated responses as is.
+        let mut exchange = Exchange::new(
+            query,
+            query_tid,
+            query_question,
+            Protocol::Tcp,
+            TruncationPolicy::RetryTcp,
+        );
+        let start = Instant::now();
+        loop {
+            query_info.attempts += 1;
+            exchange.start_attempt(self.rng.with(|rng| rng.gen()));
+            query_info.protocol = Some(Protocol::Udp);
+            let _in_flight = self.shutdown.enter()?;
+            let pending_exchange = self.events.start_exchange(&upstream_server, Protocol::Udp);
+            let res = self.backend.dns_exchange_multicast(
+                &self.local_v4_addr,
+                &upstream_server.addr,
+                exchange.query(),
+                timeout,
+            );
+            pending_exchange.finish(exchange.query(), &res);
+            match exchange.on_response(res?)? {
+                Step::Done(parsed_response) => {
+                    query_info.server = Some(upstream_server);
+                    query_info.rtt = start.elapsed();
+                    return Ok((parsed_response, query_info));
+                }
+                Step::Retry(_) => {}
+            }
         }
-        Ok(parsed_response)
     }
 
     fn query_from_parsed_query(
+        &self,
+        parsed_query: ParsedPacket,
+        options: &QueryOptions,
+    ) -> Result<(ParsedPacket, QueryInfo), io::Error> {
+        let (mut parsed_response, mut query_info) =
+            self.query_from_parsed_query_unmodified(parsed_query, options)?;
+        self.interceptors.on_response(&mut parsed_response)?;
+        query_info.set_response(&parsed_response);
+        Ok((parsed_response, query_info))
+    }
+
+    /// Resolve a query, and return an error if the response code is not
+    /// `NOERROR`.
+    fn query_checked(
+        &self,
+        parsed_query: ParsedPacket,
+        options: &QueryOptions,
+    ) -> Result<(ParsedPacket, QueryInfo), io::Error> {
+        let (parsed_response, query_info) = self.query_from_parsed_query(parsed_query, options)?;
+        DnsError::check_rcode(query_info.rcode)?;
+        Ok((parsed_response, query_info))
+    }
+
+    /// Resolve a query, without letting interceptors modify the response.
+    fn query_from_parsed_query_unmodified(
+        &self,
+        parsed_query: ParsedPacket,
+        options: &QueryOptions,
+    ) -> Result<(ParsedPacket, QueryInfo), io::Error> {
+        let options = match (options.edns_bufsize, self.edns_bufsize) {
+            (None, Some(edns_bufsize)) => Cow::Owned(QueryOptions {
+                edns_bufsize: Some(edns_bufsize),
+                ..options.clone()
+            }),
+            _ => Cow::Borrowed(options),
+        };
+        let options = options.as_ref();
+        let mut parsed_query = options.apply(parsed_query)?;
+        #[cfg(feature = "dnssec")]
+        if self.trust_anchors.is_some() {
+            parsed_query = packet::set_dnssec_ok(parsed_query)?;
+        }
+        self.interceptors.on_query(&mut parsed_query)?;
+        let question = match self.audit_log {
+            Some(_) => parsed_query.question(),
+            None if cfg!(any(feature = "metrics", feature = "tracing")) => parsed_query.question(),
+            None => None,
+        };
+        #[cfg(feature = "tracing")]
+        let query_span = crate::instrument::QuerySpan::new(&question);
+        let deadline = Deadline::after(options.total_timeout(self.total_timeout));
+        let res = {
+            #[cfg(feature = "tracing")]
+            let _enter = query_span.span().enter();
+            self.resolve_parsed_query(parsed_query, options, deadline)
+        };
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(&question, &res);
+        }
+        #[cfg(feature = "metrics")]
+        crate::telemetry::record_query(&question, &res);
+        #[cfg(feature = "tracing")]
+        query_span.finish(&res);
+        let (parsed_response, mut query_info) = res?;
+        query_info.set_response(&parsed_response);
+        #[cfg(feature = "dnssec")]
+        if let Some(trust_anchors) = &self.trust_anchors {
+            // Cached responses are validated again, other local answers are
+            // not validated
+            if query_info.server.is_some() || query_info.cache_hit {
+                query_info.dnssec = Some(self.validate_response(
+                    trust_anchors,
+                    &parsed_response,
+                    options,
+                    deadline,
+                ));
+            }
+        }
+        if self.error_reporting {
+            if let Some(name) = query_info.error_report(&parsed_response) {
+                // The response to the report carries no information
+                if let Ok(report_query) =
+                    packet::query(&name, Type::TXT, Class::IN, NameValidation::Relaxed)
+                {
+                    let _ = self.resolve_parsed_query(report_query, options, deadline);
+                }
+            }
+        }
+        Ok((parsed_response, query_info))
+    }
+
+    /// Validate a response with DNSSEC, fetching the `DS` and `DNSKEY`
+    /// records the validation needs from the upstream servers.
+    #[cfg(feature = "dnssec")]
+    fn validate_response(
+        &self,
+        trust_anchors: &[TrustAnchor],
+        parsed_response: &ParsedPacket,
+        options: &QueryOptions,
+        deadline: Deadline,
+    ) -> DnssecStatus {
+        let response = match Message::parse(parsed_response.packet()) {
+            Ok(response) => response,
+            Err(_) => return DnssecStatus::Bogus,
+        };
+        let mut validator = Validator::new(trust_anchors, response);
+        loop {
+            match validator.next() {
+                Validation::Done(status) => return status,
+                Validation::Fetch(name, rr_type) => {
+                    let res = match packet::query_type(&name, rr_type, NameValidation::Relaxed)
+                        .and_then(packet::set_dnssec_ok)
+                    {
+                        Ok(parsed_query) => {
+                            self.resolve_parsed_query(parsed_query, options, deadline)
+                        }
+                        Err(e) => Err(e),
+                    };
+                    let response = res
+                        .and_then(|(parsed_response, _)| Message::parse(parsed_response.packet()));
+                    validator.add_response(response.ok());
+                }
+            }
+        }
+    }
+
+    fn resolve_parsed_query(
         &self,
         mut parsed_query: ParsedPacket,
-    ) -> Result<ParsedPacket, io::Error> {
+        options: &QueryOptions,
+        deadline: Deadline,
+    ) -> Result<(ParsedPacket, QueryInfo), io::Error> {
         let query_tid = parsed_query.tid();
         let query_question = parsed_query.question();
         if query_question.is_none() || parsed_query.flags() & DNS_FLAG_QR != 0 {
@@ -104,25 +862,257 @@ impl DNSClient {
                 "No DNS question",
             ));
         }
-        let valid_query = parsed_query.into_packet();
-        for upstream_server in &self.upstream_servers {
-            if let Ok(parsed_response) = self.send_query_to_upstream_server(
-                upstream_server,
-                query_tid,
+        let options = self.domain_routes.apply(&query_question, options);
+        let cache_key = match self.cache {
+            Some(_) => CacheKey::new(
                 &query_question,
-                &valid_query,
-            ) {
-                return Ok(parsed_response);
+                &parsed_query,
+                options.upstream_servers.as_deref(),
+            ),
+            None => None,
+        };
+        let valid_query = parsed_query.into_packet();
+        if let Some(parsed_response) = self
+            .static_records
+            .response_for(&query_question, &valid_query)?
+        {
+            return Ok((parsed_response, QueryInfo::default()));
+        }
+        if let Some((name, ..)) = &query_question {
+            if self.blocklist.is_blocked_raw(name) {
+                let parsed_response =
+                    packet::synthesize_response(&valid_query, Rcode::NXDOMAIN.into(), &[])?;
+                return Ok((parsed_response, QueryInfo::default()));
+            }
+            if self.mdns && mdns::is_local_name(name) {
+                return self.send_multicast_query(
+                    mdns::upstream_server(),
+                    query_tid,
+                    &query_question,
+                    &valid_query,
+                    deadline.timeout(options.timeout(self.timeout))?,
+                );
+            }
+        }
+        if let (Some(cache), Some(cache_key)) = (&self.cache, &cache_key) {
+            let max_stale = self.serve_stale.unwrap_or_default();
+            if let Some(parsed_response) = cache.get(cache_key, &valid_query, max_stale) {
+                let query_info = QueryInfo {
+                    cache_hit: true,
+                    ..Default::default()
+                };
+                return Ok((parsed_response, query_info));
+            }
+        }
+        let options = match &self.resolv_conf_watcher {
+            Some(resolv_conf_watcher) => resolv_conf_watcher.apply(&options),
+            None => Cow::Borrowed(&*options),
+        };
+        let options = &*options;
+        let stale = match (&self.cache, &cache_key, self.serve_stale) {
+            (Some(cache), Some(cache_key), Some(max_stale)) => cache
+                .get_stale(cache_key, &valid_query, max_stale)
+                .map(|(parsed_response, stale_state)| {
+                    (cache, cache_key, parsed_response, stale_state)
+                }),
+            _ => None,
+        };
+        let stale_query_info = QueryInfo {
+            cache_hit: true,
+            stale: true,
+            ..Default::default()
+        };
+        if let Some((cache, cache_key, _, StaleState::RefreshDue)) = &stale {
+            cache.refresh_failed(cache_key);
+            let dns_client = self.clone();
+            let (cache_key, options) = ((*cache_key).clone(), options.clone());
+            let (valid_query, query_question) = (valid_query.clone(), query_question.clone());
+            thread::spawn(move || {
+                dns_client.refresh_stale(
+                    cache_key,
+                    &valid_query,
+                    query_tid,
+                    &query_question,
+                    &options,
+                )
+            });
+        }
+        if let Some((_, _, parsed_response, StaleState::Serving | StaleState::RefreshDue)) = stale {
+            return Ok((parsed_response, stale_query_info));
+        }
+        let context = QueryContext {
+            tid: query_tid,
+            question: &query_question,
+            options,
+            deadline,
+        };
+        let res = self.send_query_with_retries(&valid_query, context);
+        if let (Some(cache), Some(cache_key), Ok((parsed_response, _))) =
+            (&self.cache, &cache_key, &res)
+        {
+            cache.insert(cache_key.clone(), parsed_response);
+        }
+        if let (Some((cache, cache_key, parsed_response, _)), true) =
+            (stale, cache::upstream_failed(&res))
+        {
+            cache.refresh_failed(cache_key);
+            return Ok((parsed_response, stale_query_info));
+        }
+        if !exchange::resolved(&res) && self.llmnr {
+            if let Some((name, ..)) = &query_question {
+                if let (true, Ok(timeout)) = (
+                    llmnr::is_single_label_name(name),
+                    deadline.timeout(llmnr::timeout(options.timeout(self.timeout))),
+                ) {
+                    let llmnr_query = llmnr::query(&valid_query);
+                    if let Ok(llmnr_res) = self.send_multicast_query(
+                        llmnr::upstream_server(),
+                        query_tid,
+                        &query_question,
+                        &llmnr_query,
+                        timeout,
+                    ) {
+                        return Ok(llmnr_res);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Refresh a stale cached response in the background.
+    fn refresh_stale(
+        &self,
+        cache_key: CacheKey,
+        valid_query: &[u8],
+        query_tid: u16,
+        query_question: &Option<(Vec<u8>, u16, u16)>,
+        options: &QueryOptions,
+    ) {
+        let context = QueryContext {
+            tid: query_tid,
+            question: query_question,
+            options,
+            deadline: Deadline::after(options.total_timeout(self.total_timeout)),
+        };
+        if let (Some(cache), Ok((parsed_response, _))) = (
+            &self.cache,
+            self.send_query_with_retries(valid_query, context),
+        ) {
+            cache.insert(cache_key, &parsed_response);
+        }
+    }
+
+    /// Send a query to the upstream servers, sending it again according to
+    /// the retry policy if they all failed.
+    fn send_query_with_retries(
+        &self,
+        valid_query: &[u8],
+        context: QueryContext<'_>,
+    ) -> Result<(ParsedPacket, QueryInfo), io::Error> {
+        let mut retries = Retries::new(&self.retry_policy, context.deadline);
+        loop {
+            let res = self.send_query_to_upstream_servers(valid_query, context);
+            match retries.on_result(res) {
+                RetryStep::Backoff { attempt, backoff } => {
+                    self.events.emit(|| ClientEvent::Retry { attempt, backoff });
+                    thread::sleep(backoff)
+                }
+                RetryStep::Done(res) => return res,
+            }
+        }
+    }
+
+    /// The upstream servers to send a query to, in order.
+    fn ordered_upstream_servers<'t>(
+        &'t self,
+        options: &'t QueryOptions,
+    ) -> Cow<'t, [UpstreamServer]> {
+        let stats = self.events.stats();
+        let upstream_servers = self.upstream_strategy.order(
+            options.upstream_servers(&self.upstream_servers),
+            stats,
+            &self.rotation,
+            &self.rng,
+        );
+        match &self.health_policy {
+            Some(health_policy) => health_policy.order(upstream_servers, stats),
+            None => upstream_servers,
+        }
+    }
+
+    /// Send a query to the upstream servers, until one of them responds
+    /// with a response that doesn't trigger a failover.
+    fn send_query_to_upstream_servers(
+        &self,
+        valid_query: &[u8],
+        context: QueryContext<'_>,
+    ) -> Result<(ParsedPacket, QueryInfo), io::Error> {
+        let mut failover = Failover::new(
+            self.ordered_upstream_servers(context.options),
+            &self.failover_policy,
+        );
+        loop {
+            let upstream_server = match failover.upstream_server() {
+                Some(upstream_server) => upstream_server.clone(),
+                None => return failover.no_upstream_servers(),
+            };
+            let start = Instant::now();
+            let res = self.send_query_to_upstream_server(
+                &upstream_server,
+                valid_query,
+                failover.query_info(),
+                context,
+            );
+            match failover.on_result(res, start.elapsed()) {
+                FailoverStep::Next { from, to } => {
+                    self.events.emit(|| ClientEvent::Failover { from, to })
+                }
+                FailoverStep::Done(res) => return res,
             }
         }
-        Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "No response received from any servers",
-        ))
     }
 
     /// Send a raw query to the DNS server and return the response.
     pub fn query_raw(&self, query: &[u8], tid_masking: bool) -> Result<Vec<u8>, io::Error> {
+        self.query_raw_with(query, tid_masking, &QueryOptions::default())
+    }
+
+    /// Send a raw query to the DNS server and return the response.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_raw_with(
+        &self,
+        query: &[u8],
+        tid_masking: bool,
+        options: &QueryOptions,
+    ) -> Result<Vec<u8>, io::Error> {
+        self.query_raw_detailed_with(query, tid_masking, options)
+            .map(|(res, _)| res)
+    }
+
+    /// Send a raw query to the DNS server and return the response.
+    ///
+    /// Also return how the response was obtained.
+    pub fn query_raw_detailed(
+        &self,
+        query: &[u8],
+        tid_masking: bool,
+    ) -> Result<(Vec<u8>, QueryInfo), io::Error> {
+        self.query_raw_detailed_with(query, tid_masking, &QueryOptions::default())
+    }
+
+    /// Send a raw query to the DNS server and return the response.
+    ///
+    /// Also return how the response was obtained.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_raw_detailed_with(
+        &self,
+        query: &[u8],
+        tid_masking: bool,
+        options: &QueryOptions,
+    ) -> Result<(Vec<u8>, QueryInfo), io::Error> {
         let mut parsed_query = DNSSector::new(query.to_vec())
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
             .parse()
@@ -130,89 +1120,429 @@ impl DNSClient {
         let mut tid = 0;
         if tid_masking {
             tid = parsed_query.tid();
-            let mut rnd = rand::thread_rng();
-            let masked_tid: u16 = rnd.gen();
+            let masked_tid: u16 = self.rng.with(|rng| rng.gen());
             parsed_query.set_tid(masked_tid);
         }
-        let mut parsed_response = self.query_from_parsed_query(parsed_query)?;
+        let (mut parsed_response, query_info) =
+            self.query_from_parsed_query(parsed_query, options)?;
         if tid_masking {
             parsed_response.set_tid(tid);
         }
         let response = parsed_response.into_packet();
-        Ok(response)
+        Ok((response, query_info))
     }
 
-    /// Return IPv4 addresses.
-    pub fn query_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, io::Error> {
-        let parsed_query = dnssector::gen::query(
-            name.as_bytes(),
-            Type::from_string("A").unwrap(),
-            Class::from_string("IN").unwrap(),
-        )
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
-        let mut parsed_response = self.query_from_parsed_query(parsed_query)?;
-        let mut ips = vec![];
-        {
-            let mut it = parsed_response.into_iter_answer();
-            while let Some(item) = it {
-                if let Ok(IpAddr::V4(addr)) = item.rr_ip() {
-                    ips.push(addr);
-                }
-                it = item.next();
-            }
-        }
-        ips.shuffle(&mut rand::thread_rng());
-        Ok(ips)
+    /// Send a raw query to the DNS server and return the exact response
+    /// received from the server, along with its response code.
+    ///
+    /// The transaction ID and the question of the response are checked, but
+    /// the response is neither modified by interceptors nor re-serialized.
+    pub fn query_raw_passthrough(&self, query: &[u8]) -> Result<(Vec<u8>, u8), io::Error> {
+        self.query_raw_passthrough_with(query, &QueryOptions::default())
     }
 
-    /// Return IPv6 addresses.
-    pub fn query_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, io::Error> {
-        let parsed_query = dnssector::gen::query(
-            name.as_bytes(),
-            Type::from_string("AAAA").unwrap(),
-            Class::from_string("IN").unwrap(),
-        )
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
-        let mut parsed_response = self.query_from_parsed_query(parsed_query)?;
-        let mut ips = vec![];
-        {
-            let mut it = parsed_response.into_iter_answer();
-            while let Some(item) = it {
-                if let Ok(IpAddr::V6(addr)) = item.rr_ip() {
-                    ips.push(addr);
-                }
-                it = item.next();
-            }
-        }
-        ips.shuffle(&mut rand::thread_rng());
-        Ok(ips)
+    /// Send a raw query to the DNS server and return the exact response
+    /// received from the server, along with its response code.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_raw_passthrough_with(
+        &self,
+        query: &[u8],
+        options: &QueryOptions,
+    ) -> Result<(Vec<u8>, u8), io::Error> {
+        self.query_raw_passthrough_detailed_with(query, options)
+            .map(|(response, query_info)| (response, query_info.rcode))
     }
 
-    /// Return both IPv4 and IPv6 addresses.
+    /// Send a raw query to the DNS server and return the exact response
+    /// received from the server.
+    ///
+    /// Also return how the response was obtained.
+    pub fn query_raw_passthrough_detailed(
+        &self,
+        query: &[u8],
+    ) -> Result<(Vec<u8>, QueryInfo), io::Error> {
+        self.query_raw_passthrough_detailed_with(query, &QueryOptions::default())
+    }
+
+    /// Send a raw query to the DNS server and return the exact response
+    /// received from the server.
+    ///
+    /// Also return how the response was obtained.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_raw_passthrough_detailed_with(
+        &self,
+        query: &[u8],
+        options: &QueryOptions,
+    ) -> Result<(Vec<u8>, QueryInfo), io::Error> {
+        let parsed_query = DNSSector::new(query.to_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let (parsed_response, query_info) =
+            self.query_from_parsed_query_unmodified(parsed_query, options)?;
+        Ok((parsed_response.into_packet(), query_info))
+    }
+
+    /// Send raw queries to the same DNS server, over a single connection,
+    /// and return the responses, in the order of the queries.
+    ///
+    /// The queries are pipelined (RFC 7766): they are all sent before the
+    /// responses are read, over TCP, or within a single TLS session for
+    /// DNS-over-TLS servers. Other encrypted servers are skipped. The timeout
+    /// applies to the whole batch.
+    pub fn query_raw_many(&self, queries: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, io::Error> {
+        self.query_raw_many_with(queries, &QueryOptions::default())
+    }
+
+    /// Send raw queries to the same DNS server, over a single connection,
+    /// and return the responses, in the order of the queries.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_raw_many_with(
+        &self,
+        queries: &[Vec<u8>],
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<u8>>, io::Error> {
+        if queries.is_empty() {
+            return Ok(vec![]);
+        }
+        let pipelined_queries =
+            PipelinedQueries::new(queries, options, self.rng.with(|rng| rng.gen()))?;
+        let timeout = options.timeout(self.timeout);
+        let mut errors = vec![];
+        for upstream_server in options.upstream_servers(&self.upstream_servers) {
+            let local_addr = match upstream_server.addr {
+                SocketAddr::V4(_) => &self.local_v4_addr,
+                SocketAddr::V6(_) => &self.local_v6_addr,
+            };
+            let _in_flight = self.shutdown.enter()?;
+            let res = match exchange::pipelined_protocol(upstream_server) {
+                Some(Protocol::Tls) => self.backend.dns_exchange_tls_pipelined(
+                    local_addr,
+                    upstream_server,
+                    pipelined_queries.queries(),
+                    timeout,
+                ),
+                Some(_) => self.backend.dns_exchange_tcp_pipelined(
+                    local_addr,
+                    upstream_server,
+                    pipelined_queries.queries(),
+                    timeout,
+                ),
+                None => continue,
+            };
+            match res.and_then(|responses| pipelined_queries.responses(responses)) {
+                Ok(responses) => return Ok(responses),
+                Err(e) => errors.push((upstream_server.clone(), e)),
+            }
+        }
+        Err(no_response_error(errors))
+    }
+
+    /// Return IPv4 addresses.
+    pub fn query_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, io::Error> {
+        self.query_a_with(name, &QueryOptions::default())
+    }
+
+    /// Return IPv4 addresses.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_a_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Ipv4Addr>, io::Error> {
+        self.query_a_detailed_with(name, options)
+            .map(|(res, _)| res)
+    }
+
+    /// Return IPv4 addresses.
+    ///
+    /// Also return how the response was obtained.
+    pub fn query_a_detailed(&self, name: &str) -> Result<(Vec<Ipv4Addr>, QueryInfo), io::Error> {
+        self.query_a_detailed_with(name, &QueryOptions::default())
+    }
+
+    /// Return IPv4 addresses.
+    ///
+    /// Also return how the response was obtained.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_a_detailed_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<(Vec<Ipv4Addr>, QueryInfo), io::Error> {
+        let rr_type = Type::from_string("A").unwrap().into();
+        let (mut parsed_response, query_info) =
+            self.query_following_cnames(name, rr_type, options)?;
+        let mut ips = vec![];
+        {
+            let mut it = parsed_response.into_iter_answer();
+            while let Some(item) = it {
+                if let Ok(IpAddr::V4(addr)) = item.rr_ip() {
+                    ips.push(addr);
+                }
+                it = item.next();
+            }
+        }
+        self.rng.with(|rng| self.answer_order.apply(&mut ips, rng));
+        Ok((ips, query_info))
+    }
+
+    /// Return IPv6 addresses.
+    pub fn query_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, io::Error> {
+        self.query_aaaa_with(name, &QueryOptions::default())
+    }
+
+    /// Return IPv6 addresses.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_aaaa_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Ipv6Addr>, io::Error> {
+        self.query_aaaa_detailed_with(name, options)
+            .map(|(res, _)| res)
+    }
+
+    /// Return IPv6 addresses.
+    ///
+    /// Also return how the response was obtained.
+    pub fn query_aaaa_detailed(&self, name: &str) -> Result<(Vec<Ipv6Addr>, QueryInfo), io::Error> {
+        self.query_aaaa_detailed_with(name, &QueryOptions::default())
+    }
+
+    /// Return IPv6 addresses.
+    ///
+    /// Also return how the response was obtained.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_aaaa_detailed_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<(Vec<Ipv6Addr>, QueryInfo), io::Error> {
+        let rr_type = Type::from_string("AAAA").unwrap().into();
+        let (mut parsed_response, query_info) =
+            self.query_following_cnames(name, rr_type, options)?;
+        let rcode = parsed_response.rcode();
+        let mut ips = vec![];
+        {
+            let mut it = parsed_response.into_iter_answer();
+            while let Some(item) = it {
+                if let Ok(IpAddr::V6(addr)) = item.rr_ip() {
+                    ips.push(addr);
+                }
+                it = item.next();
+            }
+        }
+        if let (true, Some(dns64)) = (ips.is_empty(), self.dns64) {
+            if rcode == u8::from(Rcode::NOERROR) {
+                let (ipv4_ips, query_info) = self.query_a_detailed_with(name, options)?;
+                let ips = ipv4_ips
+                    .into_iter()
+                    .map(|ip| dns64.synthesize(ip))
+                    .collect();
+                return Ok((ips, query_info));
+            }
+        }
+        self.rng.with(|rng| self.answer_order.apply(&mut ips, rng));
+        Ok((ips, query_info))
+    }
+
+    /// Resolve a host name to IP addresses, trying the names derived from
+    /// the search list in order, until one of them has addresses.
+    ///
+    /// If none of them has, the first error is returned, if any.
+    pub fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, io::Error> {
+        let mut first_error = None;
+        for candidate in self.search_list.candidates(name) {
+            match self.query_addrs(&candidate) {
+                Ok(ips) if !ips.is_empty() => return Ok(ips),
+                Ok(_) => {}
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Send a query, then query the targets of `CNAME` chains the upstream
+    /// servers didn't resolve, up to the `CNAME` limit.
+    fn query_following_cnames(
+        &self,
+        name: &str,
+        rr_type: u16,
+        options: &QueryOptions,
+    ) -> Result<(ParsedPacket, QueryInfo), io::Error> {
+        let parsed_query = packet::query_type(name, rr_type, self.name_validation)?;
+        let mut res = self.query_checked(parsed_query, options)?;
+        let mut visited = vec![packet::normalize_name(name.as_bytes())];
+        for _ in 0..self.cname_limit.unwrap_or(0) {
+            let target = match record_data::unresolved_cname(res.0.packet())? {
+                Some(target) => target,
+                None => break,
+            };
+            let normalized_target = packet::normalize_name(target.as_bytes());
+            if visited.contains(&normalized_target) {
+                return Err(DnsError::ProtocolError("CNAME loop".to_string()).into());
+            }
+            visited.push(normalized_target);
+            let parsed_query = packet::query_type(&target, rr_type, self.name_validation)?;
+            res = self.query_checked(parsed_query, options)?;
+        }
+        Ok(res)
+    }
+
+    /// Return IPv4 and/or IPv6 addresses, according to the address family
+    /// preference. When both families are needed, both queries are performed
+    /// simultaneously.
     pub fn query_addrs(&self, name: &str) -> Result<Vec<IpAddr>, io::Error> {
-        let ipv4_ips = self.query_a(name)?;
-        let ipv6_ips = self.query_aaaa(name)?;
-        let mut ips: Vec<_> = ipv4_ips
+        self.query_addrs_with(name, &QueryOptions::default())
+    }
+
+    /// Return IPv4 and/or IPv6 addresses, according to the address family
+    /// preference.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_addrs_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<IpAddr>, io::Error> {
+        let preference = self.address_family_preference;
+        let (ipv4_ips, ipv6_ips) = match (preference.wants_ipv4(), preference.wants_ipv6()) {
+            (true, false) => (self.query_a_with(name, options)?, vec![]),
+            (false, true) => (vec![], self.query_aaaa_with(name, options)?),
+            _ => {
+                // The AAAA query runs on a scoped thread, so that it can borrow
+                // `self`. That thread starts with an empty buffer pool, at the
+                // cost of one allocation per lookup.
+                let (ipv4_ips, ipv6_ips) = thread::scope(|scope| {
+                    let ipv6_ips = scope.spawn(|| self.query_aaaa_with(name, options));
+                    let ipv4_ips = self.query_a_with(name, options);
+                    (ipv4_ips, ipv6_ips.join().unwrap())
+                });
+                merge_families(ipv4_ips, ipv6_ips)?
+            }
+        };
+        let ips = self
+            .rng
+            .with(|rng| preference.order(ipv4_ips, ipv6_ips, self.answer_order, rng));
+        match &self.reachability_probe {
+            Some(reachability_probe) => Ok(reachability_probe.order_blocking(ips)),
+            None => Ok(ips),
+        }
+    }
+
+    /// Resolve a `host:port` string into socket addresses, like
+    /// `std::net::ToSocketAddrs`.
+    ///
+    /// Numeric IP addresses are returned as-is, without sending any queries.
+    pub fn resolve_socket_addrs(&self, host_port: &str) -> Result<Vec<SocketAddr>, io::Error> {
+        Ok(self.lookup_host(host_port)?.collect())
+    }
+
+    /// Resolve `host` into socket addresses, like
+    /// `std::net::ToSocketAddrs::to_socket_addrs()`.
+    ///
+    /// `host` can be a `host:port` string, a `(host, port)` tuple or a socket
+    /// address. Numeric IP addresses are returned as-is, without sending any
+    /// queries. Addresses are ordered according to the address family
+    /// preference and the answer order.
+    pub fn lookup_host<T: ToSocketAddrs>(
+        &self,
+        host: T,
+    ) -> Result<impl Iterator<Item = SocketAddr>, io::Error> {
+        let (host, port) = match host.host_port()? {
+            HostPort::Addr(addr) => return Ok(vec![addr].into_iter()),
+            HostPort::Name(host, port) => (host, port),
+        };
+        let addrs: Vec<_> = self
+            .query_addrs(host)?
             .into_iter()
-            .map(IpAddr::from)
-            .chain(ipv6_ips.into_iter().map(IpAddr::from))
+            .map(|ip| SocketAddr::new(ip, port))
             .collect();
-        ips.shuffle(&mut rand::thread_rng());
-        Ok(ips)
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No addresses found",
+            ));
+        }
+        Ok(addrs.into_iter())
+    }
+
+    /// Check whether the resolvers are behind a captive portal, or rewrite
+    /// responses, using the default probe.
+    pub fn detect_captive_portal(&self) -> Result<CaptivePortalVerdict, io::Error> {
+        self.detect_captive_portal_with(&CaptivePortalProbe::default())
+    }
+
+    /// Check whether the resolvers are behind a captive portal, or rewrite
+    /// responses, using a custom probe.
+    pub fn detect_captive_portal_with(
+        &self,
+        probe: &CaptivePortalProbe,
+    ) -> Result<CaptivePortalVerdict, io::Error> {
+        let addrs = self.query_addrs(&probe.name);
+        if let Some(verdict) = probe.check_probe_name(addrs) {
+            return Ok(verdict);
+        }
+        let addrs = self.query_addrs(&self.rng.with(|rng| probe.nonexistent_name(rng)));
+        Ok(probe.check_nonexistent_name(addrs))
     }
 
     /// Return TXT records.
     pub fn query_txt(&self, name: &str) -> Result<Vec<Vec<u8>>, io::Error> {
+        self.query_txt_with(name, &QueryOptions::default())
+    }
+
+    /// Return TXT records.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_txt_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<u8>>, io::Error> {
+        self.query_txt_detailed_with(name, options)
+            .map(|(res, _)| res)
+    }
+
+    /// Return TXT records.
+    ///
+    /// Also return how the response was obtained.
+    pub fn query_txt_detailed(&self, name: &str) -> Result<(Vec<Vec<u8>>, QueryInfo), io::Error> {
+        self.query_txt_detailed_with(name, &QueryOptions::default())
+    }
+
+    /// Return TXT records.
+    ///
+    /// Also return how the response was obtained.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_txt_detailed_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<(Vec<Vec<u8>>, QueryInfo), io::Error> {
         let rr_class = Class::from_string("IN").unwrap();
         let rr_type = Type::from_string("TXT").unwrap();
-        let parsed_query = dnssector::gen::query(name.as_bytes(), rr_type, rr_class)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
-        let mut parsed_response = self.query_from_parsed_query(parsed_query)?;
+        let parsed_query = packet::query(name, rr_type, rr_class, self.name_validation)?;
+        let (mut parsed_response, query_info) = self.query_checked(parsed_query, options)?;
         let mut txts: Vec<Vec<u8>> = vec![];
 
         let mut it = parsed_response.into_iter_answer();
         while let Some(item) = it {
-            if item.rr_class() != rr_class.into() || item.rr_type() != rr_type.into() {
+            if item.rr_class() != u16::from(rr_class) || item.rr_type() != u16::from(rr_type) {
                 it = item.next();
                 continue;
             }
@@ -230,41 +1560,109 @@ impl DNSClient {
             }
             it = item.next();
         }
-        Ok(txts)
+        Ok((txts, query_info))
+    }
+
+    /// Convert a name returned to the application to U-labels, if requested.
+    fn output_name(&self, name: String) -> String {
+        #[cfg(feature = "idna")]
+        if self.unicode_names {
+            return crate::idn::to_unicode(&name);
+        }
+        name
     }
 
     /// Reverse IP lookup.
     pub fn query_ptr(&self, ip: &IpAddr) -> Result<Vec<String>, io::Error> {
+        self.query_ptr_with(ip, &QueryOptions::default())
+    }
+
+    /// Reverse IP lookup.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_ptr_with(
+        &self,
+        ip: &IpAddr,
+        options: &QueryOptions,
+    ) -> Result<Vec<String>, io::Error> {
+        self.query_ptr_detailed_with(ip, options)
+            .map(|(res, _)| res)
+    }
+
+    /// Reverse IP lookup.
+    ///
+    /// Also return how the response was obtained.
+    pub fn query_ptr_detailed(&self, ip: &IpAddr) -> Result<(Vec<String>, QueryInfo), io::Error> {
+        self.query_ptr_detailed_with(ip, &QueryOptions::default())
+    }
+
+    /// Reverse IP lookup.
+    ///
+    /// Also return how the response was obtained.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_ptr_detailed_with(
+        &self,
+        ip: &IpAddr,
+        options: &QueryOptions,
+    ) -> Result<(Vec<String>, QueryInfo), io::Error> {
+        let (names, query_info) = self.query_ptr_names_detailed_with(ip, options)?;
+        let names = names.into_iter().map(|ptr_name| ptr_name.name).collect();
+        Ok((names, query_info))
+    }
+
+    /// Reverse IP lookup, returning whether each name was verified.
+    pub fn query_ptr_names(&self, ip: &IpAddr) -> Result<Vec<PtrName>, io::Error> {
+        self.query_ptr_names_with(ip, &QueryOptions::default())
+    }
+
+    /// Reverse IP lookup, returning whether each name was verified.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_ptr_names_with(
+        &self,
+        ip: &IpAddr,
+        options: &QueryOptions,
+    ) -> Result<Vec<PtrName>, io::Error> {
+        self.query_ptr_names_detailed_with(ip, options)
+            .map(|(res, _)| res)
+    }
+
+    /// Reverse IP lookup, returning whether each name was verified.
+    ///
+    /// Also return how the response was obtained.
+    pub fn query_ptr_names_detailed(
+        &self,
+        ip: &IpAddr,
+    ) -> Result<(Vec<PtrName>, QueryInfo), io::Error> {
+        self.query_ptr_names_detailed_with(ip, &QueryOptions::default())
+    }
+
+    /// Reverse IP lookup, returning whether each name was verified.
+    ///
+    /// Also return how the response was obtained.
+    ///
+    /// `options` override the client settings for this query.
+    ///
+    /// If forward verification is enabled, only the verified names are
+    /// returned. Otherwise, all the names are returned, and none of them is
+    /// flagged as verified.
+    pub fn query_ptr_names_detailed_with(
+        &self,
+        ip: &IpAddr,
+        options: &QueryOptions,
+    ) -> Result<(Vec<PtrName>, QueryInfo), io::Error> {
         let rr_class = Class::from_string("IN").unwrap();
         let rr_type = Type::from_string("PTR").unwrap();
-        let rev_name = match ip {
-            IpAddr::V4(ip) => {
-                let mut octets = ip.octets();
-                octets.reverse();
-                format!(
-                    "{}.{}.{}.{}.in-addr.arpa",
-                    octets[0], octets[1], octets[2], octets[3]
-                )
-            }
-            IpAddr::V6(ip) => {
-                let mut octets = ip.octets();
-                octets.reverse();
-                let rev = octets
-                    .iter()
-                    .map(|x| x.to_string())
-                    .collect::<Vec<_>>()
-                    .join(".");
-                format!("{}.ip6.arpa", rev)
-            }
-        };
-        let parsed_query = dnssector::gen::query(rev_name.as_bytes(), rr_type, rr_class)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
-        let mut parsed_response = self.query_from_parsed_query(parsed_query)?;
-        let mut names: Vec<String> = vec![];
+        let rev_name = reverse::reverse_name(ip);
+        let parsed_query = packet::query(&rev_name, rr_type, rr_class, self.name_validation)?;
+        let (mut parsed_response, query_info) = self.query_checked(parsed_query, options)?;
+        let verify = options.verify_ptr.unwrap_or(self.ptr_verification);
+        let mut names: Vec<PtrName> = vec![];
 
         let mut it = parsed_response.into_iter_answer();
         while let Some(item) = it {
-            if item.rr_class() != rr_class.into() || item.rr_type() != rr_type.into() {
+            if item.rr_class() != u16::from(rr_class) || item.rr_type() != u16::from(rr_type) {
                 it = item.next();
                 continue;
             }
@@ -285,23 +1683,26 @@ impl DNSClient {
                     name.push(b'.');
                 }
                 if let Ok(name) = String::from_utf8(name) {
-                    match ip {
-                        IpAddr::V4(ip) => {
-                            if self.query_a(&name)?.contains(ip) {
-                                names.push(name)
+                    let verified = verify
+                        && match ip {
+                            IpAddr::V4(ip) => {
+                                no_records(self.query_a_with(&name, options))?.contains(ip)
                             }
-                        }
-                        IpAddr::V6(ip) => {
-                            if self.query_aaaa(&name)?.contains(ip) {
-                                names.push(name)
+                            IpAddr::V6(ip) => {
+                                no_records(self.query_aaaa_with(&name, options))?.contains(ip)
                             }
-                        }
-                    };
+                        };
+                    if verified || !verify {
+                        names.push(PtrName {
+                            name: self.output_name(name),
+                            verified,
+                        });
+                    }
                 }
             }
             it = item.next();
         }
-        Ok(names)
+        Ok((names, query_info))
     }
 
     /// Return the raw record data for the given query type.
@@ -311,18 +1712,58 @@ impl DNSClient {
         query_class: &str,
         query_type: &str,
     ) -> Result<Vec<Vec<u8>>, io::Error> {
+        self.query_rrs_data_with(name, query_class, query_type, &QueryOptions::default())
+    }
+
+    /// Return the raw record data for the given query type.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_rrs_data_with(
+        &self,
+        name: &str,
+        query_class: &str,
+        query_type: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<u8>>, io::Error> {
+        self.query_rrs_data_detailed_with(name, query_class, query_type, options)
+            .map(|(res, _)| res)
+    }
+
+    /// Return the raw record data for the given query type.
+    ///
+    /// Also return how the response was obtained.
+    pub fn query_rrs_data_detailed(
+        &self,
+        name: &str,
+        query_class: &str,
+        query_type: &str,
+    ) -> Result<(Vec<Vec<u8>>, QueryInfo), io::Error> {
+        self.query_rrs_data_detailed_with(name, query_class, query_type, &QueryOptions::default())
+    }
+
+    /// Return the raw record data for the given query type.
+    ///
+    /// Also return how the response was obtained.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_rrs_data_detailed_with(
+        &self,
+        name: &str,
+        query_class: &str,
+        query_type: &str,
+        options: &QueryOptions,
+    ) -> Result<(Vec<Vec<u8>>, QueryInfo), io::Error> {
         let rr_class = Class::from_string(query_class)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
         let rr_type = Type::from_string(query_type)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
-        let parsed_query = dnssector::gen::query(name.as_bytes(), rr_type, rr_class)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
-        let mut parsed_response = self.query_from_parsed_query(parsed_query)?;
+        let parsed_query = packet::query(name, rr_type, rr_class, self.name_validation)?;
+        let (mut parsed_response, query_info) = self.query_checked(parsed_query, options)?;
         let mut raw_rrs = vec![];
 
         let mut it = parsed_response.into_iter_answer();
         while let Some(item) = it {
-            if item.rr_class() != rr_class.into() || item.rr_type() != rr_type.into() {
+            if item.rr_class() != u16::from(rr_class) || item.rr_type() != u16::from(rr_type) {
                 it = item.next();
                 continue;
             }
@@ -331,38 +1772,1181 @@ impl DNSClient {
             }
             it = item.next();
         }
-        Ok(raw_rrs)
+        Ok((raw_rrs, query_info))
+    }
+
+    /// Query the `A` records of `name` with the `NSID` option (RFC 5001),
+    /// and return the identifier of the server that answered, `None` if it
+    /// didn't return any.
+    ///
+    /// This is useful to find out which instance of an anycast resolver
+    /// is being used.
+    pub fn query_nsid(&self, name: &str) -> Result<Option<String>, io::Error> {
+        let options = QueryOptions {
+            nsid: true,
+            ..Default::default()
+        };
+        self.query_rrs_data_detailed_with(name, "IN", "A", &options)
+            .map(|(_, query_info)| query_info.nsid_string())
+    }
+
+    /// Send a query for records of type `query_type` in the class
+    /// `query_class`, and return the complete response, with all its
+    /// sections.
+    pub fn query_parsed(
+        &self,
+        name: &str,
+        query_class: &str,
+        query_type: &str,
+    ) -> Result<DnsResponse, io::Error> {
+        self.query_parsed_with(name, query_class, query_type, &QueryOptions::default())
+    }
+
+    /// Send a query for records of type `query_type` in the class
+    /// `query_class`, and return the complete response, with all its
+    /// sections.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_parsed_with(
+        &self,
+        name: &str,
+        query_class: &str,
+        query_type: &str,
+        options: &QueryOptions,
+    ) -> Result<DnsResponse, io::Error> {
+        let rr_class = Class::from_string(query_class)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let rr_type = Type::from_string(query_type)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let parsed_query = packet::query(name, rr_type, rr_class, self.name_validation)?;
+        let (parsed_response, info) = self.query_from_parsed_query(parsed_query, options)?;
+        Ok(DnsResponse {
+            packet: parsed_response.into_packet(),
+            info,
+        })
+    }
+
+    /// Return the decoded answers to a query for records of type
+    /// `query_type` in the `IN` class, such as `CAA`.
+    ///
+    /// `CNAME` records leading to the queried name are included.
+    pub fn query_records(&self, name: &str, query_type: &str) -> Result<Vec<Record>, io::Error> {
+        self.query_records_with(name, query_type, &QueryOptions::default())
+    }
+
+    /// Return the decoded answers to a query for records of type
+    /// `query_type` in the `IN` class, such as `CAA`.
+    ///
+    /// `CNAME` records leading to the queried name are included.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_records_with(
+        &self,
+        name: &str,
+        query_type: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Record>, io::Error> {
+        let response = self.query_parsed_with(name, "IN", query_type, options)?;
+        DnsError::check_rcode(response.rcode())?;
+        record_data::parse_records(&response.packet)
+    }
+
+    /// Return the records of type `T` for `name`, in the `IN` class.
+    pub fn query<T: RecordData>(&self, name: &str) -> Result<Vec<T>, io::Error> {
+        self.query_with(name, &QueryOptions::default())
+    }
+
+    /// Return the records of type `T` for `name`, in the `IN` class.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_with<T: RecordData>(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<T>, io::Error> {
+        self.query_detailed_with(name, options).map(|(res, _)| res)
+    }
+
+    /// Return the records of type `T` for `name`, in the `IN` class.
+    ///
+    /// Also return how the response was obtained.
+    pub fn query_detailed<T: RecordData>(
+        &self,
+        name: &str,
+    ) -> Result<(Vec<T>, QueryInfo), io::Error> {
+        self.query_detailed_with(name, &QueryOptions::default())
+    }
+
+    /// Return the records of type `T` for `name`, in the `IN` class.
+    ///
+    /// Also return how the response was obtained.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_detailed_with<T: RecordData>(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<(Vec<T>, QueryInfo), io::Error> {
+        let parsed_query = packet::query_type(name, T::TYPE, self.name_validation)?;
+        let (parsed_response, query_info) = self.query_checked(parsed_query, options)?;
+        let records = record_data::parse_answers(&parsed_response.into_packet())?;
+        Ok((records, query_info))
+    }
+
+    /// Return the records of type `T` for `name`, in the `IN` class, along
+    /// with their TTLs.
+    pub fn query_answers<T: RecordData>(&self, name: &str) -> Result<Vec<Answer<T>>, io::Error> {
+        self.query_answers_with(name, &QueryOptions::default())
+    }
+
+    /// Return the records of type `T` for `name`, in the `IN` class, along
+    /// with their TTLs.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_answers_with<T: RecordData>(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Answer<T>>, io::Error> {
+        let parsed_query = packet::query_type(name, T::TYPE, self.name_validation)?;
+        let (parsed_response, _) = self.query_checked(parsed_query, options)?;
+        record_data::parse_answers_with_ttl(parsed_response.packet())
+    }
+
+    /// Return the mail exchangers for `name`, sorted by preference.
+    ///
+    /// `query_detailed::<Mx>()` returns them in the order of the response.
+    pub fn query_mx(&self, name: &str) -> Result<Vec<Mx>, io::Error> {
+        self.query_mx_with(name, &QueryOptions::default())
+    }
+
+    /// Return the mail exchangers for `name`, sorted by preference.
+    ///
+    /// `query_detailed::<Mx>()` returns them in the order of the response.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_mx_with(&self, name: &str, options: &QueryOptions) -> Result<Vec<Mx>, io::Error> {
+        let mut mxs = self.query_with::<Mx>(name, options)?;
+        mxs.sort_by_key(|mx| mx.preference);
+        Ok(mxs)
+    }
+
+    /// Return the service locations for `name`, such as
+    /// `_imap._tcp.example.com`, in the order they should be tried, as per
+    /// RFC 2782.
+    ///
+    /// `query_detailed::<Srv>()` returns them in the order of the response.
+    pub fn query_srv(&self, name: &str) -> Result<Vec<Srv>, io::Error> {
+        self.query_srv_with(name, &QueryOptions::default())
+    }
+
+    /// Return the service locations for `name`, such as
+    /// `_imap._tcp.example.com`, in the order they should be tried, as per
+    /// RFC 2782.
+    ///
+    /// `query_detailed::<Srv>()` returns them in the order of the response.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_srv_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Srv>, io::Error> {
+        let srvs = self.query_with::<Srv>(name, options)?;
+        Ok(self.rng.with(|rng| Srv::order_with_rng(srvs, rng)))
+    }
+
+    /// Return the target of the `CNAME` record of `name`, if it is an alias.
+    pub fn query_cname(&self, name: &str) -> Result<Option<String>, io::Error> {
+        self.query_cname_with(name, &QueryOptions::default())
+    }
+
+    /// Return the target of the `CNAME` record of `name`, if it is an alias.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_cname_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Option<String>, io::Error> {
+        let cnames = self.query_with::<Cname>(name, options)?;
+        Ok(cnames
+            .into_iter()
+            .next()
+            .map(|cname| self.output_name(cname.0)))
+    }
+
+    /// Return the `HTTPS` service bindings of `name`, sorted by priority,
+    /// aliases first.
+    pub fn query_https(&self, name: &str) -> Result<Vec<Svcb>, io::Error> {
+        self.query_https_with(name, &QueryOptions::default())
+    }
+
+    /// Return the `HTTPS` service bindings of `name`, sorted by priority,
+    /// aliases first.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_https_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Svcb>, io::Error> {
+        let mut svcbs: Vec<_> = self
+            .query_with::<Https>(name, options)?
+            .into_iter()
+            .map(|https| https.0)
+            .collect();
+        svcbs.sort_by_key(|svcb| svcb.priority);
+        Ok(svcbs)
+    }
+
+    /// Return the DANE certificate associations of a service, such as port
+    /// `443` over `tcp` on `host`, from the `TLSA` records of
+    /// `_443._tcp.host`.
+    pub fn query_tlsa(&self, port: u16, proto: &str, host: &str) -> Result<Vec<Tlsa>, io::Error> {
+        self.query_tlsa_with(port, proto, host, &QueryOptions::default())
+    }
+
+    /// Return the DANE certificate associations of a service, such as port
+    /// `443` over `tcp` on `host`, from the `TLSA` records of
+    /// `_443._tcp.host`.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_tlsa_with(
+        &self,
+        port: u16,
+        proto: &str,
+        host: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Tlsa>, io::Error> {
+        self.query_with::<Tlsa>(&format!("_{}._{}.{}", port, proto, host), options)
+    }
+
+    /// Return the naming authority pointers for `name`, sorted by order and
+    /// preference.
+    pub fn query_naptr(&self, name: &str) -> Result<Vec<Naptr>, io::Error> {
+        self.query_naptr_with(name, &QueryOptions::default())
+    }
+
+    /// Return the naming authority pointers for `name`, sorted by order and
+    /// preference.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_naptr_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<Naptr>, io::Error> {
+        let mut naptrs = self.query_with::<Naptr>(name, options)?;
+        naptrs.sort_by_key(|naptr| (naptr.order, naptr.preference));
+        Ok(naptrs)
+    }
+
+    /// Return the names of the name servers of the zone `name`.
+    pub fn query_ns(&self, name: &str) -> Result<Vec<String>, io::Error> {
+        self.query_ns_with(name, &QueryOptions::default())
+    }
+
+    /// Return the names of the name servers of the zone `name`.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_ns_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<String>, io::Error> {
+        let nss = self.query_with::<Ns>(name, options)?;
+        Ok(nss.into_iter().map(|ns| self.output_name(ns.0)).collect())
+    }
+
+    /// Return the `SOA` record of the zone `name`, `None` if `name` is not
+    /// the apex of a zone.
+    pub fn query_soa(&self, name: &str) -> Result<Option<Soa>, io::Error> {
+        self.query_soa_with(name, &QueryOptions::default())
+    }
+
+    /// Return the `SOA` record of the zone `name`, `None` if `name` is not
+    /// the apex of a zone.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_soa_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Option<Soa>, io::Error> {
+        let soas = self.query_with::<Soa>(name, options)?;
+        Ok(soas.into_iter().next())
+    }
+
+    /// Query several record types for `name`, one after the other, and
+    /// return all the answers, in presentation format.
+    ///
+    /// Types are names such as `MX`. `ANY` can be used with the servers that
+    /// still support it.
+    pub fn query_multi(
+        &self,
+        name: &str,
+        query_types: &[&str],
+    ) -> Result<Vec<AnswerRecord>, io::Error> {
+        self.query_multi_with(name, query_types, &QueryOptions::default())
+    }
+
+    /// Query several record types for `name`, one after the other, and
+    /// return all the answers, in presentation format.
+    ///
+    /// Types are names such as `MX`. `ANY` can be used with the servers that
+    /// still support it.
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_multi_with(
+        &self,
+        name: &str,
+        query_types: &[&str],
+        options: &QueryOptions,
+    ) -> Result<Vec<AnswerRecord>, io::Error> {
+        let mut records = vec![];
+        for query_type in query_types {
+            records.extend(self.query_answer_records(name, query_type, options)?);
+        }
+        Ok(records)
+    }
+
+    /// Send an `ANY` query for `name`, and return the answers, in
+    /// presentation format.
+    ///
+    /// Many servers only return a subset of the records, or a single `HINFO`
+    /// record (RFC 8482).
+    pub fn query_any(&self, name: &str) -> Result<Vec<AnswerRecord>, io::Error> {
+        self.query_any_with(name, &QueryOptions::default())
+    }
+
+    /// Send an `ANY` query for `name`, and return the answers, in
+    /// presentation format.
+    ///
+    /// Many servers only return a subset of the records, or a single `HINFO`
+    /// record (RFC 8482).
+    ///
+    /// `options` override the client settings for this query.
+    pub fn query_any_with(
+        &self,
+        name: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<AnswerRecord>, io::Error> {
+        self.query_multi_with(name, &["ANY"], options)
+    }
+
+    fn query_answer_records(
+        &self,
+        name: &str,
+        query_type: &str,
+        options: &QueryOptions,
+    ) -> Result<Vec<AnswerRecord>, io::Error> {
+        let rr_type = Type::from_string(query_type)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let rr_class = Class::from_string("IN").unwrap();
+        let parsed_query = packet::query(name, rr_type, rr_class, self.name_validation)?;
+        let (parsed_response, _) = self.query_checked(parsed_query, options)?;
+        let message = Message::parse(parsed_response.packet())?;
+        Ok(compare::answer_records(&message))
+    }
+
+    /// Return the instance names of a DNS-SD service, such as
+    /// `_http._tcp.local`, from its `PTR` records.
+    ///
+    /// Names under `.local` are resolved with multicast DNS if it is
+    /// enabled.
+    pub fn browse(&self, service: &str) -> Result<Vec<String>, io::Error> {
+        let parsed_query = packet::query_type(service, Ptr::TYPE, self.name_validation)?;
+        let (parsed_response, _) = self.query_checked(parsed_query, &QueryOptions::default())?;
+        dns_sd::instances(&parsed_response.into_packet())
+    }
+
+    /// Return the service types advertised in `domain`, such as
+    /// `_http._tcp.local` for `local`, from the `PTR` records of
+    /// `_services._dns-sd._udp.<domain>`.
+    pub fn browse_service_types(&self, domain: &str) -> Result<Vec<String>, io::Error> {
+        let name = dns_sd::service_types_name(domain);
+        let parsed_query = packet::query_type(&name, Ptr::TYPE, self.name_validation)?;
+        let (parsed_response, _) = self.query_checked(parsed_query, &QueryOptions::default())?;
+        dns_sd::service_types(&parsed_response.into_packet())
+    }
+
+    /// Resolve an instance of a DNS-SD service, as returned by `browse()`:
+    /// the host and port from its `SRV` record, and its attributes from its
+    /// `TXT` record.
+    pub fn resolve_instance(
+        &self,
+        instance: &str,
+        service: &str,
+    ) -> Result<ServiceInstance, io::Error> {
+        let srvs = self.query_instance::<Srv>(instance, service)?;
+        let txts = self.query_instance::<Txt>(instance, service)?;
+        ServiceInstance::new(instance, service, srvs, txts)
+    }
+
+    fn query_instance<T: RecordData>(
+        &self,
+        instance: &str,
+        service: &str,
+    ) -> Result<Vec<T>, io::Error> {
+        let parsed_query =
+            dns_sd::instance_query(instance, service, T::TYPE, self.name_validation)?;
+        let (parsed_response, _) = self.query_checked(parsed_query, &QueryOptions::default())?;
+        record_data::parse_answers(&parsed_response.into_packet())
+    }
+
+    /// Send a query to a single upstream server, bypassing static records,
+    /// the blocklist and interceptors.
+    fn query_upstream_server(
+        &self,
+        upstream_server: &UpstreamServer,
+        name: &str,
+        rr_type: Type,
+    ) -> Result<Vec<u8>, io::Error> {
+        let rr_class = Class::from_string("IN").unwrap();
+        let mut parsed_query = packet::query(name, rr_type, rr_class, self.name_validation)?;
+        let query_tid = parsed_query.tid();
+        let query_question = parsed_query.question();
+        let query = parsed_query.into_packet();
+        let context = QueryContext {
+            tid: query_tid,
+            question: &query_question,
+            options: &QueryOptions::default(),
+            deadline: Deadline::after(self.total_timeout),
+        };
+        self.send_query_to_upstream_server(
+            upstream_server,
+            &query,
+            &mut QueryInfo::default(),
+            context,
+        )
+        .map(|parsed_response| parsed_response.into_packet())
+    }
+
+    /// Send a battery of EDNS probes to `upstream_server`, querying the SOA
+    /// record of `zone`, and report which ones it handles correctly.
+    pub fn probe_edns_compliance(
+        &self,
+        upstream_server: &UpstreamServer,
+        zone: &str,
+    ) -> Result<EdnsComplianceReport, io::Error> {
+        let local_addr = match upstream_server.addr {
+            SocketAddr::V4(_) => &self.local_v4_addr,
+            SocketAddr::V6(_) => &self.local_v6_addr,
+        };
+        let mut probes = vec![];
+        for &probe in &EdnsProbe::ALL {
+            let (query_tid, query) = probe.query(zone, self.name_validation)?;
+            let outcome = match self.exchange(
+                local_addr,
+                upstream_server,
+                probe.protocol(),
+                &query,
+                self.timeout,
+            ) {
+                Ok(response) => probe.check(query_tid, &response),
+                Err(e) => EdnsProbeOutcome::Failed(e.to_string()),
+            };
+            probes.push((probe, outcome));
+        }
+        Ok(EdnsComplianceReport {
+            server: upstream_server.clone(),
+            probes,
+        })
+    }
+
+    /// Exercise every upstream server with the default configuration, and
+    /// report how it behaved.
+    ///
+    /// See `run_diagnostics_with()`.
+    pub fn run_diagnostics(&self) -> Result<DiagnosticsReport, io::Error> {
+        self.run_diagnostics_with(&DiagnosticsConfig::default())
+    }
+
+    /// Exercise every upstream server, and report how it behaved: whether it
+    /// responds over UDP and TCP and how fast, whether it validates DNSSEC,
+    /// whether it returns NXDOMAIN for names that don't exist, and how it
+    /// handles EDNS.
+    ///
+    /// Upstream servers are checked concurrently, using the same code paths
+    /// as regular queries, but bypassing static records, the blocklist and
+    /// interceptors.
+    pub fn run_diagnostics_with(
+        &self,
+        config: &DiagnosticsConfig,
+    ) -> Result<DiagnosticsReport, io::Error> {
+        let upstreams = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .upstream_servers
+                .iter()
+                .map(|upstream_server| {
+                    scope.spawn(move || self.diagnose_upstream_server(upstream_server, config))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<_, _>>()
+        })?;
+        Ok(DiagnosticsReport { upstreams })
+    }
+
+    fn diagnose_upstream_server(
+        &self,
+        upstream_server: &UpstreamServer,
+        config: &DiagnosticsConfig,
+    ) -> Result<UpstreamDiagnostics, io::Error> {
+        let local_addr = match upstream_server.addr {
+            SocketAddr::V4(_) => &self.local_v4_addr,
+            SocketAddr::V6(_) => &self.local_v6_addr,
+        };
+        let mut probes = vec![];
+        for &probe in &DiagnosticProbe::ALL {
+            let (query_tid, query) = self
+                .rng
+                .with(|rng| probe.query(config, rng, self.name_validation))?;
+            let start = Instant::now();
+            let outcome = match self.exchange(
+                local_addr,
+                upstream_server,
+                probe.protocol(),
+                &query,
+                self.timeout,
+            ) {
+                Ok(response) => probe.check(query_tid, &response),
+                Err(e) => DiagnosticOutcome::Failed(e.to_string()),
+            };
+            probes.push((probe, outcome, start.elapsed()));
+        }
+        let edns = self.probe_edns_compliance(upstream_server, &config.name)?;
+        Ok(UpstreamDiagnostics {
+            server: upstream_server.clone(),
+            probes,
+            edns,
+        })
+    }
+
+    /// Send a probe query to every upstream server that failed enough to be
+    /// marked down by the health policy, so that the ones that respond are
+    /// used again without waiting for the cooldown.
+    ///
+    /// Return the servers that responded. This does nothing without a health
+    /// policy.
+    pub fn check_health(&self) -> Vec<UpstreamServer> {
+        let health_policy = match &self.health_policy {
+            Some(health_policy) => health_policy,
+            None => return vec![],
+        };
+        let stats = self.events.stats();
+        self.upstream_servers
+            .iter()
+            .filter(|upstream_server| health_policy.has_failed(upstream_server, stats))
+            .filter(|upstream_server| {
+                self.query_upstream_server(upstream_server, ".", Type::NS)
+                    .is_ok()
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Send the same query to every upstream server individually, and compare
+    /// their responses.
+    ///
+    /// Static records, the blocklist and interceptors are bypassed.
+    pub fn compare_upstreams(
+        &self,
+        name: &str,
+        query_type: &str,
+    ) -> Result<UpstreamComparison, io::Error> {
+        let rr_type = Type::from_string(query_type)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let responses = self
+            .upstream_servers
+            .iter()
+            .map(|upstream_server| {
+                let response = self.query_upstream_server(upstream_server, name, rr_type);
+                (upstream_server.clone(), response)
+            })
+            .collect();
+        Ok(UpstreamComparison::new(responses))
+    }
+
+    /// Query `resolvers` concurrently, and report which ones return a record
+    /// of type `query_type` for `name` whose data is `expected`.
+    ///
+    /// `expected` is in presentation format, such as `192.0.2.1` or
+    /// `v=spf1 -all`. If `resolvers` is empty, `public_resolvers()` are
+    /// queried.
+    pub fn check_propagation(
+        &self,
+        resolvers: &[UpstreamServer],
+        name: &str,
+        query_type: &str,
+        expected: &str,
+    ) -> Result<PropagationReport, io::Error> {
+        let rr_type = Type::from_string(query_type)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let public_resolvers;
+        let resolvers = if resolvers.is_empty() {
+            public_resolvers = propagation::public_resolvers();
+            &public_resolvers[..]
+        } else {
+            resolvers
+        };
+        let responses = thread::scope(|scope| {
+            let handles: Vec<_> = resolvers
+                .iter()
+                .map(|resolver| {
+                    scope.spawn(move || {
+                        let response = self.query_upstream_server(resolver, name, rr_type);
+                        (resolver.clone(), response)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        Ok(PropagationReport::new(
+            u16::from(rr_type),
+            expected,
+            responses,
+        ))
+    }
+}
+
+impl<B: SyncBackend> Resolver for GenericDNSClient<B> {
+    fn query_raw(&self, query: &[u8], tid_masking: bool) -> Result<Vec<u8>, io::Error> {
+        GenericDNSClient::query_raw(self, query, tid_masking)
+    }
+
+    fn query_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, io::Error> {
+        GenericDNSClient::query_a(self, name)
+    }
+
+    fn query_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, io::Error> {
+        GenericDNSClient::query_aaaa(self, name)
+    }
+
+    fn query_addrs(&self, name: &str) -> Result<Vec<IpAddr>, io::Error> {
+        GenericDNSClient::query_addrs(self, name)
+    }
+
+    fn query_txt(&self, name: &str) -> Result<Vec<Vec<u8>>, io::Error> {
+        GenericDNSClient::query_txt(self, name)
+    }
+
+    fn query_ptr(&self, ip: &IpAddr) -> Result<Vec<String>, io::Error> {
+        GenericDNSClient::query_ptr(self, ip)
     }
 }
 
+#[cfg(feature = "testing")]
 #[test]
 fn test_query_a() {
-    use std::str::FromStr;
-
-    let upstream_servers = crate::system::default_resolvers().unwrap_or_else(|_| {
-        vec![
-            UpstreamServer::new(SocketAddr::from_str("1.0.0.1:53").unwrap()),
-            UpstreamServer::new(SocketAddr::from_str("1.1.1.1:53").unwrap()),
-        ]
-    });
-    let dns_client = DNSClient::new(upstream_servers);
+    let upstream_server = UpstreamServer::new(SocketAddr::from(([1, 1, 1, 1], 53)));
+    let dns_client =
+        GenericDNSClient::with_backend(vec![upstream_server], crate::testing::replay_backend());
     let r = dns_client.query_a("one.one.one.one").unwrap();
     assert!(r.contains(&Ipv4Addr::new(1, 1, 1, 1)));
 }
 
+#[cfg(feature = "testing")]
 #[test]
 fn test_query_ptr() {
-    use std::str::FromStr;
-
-    let upstream_servers = crate::system::default_resolvers().unwrap_or_else(|_| {
-        vec![
-            UpstreamServer::new(SocketAddr::from_str("1.0.0.1:53").unwrap()),
-            UpstreamServer::new(SocketAddr::from_str("1.1.1.1:53").unwrap()),
-        ]
-    });
-    let dns_client = DNSClient::new(upstream_servers);
+    let upstream_server = UpstreamServer::new(SocketAddr::from(([1, 1, 1, 1], 53)));
+    let dns_client =
+        GenericDNSClient::with_backend(vec![upstream_server], crate::testing::replay_backend());
     let r = dns_client
-        .query_ptr(&IpAddr::from_str("1.1.1.1").unwrap())
+        .query_ptr(&IpAddr::from(Ipv4Addr::new(1, 1, 1, 1)))
         .unwrap();
     assert_eq!(r[0], "one.one.one.one");
 }
+
+#[test]
+fn test_static_records() {
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    static_records.add_txt("Example.com.", b"static");
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    let r = dns_client.query_a("example.com").unwrap();
+    assert_eq!(r, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    let r = dns_client.query_txt("example.com").unwrap();
+    assert_eq!(r, vec![b"static".to_vec()]);
+    assert!(dns_client.query_aaaa("example.com").is_err());
+}
+
+#[test]
+fn test_blocklist() {
+    let mut blocklist = Blocklist::new();
+    blocklist.add_list("# comment\n0.0.0.0 ads.example.com tracker.example.com\n*.example.net\n");
+    assert!(blocklist.is_blocked("Ads.Example.com."));
+    assert!(blocklist.is_blocked("www.example.net"));
+    assert!(!blocklist.is_blocked("example.net"));
+    assert!(!blocklist.is_blocked("example.com"));
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_blocklist(blocklist);
+    let e = dns_client.query_a("tracker.example.com").unwrap_err();
+    assert!(matches!(DnsError::from(e), DnsError::NxDomain));
+}
+
+#[test]
+fn test_detect_captive_portal() {
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("dns.google", Ipv4Addr::new(10, 0, 0, 1));
+    static_records.add_aaaa("dns.google", "2001:4860:4860::8888".parse().unwrap());
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    assert_eq!(
+        dns_client.detect_captive_portal().unwrap(),
+        CaptivePortalVerdict::Hijacked(vec![IpAddr::from(Ipv4Addr::new(10, 0, 0, 1))])
+    );
+}
+
+#[test]
+fn test_address_family_preference() {
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("dual.test", Ipv4Addr::new(192, 0, 2, 1));
+    static_records.add_aaaa("dual.test", "2001:db8::1".parse().unwrap());
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    dns_client.set_address_family_preference(AddressFamilyPreference::PreferV6);
+    let r = dns_client.query_addrs("dual.test").unwrap();
+    assert_eq!(r.len(), 2);
+    assert!(r[0].is_ipv6());
+    dns_client.set_address_family_preference(AddressFamilyPreference::V4Only);
+    let r = dns_client.query_addrs("dual.test").unwrap();
+    assert_eq!(r, vec![IpAddr::from(Ipv4Addr::new(192, 0, 2, 1))]);
+}
+
+#[test]
+fn test_answer_order() {
+    let mut static_records = StaticRecords::new();
+    let ipv4_ips: Vec<_> = (0..16).map(|i| Ipv4Addr::new(192, 0, 2, i)).collect();
+    for ip in &ipv4_ips {
+        static_records.add_a("dual.test", *ip);
+    }
+    static_records.add_aaaa("dual.test", "2001:db8::1".parse().unwrap());
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    dns_client.set_answer_order(AnswerOrder::AsReceived);
+    assert_eq!(dns_client.query_a("dual.test").unwrap(), ipv4_ips);
+    dns_client.set_answer_order(AnswerOrder::Rfc6724);
+    let r = dns_client.query_addrs("dual.test").unwrap();
+    assert_eq!(r[0], "2001:db8::1".parse::<IpAddr>().unwrap());
+    assert_eq!(
+        r[1..],
+        ipv4_ips
+            .iter()
+            .map(|ip| IpAddr::from(*ip))
+            .collect::<Vec<_>>()[..]
+    );
+}
+
+#[test]
+fn test_name_validation() {
+    let mut static_records = StaticRecords::new();
+    static_records.add_txt("_dmarc.example.com", b"v=DMARC1; p=reject");
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    assert_eq!(dns_client.query_txt("_dmarc.example.com").unwrap().len(), 1);
+    assert_eq!(
+        dns_client.query_txt("example..com").unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+    dns_client.set_name_validation(NameValidation::Strict);
+    assert!(dns_client.query_txt("_dmarc.example.com").is_err());
+}
+
+#[test]
+fn test_fqdn() {
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    static_records
+        .add_rr_data(".", "NS", b"\x01a\x0croot-servers\x03net\x00".to_vec())
+        .unwrap();
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    assert_eq!(
+        dns_client.query_a("example.com.").unwrap(),
+        dns_client.query_a("example.com").unwrap()
+    );
+    assert_eq!(dns_client.query_rrs_data(".", "IN", "NS").unwrap().len(), 1);
+    assert!(dns_client.query_rrs_data("", "IN", "NS").is_err());
+}
+
+#[test]
+fn test_resolver_trait_object() {
+    let mut static_records = StaticRecords::new();
+    static_records.add_aaaa("example.com", "2001:db8::1".parse().unwrap());
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    let resolver: Box<dyn Resolver> = Box::new(dns_client);
+    let r = resolver.query_aaaa("example.com").unwrap();
+    assert_eq!(r, vec!["2001:db8::1".parse::<Ipv6Addr>().unwrap()]);
+}
+
+#[test]
+fn test_rng_seed() {
+    let mut static_records = StaticRecords::new();
+    for i in 0..16 {
+        static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, i));
+    }
+    let query_with_seed = |seed| {
+        let mut dns_client = DNSClient::new(vec![]);
+        dns_client.set_static_records(static_records.clone());
+        dns_client.set_rng_seed(seed);
+        dns_client.query_a("example.com").unwrap()
+    };
+    assert_eq!(query_with_seed(42), query_with_seed(42));
+    assert_ne!(query_with_seed(42), query_with_seed(43));
+}
+
+#[test]
+fn test_interceptors() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct DenyName(&'static [u8]);
+
+    impl Interceptor for DenyName {
+        fn on_query(&self, query: &mut ParsedPacket) -> Result<(), io::Error> {
+            match query.question() {
+                Some((name, ..)) if name == self.0 => Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "Denied by policy",
+                )),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    struct CountResponses(Arc<AtomicUsize>);
+
+    impl Interceptor for CountResponses {
+        fn on_response(&self, _response: &mut ParsedPacket) -> Result<(), io::Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    static_records.add_a("denied.example.com", Ipv4Addr::new(192, 0, 2, 2));
+    let responses = Arc::new(AtomicUsize::new(0));
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    dns_client.add_interceptor(DenyName(b"denied.example.com"));
+    dns_client.add_interceptor(CountResponses(responses.clone()));
+    assert!(dns_client.query_a("example.com").is_ok());
+    assert_eq!(
+        dns_client.query_a("denied.example.com").unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    assert_eq!(responses.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_audit_log() {
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    let buffer = SharedBuffer::default();
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    dns_client.set_audit_log(AuditLog::new(buffer.clone()));
+    dns_client.query_a("example.com").unwrap();
+    assert!(dns_client.query_aaaa("example.com").is_err());
+
+    let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<serde_json::Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["qname"], "example.com");
+    assert_eq!(lines[0]["qtype"], 1);
+    assert_eq!(lines[0]["rcode"], 0);
+    assert_eq!(lines[0]["server"], serde_json::Value::Null);
+    assert_eq!(lines[0]["cache_hit"], false);
+    assert_eq!(lines[1]["qtype"], 28);
+    assert!(lines[1]["error"].is_string());
+}
+
+#[test]
+fn test_resolve_socket_addrs() {
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    dns_client.set_address_family_preference(AddressFamilyPreference::V4Only);
+    assert_eq!(
+        dns_client.resolve_socket_addrs("example.com:443").unwrap(),
+        vec![SocketAddr::from(([192, 0, 2, 1], 443))]
+    );
+    assert_eq!(
+        dns_client.resolve_socket_addrs("[::1]:53").unwrap(),
+        vec![SocketAddr::from((Ipv6Addr::LOCALHOST, 53))]
+    );
+    assert!(dns_client.resolve_socket_addrs("example.com").is_err());
+}
+
+#[test]
+fn test_port_unreachable() {
+    let addr = std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut dns_client = DNSClient::new(vec![UpstreamServer::new(addr)]);
+    dns_client.set_timeout(Duration::from_secs(5));
+    let start = Instant::now();
+    let e = dns_client.query_a("example.com").unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+    assert!(e.to_string().contains(&addr.to_string()));
+}
+
+#[test]
+fn test_clone() {
+    let mut static_records = StaticRecords::new();
+    static_records.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1));
+    let mut dns_client = DNSClient::new(vec![]);
+    dns_client.set_static_records(static_records);
+    let mut clone = dns_client.clone();
+    assert!(Arc::ptr_eq(
+        &dns_client.static_records,
+        &clone.static_records
+    ));
+    assert!(Arc::ptr_eq(
+        &dns_client.upstream_servers,
+        &clone.upstream_servers
+    ));
+    clone.set_static_records(StaticRecords::new());
+    assert!(clone.query_a("example.com").is_err());
+    assert_eq!(
+        dns_client.query_a("example.com").unwrap(),
+        vec![Ipv4Addr::new(192, 0, 2, 1)]
+    );
+}
+
+#[test]
+fn test_retries() {
+    // A server that drops the first query, and responds to the second one
+    let responder = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = responder.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut query = vec![0; 512];
+        responder.recv_from(&mut query).unwrap();
+        let (len, client_addr) = responder.recv_from(&mut query).unwrap();
+        query.truncate(len);
+        let a: &[u8] = &[192, 0, 2, 1];
+        let response = packet::synthesize_response(&query, 0, &[(Type::A.into(), 60, a)]).unwrap();
+        responder
+            .send_to(&response.into_packet(), client_addr)
+            .unwrap();
+    });
+    let mut dns_client = DNSClient::new(vec![UpstreamServer::new(addr)]);
+    dns_client.set_timeout(Duration::from_millis(500));
+    dns_client.set_retries(1);
+    let ips = dns_client.query_a("example.com").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    server.join().unwrap();
+}
+
+#[test]
+fn test_total_timeout() {
+    // Servers that never respond
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = silent.local_addr().unwrap();
+    let mut dns_client = DNSClient::new(vec![UpstreamServer::new(addr), UpstreamServer::new(addr)]);
+    dns_client.set_timeout(Duration::from_secs(5));
+    dns_client.set_total_timeout(Some(Duration::from_millis(200)));
+    let start = Instant::now();
+    let e = dns_client.query_a("example.com").unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn test_total_timeout_override() {
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = silent.local_addr().unwrap();
+    let mut dns_client = DNSClient::new(vec![UpstreamServer::new(addr), UpstreamServer::new(addr)]);
+    dns_client.set_timeout(Duration::from_secs(5));
+    let options = QueryOptions {
+        total_timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let e = dns_client
+        .query_a_with("example.com", &options)
+        .unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_serve_stale() {
+    use crate::testing::{mock_client, MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    backend
+        .set_action(
+            "example.com",
+            "A",
+            MockAction::Respond {
+                rcode: 0,
+                answers: vec![(Type::A.into(), 1, vec![192, 0, 2, 1])],
+            },
+        )
+        .unwrap();
+    let mut dns_client = mock_client(&backend);
+    dns_client.enable_cache(10);
+    dns_client.set_serve_stale(Some(Duration::from_secs(3600)));
+    dns_client.query_a("example.com").unwrap();
+    thread::sleep(Duration::from_millis(1100));
+
+    backend
+        .set_action(
+            "example.com",
+            "A",
+            MockAction::Fail(io::ErrorKind::TimedOut),
+        )
+        .unwrap();
+    let (ips, info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    assert!(info.cache_hit && info.stale);
+    assert_eq!(info.min_ttl, Some(30));
+
+    // Stale responses are then served without trying to refresh them
+    backend.clear_queries();
+    let (_, info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert!(info.stale);
+    assert!(backend.queries().is_empty());
+
+    dns_client.set_serve_stale(None);
+    assert!(dns_client.query_a("example.com").is_err());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_cname_chain() {
+    use crate::record_data::{Cname, RecordData};
+    use crate::testing::{MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    let cname = |target: &[u8]| MockAction::Respond {
+        rcode: 0,
+        answers: vec![(Cname::TYPE, 3600, target.to_vec())],
+    };
+    backend
+        .set_action("www.example", "A", cname(b"\x04edge\x03cdn\x00"))
+        .unwrap();
+    backend
+        .set_action("www.example", "CNAME", cname(b"\x04edge\x03cdn\x00"))
+        .unwrap();
+    backend.add_a("edge.cdn", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    backend
+        .set_action("loop.example", "A", cname(b"\x04loop\x07example\x00"))
+        .unwrap();
+    let upstream_servers = vec![UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)))];
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    assert_eq!(
+        dns_client.query_cname("www.example").unwrap(),
+        Some("edge.cdn".to_string())
+    );
+    assert!(dns_client.query_a("www.example").unwrap().is_empty());
+    dns_client.set_cname_limit(Some(8));
+    assert_eq!(
+        dns_client.query_a("www.example").unwrap(),
+        vec![Ipv4Addr::new(192, 0, 2, 1)]
+    );
+    assert!(dns_client.query_a("loop.example").is_err());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_query_multi() {
+    use crate::testing::{MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    backend.add_aaaa("example.com", &[Ipv6Addr::LOCALHOST]);
+    let no_data = MockAction::Respond {
+        rcode: 0,
+        answers: vec![],
+    };
+    backend.set_action("example.com", "MX", no_data).unwrap();
+    let upstream_servers = vec![UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)))];
+    let dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    let records = dns_client
+        .query_multi("example.com", &["A", "AAAA", "MX"])
+        .unwrap();
+    let data: Vec<_> = records.iter().map(|record| record.data.as_str()).collect();
+    assert_eq!(data, vec!["192.0.2.1", "::1"]);
+    assert!(dns_client.query_multi("example.com", &["BOGUS"]).is_err());
+
+    let response = dns_client
+        .query_parsed("example.com", "IN", "AAAA")
+        .unwrap();
+    assert_eq!(response.rcode(), 0);
+    assert_eq!(response.answers().unwrap()[0].data_to_string(), "::1");
+    assert_eq!(
+        dns_client.query_records("example.com", "A").unwrap(),
+        vec![crate::record_data::Record::A(Ipv4Addr::new(192, 0, 2, 1))]
+    );
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_query_raw_many() {
+    use crate::testing::{MockBackend, MockDnsServer};
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    backend.add_aaaa("example.com", &[Ipv6Addr::LOCALHOST]);
+    let server = MockDnsServer::start(backend).unwrap();
+    let dns_client = crate::sync::DNSClient::new(vec![server.upstream_server()]);
+    let queries: Vec<_> = [Type::A, Type::AAAA, Type::A]
+        .iter()
+        .map(|&rr_type| {
+            let mut parsed_query =
+                packet::query("example.com", rr_type, Class::IN, Default::default()).unwrap();
+            // Queries can share a transaction ID
+            parsed_query.set_tid(0x1234);
+            parsed_query.into_packet()
+        })
+        .collect();
+    let responses = dns_client.query_raw_many(&queries).unwrap();
+    assert_eq!(responses.len(), 3);
+    for (query, response) in queries.iter().zip(&responses) {
+        let mut parsed_response = DNSSector::new(response.clone()).unwrap().parse().unwrap();
+        assert_eq!(parsed_response.tid(), 0x1234);
+        assert_eq!(
+            parsed_response.question(),
+            DNSSector::new(query.clone())
+                .unwrap()
+                .parse()
+                .unwrap()
+                .question()
+        );
+        assert_eq!(DNSSector::ancount(response), 1);
+    }
+    assert!(dns_client.query_raw_many(&[]).unwrap().is_empty());
+}
@@ -1,46 +1,555 @@
+use std::borrow::Cow;
 use std::fs;
 use std::io;
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-use crate::UpstreamServer;
+use crate::query_options::QueryOptions;
+use crate::time::Instant;
+use crate::{DNSClientBuilder, DomainRoutes, SearchList, UpstreamServer, UpstreamStrategy};
 
-/// Return the set of default (system) resolvers, by parsing /etc/resolv.conf
-#[cfg(unix)]
-pub fn default_resolvers() -> Result<Vec<UpstreamServer>, io::Error> {
-    let data = fs::read_to_string("/etc/resolv.conf")?;
-    let mut upstream_servers = vec![];
-    for line in data.lines() {
-        let line = line.trim();
-        if !line.starts_with("nameserver") {
-            continue;
+/// The configuration of the system resolver, from /etc/resolv.conf
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolvConf {
+    /// The `nameserver` entries. Scoped IPv6 addresses such as
+    /// `fe80::1%eth0` are supported.
+    pub nameservers: Vec<UpstreamServer>,
+    /// The `search` or `domain` entries, and the `ndots` option.
+    pub search_list: SearchList,
+    /// The `sortlist` entries, as addresses and optional netmasks.
+    pub sortlist: Vec<(IpAddr, Option<IpAddr>)>,
+    /// The `timeout` option, bounding every exchange.
+    pub timeout: Option<Duration>,
+    /// The `attempts` option, the number of times every server is tried.
+    pub attempts: Option<u32>,
+    /// The `rotate` option, spreading queries over the servers.
+    pub rotate: bool,
+    /// The `use-vc` option, sending queries over TCP.
+    pub use_vc: bool,
+    /// The `edns0` option, adding an EDNS section to queries.
+    pub edns0: bool,
+    /// Per-domain resolvers. On macOS, they are read from the system
+    /// configuration, as VPNs don't register them in /etc/resolv.conf.
+    pub domain_routes: DomainRoutes,
+}
+
+impl ResolvConf {
+    /// A client configuration honoring the options: the `timeout`, the
+    /// number of `attempts`, `rotate`, `use-vc`, `edns0`, the search list and
+    /// per-domain resolvers.
+    pub fn to_builder(&self) -> DNSClientBuilder {
+        let mut builder = DNSClientBuilder::new(self.nameservers.clone())
+            .search_list(self.search_list.clone())
+            .domain_routes(self.domain_routes.clone());
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
         }
-        let mut it = line.split_whitespace();
-        if it.next().is_none() {
-            continue;
+        if let Some(attempts) = self.attempts {
+            builder = builder.retries(attempts.saturating_sub(1));
         }
-        if let Some(addr) = it.next() {
-            let ip = match addr.parse::<IpAddr>() {
-                Ok(ip) => ip,
-                _ => continue,
-            };
-            let addr = SocketAddr::new(ip, 53);
-            let upstream_server = UpstreamServer::new(addr);
-            upstream_servers.push(upstream_server);
+        if self.rotate {
+            builder = builder.strategy(UpstreamStrategy::RoundRobin);
         }
+        if self.use_vc {
+            builder = builder.force_tcp(true);
+        }
+        if self.edns0 {
+            builder = builder.edns_bufsize(1232);
+        }
+        builder
     }
-    if upstream_servers.is_empty() {
+}
+
+/// Return the configuration of the system resolver, by parsing
+/// /etc/resolv.conf
+#[cfg(unix)]
+pub fn resolv_conf() -> Result<ResolvConf, io::Error> {
+    let data = fs::read_to_string("/etc/resolv.conf")?;
+    #[allow(unused_mut)]
+    let mut resolv_conf = parse_resolv_conf(&data);
+    #[cfg(target_os = "macos")]
+    if let Ok(domain_routes) = domain_routes() {
+        resolv_conf.domain_routes = domain_routes;
+    }
+    if resolv_conf.nameservers.is_empty() {
         return Err(io::Error::new(
             io::ErrorKind::NotFound,
             "No upstream servers found",
         ));
     }
-    Ok(upstream_servers)
+    Ok(resolv_conf)
 }
 
 #[cfg(not(unix))]
-pub fn default_resolvers() -> Result<Vec<UpstreamServer>, io::Error> {
+pub fn resolv_conf() -> Result<ResolvConf, io::Error> {
     Err(io::Error::new(
         io::ErrorKind::NotFound,
         "System resolvers are not supported by the software on this platform",
     ))
 }
+
+/// Return the set of default (system) resolvers, by parsing /etc/resolv.conf
+pub fn default_resolvers() -> Result<Vec<UpstreamServer>, io::Error> {
+    resolv_conf().map(|resolv_conf| resolv_conf.nameservers)
+}
+
+/// Return the search list, by parsing the `domain`, `search` and `ndots`
+/// options of /etc/resolv.conf
+pub fn search_list() -> Result<SearchList, io::Error> {
+    resolv_conf().map(|resolv_conf| resolv_conf.search_list)
+}
+
+/// Return the per-domain resolvers of the system configuration, such as
+/// the ones registered by VPNs, from the output of `scutil --dns`
+///
+/// If the configuration can't be read, or if resolvers can't be parsed,
+/// they are ignored, and fewer or no routes are returned.
+#[cfg(target_os = "macos")]
+pub fn domain_routes() -> Result<DomainRoutes, io::Error> {
+    let output = match std::process::Command::new("/usr/sbin/scutil")
+        .arg("--dns")
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Ok(DomainRoutes::new()),
+    };
+    Ok(parse_scutil_dns(&String::from_utf8_lossy(&output.stdout)))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn domain_routes() -> Result<DomainRoutes, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "Per-domain resolvers are not supported by the software on this platform",
+    ))
+}
+
+/// Parse the resolvers of the `scutil --dns` output that have a `domain`,
+/// skipping multicast DNS and the scoped queries section. Lines that can't
+/// be parsed are ignored, and so are resolvers left without a valid domain
+/// or any valid nameserver.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_scutil_dns(output: &str) -> DomainRoutes {
+    let mut domain_routes = DomainRoutes::new();
+    let mut add = |domain: &mut Option<String>, nameservers: &mut Vec<UpstreamServer>| {
+        if let Some(domain) = domain.take() {
+            if !domain.is_empty()
+                && !domain.contains(char::is_whitespace)
+                && !nameservers.is_empty()
+            {
+                domain_routes.add(&domain, std::mem::take(nameservers));
+            }
+        }
+        nameservers.clear();
+    };
+    let (mut domain, mut nameservers) = (None, vec![]);
+    for line in output.lines() {
+        let line = line.trim();
+        if line.starts_with("DNS configuration (") {
+            break;
+        }
+        if line.starts_with("resolver #") {
+            add(&mut domain, &mut nameservers);
+            continue;
+        }
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        match key {
+            "domain" => domain = Some(value.to_string()),
+            "options" if value.split_whitespace().any(|option| option == "mdns") => {
+                domain = None;
+                nameservers.clear();
+            }
+            key if key.starts_with("nameserver[") => {
+                if let Some(addr) = parse_nameserver(value) {
+                    nameservers.push(UpstreamServer::new(addr));
+                }
+            }
+            _ => {}
+        }
+    }
+    add(&mut domain, &mut nameservers);
+    domain_routes
+}
+
+/// Reloads the nameservers of /etc/resolv.conf when the file changes, so that
+/// long-running clients follow network changes.
+#[derive(Debug)]
+pub(crate) struct ResolvConfWatcher {
+    path: PathBuf,
+    interval: Duration,
+    state: Mutex<WatcherState>,
+}
+
+#[derive(Debug, Default)]
+struct WatcherState {
+    checked_at: Option<Instant>,
+    fingerprint: Option<(SystemTime, u64)>,
+    nameservers: Option<Arc<Vec<UpstreamServer>>>,
+}
+
+impl ResolvConfWatcher {
+    /// Check the file for changes at most every `interval`.
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self::with_path("/etc/resolv.conf", interval)
+    }
+
+    fn with_path<P: Into<PathBuf>>(path: P, interval: Duration) -> Self {
+        ResolvConfWatcher {
+            path: path.into(),
+            interval,
+            state: Mutex::default(),
+        }
+    }
+
+    /// The current nameservers, `None` if the file couldn't be read yet, or
+    /// didn't list any.
+    pub(crate) fn nameservers(&self) -> Option<Arc<Vec<UpstreamServer>>> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if state
+            .checked_at
+            .is_some_and(|checked_at| now.duration_since(checked_at) < self.interval)
+        {
+            return state.nameservers.clone();
+        }
+        state.checked_at = Some(now);
+        let fingerprint = fs::metadata(&self.path)
+            .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+            .ok();
+        if fingerprint.is_some() && fingerprint != state.fingerprint {
+            if let Ok(data) = fs::read_to_string(&self.path) {
+                state.fingerprint = fingerprint;
+                let nameservers = parse_resolv_conf(&data).nameservers;
+                if !nameservers.is_empty() {
+                    state.nameservers = Some(Arc::new(nameservers));
+                }
+            }
+        }
+        state.nameservers.clone()
+    }
+
+    /// Send a query to the current nameservers, unless `options` already
+    /// set upstream servers.
+    pub(crate) fn apply<'t>(&self, options: &'t QueryOptions) -> Cow<'t, QueryOptions> {
+        if options.upstream_servers.is_some() {
+            return Cow::Borrowed(options);
+        }
+        match self.nameservers() {
+            Some(nameservers) => Cow::Owned(QueryOptions {
+                upstream_servers: Some(nameservers.to_vec()),
+                ..options.clone()
+            }),
+            None => Cow::Borrowed(options),
+        }
+    }
+}
+
+fn parse_resolv_conf(data: &str) -> ResolvConf {
+    let mut resolv_conf = ResolvConf::default();
+    for line in data.lines() {
+        let mut it = line.split_whitespace();
+        match it.next() {
+            Some("nameserver") => {
+                if let Some(addr) = it.next().and_then(parse_nameserver) {
+                    resolv_conf.nameservers.push(UpstreamServer::new(addr));
+                }
+            }
+            // The last `domain` or `search` line takes precedence
+            Some("domain") => {
+                resolv_conf.search_list.domains =
+                    it.next().map(str::to_string).into_iter().collect()
+            }
+            Some("search") => resolv_conf.search_list.domains = it.map(str::to_string).collect(),
+            Some("sortlist") => {
+                resolv_conf.sortlist = it
+                    .filter_map(|entry| {
+                        let mut parts = entry.splitn(2, '/');
+                        let addr = parts.next()?.parse().ok()?;
+                        let netmask = match parts.next() {
+                            Some(netmask) => Some(netmask.parse().ok()?),
+                            None => None,
+                        };
+                        Some((addr, netmask))
+                    })
+                    .collect()
+            }
+            Some("options") => {
+                for option in it {
+                    let (name, value) = match option.split_once(':') {
+                        Some((name, value)) => (name, value.parse::<u32>().ok()),
+                        None => (option, None),
+                    };
+                    match (name, value) {
+                        ("ndots", Some(ndots)) => {
+                            resolv_conf.search_list.ndots = ndots.min(15) as usize
+                        }
+                        ("timeout", Some(timeout)) => {
+                            resolv_conf.timeout =
+                                Some(Duration::from_secs(u64::from(timeout.clamp(1, 30))))
+                        }
+                        ("attempts", Some(attempts)) => {
+                            resolv_conf.attempts = Some(attempts.clamp(1, 5))
+                        }
+                        ("rotate", _) => resolv_conf.rotate = true,
+                        ("use-vc", _) | ("usevc", _) => resolv_conf.use_vc = true,
+                        ("edns0", _) => resolv_conf.edns0 = true,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    resolv_conf
+}
+
+/// Parse a nameserver address, possibly an IPv6 address with a scope, given
+/// as an interface name or index.
+fn parse_nameserver(addr: &str) -> Option<SocketAddr> {
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, 53));
+    }
+    let (ip, scope) = addr.split_once('%')?;
+    let ip = ip.parse::<Ipv6Addr>().ok()?;
+    let scope_id = match scope.parse::<u32>() {
+        Ok(scope_id) => scope_id,
+        Err(_) => interface_index(scope)?,
+    };
+    Some(SocketAddr::V6(SocketAddrV6::new(ip, 53, 0, scope_id)))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        index => Some(index),
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+/// A local network interface, along with its usable unicast addresses
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LocalInterface {
+    pub name: String,
+    pub is_loopback: bool,
+    pub addrs: Vec<IpAddr>,
+}
+
+/// Return the interfaces that are up, and their unicast addresses.
+///
+/// Unspecified, multicast and IPv6 link-local addresses are skipped, so that
+/// every returned address can be used as-is with `set_local_v4_addr()` and
+/// `set_local_v6_addr()`.
+#[cfg(unix)]
+pub fn local_addresses() -> Result<Vec<LocalInterface>, io::Error> {
+    use std::ffi::CStr;
+    use std::net::Ipv4Addr;
+
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut interfaces: Vec<LocalInterface> = vec![];
+    let mut ifa = ifap;
+    while !ifa.is_null() {
+        let entry = unsafe { &*ifa };
+        ifa = entry.ifa_next;
+        let flags = entry.ifa_flags as libc::c_int;
+        if entry.ifa_addr.is_null() || entry.ifa_name.is_null() || flags & libc::IFF_UP == 0 {
+            continue;
+        }
+        let ip = match unsafe { (*entry.ifa_addr).sa_family } as libc::c_int {
+            libc::AF_INET => {
+                let sin = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+        let usable = match ip {
+            IpAddr::V4(ip) => !(ip.is_unspecified() || ip.is_multicast() || ip.is_broadcast()),
+            IpAddr::V6(ip) => {
+                !(ip.is_unspecified() || ip.is_multicast() || ip.segments()[0] & 0xffc0 == 0xfe80)
+            }
+        };
+        if !usable {
+            continue;
+        }
+        let name = unsafe { CStr::from_ptr(entry.ifa_name) }
+            .to_string_lossy()
+            .into_owned();
+        match interfaces
+            .iter_mut()
+            .find(|interface| interface.name == name)
+        {
+            Some(interface) => interface.addrs.push(ip),
+            None => interfaces.push(LocalInterface {
+                name,
+                is_loopback: flags & libc::IFF_LOOPBACK != 0,
+                addrs: vec![ip],
+            }),
+        }
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+    Ok(interfaces)
+}
+
+#[cfg(not(unix))]
+pub fn local_addresses() -> Result<Vec<LocalInterface>, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "Enumerating local addresses is not supported by the software on this platform",
+    ))
+}
+
+#[cfg(unix)]
+#[test]
+fn test_parse_resolv_conf() {
+    let resolv_conf = parse_resolv_conf(
+        "# comment\n\
+         nameserver 192.0.2.53\n\
+         nameserver fe80::1%1\n\
+         nameserver invalid\n\
+         domain example.com\n\
+         search a.example b.example\n\
+         sortlist 192.0.2.0/255.255.255.0 198.51.100.1\n\
+         options ndots:2 timeout:3 attempts:9 rotate use-vc edns0\n",
+    );
+    assert_eq!(
+        resolv_conf.nameservers,
+        vec![
+            UpstreamServer::new(([192, 0, 2, 53], 53)),
+            UpstreamServer::new(SocketAddr::V6(SocketAddrV6::new(
+                "fe80::1".parse().unwrap(),
+                53,
+                0,
+                1
+            ))),
+        ]
+    );
+    assert_eq!(
+        resolv_conf.search_list.domains,
+        vec!["a.example", "b.example"]
+    );
+    assert_eq!(resolv_conf.search_list.ndots, 2);
+    assert_eq!(resolv_conf.sortlist.len(), 2);
+    assert_eq!(resolv_conf.timeout, Some(Duration::from_secs(3)));
+    assert_eq!(resolv_conf.attempts, Some(5));
+    assert!(resolv_conf.rotate && resolv_conf.use_vc && resolv_conf.edns0);
+    let _: crate::sync::DNSClient = resolv_conf.to_builder().build();
+}
+
+#[test]
+fn test_parse_scutil_dns() {
+    // Output of `scutil --dns` with a VPN adding a resolver for a domain
+    let domain_routes = parse_scutil_dns(
+        "DNS configuration\n\
+         \n\
+         resolver #1\n\
+         \x20 search domain[0] : example.com\n\
+         \x20 nameserver[0] : 192.168.1.1\n\
+         \x20 if_index : 15 (en0)\n\
+         \x20 flags    : Request A records\n\
+         \x20 reach    : 0x00020002 (Reachable,Directly Reachable Address)\n\
+         \n\
+         resolver #2\n\
+         \x20 domain   : corp.example\n\
+         \x20 nameserver[0] : 10.0.0.53\n\
+         \x20 nameserver[1] : fe80::1%1\n\
+         \x20 flags    : Supplemental, Request A records\n\
+         \x20 reach    : 0x00000003 (Reachable,Transient Connection)\n\
+         \x20 order    : 102400\n\
+         \n\
+         resolver #3\n\
+         \x20 domain   : local\n\
+         \x20 options  : mdns\n\
+         \x20 timeout  : 5\n\
+         \x20 flags    : Request A records\n\
+         \x20 reach    : 0x00000000 (Not Reachable)\n\
+         \x20 order    : 300000\n\
+         \n\
+         DNS configuration (for scoped queries)\n\
+         \n\
+         resolver #1\n\
+         \x20 domain   : scoped.example\n\
+         \x20 nameserver[0] : 10.1.0.53\n\
+         \x20 if_index : 15 (en0)\n\
+         \x20 flags    : Scoped, Request A records\n",
+    );
+    let corp = domain_routes
+        .upstream_servers_for("intranet.corp.example")
+        .unwrap();
+    assert_eq!(corp.len(), 2);
+    assert_eq!(corp[0], UpstreamServer::new(([10, 0, 0, 53], 53)));
+    assert!(domain_routes
+        .upstream_servers_for("printer.local")
+        .is_none());
+    assert!(domain_routes
+        .upstream_servers_for("scoped.example")
+        .is_none());
+    assert!(domain_routes.upstream_servers_for("example.com").is_none());
+
+    // Unparsable output doesn't return any servers
+    assert!(parse_scutil_dns("").is_empty());
+    assert!(parse_scutil_dns("\u{fffd}\u{fffd}\nresolver\n:::\n").is_empty());
+    let domain_routes = parse_scutil_dns(
+        "resolver #1\n\
+         \x20 domain   : bad.example\n\
+         \x20 nameserver[0] : 10.0.0.999\n\
+         resolver #2\n\
+         \x20 domain   : not a domain\n\
+         \x20 nameserver[0] : 10.0.0.53\n\
+         resolver #3\n\
+         \x20 domain   :\n\
+         \x20 nameserver[0] : 10.0.0.53\n",
+    );
+    assert!(domain_routes.is_empty());
+}
+
+#[test]
+fn test_resolv_conf_watcher() {
+    let path = std::env::temp_dir().join(format!("dnsclient-resolv-{}.conf", std::process::id()));
+    let watcher = ResolvConfWatcher::with_path(&path, Duration::default());
+    assert!(watcher.nameservers().is_none());
+    fs::write(&path, "nameserver 192.0.2.53\n").unwrap();
+    assert_eq!(
+        *watcher.nameservers().unwrap(),
+        vec![UpstreamServer::new(([192, 0, 2, 53], 53))]
+    );
+    fs::write(&path, "nameserver 198.51.100.53\nnameserver 203.0.113.53\n").unwrap();
+    let options = QueryOptions::default();
+    assert_eq!(
+        watcher.apply(&options).upstream_servers,
+        Some(vec![
+            UpstreamServer::new(([198, 51, 100, 53], 53)),
+            UpstreamServer::new(([203, 0, 113, 53], 53)),
+        ])
+    );
+    fs::write(&path, "# no nameservers\n").unwrap();
+    assert_eq!(watcher.nameservers().unwrap().len(), 2);
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_local_addresses() {
+    let interfaces = local_addresses().unwrap();
+    assert!(interfaces
+        .iter()
+        .filter(|interface| interface.is_loopback)
+        .any(|interface| interface.addrs.iter().any(|ip| ip.is_loopback())));
+}
@@ -0,0 +1,105 @@
+use std::io;
+
+use dnssector::ParsedPacket;
+
+use crate::events::{ClientEvent, Protocol};
+use crate::query_info::QueryInfo;
+
+fn protocol_label(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Udp => "udp",
+        Protocol::Tcp => "tcp",
+        Protocol::Tls => "tls",
+        Protocol::Https => "https",
+        Protocol::Quic => "quic",
+        Protocol::ObliviousHttps => "odoh",
+    }
+}
+
+/// Update the counters and histograms tracking upstream servers.
+pub(crate) fn record_event(event: &ClientEvent) {
+    match event {
+        ClientEvent::QuerySent { server, protocol } => metrics::counter!(
+            "dnsclient_upstream_queries_total",
+            "upstream" => server.to_string(),
+            "protocol" => protocol_label(*protocol)
+        )
+        .increment(1),
+        ClientEvent::Timeout { server, protocol } => metrics::counter!(
+            "dnsclient_upstream_timeouts_total",
+            "upstream" => server.to_string(),
+            "protocol" => protocol_label(*protocol)
+        )
+        .increment(1),
+        ClientEvent::QueryCancelled { server, protocol } => metrics::counter!(
+            "dnsclient_upstream_cancellations_total",
+            "upstream" => server.to_string(),
+            "protocol" => protocol_label(*protocol)
+        )
+        .increment(1),
+        ClientEvent::QueryFailed {
+            server, protocol, ..
+        } => metrics::counter!(
+            "dnsclient_upstream_errors_total",
+            "upstream" => server.to_string(),
+            "protocol" => protocol_label(*protocol)
+        )
+        .increment(1),
+        ClientEvent::TruncatedRetry { server } => metrics::counter!(
+            "dnsclient_upstream_truncations_total",
+            "upstream" => server.to_string()
+        )
+        .increment(1),
+        ClientEvent::Failover { from, .. } => metrics::counter!(
+            "dnsclient_upstream_failovers_total",
+            "upstream" => from.to_string()
+        )
+        .increment(1),
+        ClientEvent::Retry { .. } => metrics::counter!("dnsclient_retries_total").increment(1),
+        ClientEvent::InvalidResponse { server, .. } => metrics::counter!(
+            "dnsclient_upstream_invalid_responses_total",
+            "upstream" => server.to_string()
+        )
+        .increment(1),
+        ClientEvent::ResponseReceived {
+            server,
+            protocol,
+            rtt,
+        } => metrics::histogram!(
+            "dnsclient_upstream_rtt_seconds",
+            "upstream" => server.to_string(),
+            "protocol" => protocol_label(*protocol)
+        )
+        .record(rtt.as_secs_f64()),
+    }
+}
+
+/// Count a completed query by type and response code, and record its
+/// latency.
+pub(crate) fn record_query(
+    question: &Option<(Vec<u8>, u16, u16)>,
+    res: &Result<(ParsedPacket, QueryInfo), io::Error>,
+) {
+    let qtype = match question {
+        Some((_, rr_type, _)) => rr_type.to_string(),
+        None => "none".to_string(),
+    };
+    match res {
+        Ok((parsed_response, query_info)) => {
+            metrics::counter!(
+                "dnsclient_queries_total",
+                "qtype" => qtype,
+                "rcode" => parsed_response.rcode().to_string()
+            )
+            .increment(1);
+            metrics::histogram!("dnsclient_query_duration_seconds")
+                .record(query_info.rtt.as_secs_f64());
+        }
+        Err(_) => metrics::counter!(
+            "dnsclient_queries_total",
+            "qtype" => qtype,
+            "rcode" => "error"
+        )
+        .increment(1),
+    }
+}
@@ -0,0 +1,1293 @@
+//! Helpers to test code using a client, without network access.
+
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::Path;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use dnssector::constants::*;
+use dnssector::*;
+
+use crate::backend::{AsyncBackend, SyncBackend};
+use crate::packet;
+use crate::upstream_server::UpstreamServer;
+
+/// What a `MockBackend` does when it receives a query.
+#[derive(Clone, Debug)]
+pub enum MockAction {
+    /// Respond with an rcode and answers, given as `(type, ttl, rdata)`.
+    Respond {
+        rcode: u8,
+        answers: Vec<(u16, u32, Vec<u8>)>,
+    },
+    /// Respond with a raw packet, whose transaction ID is replaced with the
+    /// one of the query.
+    Raw(Vec<u8>),
+    /// Respond with the truncation bit set over UDP, and perform the inner
+    /// action over TCP.
+    Truncate(Box<MockAction>),
+    /// Fail with an I/O error of that kind.
+    Fail(io::ErrorKind),
+}
+
+type Rules = HashMap<(Vec<u8>, u16), MockRule>;
+
+#[derive(Clone, Debug)]
+struct MockRule {
+    action: MockAction,
+    delay: Duration,
+}
+
+/// A backend answering queries from canned responses, for both the
+/// synchronous and the async clients.
+///
+/// Queries for which no action was registered get an `NXDOMAIN` response.
+///
+/// Delays are virtual: a query whose delay is at least the client timeout
+/// fails with `io::ErrorKind::TimedOut`, and is answered immediately
+/// otherwise. This keeps tests deterministic and fast.
+///
+/// Clones share the same rules and record the same queries, so a backend
+/// can still be configured and inspected after a clone has been given to a
+/// client.
+#[derive(Clone, Debug, Default)]
+pub struct MockBackend {
+    rules: Arc<Mutex<Rules>>,
+    queries: Arc<Mutex<Vec<(String, u16)>>>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the action performed for queries of type `query_type` for `name`.
+    pub fn set_action(
+        &self,
+        name: &str,
+        query_type: &str,
+        action: MockAction,
+    ) -> Result<(), io::Error> {
+        let key = Self::key(name, query_type)?;
+        let mut rules = self.rules.lock().unwrap();
+        match rules.get_mut(&key) {
+            Some(rule) => rule.action = action,
+            None => {
+                rules.insert(
+                    key,
+                    MockRule {
+                        action,
+                        delay: Duration::default(),
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the delay before queries of type `query_type` for `name` are
+    /// answered.
+    pub fn set_delay(
+        &self,
+        name: &str,
+        query_type: &str,
+        delay: Duration,
+    ) -> Result<(), io::Error> {
+        let key = Self::key(name, query_type)?;
+        let mut rules = self.rules.lock().unwrap();
+        rules
+            .entry(key)
+            .or_insert_with(|| MockRule {
+                action: MockAction::Respond {
+                    rcode: Rcode::NXDOMAIN.into(),
+                    answers: vec![],
+                },
+                delay,
+            })
+            .delay = delay;
+        Ok(())
+    }
+
+    /// Respond to `A` queries for `name` with `ips`.
+    pub fn add_a(&self, name: &str, ips: &[Ipv4Addr]) {
+        let answers = ips
+            .iter()
+            .map(|ip| (Type::A.into(), 3600, ip.octets().to_vec()))
+            .collect();
+        let action = MockAction::Respond { rcode: 0, answers };
+        self.set_action(name, "A", action).unwrap();
+    }
+
+    /// Respond to `AAAA` queries for `name` with `ips`.
+    pub fn add_aaaa(&self, name: &str, ips: &[Ipv6Addr]) {
+        let answers = ips
+            .iter()
+            .map(|ip| (Type::AAAA.into(), 3600, ip.octets().to_vec()))
+            .collect();
+        let action = MockAction::Respond { rcode: 0, answers };
+        self.set_action(name, "AAAA", action).unwrap();
+    }
+
+    /// The name and type of every query received so far, in order.
+    ///
+    /// Names are lowercased, without a trailing dot.
+    pub fn queries(&self) -> Vec<(String, u16)> {
+        self.queries.lock().unwrap().clone()
+    }
+
+    /// Forget the queries received so far.
+    pub fn clear_queries(&self) {
+        self.queries.lock().unwrap().clear();
+    }
+
+    fn key(name: &str, query_type: &str) -> Result<(Vec<u8>, u16), io::Error> {
+        let rr_type = Type::from_string(query_type)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        Ok((packet::normalize_name(name.as_bytes()), rr_type.into()))
+    }
+
+    fn exchange(&self, query: &[u8], timeout: Duration, tcp: bool) -> io::Result<Vec<u8>> {
+        let (delay, response) = self.respond(query, tcp);
+        if delay >= timeout {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Timeout"));
+        }
+        response
+    }
+
+    /// Return the delay and the response for `query`.
+    fn respond(&self, query: &[u8], tcp: bool) -> (Duration, io::Result<Vec<u8>>) {
+        let rule = match self.rule_for(query) {
+            Ok(rule) => rule,
+            Err(e) => return (Duration::default(), Err(e)),
+        };
+        (rule.delay, Self::perform(rule.action, query, tcp))
+    }
+
+    fn rule_for(&self, query: &[u8]) -> io::Result<MockRule> {
+        let mut parsed_query = DNSSector::new(query.to_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let (name, rr_type, _) = parsed_query
+            .question()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No DNS question"))?;
+        let name = packet::normalize_name(&name);
+        self.queries
+            .lock()
+            .unwrap()
+            .push((String::from_utf8_lossy(&name).into_owned(), rr_type));
+        let rule = self.rules.lock().unwrap().get(&(name, rr_type)).cloned();
+        Ok(rule.unwrap_or(MockRule {
+            action: MockAction::Respond {
+                rcode: Rcode::NXDOMAIN.into(),
+                answers: vec![],
+            },
+            delay: Duration::default(),
+        }))
+    }
+
+    fn perform(mut action: MockAction, query: &[u8], tcp: bool) -> io::Result<Vec<u8>> {
+        loop {
+            match action {
+                MockAction::Respond { rcode, answers } => {
+                    let answers: Vec<_> = answers
+                        .iter()
+                        .map(|(rr_type, ttl, rdata)| (*rr_type, *ttl, rdata.as_slice()))
+                        .collect();
+                    return Ok(packet::synthesize_response(query, rcode, &answers)?.into_packet());
+                }
+                MockAction::Raw(mut response) => {
+                    if response.len() >= DNS_HEADER_SIZE {
+                        response[..2].copy_from_slice(&query[..2]);
+                    }
+                    return Ok(response);
+                }
+                MockAction::Truncate(inner) if tcp => action = *inner,
+                MockAction::Truncate(_) => {
+                    let mut response = packet::synthesize_response(query, 0, &[])?.into_packet();
+                    response[DNS_FLAGS_OFFSET] |= (DNS_FLAG_TC >> 8) as u8;
+                    return Ok(response);
+                }
+                MockAction::Fail(kind) => {
+                    return Err(io::Error::new(kind, "Mock failure"));
+                }
+            }
+        }
+    }
+}
+
+impl SyncBackend for MockBackend {
+    fn dns_exchange_udp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, false)
+    }
+
+    fn dns_exchange_tcp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, true)
+    }
+
+    fn dns_exchange_tls(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, true)
+    }
+
+    fn dns_exchange_https(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, true)
+    }
+
+    fn dns_exchange_odoh(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, true)
+    }
+
+    fn dns_exchange_multicast(
+        &self,
+        _local_addr: &SocketAddr,
+        _group_addr: &SocketAddr,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, false)
+    }
+}
+
+impl AsyncBackend for MockBackend {
+    type TcpStream = TcpStream;
+
+    async fn dns_exchange_udp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, false)
+    }
+
+    async fn dns_exchange_tcp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, true)
+    }
+
+    async fn dns_exchange_tls(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, true)
+    }
+
+    async fn dns_exchange_https(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, true)
+    }
+
+    async fn dns_exchange_odoh(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, true)
+    }
+
+    async fn dns_exchange_quic(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, true)
+    }
+
+    async fn dns_exchange_multicast(
+        &self,
+        _local_addr: &SocketAddr,
+        _group_addr: &SocketAddr,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(query, timeout, false)
+    }
+
+    async fn join<F1, F2>(&self, f1: F1, f2: F2) -> (F1::Output, F2::Output)
+    where
+        F1: Future + Send,
+        F2: Future + Send,
+        F1::Output: Send,
+        F2::Output: Send,
+    {
+        (f1.await, f2.await)
+    }
+
+    /// Tasks run to completion before this returns, so that their effects
+    /// can be checked right away.
+    fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) -> bool {
+        block_on(task);
+        true
+    }
+
+    async fn connect_staggered(
+        &self,
+        _addrs: Vec<SocketAddr>,
+        _attempt_delay: Duration,
+        _timeout: Duration,
+    ) -> io::Result<TcpStream> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The mock backend doesn't open connections",
+        ))
+    }
+}
+
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+/// A DNS server bound to the loopback interface, answering queries over UDP
+/// and TCP on the same port, with the rules of a `MockBackend`.
+///
+/// Unlike `MockBackend`, this exercises the real backends: delays are
+/// applied before responding, and failing actions drop the query (UDP) or
+/// close the connection (TCP).
+///
+/// The server stops when it is dropped.
+#[derive(Debug)]
+pub struct MockDnsServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl MockDnsServer {
+    /// Start a server answering with the rules of `backend`.
+    pub fn start(backend: MockBackend) -> Result<Self, io::Error> {
+        let (udp_socket, tcp_listener) = Self::bind()?;
+        let addr = udp_socket.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = vec![];
+        {
+            let (backend, stop) = (backend.clone(), stop.clone());
+            threads.push(thread::spawn(move || {
+                Self::serve_udp(udp_socket, backend, stop)
+            }));
+        }
+        {
+            let stop = stop.clone();
+            threads.push(thread::spawn(move || {
+                Self::serve_tcp(tcp_listener, backend, stop)
+            }));
+        }
+        Ok(MockDnsServer {
+            addr,
+            stop,
+            threads,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn upstream_server(&self) -> UpstreamServer {
+        UpstreamServer::new(self.addr)
+    }
+
+    fn bind() -> io::Result<(UdpSocket, TcpListener)> {
+        let mut last_error = None;
+        for _ in 0..16 {
+            let tcp_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+            match UdpSocket::bind(tcp_listener.local_addr()?) {
+                Ok(udp_socket) => return Ok((udp_socket, tcp_listener)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap())
+    }
+
+    fn serve_udp(socket: UdpSocket, backend: MockBackend, stop: Arc<AtomicBool>) {
+        let mut query = vec![0; DNS_MAX_COMPRESSED_SIZE];
+        while !stop.load(Ordering::Relaxed) {
+            let (query_len, client_addr) = match socket.recv_from(&mut query) {
+                Ok(res) => res,
+                Err(_) => continue,
+            };
+            let (delay, response) = backend.respond(&query[..query_len], false);
+            thread::sleep(delay);
+            if let Ok(response) = response {
+                let _ = socket.send_to(&response, client_addr);
+            }
+        }
+    }
+
+    fn serve_tcp(listener: TcpListener, backend: MockBackend, stop: Arc<AtomicBool>) {
+        for stream in listener.incoming() {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let backend = backend.clone();
+            thread::spawn(move || Self::serve_tcp_connection(stream, backend));
+        }
+    }
+
+    fn serve_tcp_connection(mut stream: TcpStream, backend: MockBackend) -> io::Result<()> {
+        loop {
+            let mut query_len_bytes = [0u8; 2];
+            stream.read_exact(&mut query_len_bytes)?;
+            let mut query = vec![0; u16::from_be_bytes(query_len_bytes) as usize];
+            stream.read_exact(&mut query)?;
+            let (delay, response) = backend.respond(&query, true);
+            thread::sleep(delay);
+            let response = response?;
+            let mut tcp_response = Vec::with_capacity(2 + response.len());
+            tcp_response.extend_from_slice(&(response.len() as u16).to_be_bytes());
+            tcp_response.extend_from_slice(&response);
+            stream.write_all(&tcp_response)?;
+        }
+    }
+}
+
+impl Drop for MockDnsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wake up the threads blocked on the sockets
+        if let Ok(socket) = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)) {
+            let _ = socket.send_to(&[], self.addr);
+        }
+        let _ = TcpStream::connect(self.addr);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Recorded exchanges, as `(tcp, query, response)` tuples.
+type Exchanges = Vec<(bool, Vec<u8>, Vec<u8>)>;
+
+/// Exchanges recorded by a `RecordingBackend`, that a `ReplayBackend` can
+/// answer from.
+///
+/// Cassettes are stored as text files, with one exchange per line: the
+/// transport (`udp` or `tcp`), then the query and the response, hex-encoded.
+/// Encrypted exchanges are recorded as `tcp` exchanges.
+///
+/// Clones share the same exchanges.
+#[derive(Clone, Debug, Default)]
+pub struct Cassette {
+    exchanges: Arc<Mutex<Exchanges>>,
+}
+
+impl Cassette {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a cassette previously written by `save()`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid cassette");
+        let mut exchanges = vec![];
+        for line in fs::read_to_string(path)?.lines() {
+            let mut parts = line.split_whitespace();
+            let tcp = match parts.next() {
+                None => continue,
+                Some("udp") => false,
+                Some("tcp") => true,
+                Some(_) => return Err(invalid()),
+            };
+            let query = parts.next().and_then(hex_decode).ok_or_else(invalid)?;
+            let response = parts.next().and_then(hex_decode).ok_or_else(invalid)?;
+            exchanges.push((tcp, query, response));
+        }
+        Ok(Cassette {
+            exchanges: Arc::new(Mutex::new(exchanges)),
+        })
+    }
+
+    /// Write the recorded exchanges to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        let mut out = String::new();
+        for (tcp, query, response) in self.exchanges.lock().unwrap().iter() {
+            out.push_str(if *tcp { "tcp " } else { "udp " });
+            out.push_str(&hex_encode(query));
+            out.push(' ');
+            out.push_str(&hex_encode(response));
+            out.push('\n');
+        }
+        fs::write(path, out)
+    }
+
+    pub fn len(&self) -> usize {
+        self.exchanges.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn record(&self, tcp: bool, query: &[u8], response: &[u8]) {
+        self.exchanges
+            .lock()
+            .unwrap()
+            .push((tcp, query.to_vec(), response.to_vec()));
+    }
+
+    /// Return the first recorded response to a query with the same question
+    /// and transport as `query`, with the transaction ID of `query`.
+    fn replay(&self, tcp: bool, query: &[u8]) -> io::Result<Vec<u8>> {
+        let question = question_of(query)?;
+        let exchanges = self.exchanges.lock().unwrap();
+        let mut response = exchanges
+            .iter()
+            .find(|(recorded_tcp, recorded_query, _)| {
+                *recorded_tcp == tcp && question_of(recorded_query).ok() == Some(question.clone())
+            })
+            .map(|(_, _, response)| response.clone())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No recorded response"))?;
+        if response.len() >= DNS_HEADER_SIZE {
+            response[..2].copy_from_slice(&query[..2]);
+        }
+        Ok(response)
+    }
+}
+
+fn question_of(query: &[u8]) -> io::Result<(Vec<u8>, u16, u16)> {
+    DNSSector::new(query.to_vec())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+        .question()
+        .map(|(name, rr_type, rr_class)| (packet::normalize_name(&name), rr_type, rr_class))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No DNS question"))
+}
+
+fn hex_encode(bin: &[u8]) -> String {
+    bin.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    let pairs = hex.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    pairs
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// A backend forwarding queries to another backend, and recording the
+/// exchanges into a `Cassette`.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingBackend<B> {
+    inner: B,
+    cassette: Cassette,
+}
+
+impl<B> RecordingBackend<B> {
+    pub fn new(inner: B, cassette: Cassette) -> Self {
+        RecordingBackend { inner, cassette }
+    }
+
+    pub fn cassette(&self) -> &Cassette {
+        &self.cassette
+    }
+}
+
+impl<B: SyncBackend> SyncBackend for RecordingBackend<B> {
+    fn dns_exchange_udp(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = SyncBackend::dns_exchange_udp(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )?;
+        self.cassette.record(false, query, &response);
+        Ok(response)
+    }
+
+    fn dns_exchange_tcp(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = SyncBackend::dns_exchange_tcp(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )?;
+        self.cassette.record(true, query, &response);
+        Ok(response)
+    }
+
+    fn dns_exchange_tls(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = SyncBackend::dns_exchange_tls(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )?;
+        self.cassette.record(true, query, &response);
+        Ok(response)
+    }
+
+    fn dns_exchange_https(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = SyncBackend::dns_exchange_https(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )?;
+        self.cassette.record(true, query, &response);
+        Ok(response)
+    }
+
+    fn dns_exchange_odoh(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = SyncBackend::dns_exchange_odoh(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )?;
+        self.cassette.record(true, query, &response);
+        Ok(response)
+    }
+
+    fn dns_exchange_multicast(
+        &self,
+        local_addr: &SocketAddr,
+        group_addr: &SocketAddr,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = SyncBackend::dns_exchange_multicast(
+            &self.inner,
+            local_addr,
+            group_addr,
+            query,
+            timeout,
+        )?;
+        self.cassette.record(false, query, &response);
+        Ok(response)
+    }
+}
+
+impl<B: AsyncBackend> AsyncBackend for RecordingBackend<B> {
+    type TcpStream = B::TcpStream;
+
+    async fn dns_exchange_udp(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = AsyncBackend::dns_exchange_udp(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )
+        .await?;
+        self.cassette.record(false, query, &response);
+        Ok(response)
+    }
+
+    async fn dns_exchange_tcp(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = AsyncBackend::dns_exchange_tcp(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )
+        .await?;
+        self.cassette.record(true, query, &response);
+        Ok(response)
+    }
+
+    async fn dns_exchange_tls(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = AsyncBackend::dns_exchange_tls(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )
+        .await?;
+        self.cassette.record(true, query, &response);
+        Ok(response)
+    }
+
+    async fn dns_exchange_https(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = AsyncBackend::dns_exchange_https(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )
+        .await?;
+        self.cassette.record(true, query, &response);
+        Ok(response)
+    }
+
+    async fn dns_exchange_odoh(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = AsyncBackend::dns_exchange_odoh(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )
+        .await?;
+        self.cassette.record(true, query, &response);
+        Ok(response)
+    }
+
+    async fn dns_exchange_quic(
+        &self,
+        local_addr: &SocketAddr,
+        upstream_server: &UpstreamServer,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = AsyncBackend::dns_exchange_quic(
+            &self.inner,
+            local_addr,
+            upstream_server,
+            query,
+            timeout,
+        )
+        .await?;
+        self.cassette.record(true, query, &response);
+        Ok(response)
+    }
+
+    async fn dns_exchange_multicast(
+        &self,
+        local_addr: &SocketAddr,
+        group_addr: &SocketAddr,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let response = AsyncBackend::dns_exchange_multicast(
+            &self.inner,
+            local_addr,
+            group_addr,
+            query,
+            timeout,
+        )
+        .await?;
+        self.cassette.record(false, query, &response);
+        Ok(response)
+    }
+
+    fn delay(&self, duration: Duration) -> impl Future<Output = ()> + Send + '_ {
+        self.inner.delay(duration)
+    }
+
+    fn join<F1, F2>(&self, f1: F1, f2: F2) -> impl Future<Output = (F1::Output, F2::Output)> + Send
+    where
+        F1: Future + Send,
+        F2: Future + Send,
+        F1::Output: Send,
+        F2::Output: Send,
+    {
+        self.inner.join(f1, f2)
+    }
+
+    fn connect_staggered(
+        &self,
+        addrs: Vec<SocketAddr>,
+        attempt_delay: Duration,
+        timeout: Duration,
+    ) -> impl Future<Output = io::Result<B::TcpStream>> + Send + '_ {
+        self.inner.connect_staggered(addrs, attempt_delay, timeout)
+    }
+}
+
+/// A backend answering queries from the exchanges of a `Cassette`, without
+/// any network access.
+///
+/// Queries are matched by question and transport. Queries that were never
+/// recorded fail with `io::ErrorKind::NotFound`.
+#[derive(Clone, Debug, Default)]
+pub struct ReplayBackend {
+    cassette: Cassette,
+}
+
+impl ReplayBackend {
+    pub fn new(cassette: Cassette) -> Self {
+        ReplayBackend { cassette }
+    }
+}
+
+impl SyncBackend for ReplayBackend {
+    fn dns_exchange_udp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(false, query)
+    }
+
+    fn dns_exchange_tcp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(true, query)
+    }
+
+    fn dns_exchange_tls(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(true, query)
+    }
+
+    fn dns_exchange_https(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(true, query)
+    }
+
+    fn dns_exchange_odoh(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(true, query)
+    }
+
+    fn dns_exchange_multicast(
+        &self,
+        _local_addr: &SocketAddr,
+        _group_addr: &SocketAddr,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(false, query)
+    }
+}
+
+impl AsyncBackend for ReplayBackend {
+    type TcpStream = TcpStream;
+
+    async fn dns_exchange_udp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(false, query)
+    }
+
+    async fn dns_exchange_tcp(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(true, query)
+    }
+
+    async fn dns_exchange_tls(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(true, query)
+    }
+
+    async fn dns_exchange_https(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(true, query)
+    }
+
+    async fn dns_exchange_odoh(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(true, query)
+    }
+
+    async fn dns_exchange_quic(
+        &self,
+        _local_addr: &SocketAddr,
+        _upstream_server: &UpstreamServer,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(true, query)
+    }
+
+    async fn dns_exchange_multicast(
+        &self,
+        _local_addr: &SocketAddr,
+        _group_addr: &SocketAddr,
+        query: &[u8],
+        _timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.cassette.replay(false, query)
+    }
+
+    async fn join<F1, F2>(&self, f1: F1, f2: F2) -> (F1::Output, F2::Output)
+    where
+        F1: Future + Send,
+        F2: Future + Send,
+        F1::Output: Send,
+        F2::Output: Send,
+    {
+        (f1.await, f2.await)
+    }
+
+    async fn connect_staggered(
+        &self,
+        _addrs: Vec<SocketAddr>,
+        _attempt_delay: Duration,
+        _timeout: Duration,
+    ) -> io::Result<TcpStream> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The replay backend doesn't open connections",
+        ))
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn mock_client(backend: &MockBackend) -> crate::sync::GenericDNSClient<MockBackend> {
+    let upstream_server = UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)));
+    crate::sync::GenericDNSClient::with_backend(vec![upstream_server], backend.clone())
+}
+
+/// The exchanges replayed by the tests of the clients that used to query
+/// public resolvers. `record_test_cassette()` records them again.
+#[cfg(test)]
+pub(crate) const TEST_CASSETTE: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/queries.cassette");
+
+#[cfg(test)]
+pub(crate) fn replay_backend() -> ReplayBackend {
+    ReplayBackend::new(Cassette::load(TEST_CASSETTE).unwrap())
+}
+
+/// Record `TEST_CASSETTE` from public resolvers, with
+/// `cargo test --features testing -- --ignored record_test_cassette`.
+#[test]
+#[ignore]
+fn record_test_cassette() {
+    let recording_backend =
+        RecordingBackend::new(crate::backend::sync::StdBackend::default(), Cassette::new());
+    let dns_client = crate::sync::GenericDNSClient::with_backend(
+        vec![
+            UpstreamServer::new(SocketAddr::from(([1, 1, 1, 1], 53))),
+            UpstreamServer::new(SocketAddr::from(([1, 0, 0, 1], 53))),
+        ],
+        recording_backend.clone(),
+    );
+    dns_client.query_a("one.one.one.one").unwrap();
+    dns_client.query_aaaa("one.one.one.one").unwrap();
+    dns_client
+        .query_ptr(&Ipv4Addr::new(1, 1, 1, 1).into())
+        .unwrap();
+    dns_client.query_txt("fastly.com").unwrap();
+    recording_backend.cassette().save(TEST_CASSETTE).unwrap();
+}
+
+#[test]
+fn test_mock_responses() {
+    let backend = MockBackend::new();
+    let dns_client = mock_client(&backend);
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    assert_eq!(
+        dns_client.query_a("example.com").unwrap(),
+        vec![Ipv4Addr::new(192, 0, 2, 1)]
+    );
+    assert!(dns_client.query_aaaa("example.com").is_err());
+
+    let truncated = MockAction::Respond {
+        rcode: 0,
+        answers: vec![(Type::AAAA.into(), 60, Ipv6Addr::LOCALHOST.octets().to_vec())],
+    };
+    backend
+        .set_action(
+            "example.com",
+            "AAAA",
+            MockAction::Truncate(Box::new(truncated)),
+        )
+        .unwrap();
+    assert_eq!(
+        dns_client.query_aaaa("example.com").unwrap(),
+        vec![Ipv6Addr::LOCALHOST]
+    );
+}
+
+#[test]
+fn test_mock_queries() {
+    let backend = MockBackend::new();
+    let dns_client = mock_client(&backend);
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    dns_client.query_a("Example.COM").unwrap();
+    assert_eq!(
+        backend.queries(),
+        vec![("example.com".to_string(), u16::from(Type::A))]
+    );
+
+    // Truncated responses are retried over TCP
+    backend.clear_queries();
+    backend
+        .set_action(
+            "example.com",
+            "AAAA",
+            MockAction::Truncate(Box::new(MockAction::Respond {
+                rcode: 0,
+                answers: vec![],
+            })),
+        )
+        .unwrap();
+    dns_client.query_aaaa("example.com").unwrap();
+    assert_eq!(
+        backend.queries(),
+        vec![
+            ("example.com".to_string(), u16::from(Type::AAAA)),
+            ("example.com".to_string(), u16::from(Type::AAAA))
+        ]
+    );
+}
+
+#[test]
+fn test_mock_failures() {
+    let backend = MockBackend::new();
+    let mut dns_client = mock_client(&backend);
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    backend
+        .set_delay("example.com", "A", Duration::from_secs(2))
+        .unwrap();
+    assert!(dns_client.query_a("example.com").is_ok());
+    dns_client.set_timeout(Duration::from_secs(1));
+    assert!(dns_client.query_a("example.com").is_err());
+
+    backend
+        .set_action(
+            "example.com",
+            "TXT",
+            MockAction::Fail(io::ErrorKind::ConnectionRefused),
+        )
+        .unwrap();
+    assert!(dns_client.query_txt("example.com").is_err());
+}
+
+#[test]
+fn test_mock_dns_server() {
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let truncated = MockAction::Respond {
+        rcode: 0,
+        answers: vec![(Type::AAAA.into(), 60, Ipv6Addr::LOCALHOST.octets().to_vec())],
+    };
+    backend
+        .set_action(
+            "example.com",
+            "AAAA",
+            MockAction::Truncate(Box::new(truncated)),
+        )
+        .unwrap();
+    backend.add_a("slow.example.com", &[Ipv4Addr::new(192, 0, 2, 2)]);
+    backend
+        .set_delay("slow.example.com", "A", Duration::from_millis(500))
+        .unwrap();
+    let server = MockDnsServer::start(backend).unwrap();
+
+    let mut dns_client = crate::sync::DNSClient::new(vec![server.upstream_server()]);
+    assert_eq!(
+        dns_client.query_a("example.com").unwrap(),
+        vec![Ipv4Addr::new(192, 0, 2, 1)]
+    );
+    assert_eq!(
+        dns_client.query_aaaa("example.com").unwrap(),
+        vec![Ipv6Addr::LOCALHOST]
+    );
+    dns_client.set_timeout(Duration::from_millis(100));
+    assert!(dns_client.query_a("slow.example.com").is_err());
+}
+
+#[test]
+fn test_cassette() {
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let recording_backend = RecordingBackend::new(backend, Cassette::new());
+    let upstream_server = UpstreamServer::new(SocketAddr::from(([192, 0, 2, 53], 53)));
+    let dns_client = crate::sync::GenericDNSClient::with_backend(
+        vec![upstream_server.clone()],
+        recording_backend.clone(),
+    );
+    dns_client.query_a("example.com").unwrap();
+    assert_eq!(recording_backend.cassette().len(), 1);
+
+    let path = std::env::temp_dir().join(format!("dnsclient-cassette-{}", std::process::id()));
+    recording_backend.cassette().save(&path).unwrap();
+    let cassette = Cassette::load(&path).unwrap();
+    let _ = fs::remove_file(&path);
+
+    let dns_client = crate::sync::GenericDNSClient::with_backend(
+        vec![upstream_server],
+        ReplayBackend::new(cassette),
+    );
+    assert_eq!(
+        dns_client.query_a("example.com").unwrap(),
+        vec![Ipv4Addr::new(192, 0, 2, 1)]
+    );
+    assert!(dns_client.query_aaaa("example.com").is_err());
+}
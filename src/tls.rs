@@ -0,0 +1,447 @@
+//! DNS-over-TLS (RFC 7858), and the TLS sessions shared with
+//! DNS-over-HTTPS.
+//!
+//! Messages are exchanged like over TCP, length-prefixed, within a TLS
+//! session. Certificates are verified against the Mozilla root store.
+//!
+//! Async exchanges only require the TCP primitives of a `RuntimeAdapter`:
+//! TLS records are read one at a time, using their length from the record
+//! header, and fed to `rustls`.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use dnssector::constants::DNS_MAX_COMPRESSED_SIZE;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::backend::sync::StdBackend;
+use crate::backend::tcp_pool::Pipeline;
+use crate::backend::RuntimeAdapter;
+use crate::socks5;
+use crate::upstream_server::UpstreamServer;
+
+const TLS_RECORD_HEADER_SIZE: usize = 5;
+
+/// The configuration shared by all the DNS-over-TLS sessions.
+pub(crate) fn client_config() -> Arc<ClientConfig> {
+    static CLIENT_CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CLIENT_CONFIG
+        .get_or_init(|| {
+            let root_store = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let config = ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .expect("Unsupported TLS protocol versions")
+                .with_root_certificates(root_store)
+                .with_no_client_auth();
+            Arc::new(config)
+        })
+        .clone()
+}
+
+fn tls_error(e: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn connection(
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+) -> Result<ClientConnection, io::Error> {
+    let server_name = upstream_server.tls_server_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("[{}] is not an encrypted server", upstream_server),
+        )
+    })?;
+    let server_name = ServerName::try_from(server_name.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    ClientConnection::new(config, server_name).map_err(tls_error)
+}
+
+pub(crate) fn length_prefixed(query: &[u8]) -> Vec<u8> {
+    let mut tcp_query = Vec::with_capacity(2 + query.len());
+    tcp_query.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    tcp_query.extend_from_slice(query);
+    tcp_query
+}
+
+/// Return the response once `plaintext` contains a complete length-prefixed
+/// message.
+pub(crate) fn complete_response(
+    plaintext: &[u8],
+    _eof: bool,
+) -> Result<Option<Vec<u8>>, io::Error> {
+    if plaintext.len() < 2 {
+        return Ok(None);
+    }
+    let response_len = u16::from_be_bytes([plaintext[0], plaintext[1]]) as usize;
+    if response_len > DNS_MAX_COMPRESSED_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Response too large",
+        ));
+    }
+    Ok(plaintext
+        .get(2..2 + response_len)
+        .map(|response| response.to_vec()))
+}
+
+/// Return the plaintext once it contains `count` complete length-prefixed
+/// messages.
+fn complete_responses(plaintext: &[u8], count: usize) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut offset = 0;
+    for _ in 0..count {
+        match complete_response(&plaintext[offset..], false)? {
+            Some(response) => offset += 2 + response.len(),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(plaintext[..offset].to_vec()))
+}
+
+/// Match the length-prefixed messages of `plaintext` to the pipelined
+/// queries.
+fn pipelined_responses(
+    mut pipeline: Pipeline<'_>,
+    mut plaintext: &[u8],
+) -> io::Result<Vec<Vec<u8>>> {
+    while let Some(response) = complete_response(plaintext, false)? {
+        plaintext = &plaintext[2 + response.len()..];
+        pipeline.on_response(response);
+    }
+    pipeline.into_responses()
+}
+
+fn closed_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Connection closed before a response was received",
+    )
+}
+
+/// Send `request` within a new TLS session, using blocking sockets, and
+/// read the response with `read_response`.
+///
+/// `read_response` extracts the response from the plaintext received so
+/// far, returning `None` until it is complete. Its flag is set once the
+/// server closed the session.
+pub(crate) fn session_blocking(
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    request: &[u8],
+    read_response: impl Fn(&[u8], bool) -> Result<Option<Vec<u8>>, io::Error>,
+    backend: &StdBackend,
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let connection = connection(config, upstream_server)?;
+    let stream = backend.tcp_connect(&upstream_server.addr, timeout)?;
+    let _ = stream.set_read_timeout(Some(timeout));
+    let _ = stream.set_write_timeout(Some(timeout));
+    let mut stream = StreamOwned::new(connection, stream);
+    stream.write_all(request)?;
+    stream.flush()?;
+    let mut plaintext = vec![];
+    let mut buf = [0u8; 4096];
+    loop {
+        let len = stream.read(&mut buf)?;
+        plaintext.extend_from_slice(&buf[..len]);
+        if let Some(response) = read_response(&plaintext, len == 0)? {
+            return Ok(response);
+        }
+        if len == 0 {
+            return Err(closed_error());
+        }
+    }
+}
+
+/// Exchange a query with a DNS-over-TLS server, using blocking sockets.
+pub(crate) fn exchange_blocking(
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    query: &[u8],
+    backend: &StdBackend,
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    session_blocking(
+        config,
+        upstream_server,
+        &length_prefixed(query),
+        complete_response,
+        backend,
+        timeout,
+    )
+}
+
+/// Exchange pipelined queries with a DNS-over-TLS server, within a single
+/// session, using blocking sockets.
+pub(crate) fn exchange_pipelined_blocking(
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    queries: &[Vec<u8>],
+    backend: &StdBackend,
+    timeout: Duration,
+) -> io::Result<Vec<Vec<u8>>> {
+    let pipeline = Pipeline::new(queries);
+    let plaintext = session_blocking(
+        config,
+        upstream_server,
+        &pipeline.framed_queries(),
+        |plaintext, _eof| complete_responses(plaintext, queries.len()),
+        backend,
+        timeout,
+    )?;
+    pipelined_responses(pipeline, &plaintext)
+}
+
+/// Send the pending TLS records.
+async fn write_records<R: RuntimeAdapter>(
+    runtime: &R,
+    stream: &mut R::TcpStream,
+    connection: &mut ClientConnection,
+) -> io::Result<()> {
+    while connection.wants_write() {
+        let mut records = vec![];
+        connection.write_tls(&mut records)?;
+        runtime.tcp_write_all(stream, &records).await?;
+    }
+    Ok(())
+}
+
+/// Receive a single TLS record, and process it.
+async fn read_record<R: RuntimeAdapter>(
+    runtime: &R,
+    stream: &mut R::TcpStream,
+    connection: &mut ClientConnection,
+) -> io::Result<()> {
+    let mut record = vec![0; TLS_RECORD_HEADER_SIZE];
+    runtime.tcp_read_exact(stream, &mut record).await?;
+    let record_len = u16::from_be_bytes([record[3], record[4]]) as usize;
+    record.resize(TLS_RECORD_HEADER_SIZE + record_len, 0);
+    runtime
+        .tcp_read_exact(stream, &mut record[TLS_RECORD_HEADER_SIZE..])
+        .await?;
+    let mut record = &record[..];
+    while !record.is_empty() {
+        connection.read_tls(&mut record)?;
+        connection.process_new_packets().map_err(tls_error)?;
+    }
+    Ok(())
+}
+
+/// Send `request` within a new TLS session, over a TCP stream of `runtime`,
+/// and read the response with `read_response`. The caller is responsible for
+/// the timeout.
+pub(crate) async fn session<R: RuntimeAdapter>(
+    runtime: &R,
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    request: &[u8],
+    read_response: impl Fn(&[u8], bool) -> Result<Option<Vec<u8>>, io::Error>,
+) -> io::Result<Vec<u8>> {
+    let mut connection = connection(config, upstream_server)?;
+    let mut stream = socks5::connect(runtime, upstream_server.addr).await?;
+    connection.writer().write_all(request)?;
+    let mut plaintext = vec![];
+    loop {
+        write_records(runtime, &mut stream, &mut connection).await?;
+        let mut reader = connection.reader();
+        let mut buf = [0u8; 4096];
+        let mut eof = false;
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => {
+                    eof = true;
+                    break;
+                }
+                Ok(len) => plaintext.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(response) = read_response(&plaintext, eof)? {
+            return Ok(response);
+        }
+        if eof {
+            return Err(closed_error());
+        }
+        read_record(runtime, &mut stream, &mut connection).await?;
+    }
+}
+
+/// Exchange a query with a DNS-over-TLS server, over a TCP stream of
+/// `runtime`. The caller is responsible for the timeout.
+pub(crate) async fn exchange<R: RuntimeAdapter>(
+    runtime: &R,
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    query: &[u8],
+) -> io::Result<Vec<u8>> {
+    session(
+        runtime,
+        config,
+        upstream_server,
+        &length_prefixed(query),
+        complete_response,
+    )
+    .await
+}
+
+/// Exchange pipelined queries with a DNS-over-TLS server, within a single
+/// session, over a TCP stream of `runtime`. The caller is responsible for
+/// the timeout.
+pub(crate) async fn exchange_pipelined<R: RuntimeAdapter>(
+    runtime: &R,
+    config: Arc<ClientConfig>,
+    upstream_server: &UpstreamServer,
+    queries: &[Vec<u8>],
+) -> io::Result<Vec<Vec<u8>>> {
+    let pipeline = Pipeline::new(queries);
+    let plaintext = session(
+        runtime,
+        config,
+        upstream_server,
+        &pipeline.framed_queries(),
+        |plaintext, _eof| complete_responses(plaintext, queries.len()),
+    )
+    .await?;
+    pipelined_responses(pipeline, &plaintext)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::{ServerConfig, ServerConnection};
+
+    use super::*;
+
+    /// A self-signed certificate for `dns.test`.
+    pub(crate) const CERTIFICATE: &str = include_str!("../testdata/dns.test.crt");
+    pub(crate) const PRIVATE_KEY: &str = include_str!("../testdata/dns.test.key");
+
+    /// A client configuration trusting the test certificate.
+    pub(crate) fn client_config() -> Arc<ClientConfig> {
+        let mut root_store = RootCertStore::empty();
+        root_store
+            .add(CertificateDer::from_pem_slice(CERTIFICATE.as_bytes()).unwrap())
+            .unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        Arc::new(config)
+    }
+
+    /// Start a TLS server accepting a single session, handled by `handle`,
+    /// and return its address.
+    pub(crate) fn serve<F>(handle: F) -> (SocketAddr, thread::JoinHandle<io::Result<Vec<u8>>>)
+    where
+        F: FnOnce(&mut StreamOwned<ServerConnection, TcpStream>) -> io::Result<Vec<u8>>
+            + Send
+            + 'static,
+    {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from_pem_slice(CERTIFICATE.as_bytes()).unwrap()],
+                PrivateKeyDer::from_pem_slice(PRIVATE_KEY.as_bytes()).unwrap(),
+            )
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            let connection = ServerConnection::new(Arc::new(config)).map_err(tls_error)?;
+            let mut stream = StreamOwned::new(connection, stream);
+            handle(&mut stream)
+        });
+        (addr, server)
+    }
+
+    /// Start a DNS-over-TLS server answering a single query with `response`,
+    /// and return its address.
+    pub(crate) fn serve_once(
+        response: Vec<u8>,
+    ) -> (SocketAddr, thread::JoinHandle<io::Result<Vec<u8>>>) {
+        serve(move |stream| {
+            let mut query_len = [0u8; 2];
+            stream.read_exact(&mut query_len)?;
+            let mut query = vec![0; u16::from_be_bytes(query_len) as usize];
+            stream.read_exact(&mut query)?;
+            stream.write_all(&length_prefixed(&response))?;
+            stream.flush()?;
+            Ok(query)
+        })
+    }
+
+    #[test]
+    fn test_exchange_blocking() {
+        let (addr, server) = serve_once(b"response".to_vec());
+        let upstream_server = UpstreamServer::new_tls(addr, "dns.test");
+        let response = exchange_blocking(
+            client_config(),
+            &upstream_server,
+            b"query",
+            &StdBackend::default(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(response, b"response");
+        assert_eq!(server.join().unwrap().unwrap(), b"query");
+
+        // The certificate is not valid for another name
+        let (addr, _server) = serve_once(b"response".to_vec());
+        let upstream_server = UpstreamServer::new_tls(addr, "other.test");
+        assert!(exchange_blocking(
+            client_config(),
+            &upstream_server,
+            b"query",
+            &StdBackend::default(),
+            Duration::from_secs(5)
+        )
+        .is_err());
+
+        // The default configuration doesn't trust the test certificate
+        let (addr, _server) = serve_once(b"response".to_vec());
+        let upstream_server = UpstreamServer::new_tls(addr, "dns.test");
+        assert!(exchange_blocking(
+            super::client_config(),
+            &upstream_server,
+            b"query",
+            &StdBackend::default(),
+            Duration::from_secs(5)
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "async-io")]
+    #[test]
+    fn test_exchange() {
+        use crate::backend::async_io::AsyncIoBackend;
+
+        let (addr, server) = serve_once(vec![0x42; 1000]);
+        let upstream_server = UpstreamServer::new_tls(addr, "dns.test");
+        let response = ::async_io::block_on(exchange(
+            &AsyncIoBackend::default(),
+            client_config(),
+            &upstream_server,
+            b"query",
+        ))
+        .unwrap();
+        assert_eq!(response, vec![0x42; 1000]);
+        assert_eq!(server.join().unwrap().unwrap(), b"query");
+    }
+}
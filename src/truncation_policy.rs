@@ -0,0 +1,63 @@
+/// What the client does when a UDP response is truncated (has the `TC`
+/// flag set).
+///
+/// Whether a response was received after a TCP retry is in
+/// `QueryInfo::tcp_fallback`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum TruncationPolicy {
+    /// Send the query again over TCP.
+    #[default]
+    RetryTcp,
+    /// Return the truncated response as is, never opening a TCP connection.
+    ReturnTruncated,
+    /// Fail with a `DnsError::Truncated` error, never opening a TCP
+    /// connection.
+    Fail,
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_truncation_policy() {
+    use crate::error::DnsError;
+    use crate::events::Protocol;
+    use crate::query_options::QueryOptions;
+    use crate::testing::{mock_client, MockAction, MockBackend};
+
+    let backend = MockBackend::new();
+    backend
+        .set_action(
+            "example.com",
+            "AAAA",
+            MockAction::Truncate(Box::new(MockAction::Respond {
+                rcode: 0,
+                answers: vec![],
+            })),
+        )
+        .unwrap();
+    let mut dns_client = mock_client(&backend);
+
+    dns_client.set_truncation_policy(TruncationPolicy::ReturnTruncated);
+    let (_, info) = dns_client.query_aaaa_detailed("example.com").unwrap();
+    assert_eq!(info.protocol, Some(Protocol::Udp));
+    assert_eq!(info.attempts, 1);
+    assert!(!info.tcp_fallback);
+
+    dns_client.set_truncation_policy(TruncationPolicy::Fail);
+    let e = dns_client.query_aaaa_detailed("example.com").unwrap_err();
+    assert!(matches!(DnsError::from(e), DnsError::Truncated));
+
+    let options = QueryOptions {
+        truncation_policy: Some(TruncationPolicy::RetryTcp),
+        ..Default::default()
+    };
+    let (_, info) = dns_client
+        .query_aaaa_detailed_with("example.com", &options)
+        .unwrap();
+    assert_eq!(info.protocol, Some(Protocol::Tcp));
+    assert!(info.tcp_fallback);
+}
@@ -1,12 +1,753 @@
-use std::net::SocketAddr;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 
-#[derive(Clone, Debug)]
+use crate::error::DnsError;
+use crate::stamp::StampKind;
+
+/// The default port of DNS-over-TLS and DNS-over-QUIC servers.
+pub(crate) const DOT_PORT: u16 = 853;
+
+/// The default port of DNS-over-HTTPS servers.
+pub(crate) const DOH_PORT: u16 = 443;
+
+/// How queries are sent to an upstream server.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Transport {
+    /// UDP, and TCP for truncated responses.
+    Plain,
+    /// TCP only.
+    Tcp,
+    /// DNS-over-TLS (RFC 7858). The server certificate must be valid for
+    /// `server_name`.
+    Tls { server_name: String },
+    /// DNS-over-HTTPS (RFC 8484). Queries are POSTed to `path`, and the
+    /// server certificate must be valid for `server_name`, the host of the
+    /// URL.
+    Https { server_name: String, path: String },
+    /// DNS-over-QUIC (RFC 9250). The server certificate must be valid for
+    /// `server_name`.
+    Quic { server_name: String },
+    /// Oblivious DNS-over-HTTPS (RFC 9230), through a proxy. Queries are
+    /// encrypted to `target`, a DNS-over-HTTPS server, and POSTed to the
+    /// proxy at `path`. The proxy certificate must be valid for
+    /// `server_name`.
+    ObliviousHttps {
+        server_name: String,
+        path: String,
+        target: Box<UpstreamServer>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct UpstreamServer {
     pub addr: SocketAddr,
+    pub transport: Transport,
+    /// Servers with a lower priority are used first by
+    /// `UpstreamStrategy::Weighted`. `0` by default.
+    pub priority: u16,
+    /// The share of the queries sent to this server, relative to the other
+    /// servers of the same priority, with `UpstreamStrategy::Weighted`. `1`
+    /// by default; servers with a weight of `0` are only used when the other
+    /// servers of the same priority fail.
+    pub weight: u16,
 }
 
 impl UpstreamServer {
     pub fn new<T: Into<SocketAddr>>(addr: T) -> Self {
-        UpstreamServer { addr: addr.into() }
+        UpstreamServer {
+            addr: addr.into(),
+            transport: Transport::Plain,
+            priority: 0,
+            weight: 1,
+        }
+    }
+
+    /// A server that is only queried over TCP.
+    pub fn new_tcp<T: Into<SocketAddr>>(addr: T) -> Self {
+        UpstreamServer {
+            addr: addr.into(),
+            transport: Transport::Tcp,
+            priority: 0,
+            weight: 1,
+        }
+    }
+
+    /// A DNS-over-TLS server (RFC 7858), whose certificate must be valid for
+    /// `server_name`. Queries are only sent to this server over TLS.
+    ///
+    /// Exchanging queries with it requires the `tls` feature.
+    pub fn new_tls<T: Into<SocketAddr>>(addr: T, server_name: &str) -> Self {
+        UpstreamServer {
+            addr: addr.into(),
+            transport: Transport::Tls {
+                server_name: server_name.to_string(),
+            },
+            priority: 0,
+            weight: 1,
+        }
+    }
+
+    /// A DNS-over-QUIC server (RFC 9250), whose certificate must be valid
+    /// for `server_name`. Queries are only sent to this server over QUIC.
+    ///
+    /// Exchanging queries with it requires the `doq` feature, and the async
+    /// client.
+    pub fn new_quic<T: Into<SocketAddr>>(addr: T, server_name: &str) -> Self {
+        UpstreamServer {
+            addr: addr.into(),
+            transport: Transport::Quic {
+                server_name: server_name.to_string(),
+            },
+            priority: 0,
+            weight: 1,
+        }
+    }
+
+    /// A DNS-over-HTTPS server (RFC 8484), such as
+    /// `https://1.1.1.1/dns-query`.
+    ///
+    /// The host of the URL must be an IP address; servers known by name are
+    /// created with `new_doh_with_addr()`, so that no resolution is needed
+    /// to reach them. On WebAssembly, any host name is accepted, and left to
+    /// the browser to resolve.
+    ///
+    /// Exchanging queries with it requires the `doh` feature, or the `wasm`
+    /// feature in browsers.
+    pub fn new_doh(url: &str) -> Result<Self, io::Error> {
+        let (server_name, port, path) = parse_https_url(url)?;
+        let addr = match server_name.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, port),
+            // Browsers resolve the host name themselves
+            #[cfg(target_arch = "wasm32")]
+            Err(_) => SocketAddr::from(([0, 0, 0, 0], port)),
+            #[cfg(not(target_arch = "wasm32"))]
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "The address of the DNS-over-HTTPS server [{}] is required",
+                        server_name
+                    ),
+                ))
+            }
+        };
+        Ok(UpstreamServer {
+            addr,
+            transport: Transport::Https { server_name, path },
+            priority: 0,
+            weight: 1,
+        })
+    }
+
+    /// A DNS-over-HTTPS server (RFC 8484) reachable at `addr`, whatever the
+    /// host and the port of `url` are.
+    ///
+    /// Exchanging queries with it requires the `doh` feature.
+    pub fn new_doh_with_addr<T: Into<SocketAddr>>(addr: T, url: &str) -> Result<Self, io::Error> {
+        let (server_name, _, path) = parse_https_url(url)?;
+        Ok(UpstreamServer {
+            addr: addr.into(),
+            transport: Transport::Https { server_name, path },
+            priority: 0,
+            weight: 1,
+        })
+    }
+
+    /// An Oblivious DNS-over-HTTPS target (RFC 9230), queried through
+    /// `proxy`. Both are DNS-over-HTTPS servers, such as the ones returned by
+    /// `new_doh()`.
+    ///
+    /// The proxy sees the address of the client, but not the queries, and
+    /// the target sees the queries, but not the address of the client. The
+    /// keys of the target are fetched from the target itself.
+    ///
+    /// Exchanging queries with it requires the `odoh` feature.
+    pub fn new_odoh(proxy: UpstreamServer, target: UpstreamServer) -> Result<Self, io::Error> {
+        let (server_name, path) = match proxy.transport {
+            Transport::Https { server_name, path } => (server_name, path),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Oblivious DNS-over-HTTPS proxies must be DNS-over-HTTPS servers",
+                ))
+            }
+        };
+        if !target.is_doh() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Oblivious DNS-over-HTTPS targets must be DNS-over-HTTPS servers",
+            ));
+        }
+        Ok(UpstreamServer {
+            addr: proxy.addr,
+            transport: Transport::ObliviousHttps {
+                server_name,
+                path,
+                target: Box::new(target),
+            },
+            priority: 0,
+            weight: 1,
+        })
+    }
+
+    /// An upstream server described by a DNS stamp (`sdns://...`), as found
+    /// in public resolver lists. Stamps of plain DNS, DNS-over-HTTPS,
+    /// DNS-over-TLS and DNS-over-QUIC servers are supported, and select the
+    /// matching transport.
+    ///
+    /// Certificate hashes, bootstrap resolvers and properties are ignored.
+    /// Stamps that don't include an address are rejected, unless the host of
+    /// the server is an IP address.
+    ///
+    /// Oblivious DNS-over-HTTPS stamps describe a target or a relay, and
+    /// must be combined with `new_odoh()`, or written as `target via relay`.
+    pub fn from_stamp(stamp: &str) -> Result<Self, io::Error> {
+        match crate::stamp::parse(stamp)? {
+            (StampKind::Server, upstream_server) => Ok(upstream_server),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Oblivious DNS-over-HTTPS stamps must be combined as a target and a relay",
+            )),
+        }
+    }
+
+    /// Set the priority of the server, used by `UpstreamStrategy::Weighted`.
+    pub fn with_priority(mut self, priority: u16) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set the weight of the server, used by `UpstreamStrategy::Weighted`.
+    pub fn with_weight(mut self, weight: u16) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Whether this is a DNS-over-TLS server.
+    pub fn is_tls(&self) -> bool {
+        matches!(self.transport, Transport::Tls { .. })
+    }
+
+    /// Whether this is a DNS-over-HTTPS server.
+    pub fn is_doh(&self) -> bool {
+        matches!(self.transport, Transport::Https { .. })
+    }
+
+    /// Whether this is a DNS-over-QUIC server.
+    pub fn is_quic(&self) -> bool {
+        matches!(self.transport, Transport::Quic { .. })
+    }
+
+    /// Whether this is an Oblivious DNS-over-HTTPS target.
+    pub fn is_odoh(&self) -> bool {
+        matches!(self.transport, Transport::ObliviousHttps { .. })
+    }
+
+    /// The name the certificate of an encrypted server must be valid for.
+    pub fn tls_server_name(&self) -> Option<&str> {
+        match &self.transport {
+            Transport::Plain | Transport::Tcp => None,
+            Transport::Tls { server_name }
+            | Transport::Https { server_name, .. }
+            | Transport::Quic { server_name }
+            | Transport::ObliviousHttps { server_name, .. } => Some(server_name),
+        }
+    }
+}
+
+fn fmt_https(
+    f: &mut fmt::Formatter<'_>,
+    server_name: &str,
+    path: &str,
+    addr: &SocketAddr,
+) -> fmt::Result {
+    match server_name.parse::<IpAddr>() {
+        Ok(ip) if ip == addr.ip() => write!(f, "https://{}{}", addr, path),
+        _ => write!(f, "https://{}{}#{}", url_host(server_name), path, addr),
+    }
+}
+
+impl fmt::Display for UpstreamServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.transport {
+            Transport::Plain => self.addr.fmt(f),
+            Transport::Tcp => write!(f, "tcp://{}", self.addr),
+            Transport::Tls { server_name } => write!(f, "tls://{}@{}", server_name, self.addr),
+            Transport::Quic { server_name } => write!(f, "quic://{}@{}", server_name, self.addr),
+            Transport::Https { server_name, path } => fmt_https(f, server_name, path, &self.addr),
+            Transport::ObliviousHttps {
+                server_name,
+                path,
+                target,
+            } => {
+                write!(f, "{} via ", target)?;
+                fmt_https(f, server_name, path, &self.addr)
+            }
+        }
+    }
+}
+
+/// The error returned when no upstream servers responded, listing the error
+/// of every server.
+///
+/// Its kind is the kind shared by all these errors, if any. If every server
+/// returned a truncated response, that couldn't be retried over TCP, the
+/// error is a `DnsError::Truncated` error.
+pub(crate) fn no_response_error(errors: Vec<(UpstreamServer, io::Error)>) -> io::Error {
+    let truncated = |e: &io::Error| {
+        e.get_ref()
+            .and_then(|inner| inner.downcast_ref::<DnsError>())
+            .is_some_and(|e| matches!(e, DnsError::Truncated))
+    };
+    if !errors.is_empty() && errors.iter().all(|(_, e)| truncated(e)) {
+        return DnsError::Truncated.into();
+    }
+    let mut kinds = errors.iter().map(|(_, e)| e.kind());
+    let kind = match kinds.next() {
+        Some(kind) if kinds.all(|other| other == kind) => kind,
+        _ => io::ErrorKind::InvalidInput,
+    };
+    if errors.is_empty() {
+        return io::Error::new(kind, "No response received from any servers");
+    }
+    let details: Vec<_> = errors
+        .iter()
+        .map(|(upstream_server, e)| format!("{}: {}", upstream_server, e))
+        .collect();
+    io::Error::new(
+        kind,
+        format!(
+            "No response received from any servers ({})",
+            details.join(", ")
+        ),
+    )
+}
+
+/// Parse an IP address, optionally followed by a port. IPv6 addresses can be
+/// written with or without brackets; `default_port` is used if there is no port.
+pub(crate) fn parse_addr(s: &str, default_port: u16) -> Result<SocketAddr, io::Error> {
+    let ip = s
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(s);
+    if let Ok(ip) = ip.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, default_port));
     }
+    s.parse::<SocketAddr>().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid server address [{}]", s),
+        )
+    })
+}
+
+/// A host name or an IP address, as written in URLs and `Host` headers.
+pub(crate) fn url_host(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+        _ => host.to_string(),
+    }
+}
+
+/// Split a `https://` URL into its host, port and path. The path defaults
+/// to `/dns-query`.
+fn parse_https_url(url: &str) -> Result<(String, u16, String), io::Error> {
+    let invalid = |reason: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid DNS-over-HTTPS URL [{}]: {}", url, reason),
+        )
+    };
+    let rest = url
+        .strip_prefix("https://")
+        .ok_or_else(|| invalid("the scheme must be https"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/dns-query"),
+    };
+    if authority.contains('@') {
+        return Err(invalid("credentials are not supported"));
+    }
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, port) = bracketed
+                .split_once(']')
+                .ok_or_else(|| invalid("unterminated IPv6 address"))?;
+            (host, port.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        return Err(invalid("missing host"));
+    }
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid("invalid port"))?,
+        None => DOH_PORT,
+    };
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// Parse the target or the proxy of an Oblivious DNS-over-HTTPS target,
+/// written as a DNS-over-HTTPS server, or as a stamp of the given kind.
+fn parse_odoh_endpoint(s: &str, kind: StampKind) -> Result<UpstreamServer, io::Error> {
+    if !s.starts_with("sdns://") {
+        return s.parse();
+    }
+    match crate::stamp::parse(s)? {
+        (stamp_kind, upstream_server) if stamp_kind == kind || stamp_kind == StampKind::Server => {
+            Ok(upstream_server)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Oblivious DNS-over-HTTPS stamps must be written as target via relay",
+        )),
+    }
+}
+
+/// Parse an upstream server from an IP address, with an optional port
+/// (`9.9.9.9`, `1.1.1.1:5353`, `[2620:fe::fe]:53`).
+///
+/// Plain DNS servers can be prefixed with `udp://`, and servers that must
+/// only be queried over TCP are written with `tcp://` (`tcp://192.0.2.1`).
+///
+/// DNS-over-TLS servers are written as `tls://` followed by the name of the
+/// server and its address, whose port defaults to 853
+/// (`tls://dns.quad9.net@9.9.9.9`). DNS-over-QUIC servers are written the
+/// same way, with `quic://`.
+///
+/// DNS-over-HTTPS servers are written as URLs, optionally followed by `#`
+/// and the address of the server (`https://dns.quad9.net/dns-query#9.9.9.9`).
+/// The address can only be omitted if the host of the URL is an IP address.
+///
+/// Oblivious DNS-over-HTTPS targets are written as the target, followed by
+/// ` via ` and the proxy, both written as DNS-over-HTTPS servers
+/// (`https://odoh.example/dns-query#192.0.2.1 via https://odoh-relay.example/proxy#192.0.2.2`).
+///
+/// DNS stamps (`sdns://...`) are parsed like `from_stamp()` does, and
+/// Oblivious DNS-over-HTTPS target and relay stamps can be combined with
+/// ` via `.
+impl FromStr for UpstreamServer {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((target, proxy)) = s.split_once(" via ") {
+            return UpstreamServer::new_odoh(
+                parse_odoh_endpoint(proxy, StampKind::ObliviousRelay)?,
+                parse_odoh_endpoint(target, StampKind::ObliviousTarget)?,
+            );
+        }
+        if s.starts_with("sdns://") {
+            return UpstreamServer::from_stamp(s);
+        }
+        for (scheme, protocol_name) in [("tls://", "TLS"), ("quic://", "QUIC")] {
+            let server = match s.strip_prefix(scheme) {
+                Some(server) => server,
+                None => continue,
+            };
+            let (server_name, addr) = server.rsplit_once('@').ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "DNS-over-{} servers must be written as {}name@address",
+                        protocol_name, scheme
+                    ),
+                )
+            })?;
+            if server_name.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Missing DNS-over-{} server name", protocol_name),
+                ));
+            }
+            let addr = parse_addr(addr, DOT_PORT)?;
+            return Ok(match scheme {
+                "tls://" => UpstreamServer::new_tls(addr, server_name),
+                _ => UpstreamServer::new_quic(addr, server_name),
+            });
+        }
+        if s.starts_with("https://") {
+            return match s.split_once('#') {
+                Some((url, addr)) => {
+                    let (_, port, _) = parse_https_url(url)?;
+                    UpstreamServer::new_doh_with_addr(parse_addr(addr, port)?, url)
+                }
+                None => UpstreamServer::new_doh(s),
+            };
+        }
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            return parse_addr(addr, 53).map(UpstreamServer::new_tcp);
+        }
+        let addr = s.strip_prefix("udp://").unwrap_or(s);
+        parse_addr(addr, 53).map(UpstreamServer::new)
+    }
+}
+
+impl TryFrom<&str> for UpstreamServer {
+    type Error = io::Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for UpstreamServer {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for UpstreamServer {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[test]
+fn test_upstream_server_from_str() {
+    let upstream_server: UpstreamServer = "9.9.9.9".parse().unwrap();
+    assert_eq!(upstream_server.addr, SocketAddr::from(([9, 9, 9, 9], 53)));
+    let upstream_server: UpstreamServer = "[2620:fe::fe]:5353".parse().unwrap();
+    assert_eq!(upstream_server.to_string(), "[2620:fe::fe]:5353");
+    assert!("dns.quad9.net".parse::<UpstreamServer>().is_err());
+    let upstream_server = UpstreamServer::try_from("udp://1.1.1.1:5353").unwrap();
+    assert_eq!(upstream_server, UpstreamServer::new(([1, 1, 1, 1], 5353)));
+    let upstream_server: UpstreamServer = "udp://[2001:db8::1]".parse().unwrap();
+    assert_eq!(
+        upstream_server.addr,
+        "[2001:db8::1]:53".parse::<SocketAddr>().unwrap()
+    );
+    let upstream_server: UpstreamServer = "tls://dns.test@[2001:db8::1]".parse().unwrap();
+    assert_eq!(
+        upstream_server.addr,
+        "[2001:db8::1]:853".parse::<SocketAddr>().unwrap()
+    );
+    assert!("udp://[2001:db8::1".parse::<UpstreamServer>().is_err());
+
+    let upstream_server: UpstreamServer = "tcp://192.0.2.1".parse().unwrap();
+    assert_eq!(upstream_server.transport, Transport::Tcp);
+    assert_eq!(upstream_server.addr, SocketAddr::from(([192, 0, 2, 1], 53)));
+    assert_eq!(upstream_server.to_string(), "tcp://192.0.2.1:53");
+    assert_eq!(
+        upstream_server
+            .to_string()
+            .parse::<UpstreamServer>()
+            .unwrap(),
+        upstream_server
+    );
+    assert!("tcp://dns.quad9.net".parse::<UpstreamServer>().is_err());
+
+    let upstream_server: UpstreamServer = "tls://dns.quad9.net@9.9.9.9".parse().unwrap();
+    assert_eq!(upstream_server.addr, SocketAddr::from(([9, 9, 9, 9], 853)));
+    assert_eq!(upstream_server.tls_server_name(), Some("dns.quad9.net"));
+    assert_eq!(
+        upstream_server.to_string(),
+        "tls://dns.quad9.net@9.9.9.9:853"
+    );
+    assert_eq!(
+        upstream_server
+            .to_string()
+            .parse::<UpstreamServer>()
+            .unwrap(),
+        upstream_server
+    );
+    assert!("tls://9.9.9.9".parse::<UpstreamServer>().is_err());
+    assert!("tls://@9.9.9.9".parse::<UpstreamServer>().is_err());
+
+    let upstream_server: UpstreamServer =
+        "quic://dns.adguard-dns.com@94.140.14.14".parse().unwrap();
+    assert!(upstream_server.is_quic());
+    assert_eq!(
+        upstream_server.addr,
+        SocketAddr::from(([94, 140, 14, 14], 853))
+    );
+    assert_eq!(
+        upstream_server.to_string(),
+        "quic://dns.adguard-dns.com@94.140.14.14:853"
+    );
+    assert!("quic://94.140.14.14".parse::<UpstreamServer>().is_err());
+
+    let upstream_server: UpstreamServer =
+        "https://odoh.test/dns-query#192.0.2.1 via https://proxy.test/proxy#192.0.2.2"
+            .parse()
+            .unwrap();
+    assert!(upstream_server.is_odoh());
+    assert_eq!(
+        upstream_server.addr,
+        SocketAddr::from(([192, 0, 2, 2], 443))
+    );
+    assert_eq!(upstream_server.tls_server_name(), Some("proxy.test"));
+    assert_eq!(
+        upstream_server.to_string(),
+        "https://odoh.test/dns-query#192.0.2.1:443 via https://proxy.test/proxy#192.0.2.2:443"
+    );
+    assert_eq!(
+        upstream_server
+            .to_string()
+            .parse::<UpstreamServer>()
+            .unwrap(),
+        upstream_server
+    );
+    assert!("9.9.9.9 via https://proxy.test/proxy#192.0.2.2"
+        .parse::<UpstreamServer>()
+        .is_err());
+
+    let upstream_server: UpstreamServer =
+        "https://dns.quad9.net/dns-query#9.9.9.9".parse().unwrap();
+    assert_eq!(upstream_server.addr, SocketAddr::from(([9, 9, 9, 9], 443)));
+    assert_eq!(
+        upstream_server.transport,
+        Transport::Https {
+            server_name: "dns.quad9.net".to_string(),
+            path: "/dns-query".to_string(),
+        }
+    );
+    assert_eq!(
+        upstream_server.to_string(),
+        "https://dns.quad9.net/dns-query#9.9.9.9:443"
+    );
+    assert_eq!(
+        upstream_server
+            .to_string()
+            .parse::<UpstreamServer>()
+            .unwrap(),
+        upstream_server
+    );
+    let upstream_server: UpstreamServer = "https://[2620:fe::fe]:8443".parse().unwrap();
+    assert_eq!(
+        upstream_server.addr,
+        "[2620:fe::fe]:8443".parse::<SocketAddr>().unwrap()
+    );
+    assert_eq!(upstream_server.tls_server_name(), Some("2620:fe::fe"));
+    assert_eq!(
+        upstream_server.to_string(),
+        "https://[2620:fe::fe]:8443/dns-query"
+    );
+    let upstream_server =
+        UpstreamServer::new_doh_with_addr(([9, 9, 9, 9], 443), "https://[2620:fe::fe]/").unwrap();
+    assert_eq!(
+        upstream_server.to_string(),
+        "https://[2620:fe::fe]/#9.9.9.9:443"
+    );
+    assert_eq!(
+        upstream_server
+            .to_string()
+            .parse::<UpstreamServer>()
+            .unwrap(),
+        upstream_server
+    );
+    let upstream_server: UpstreamServer = "https://1.1.1.1/dns-query".parse().unwrap();
+    assert_eq!(upstream_server.to_string(), "https://1.1.1.1:443/dns-query");
+    assert_eq!(
+        upstream_server
+            .to_string()
+            .parse::<UpstreamServer>()
+            .unwrap(),
+        upstream_server
+    );
+    assert!("https://user@1.1.1.1/dns-query"
+        .parse::<UpstreamServer>()
+        .is_err());
+    assert!("https:///dns-query".parse::<UpstreamServer>().is_err());
+    assert_eq!(
+        "https://dns.test/dns-query"
+            .parse::<UpstreamServer>()
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidInput
+    );
+    assert!("https://1.1.1.1:dns/dns-query"
+        .parse::<UpstreamServer>()
+        .is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_upstream_server_serde() {
+    let upstream_servers: Vec<UpstreamServer> =
+        serde_json::from_str(r#"["1.1.1.1", "[2606:4700:4700::1111]:53"]"#).unwrap();
+    assert_eq!(
+        upstream_servers[0].addr,
+        SocketAddr::from(([1, 1, 1, 1], 53))
+    );
+    assert_eq!(
+        serde_json::to_string(&upstream_servers).unwrap(),
+        r#"["1.1.1.1:53","[2606:4700:4700::1111]:53"]"#
+    );
+}
+
+#[test]
+fn test_upstream_server_from_stamp() {
+    use crate::stamp::stamp;
+
+    let doh = stamp(0x02, &[b"9.9.9.9", b"", b"dns.quad9.net", b"/dns-query"]);
+    let upstream_server = UpstreamServer::from_stamp(&doh).unwrap();
+    assert!(upstream_server.is_doh());
+    assert_eq!(doh.parse::<UpstreamServer>().unwrap(), upstream_server);
+
+    let target = stamp(0x05, &[b"192.0.2.1", b"/dns-query"]);
+    let relay = stamp(0x85, &[b"192.0.2.2", b"", b"relay.test", b"/proxy"]);
+    assert!(UpstreamServer::from_stamp(&target).is_err());
+    assert!(UpstreamServer::from_stamp(&relay).is_err());
+    let upstream_server: UpstreamServer = format!("{} via {}", target, relay).parse().unwrap();
+    assert!(upstream_server.is_odoh());
+    assert_eq!(upstream_server.tls_server_name(), Some("relay.test"));
+    assert!(format!("{} via {}", relay, target)
+        .parse::<UpstreamServer>()
+        .is_err());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_tls_upstream_server() {
+    use std::net::Ipv4Addr;
+
+    use crate::events::Protocol;
+    use crate::testing::MockBackend;
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let upstream_servers = vec![UpstreamServer::new_tls(
+        SocketAddr::from(([192, 0, 2, 53], 853)),
+        "dns.example",
+    )];
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    let (ips, query_info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    assert_eq!(query_info.protocol, Some(Protocol::Tls));
+    dns_client.force_tcp(true);
+    let (_, query_info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert_eq!(query_info.protocol, Some(Protocol::Tls));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_doh_upstream_server() {
+    use std::net::Ipv4Addr;
+
+    use crate::events::Protocol;
+    use crate::testing::MockBackend;
+
+    let backend = MockBackend::new();
+    backend.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)]);
+    let upstream_servers = vec![
+        UpstreamServer::new_doh("https://192.0.2.53/dns-query").unwrap(),
+        UpstreamServer::new(SocketAddr::from(([192, 0, 2, 54], 53))),
+    ];
+    let mut dns_client = crate::sync::GenericDNSClient::with_backend(upstream_servers, backend);
+    let (ips, query_info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert_eq!(ips, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    assert_eq!(query_info.protocol, Some(Protocol::Https));
+    dns_client.force_tcp(true);
+    let (_, query_info) = dns_client.query_a_detailed("example.com").unwrap();
+    assert_eq!(query_info.protocol, Some(Protocol::Https));
 }
@@ -0,0 +1,210 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+
+use crate::rng::ClientRng;
+use crate::stats::Stats;
+use crate::upstream_server::UpstreamServer;
+
+/// How the upstream servers are ordered before a query is sent to them.
+///
+/// Queries are sent to the first server, and to the next ones when it fails.
+/// Servers marked down by the health policy are moved last, regardless of the
+/// strategy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum UpstreamStrategy {
+    /// Keep the configured order, so that the first healthy server receives
+    /// every query.
+    #[default]
+    FirstHealthy,
+    /// Start from the next server for every query, like the `rotate` option
+    /// of `resolv.conf`, to spread the load across servers.
+    RoundRobin,
+    /// Shuffle the servers for every query.
+    Random,
+    /// Use the servers with the lowest `priority` first, and pick servers of
+    /// the same priority randomly, in proportion to their `weight`, like SRV
+    /// records (RFC 2782).
+    Weighted,
+    /// Prefer the servers with the lowest smoothed response time. Servers
+    /// that didn't respond to their last query come last, and servers that
+    /// were never used first, so that their response time gets measured.
+    Fastest,
+}
+
+impl UpstreamStrategy {
+    pub(crate) fn order<'t>(
+        self,
+        upstream_servers: &'t [UpstreamServer],
+        stats: &Stats,
+        rotation: &AtomicUsize,
+        rng: &ClientRng,
+    ) -> Cow<'t, [UpstreamServer]> {
+        match self {
+            UpstreamStrategy::FirstHealthy => Cow::Borrowed(upstream_servers),
+            _ if upstream_servers.len() < 2 => Cow::Borrowed(upstream_servers),
+            UpstreamStrategy::RoundRobin => {
+                let mut ordered = upstream_servers.to_vec();
+                let first = rotation.fetch_add(1, Ordering::Relaxed) % ordered.len();
+                ordered.rotate_left(first);
+                Cow::Owned(ordered)
+            }
+            UpstreamStrategy::Random => {
+                let mut ordered = upstream_servers.to_vec();
+                rng.with(|rng| ordered.shuffle(rng));
+                Cow::Owned(ordered)
+            }
+            UpstreamStrategy::Weighted => {
+                let mut servers = upstream_servers.to_vec();
+                servers.sort_by_key(|server| server.priority);
+                let mut ordered = Vec::with_capacity(servers.len());
+                rng.with(|rng| {
+                    let mut start = 0;
+                    while start < servers.len() {
+                        let priority = servers[start].priority;
+                        let end = servers[start..]
+                            .iter()
+                            .position(|server| server.priority != priority)
+                            .map_or(servers.len(), |len| start + len);
+                        weighted_shuffle(&servers[start..end], &mut ordered, rng);
+                        start = end;
+                    }
+                });
+                Cow::Owned(ordered)
+            }
+            UpstreamStrategy::Fastest => {
+                let mut ordered = upstream_servers.to_vec();
+                ordered.sort_by_cached_key(|server| {
+                    let failing = stats.consecutive_failures(server) > 0;
+                    (failing, stats.srtt(server).unwrap_or(Duration::ZERO))
+                });
+                Cow::Owned(ordered)
+            }
+        }
+    }
+}
+
+/// Append the servers of a group to `ordered`, picking each of them with a
+/// probability proportional to its weight among the remaining ones.
+fn weighted_shuffle(
+    group: &[UpstreamServer],
+    ordered: &mut Vec<UpstreamServer>,
+    rng: &mut dyn RngCore,
+) {
+    let mut remaining = group.to_vec();
+    while !remaining.is_empty() {
+        let total: u32 = remaining
+            .iter()
+            .map(|server| u32::from(server.weight))
+            .sum();
+        if total == 0 {
+            ordered.append(&mut remaining);
+            break;
+        }
+        let mut pick = rng.gen_range(0..total);
+        let i = remaining
+            .iter()
+            .position(|server| match pick.checked_sub(u32::from(server.weight)) {
+                Some(rest) => {
+                    pick = rest;
+                    false
+                }
+                None => true,
+            })
+            .unwrap();
+        ordered.push(remaining.remove(i));
+    }
+}
+
+#[test]
+fn test_fastest() {
+    use crate::events::{ClientEvent, Protocol};
+
+    let servers: Vec<_> = (1..=3)
+        .map(|i| UpstreamServer::new(([192, 0, 2, i], 53)))
+        .collect();
+    let stats = Stats::default();
+    let response = |server: &UpstreamServer, rtt| ClientEvent::ResponseReceived {
+        server: server.clone(),
+        protocol: Protocol::Udp,
+        rtt: Duration::from_millis(rtt),
+    };
+    stats.record_event(&response(&servers[0], 100));
+    stats.record_event(&response(&servers[1], 10));
+    stats.record_event(&response(&servers[2], 1));
+    stats.record_event(&ClientEvent::Timeout {
+        server: servers[2].clone(),
+        protocol: Protocol::Udp,
+    });
+    let rotation = AtomicUsize::new(0);
+    let rng = ClientRng::default();
+    let ordered = UpstreamStrategy::Fastest.order(&servers, &stats, &rotation, &rng);
+    assert_eq!(
+        ordered.as_ref(),
+        &[servers[1].clone(), servers[0].clone(), servers[2].clone()][..]
+    );
+    let ordered = UpstreamStrategy::FirstHealthy.order(&servers, &stats, &rotation, &rng);
+    assert_eq!(ordered.as_ref(), &servers[..]);
+}
+
+#[test]
+fn test_round_robin() {
+    let servers: Vec<_> = (1..=3)
+        .map(|i| UpstreamServer::new(([192, 0, 2, i], 53)))
+        .collect();
+    let stats = Stats::default();
+    let rotation = AtomicUsize::new(0);
+    let rng = ClientRng::default();
+    let first_servers: Vec<_> = (0..4)
+        .map(|_| UpstreamStrategy::RoundRobin.order(&servers, &stats, &rotation, &rng)[0].clone())
+        .collect();
+    assert_eq!(
+        first_servers,
+        vec![
+            servers[0].clone(),
+            servers[1].clone(),
+            servers[2].clone(),
+            servers[0].clone()
+        ]
+    );
+    let mut shuffled = UpstreamStrategy::Random
+        .order(&servers, &stats, &rotation, &rng)
+        .into_owned();
+    shuffled.sort();
+    assert_eq!(shuffled, servers);
+}
+
+#[test]
+fn test_weighted() {
+    let primary = UpstreamServer::new(([192, 0, 2, 1], 53)).with_weight(9);
+    let secondary = UpstreamServer::new(([192, 0, 2, 2], 53));
+    let backup = UpstreamServer::new(([192, 0, 2, 3], 53)).with_priority(1);
+    let unused = UpstreamServer::new(([192, 0, 2, 4], 53)).with_weight(0);
+    let servers = vec![
+        backup.clone(),
+        unused.clone(),
+        secondary.clone(),
+        primary.clone(),
+    ];
+    let stats = Stats::default();
+    let rotation = AtomicUsize::new(0);
+    let rng = ClientRng::new(<rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(0));
+    let mut primary_first = 0;
+    for _ in 0..1000 {
+        let ordered = UpstreamStrategy::Weighted.order(&servers, &stats, &rotation, &rng);
+        assert_eq!(ordered[2], unused);
+        assert_eq!(ordered[3], backup);
+        if ordered[0] == primary {
+            primary_first += 1;
+        }
+    }
+    assert!((800..=980).contains(&primary_first));
+}